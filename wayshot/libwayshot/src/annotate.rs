@@ -0,0 +1,410 @@
+//! Drawing annotations like arrows, boxes and numbered markers onto
+//! screenshots, e.g. for documentation.
+//!
+//! Coordinates, lengths and font sizes are in logical pixels, with the origin
+//! at the top left corner of the screenshot, see [`crate::composited_scale`].
+
+use std::mem;
+
+use ab_glyph::{Font, FontRef, OutlinedGlyph, PxScale, Rect, ScaleFont, point};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::{
+    image_util,
+    region::{LogicalRegion, Position, Region, Size},
+};
+
+/// DejaVu Sans, see `fonts/LICENSE`.
+const FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+/// Space between text and the edges of its background, relative to the font
+/// size.
+const TEXT_PADDING: f64 = 0.3;
+/// Length of the head of an arrow relative to the width of its line, and the
+/// smallest length in logical pixels.
+const ARROW_HEAD: (f64, f64) = (4.0, 10.0);
+
+/// Color and width of lines.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stroke {
+    pub color: Rgba<u8>,
+    /// Width in logical pixels.
+    pub width: f64,
+}
+
+/// Something to draw with [`annotate`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Annotation {
+    /// A box, with the stroke centered on its edges.
+    Rectangle {
+        region: LogicalRegion,
+        stroke: Option<Stroke>,
+        fill: Option<Rgba<u8>>,
+    },
+    /// A line from `from` with a head pointing at `to`.
+    Arrow {
+        from: Position,
+        to: Position,
+        stroke: Stroke,
+    },
+    /// A circle, with the stroke centered on its edge.
+    Circle {
+        center: Position,
+        /// Radius in logical pixels.
+        radius: f64,
+        stroke: Option<Stroke>,
+        fill: Option<Rgba<u8>>,
+    },
+    /// Text with its top left corner at `position`. Lines are separated with
+    /// `\n`.
+    Text {
+        position: Position,
+        text: String,
+        /// Font size in logical pixels.
+        size: f64,
+        color: Rgba<u8>,
+        /// Drawn behind the text, which is padded relative to its size.
+        background: Option<Rgba<u8>>,
+    },
+    /// A filled circle with a number in it, to refer to parts of the
+    /// screenshot from the text around it.
+    Marker {
+        center: Position,
+        number: u32,
+        /// Radius in logical pixels.
+        radius: f64,
+        /// Color of the circle. The number is white or black, whichever is
+        /// easier to read on it.
+        color: Rgba<u8>,
+    },
+}
+
+/// Draw `annotations` onto `image`, a screenshot that is `logical_size` large
+/// in logical pixels, in the given order.
+pub fn annotate(image: &mut DynamicImage, logical_size: Size, annotations: &[Annotation]) {
+    let mut canvas = Canvas {
+        scale: image_util::composited_scale(image, logical_size),
+        image: mem::take(image).into_rgba8(),
+        font: FontRef::try_from_slice(FONT)
+            .inspect_err(|e| tracing::error!("Failed to load the bundled font: {e}"))
+            .ok(),
+    };
+    for annotation in annotations {
+        canvas.draw(annotation);
+    }
+    *image = canvas.image.into();
+}
+
+struct Canvas {
+    image: RgbaImage,
+    scale: f64,
+    /// `None` if the bundled font couldn't be loaded, text is left out then.
+    font: Option<FontRef<'static>>,
+}
+
+impl Canvas {
+    fn draw(&mut self, annotation: &Annotation) {
+        let s = self.scale;
+        match annotation {
+            Annotation::Rectangle {
+                region,
+                stroke,
+                fill,
+            } => {
+                let Region { position, size } = region.inner;
+                let (left, top) = (position.x as f64 * s, position.y as f64 * s);
+                let (right, bottom) = (left + size.width as f64 * s, top + size.height as f64 * s);
+                let center = ((left + right) / 2.0, (top + bottom) / 2.0);
+                let half = ((right - left) / 2.0, (bottom - top) / 2.0);
+                let distance =
+                    |x: f64, y: f64| image_util::rounded_box_distance((x, y), center, half, 0.0);
+                if let Some(fill) = fill {
+                    self.paint([left, top, right, bottom], *fill, distance);
+                }
+                if let Some(Stroke { color, width }) = stroke {
+                    let half_width = width * s / 2.0;
+                    self.paint(
+                        [
+                            left - half_width,
+                            top - half_width,
+                            right + half_width,
+                            bottom + half_width,
+                        ],
+                        *color,
+                        |x, y| distance(x, y).abs() - half_width,
+                    );
+                }
+            }
+            Annotation::Arrow { from, to, stroke } => {
+                let from = (from.x as f64 * s, from.y as f64 * s);
+                let to = (to.x as f64 * s, to.y as f64 * s);
+                let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                let length = dx.hypot(dy).max(f64::EPSILON);
+                let direction = (dx / length, dy / length);
+                let half_width = stroke.width * s / 2.0;
+                let head_length = (stroke.width * ARROW_HEAD.0).max(ARROW_HEAD.1) * s;
+                // The line ends inside of the head, so that its round end
+                // doesn't poke out of the tip.
+                let shaft_end = (
+                    to.0 - direction.0 * head_length * 0.5,
+                    to.1 - direction.1 * head_length * 0.5,
+                );
+                let base = (
+                    to.0 - direction.0 * head_length,
+                    to.1 - direction.1 * head_length,
+                );
+                let normal = (
+                    -direction.1 * head_length / 2.0,
+                    direction.0 * head_length / 2.0,
+                );
+                let head = [
+                    to,
+                    (base.0 + normal.0, base.1 + normal.1),
+                    (base.0 - normal.0, base.1 - normal.1),
+                ];
+                let margin = half_width.max(head_length);
+                self.paint(
+                    [
+                        from.0.min(to.0) - margin,
+                        from.1.min(to.1) - margin,
+                        from.0.max(to.0) + margin,
+                        from.1.max(to.1) + margin,
+                    ],
+                    stroke.color,
+                    |x, y| {
+                        let shaft = segment_distance((x, y), from, shaft_end) - half_width;
+                        shaft.min(triangle_distance((x, y), head))
+                    },
+                );
+            }
+            Annotation::Circle {
+                center,
+                radius,
+                stroke,
+                fill,
+            } => {
+                let center = (center.x as f64 * s, center.y as f64 * s);
+                let radius = radius * s;
+                let distance = |x: f64, y: f64| (x - center.0).hypot(y - center.1) - radius;
+                let bounds = |margin: f64| {
+                    [
+                        center.0 - radius - margin,
+                        center.1 - radius - margin,
+                        center.0 + radius + margin,
+                        center.1 + radius + margin,
+                    ]
+                };
+                if let Some(fill) = fill {
+                    self.paint(bounds(0.0), *fill, distance);
+                }
+                if let Some(Stroke { color, width }) = stroke {
+                    let half_width = width * s / 2.0;
+                    self.paint(bounds(half_width), *color, |x, y| {
+                        distance(x, y).abs() - half_width
+                    });
+                }
+            }
+            Annotation::Text {
+                position,
+                text,
+                size,
+                color,
+                background,
+            } => {
+                let size = size * s;
+                let padding = if background.is_some() {
+                    size * TEXT_PADDING
+                } else {
+                    0.0
+                };
+                let origin = (position.x as f64 * s, position.y as f64 * s);
+                let glyphs = self.layout(text, size, (origin.0 + padding, origin.1 + padding));
+                if let Some(background) = background {
+                    let (width, height) = self.text_size(text, size);
+                    let (right, bottom) = (
+                        origin.0 + width + 2.0 * padding,
+                        origin.1 + height + 2.0 * padding,
+                    );
+                    let center = ((origin.0 + right) / 2.0, (origin.1 + bottom) / 2.0);
+                    let half = ((right - origin.0) / 2.0, (bottom - origin.1) / 2.0);
+                    self.paint([origin.0, origin.1, right, bottom], *background, |x, y| {
+                        image_util::rounded_box_distance((x, y), center, half, 0.0)
+                    });
+                }
+                self.fill_glyphs(&glyphs, *color);
+            }
+            Annotation::Marker {
+                center,
+                number,
+                radius,
+                color,
+            } => {
+                let center = (center.x as f64 * s, center.y as f64 * s);
+                let radius = radius * s;
+                self.paint(
+                    [
+                        center.0 - radius,
+                        center.1 - radius,
+                        center.0 + radius,
+                        center.1 + radius,
+                    ],
+                    *color,
+                    |x, y| (x - center.0).hypot(y - center.1) - radius,
+                );
+
+                // Center the ink of the digits, which don't reach as far down
+                // as the descent of the font.
+                let number = number.to_string();
+                let glyphs = self.layout(&number, radius * 1.2, (0.0, 0.0));
+                let Some(ink) = glyphs
+                    .iter()
+                    .map(OutlinedGlyph::px_bounds)
+                    .reduce(|a, b| Rect {
+                        min: point(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+                        max: point(a.max.x.max(b.max.x), a.max.y.max(b.max.y)),
+                    })
+                else {
+                    return;
+                };
+                let offset = (
+                    center.0 - (ink.min.x + ink.max.x) as f64 / 2.0,
+                    center.1 - (ink.min.y + ink.max.y) as f64 / 2.0,
+                );
+                let glyphs = self.layout(&number, radius * 1.2, offset);
+                let [red, green, blue, _] = color.0;
+                let luminance = 0.2126 * red as f64 + 0.7152 * green as f64 + 0.0722 * blue as f64;
+                let text_color = if luminance > 150.0 {
+                    Rgba([0, 0, 0, 255])
+                } else {
+                    Rgba([255, 255, 255, 255])
+                };
+                self.fill_glyphs(&glyphs, text_color);
+            }
+        }
+    }
+
+    /// Outlines of the glyphs of `text` in a font `size` pixels large, with
+    /// the top left corner of the first line at `origin`.
+    fn layout(&self, text: &str, size: f64, origin: (f64, f64)) -> Vec<OutlinedGlyph> {
+        let Some(font) = &self.font else {
+            return Vec::new();
+        };
+        let scaled = font.as_scaled(PxScale::from(size as f32));
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut glyphs = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let baseline = origin.1 as f32 + scaled.ascent() + index as f32 * line_height;
+            let mut caret = origin.0 as f32;
+            let mut previous = None;
+            for character in line.chars() {
+                let mut glyph = scaled.scaled_glyph(character);
+                if let Some(previous) = previous {
+                    caret += scaled.kern(previous, glyph.id);
+                }
+                glyph.position = point(caret, baseline);
+                caret += scaled.h_advance(glyph.id);
+                previous = Some(glyph.id);
+                glyphs.extend(font.outline_glyph(glyph));
+            }
+        }
+        glyphs
+    }
+
+    /// Width and height of `text` in a font `size` pixels large.
+    fn text_size(&self, text: &str, size: f64) -> (f64, f64) {
+        let Some(font) = &self.font else {
+            return (0.0, 0.0);
+        };
+        let font = font.as_scaled(PxScale::from(size as f32));
+        let width = text
+            .lines()
+            .map(|line| {
+                let mut previous = None;
+                line.chars()
+                    .map(|character| {
+                        let id = font.glyph_id(character);
+                        let kern = previous.map_or(0.0, |previous| font.kern(previous, id));
+                        previous = Some(id);
+                        kern + font.h_advance(id)
+                    })
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max);
+        let lines = text.lines().count().max(1) as f32;
+        let height = lines * font.height() + (lines - 1.0) * font.line_gap();
+        (width as f64, height as f64)
+    }
+
+    fn fill_glyphs(&mut self, glyphs: &[OutlinedGlyph], color: Rgba<u8>) {
+        for glyph in glyphs {
+            let bounds = glyph.px_bounds();
+            glyph.draw(|x, y, coverage| {
+                self.blend(
+                    bounds.min.x as i64 + x as i64,
+                    bounds.min.y as i64 + y as i64,
+                    color,
+                    coverage as f64,
+                );
+            });
+        }
+    }
+
+    /// Paint the pixels within `bounds`, given as left, top, right and bottom
+    /// edge, with `color`. `distance` is the signed distance of a point to the
+    /// edge of the painted shape, negative inside of it, and is used to smooth
+    /// the edge. Only the part of `bounds` inside of the image is visited.
+    fn paint(&mut self, bounds: [f64; 4], color: Rgba<u8>, distance: impl Fn(f64, f64) -> f64) {
+        let [left, top, right, bottom] = bounds;
+        let (width, height) = (self.image.width() as i64, self.image.height() as i64);
+        // Out of range and NaN edges saturate when cast, so the loops stay
+        // within the image whatever was drawn.
+        let columns = (left.floor() as i64 - 1).max(0)..=(right.ceil() as i64).min(width - 1);
+        let rows = (top.floor() as i64 - 1).max(0)..=(bottom.ceil() as i64).min(height - 1);
+        for y in rows {
+            for x in columns.clone() {
+                let coverage = (0.5 - distance(x as f64 + 0.5, y as f64 + 0.5)).clamp(0.0, 1.0);
+                self.blend(x, y, color, coverage);
+            }
+        }
+    }
+
+    /// Draw `color` over the pixel at `x`, `y`, with its alpha multiplied by
+    /// `coverage`. Pixels outside of the image are ignored.
+    fn blend(&mut self, x: i64, y: i64, color: Rgba<u8>, coverage: f64) {
+        if coverage <= 0.0 || x < 0 || y < 0 {
+            return;
+        }
+        let Some(pixel) = self.image.get_pixel_mut_checked(x as u32, y as u32) else {
+            return;
+        };
+        image_util::blend(pixel, color, coverage);
+    }
+}
+
+/// Distance of `point` to the line segment from `a` to `b`.
+fn segment_distance(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (pa, ba) = ((point.0 - a.0, point.1 - a.1), (b.0 - a.0, b.1 - a.1));
+    let length = ba.0 * ba.0 + ba.1 * ba.1;
+    let t = if length > 0.0 {
+        ((pa.0 * ba.0 + pa.1 * ba.1) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (pa.0 - ba.0 * t).hypot(pa.1 - ba.1 * t)
+}
+
+/// Signed distance of `point` to the edge of a triangle, exact inside of it
+/// and close enough outside of it to smooth its edges.
+fn triangle_distance(point: (f64, f64), corners: [(f64, f64); 3]) -> f64 {
+    // Either winding, the distance to each edge is positive on its inside.
+    let [a, b, c] = corners;
+    let clockwise = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0) > 0.0;
+    (0..3)
+        .map(|index| {
+            let (from, to) = (corners[index], corners[(index + 1) % 3]);
+            let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+            let length = dx.hypot(dy).max(f64::EPSILON);
+            let cross = (dx * (point.1 - from.1) - dy * (point.0 - from.0)) / length;
+            if clockwise { -cross } else { cross }
+        })
+        .fold(f64::MIN, f64::max)
+}
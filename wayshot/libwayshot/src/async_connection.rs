@@ -0,0 +1,450 @@
+//! Async variant of [`WayshotConnection`], driven by the tokio reactor.
+//!
+//! Instead of blocking on the Wayland socket, waiting captures register the
+//! socket with tokio. Whichever capture sees the socket become readable reads
+//! the events and wakes up all others, so any number of captures can wait at
+//! the same time without a thread of their own.
+
+use std::{
+    io,
+    os::fd::OwnedFd,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use tokio::{
+    io::{Interest, unix::AsyncFd},
+    sync::watch,
+};
+use wayland_client::{
+    Connection, EventQueue,
+    backend::{Backend, ObjectData, ObjectId, WaylandError, protocol::Message},
+    protocol::wl_display,
+};
+
+use crate::{
+    CapturePhase, Error, Result, Sessions, Timeouts, WayshotConnection,
+    capture::{CaptureBatch, SessionCache},
+    dispatch::OutputCaptureState,
+    image_util,
+    output::{OutputInfo, OutputRefresh},
+    region::{EmbeddedRegion, LogicalRegion, RegionCapturer},
+    screencopy::{FrameCopy, FrameGuard},
+};
+
+/// Like [`WayshotConnection`], but returns futures instead of blocking the
+/// calling thread. Requires the `tokio` feature.
+///
+/// Don't use the same [`Connection`] for blocking calls at the same time, a
+/// blocking read on it also blocks the runtime thread reading events.
+///
+/// # Example usage
+///
+/// ```ignore
+/// use libwayshot::AsyncWayshotConnection;
+/// let wayshot_connection = AsyncWayshotConnection::new().await?;
+/// let image_buffer = wayshot_connection.screenshot_all(false).await?;
+/// ```
+#[derive(Debug)]
+pub struct AsyncWayshotConnection {
+    inner: WayshotConnection,
+    reader: EventReader,
+}
+
+impl AsyncWayshotConnection {
+    pub async fn new() -> Result<Self> {
+        let conn = Connection::connect_to_env()?;
+
+        Self::from_connection(conn).await
+    }
+
+    /// Recommended if you already have a [`wayland_client::Connection`].
+    ///
+    /// Fetching the globals of the compositor is a single blocking roundtrip,
+    /// everything after it is async.
+    pub async fn from_connection(conn: Connection) -> Result<Self> {
+        let reader = EventReader::new(conn.clone())?;
+        let initial_state = Self {
+            inner: WayshotConnection::from_connection_without_outputs(conn)?,
+            reader,
+        };
+
+        initial_state.refresh_outputs().await?;
+
+        Ok(initial_state)
+    }
+
+    /// Timeouts used when waiting on the compositor during captures.
+    pub fn timeouts(&self) -> Timeouts {
+        self.inner.timeouts()
+    }
+
+    /// See [`WayshotConnection::set_timeouts`].
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.inner.set_timeouts(timeouts);
+    }
+
+    /// Fetch all accessible wayland outputs.
+    pub fn get_all_outputs(&self) -> Vec<OutputInfo> {
+        self.inner.get_all_outputs()
+    }
+
+    /// refresh the outputs, to get new outputs
+    pub async fn refresh_outputs(&self) -> Result<()> {
+        let mut state = OutputCaptureState {
+            outputs: Vec::new(),
+        };
+        let mut event_queue = self.inner.conn.new_event_queue::<OutputCaptureState>();
+        let qh = event_queue.handle();
+
+        // Read the globals that were announced meanwhile.
+        self.reader.roundtrip(&mut event_queue, &mut state).await?;
+        let mut refresh = OutputRefresh::start(
+            &self.inner.globals,
+            &self.inner.bound_globals,
+            &mut state,
+            &qh,
+        )?;
+        self.reader.roundtrip(&mut event_queue, &mut state).await?;
+
+        refresh.request_positions(&state, &qh);
+        self.reader.roundtrip(&mut event_queue, &mut state).await?;
+
+        let outputs = refresh.finish(state)?;
+        self.inner.set_outputs(outputs);
+
+        Ok(())
+    }
+
+    /// Take a screenshot of the given region, which may span several outputs.
+    pub async fn screenshot(
+        &self,
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        let outputs_capture_regions = self
+            .inner
+            .capture_targets(&RegionCapturer::Region(capture_region));
+        self.screenshot_capture_regions(&outputs_capture_regions, capture_region, cursor_overlay)
+            .await
+    }
+
+    /// Take a screenshot of a single output.
+    pub async fn screenshot_single_output(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_outputs(std::slice::from_ref(output_info), cursor_overlay)
+            .await
+    }
+
+    pub async fn screenshot_all(&self, cursor_overlay: bool) -> Result<DynamicImage> {
+        self.screenshot_outputs(&self.get_all_outputs(), cursor_overlay)
+            .await
+    }
+
+    pub async fn screenshot_outputs(
+        &self,
+        outputs: &[OutputInfo],
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        if outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+
+        let capture_region = outputs.try_into()?;
+        let outputs_capture_regions = self
+            .inner
+            .capture_targets(&RegionCapturer::Outputs(outputs.to_owned()));
+        self.screenshot_capture_regions(&outputs_capture_regions, capture_region, cursor_overlay)
+            .await
+    }
+
+    async fn screenshot_capture_regions(
+        &self,
+        outputs_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        let frames = self
+            .capture_frames(
+                outputs_capture_regions,
+                cursor_overlay,
+                self.inner.timeouts(),
+                Sessions::OneShot,
+            )
+            .await?;
+
+        let frames = frames
+            .into_iter()
+            .map(|(frame_copy, _, output_info)| (frame_copy, output_info))
+            .collect();
+        // Compositing is CPU bound, keep it off the runtime threads.
+        tokio::task::spawn_blocking(move || image_util::composite_frames(frames, capture_region))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// Capture a frame of every output in `output_capture_regions`, see
+    /// [`WayshotConnection::capture_frame_copies`].
+    pub async fn capture_frame_copies(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        self.capture_frames(
+            output_capture_regions,
+            cursor_overlay,
+            self.inner.timeouts(),
+            Sessions::OneShot,
+        )
+        .await
+    }
+
+    /// Capture a frame of every output in `output_capture_regions` with the
+    /// sessions of the previous call, see
+    /// [`WayshotConnection::capture_frame_copies_until`].
+    pub async fn capture_frame_copies_until(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+        deadline: Instant,
+    ) -> Result<Option<Vec<(FrameCopy, FrameGuard, OutputInfo)>>> {
+        let (timeouts, deadline_first) = self.inner.timeouts().until(deadline);
+        match self
+            .capture_frames(
+                output_capture_regions,
+                cursor_overlay,
+                timeouts,
+                Sessions::Reuse,
+            )
+            .await
+        {
+            Ok(frames) => Ok(Some(frames)),
+            Err(Error::Timeout {
+                phase: CapturePhase::Copy,
+                ..
+            }) if deadline_first => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn capture_frames(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+        timeouts: Timeouts,
+        sessions: Sessions,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        if output_capture_regions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut cache = self.inner.checkout_capture_sessions(sessions);
+        let (batch, frames) = match CaptureBatch::start(
+            &mut cache,
+            &self.inner.globals,
+            &self.inner.bound_globals,
+            output_capture_regions.to_vec(),
+            cursor_overlay,
+        ) {
+            Ok(mut batch) => {
+                let frames = self
+                    .run_capture_batch(&mut cache, &mut batch, timeouts)
+                    .await;
+                (Some(batch), frames)
+            }
+            Err(e) => (None, Err(e)),
+        };
+        self.inner.end_capture(cache, batch, &frames, sessions);
+
+        frames
+    }
+
+    async fn run_capture_batch(
+        &self,
+        cache: &mut SessionCache,
+        batch: &mut CaptureBatch,
+        timeouts: Timeouts,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        let qh = cache.event_queue.handle();
+
+        // Wait for every new session to send its buffer constraints.
+        self.reader
+            .dispatch_until(
+                &mut cache.event_queue,
+                &mut cache.state,
+                timeouts.negotiation,
+                CapturePhase::Negotiation,
+                |state| batch.pending_negotiation(state),
+            )
+            .await?;
+
+        batch.capture(&cache.state, &qh)?;
+
+        // Wait for the compositor to either copy every frame or fail doing so.
+        self.reader
+            .dispatch_until(
+                &mut cache.event_queue,
+                &mut cache.state,
+                timeouts.copy,
+                CapturePhase::Copy,
+                |state| batch.pending_copy(state),
+            )
+            .await?;
+
+        batch.finish(&cache.state, None)
+    }
+}
+
+/// Reads events from the Wayland socket when it becomes readable and lets
+/// everyone waiting on an event queue know.
+#[derive(Debug)]
+struct EventReader {
+    conn: Connection,
+    fd: AsyncFd<OwnedFd>,
+    events_read: watch::Sender<u64>,
+}
+
+impl EventReader {
+    fn new(conn: Connection) -> Result<Self> {
+        let fd = conn.backend().poll_fd().try_clone_to_owned()?;
+
+        Ok(Self {
+            fd: AsyncFd::with_interest(fd, Interest::READABLE)?,
+            events_read: watch::Sender::new(0),
+            conn,
+        })
+    }
+
+    /// Dispatch events on `event_queue` while `pending` returns true.
+    async fn dispatch_while<State>(
+        &self,
+        event_queue: &mut EventQueue<State>,
+        state: &mut State,
+        mut pending: impl FnMut(&State) -> bool,
+    ) -> Result<()> {
+        let mut events_read = self.events_read.subscribe();
+
+        loop {
+            events_read.mark_unchanged();
+            event_queue.dispatch_pending(state)?;
+            if !pending(state) {
+                return Ok(());
+            }
+
+            event_queue.flush()?;
+            self.wait(&mut events_read).await?;
+        }
+    }
+
+    /// Async counterpart of `timeout::dispatch_until`.
+    async fn dispatch_until<State>(
+        &self,
+        event_queue: &mut EventQueue<State>,
+        state: &mut State,
+        timeout: Option<Duration>,
+        phase: CapturePhase,
+        mut pending: impl FnMut(&State) -> Option<String>,
+    ) -> Result<()> {
+        let Some(timeout) = timeout else {
+            return self
+                .dispatch_while(event_queue, state, |state| pending(state).is_some())
+                .await;
+        };
+
+        let wait = self.dispatch_while(event_queue, state, |state| pending(state).is_some());
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => result,
+            Err(_) => {
+                let output = pending(state).unwrap_or_default();
+                // Not necessarily an error, the caller may have expected it.
+                tracing::debug!("Timed out during {phase} on output {output}");
+                Err(Error::Timeout { phase, output })
+            }
+        }
+    }
+
+    /// Async counterpart of `EventQueue::roundtrip`.
+    async fn roundtrip<State>(
+        &self,
+        event_queue: &mut EventQueue<State>,
+        state: &mut State,
+    ) -> Result<()> {
+        let done = Arc::new(SyncDone::default());
+        self.conn
+            .send_request(
+                &self.conn.display(),
+                wl_display::Request::Sync {},
+                Some(done.clone()),
+            )
+            .map_err(|_| WaylandError::Io(io::ErrorKind::BrokenPipe.into()))?;
+
+        self.dispatch_while(event_queue, state, |_| !done.0.load(Ordering::Acquire))
+            .await
+    }
+
+    /// Wait until new events may have been queued, either because the socket
+    /// became readable and we read them, or because someone else did.
+    async fn wait(&self, events_read: &mut watch::Receiver<u64>) -> Result<()> {
+        tokio::select! {
+            // We keep the sender alive, so this never fails.
+            _ = events_read.changed() => Ok(()),
+            ready = self.fd.readable() => {
+                let mut ready = ready?;
+                if !self.read()? {
+                    ready.clear_ready();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Read events from the socket, returns false if there was nothing to
+    /// read.
+    fn read(&self) -> Result<bool> {
+        match self.conn.prepare_read() {
+            Some(guard) => match guard.read() {
+                // libwayland reads no events instead of failing with
+                // WouldBlock.
+                Ok(0) => return Ok(false),
+                Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(false);
+                }
+                result => {
+                    result?;
+                }
+            },
+            // Events were read but not dispatched to their queues yet.
+            None => {
+                self.conn.backend().dispatch_inner_queue()?;
+            }
+        }
+        self.events_read
+            .send_modify(|count| *count = count.wrapping_add(1));
+
+        Ok(true)
+    }
+}
+
+/// Set once the compositor answered a `wl_display.sync` request.
+#[derive(Debug, Default)]
+struct SyncDone(AtomicBool);
+
+impl ObjectData for SyncDone {
+    fn event(
+        self: Arc<Self>,
+        _backend: &Backend,
+        _msg: Message<ObjectId, OwnedFd>,
+    ) -> Option<Arc<dyn ObjectData>> {
+        self.0.store(true, Ordering::Release);
+        None
+    }
+
+    fn destroyed(&self, _object_id: ObjectId) {}
+}
@@ -0,0 +1,566 @@
+//! [`calloop`] event source running captures on the caller's event loop.
+//!
+//! Captures advance whenever the Wayland socket becomes readable or events
+//! for their queue were read by someone else, so starting one never blocks
+//! the loop. Compositing the frames into an image happens on a thread of its
+//! own, the result comes back through a channel.
+
+use std::{
+    io,
+    os::fd::OwnedFd,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::SendError,
+    },
+    task::{self, Wake, Waker},
+    thread,
+    time::Instant,
+};
+
+use calloop::{
+    EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+    channel::{self, Channel, Sender},
+    generic::Generic,
+    ping::{self, Ping, PingSource},
+    timer::{TimeoutAction, Timer},
+    transient::TransientSource,
+};
+use image::DynamicImage;
+use wayland_client::{Connection, backend::WaylandError};
+
+use crate::{
+    CapturePhase, Error, Result, Sessions, WayshotConnection,
+    capture::{CaptureBatch, SessionCache},
+    image_util,
+    output::OutputInfo,
+    region::{EmbeddedRegion, LogicalRegion, RegionCapturer},
+    screencopy::{FrameCopy, FrameGuard},
+};
+
+/// Identifies a capture submitted through a [`CaptureSender`], the event
+/// delivering its result carries the same id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CaptureId(u64);
+
+/// What to capture, mirroring the screenshot methods of
+/// [`WayshotConnection`].
+#[derive(Debug, Clone)]
+pub enum CaptureRequest {
+    /// Like [`WayshotConnection::screenshot`], results in
+    /// [`Captured::Image`].
+    Region {
+        region: LogicalRegion,
+        cursor_overlay: bool,
+    },
+    /// Like [`WayshotConnection::screenshot_outputs`], results in
+    /// [`Captured::Image`].
+    Outputs {
+        outputs: Vec<OutputInfo>,
+        cursor_overlay: bool,
+    },
+    /// Like [`WayshotConnection::capture_frame_copies`], results in
+    /// [`Captured::Frames`]. Every capture uses new capture sessions.
+    FrameCopies {
+        output_capture_regions: Vec<(OutputInfo, Option<EmbeddedRegion>)>,
+        cursor_overlay: bool,
+    },
+}
+
+/// Result of a finished [`CaptureRequest`].
+pub enum Captured {
+    Image(DynamicImage),
+    Frames(Vec<(FrameCopy, FrameGuard, OutputInfo)>),
+}
+
+/// Submits captures to a [`WayshotSource`]. Can be cloned and sent to other
+/// threads.
+#[derive(Debug, Clone)]
+pub struct CaptureSender {
+    sender: Sender<(CaptureId, CaptureRequest)>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl CaptureSender {
+    /// Queue a capture, its result is delivered as an event of the
+    /// [`WayshotSource`] once the compositor copied every frame.
+    ///
+    /// Fails if the source was dropped.
+    pub fn capture(
+        &self,
+        request: CaptureRequest,
+    ) -> std::result::Result<CaptureId, SendError<CaptureRequest>> {
+        let id = CaptureId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.sender
+            .send((id, request))
+            .map_err(|SendError((_, request))| SendError(request))?;
+
+        Ok(id)
+    }
+}
+
+/// Event source performing captures without blocking the event loop it is
+/// inserted into. Requires the `calloop` feature.
+///
+/// Captures are submitted through the [`CaptureSender`] returned alongside
+/// the source, each one produces a single `(CaptureId, Result<Captured>)`
+/// event. The source removes itself from the loop once every sender is
+/// dropped and all captures finished.
+///
+/// # Example usage
+///
+/// ```ignore
+/// use libwayshot::{CaptureRequest, WayshotSource};
+/// let (source, sender) = WayshotSource::from_connection(conn)?;
+/// let outputs = source.connection().get_all_outputs();
+/// event_loop.handle().insert_source(source, |(id, result), _, state| {
+///     // Handle the captured image
+/// })?;
+/// sender.capture(CaptureRequest::Outputs { outputs, cursor_overlay: false })?;
+/// ```
+pub struct WayshotSource {
+    inner: WayshotConnection,
+    socket: Generic<OwnedFd>,
+    requests: TransientSource<Channel<(CaptureId, CaptureRequest)>>,
+    requests_closed: bool,
+    /// Pinged when events were queued for one of the captures.
+    wake: PingSource,
+    waker: Waker,
+    /// Armed for the earliest deadline of all captures.
+    timer: TransientSource<Timer>,
+    timer_deadline: Option<Instant>,
+    composited: Channel<(CaptureId, Result<DynamicImage>)>,
+    composited_sender: Sender<(CaptureId, Result<DynamicImage>)>,
+    compositing: usize,
+    captures: Vec<PendingCapture>,
+}
+
+impl WayshotSource {
+    /// Like [`WayshotConnection::from_connection`], which blocks until the
+    /// globals and outputs of the compositor are known.
+    pub fn from_connection(conn: Connection) -> Result<(Self, CaptureSender)> {
+        Self::from_wayshot_connection(WayshotConnection::from_connection(conn)?)
+    }
+
+    /// Run the captures on an existing [`WayshotConnection`].
+    pub fn from_wayshot_connection(inner: WayshotConnection) -> Result<(Self, CaptureSender)> {
+        let fd = inner.conn.backend().poll_fd().try_clone_to_owned()?;
+        let (sender, requests) = channel::channel();
+        let (ping, wake) = ping::make_ping()?;
+        let (composited_sender, composited) = channel::channel();
+
+        let source = Self {
+            inner,
+            socket: Generic::new(fd, Interest::READ, Mode::Level),
+            requests: requests.into(),
+            requests_closed: false,
+            wake,
+            waker: Arc::new(PingWaker(ping)).into(),
+            // Fires once right away, is only armed while a capture has a
+            // deadline after that.
+            timer: Timer::immediate().into(),
+            timer_deadline: None,
+            composited,
+            composited_sender,
+            compositing: 0,
+            captures: Vec::new(),
+        };
+        let sender = CaptureSender {
+            sender,
+            next_id: Arc::default(),
+        };
+
+        Ok((source, sender))
+    }
+
+    /// The connection captures run on, e.g. to look up outputs before
+    /// inserting the source.
+    pub fn connection(&self) -> &WayshotConnection {
+        &self.inner
+    }
+
+    /// Read events from the Wayland socket, they are dispatched to the queue
+    /// of the capture they belong to.
+    fn read(&self) -> Result<()> {
+        let conn = &self.inner.conn;
+        let result = match conn.prepare_read() {
+            Some(guard) => guard.read().map(|_| ()),
+            // Events were read but not dispatched to their queues yet.
+            None => conn.backend().dispatch_inner_queue().map(|_| ()),
+        };
+        match result {
+            Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    fn start(
+        &mut self,
+        id: CaptureId,
+        request: CaptureRequest,
+    ) -> Option<(CaptureId, Result<Captured>)> {
+        let (targets, composite, cursor_overlay) = match request {
+            CaptureRequest::Region {
+                region,
+                cursor_overlay,
+            } => (
+                self.inner.capture_targets(&RegionCapturer::Region(region)),
+                Some(region),
+                cursor_overlay,
+            ),
+            CaptureRequest::Outputs {
+                outputs,
+                cursor_overlay,
+            } => {
+                if outputs.is_empty() {
+                    return Some((id, Err(Error::NoOutputs)));
+                }
+                let region = match outputs.as_slice().try_into() {
+                    Ok(region) => region,
+                    Err(e) => return Some((id, Err(e))),
+                };
+                (
+                    self.inner
+                        .capture_targets(&RegionCapturer::Outputs(outputs)),
+                    Some(region),
+                    cursor_overlay,
+                )
+            }
+            CaptureRequest::FrameCopies {
+                output_capture_regions,
+                cursor_overlay,
+            } => (output_capture_regions, None, cursor_overlay),
+        };
+
+        if targets.is_empty() {
+            return self.captured(id, composite, Ok(Vec::new()));
+        }
+
+        let mut cache = self.inner.checkout_capture_sessions(Sessions::OneShot);
+        match CaptureBatch::start(
+            &mut cache,
+            &self.inner.globals,
+            &self.inner.bound_globals,
+            targets,
+            cursor_overlay,
+        ) {
+            Ok(batch) => {
+                self.captures.push(PendingCapture {
+                    id,
+                    composite,
+                    cache,
+                    batch,
+                    phase: CapturePhase::Negotiation,
+                    deadline: self
+                        .inner
+                        .timeouts()
+                        .negotiation
+                        .map(|timeout| Instant::now() + timeout),
+                });
+                None
+            }
+            Err(e) => {
+                let result = Err(e);
+                self.inner
+                    .end_capture(cache, None, &result, Sessions::OneShot);
+                Some((id, result))
+            }
+        }
+    }
+
+    /// Advance every capture, returning the results of those that finished.
+    fn advance(&mut self) -> Vec<(CaptureId, Result<Captured>)> {
+        let mut results = Vec::new();
+        let mut i = 0;
+        while i < self.captures.len() {
+            let Some(frames) = self.captures[i].advance(&self.inner, &self.waker) else {
+                i += 1;
+                continue;
+            };
+
+            let PendingCapture {
+                id,
+                composite,
+                cache,
+                batch,
+                ..
+            } = self.captures.swap_remove(i);
+            self.inner
+                .end_capture(cache, Some(batch), &frames, Sessions::OneShot);
+
+            results.extend(self.captured(id, composite, frames));
+        }
+
+        results
+    }
+
+    /// Deliver the frames of a finished capture, or composite them on another
+    /// thread first.
+    fn captured(
+        &mut self,
+        id: CaptureId,
+        composite: Option<LogicalRegion>,
+        frames: Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>>,
+    ) -> Option<(CaptureId, Result<Captured>)> {
+        let Some(capture_region) = composite else {
+            return Some((id, frames.map(Captured::Frames)));
+        };
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(e) => return Some((id, Err(e))),
+        };
+        let frames = frames
+            .into_iter()
+            .map(|(frame_copy, _, output_info)| (frame_copy, output_info))
+            .collect();
+
+        let sender = self.composited_sender.clone();
+        let spawned = thread::Builder::new()
+            .name("wayshot-composite".to_owned())
+            .spawn(move || {
+                // The source is gone if sending fails, nobody wants the image.
+                let _ = sender.send((id, image_util::composite_frames(frames, capture_region)));
+            });
+        match spawned {
+            Ok(_) => {
+                self.compositing += 1;
+                None
+            }
+            Err(e) => Some((id, Err(e.into()))),
+        }
+    }
+
+    /// Arm the timer for the earliest deadline of all captures. Returns true
+    /// if the source has to be reregistered.
+    fn update_timer(&mut self) -> bool {
+        let deadline = self
+            .captures
+            .iter()
+            .filter_map(|capture| capture.deadline)
+            .min();
+        if deadline == self.timer_deadline {
+            return false;
+        }
+
+        self.timer_deadline = deadline;
+        match deadline {
+            // Replacing has no effect once the timer was removed.
+            Some(deadline) if self.timer.is_none() => {
+                self.timer = Timer::from_deadline(deadline).into();
+            }
+            Some(deadline) => self.timer.replace(Timer::from_deadline(deadline)),
+            None => self.timer.remove(),
+        }
+        true
+    }
+}
+
+impl EventSource for WayshotSource {
+    type Event = (CaptureId, Result<Captured>);
+    type Metadata = ();
+    type Ret = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> std::result::Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mut reregister = false;
+
+        let mut readable = false;
+        self.socket.process_events(readiness, token, |_, _| {
+            readable = true;
+            Ok(PostAction::Continue)
+        })?;
+        // A broken connection fails every capture below, the error is
+        // returned once their results are delivered.
+        let mut connection_error = None;
+        if readable {
+            connection_error = self.read().err();
+        }
+
+        self.wake.process_events(readiness, token, |(), _| {})?;
+
+        let fired = self
+            .timer
+            .process_events(readiness, token, |_, _| TimeoutAction::Drop)?;
+        if fired == PostAction::Reregister {
+            self.timer_deadline = None;
+            reregister = true;
+        }
+
+        let mut results = Vec::new();
+        self.composited
+            .process_events(readiness, token, |event, _| {
+                if let channel::Event::Msg((id, image)) = event {
+                    results.push((id, image.map(Captured::Image)));
+                }
+            })?;
+        self.compositing -= results.len();
+
+        let mut requests = Vec::new();
+        let requests_closed = &mut self.requests_closed;
+        let post_action =
+            self.requests
+                .process_events(readiness, token, |event, _| match event {
+                    channel::Event::Msg(request) => requests.push(request),
+                    channel::Event::Closed => *requests_closed = true,
+                })?;
+        reregister |= post_action == PostAction::Reregister;
+
+        for (id, request) in requests {
+            results.extend(self.start(id, request));
+        }
+        results.extend(self.advance());
+
+        if let Err(e) = self.inner.conn.flush() {
+            connection_error.get_or_insert(e.into());
+        }
+        reregister |= self.update_timer();
+
+        for result in results {
+            callback(result, &mut ());
+        }
+
+        if let Some(e) = connection_error {
+            return Err(e.into());
+        }
+
+        Ok(
+            if self.requests_closed && self.captures.is_empty() && self.compositing == 0 {
+                PostAction::Remove
+            } else if reregister {
+                PostAction::Reregister
+            } else {
+                PostAction::Continue
+            },
+        )
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.socket.register(poll, token_factory)?;
+        self.wake.register(poll, token_factory)?;
+        self.timer.register(poll, token_factory)?;
+        self.composited.register(poll, token_factory)?;
+        self.requests.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.socket.reregister(poll, token_factory)?;
+        self.wake.reregister(poll, token_factory)?;
+        self.timer.reregister(poll, token_factory)?;
+        if self.timer.is_none() {
+            // Keep the tokens of the sources after the timer while it is
+            // gone, events read for their old tokens would go to the wrong
+            // source otherwise.
+            token_factory.token();
+        }
+        self.composited.reregister(poll, token_factory)?;
+        self.requests.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.socket.unregister(poll)?;
+        self.wake.unregister(poll)?;
+        self.timer.unregister(poll)?;
+        self.composited.unregister(poll)?;
+        self.requests.unregister(poll)
+    }
+}
+
+/// A capture waiting on the compositor.
+struct PendingCapture {
+    id: CaptureId,
+    /// Region to composite the frames into, `None` to deliver the frames.
+    composite: Option<LogicalRegion>,
+    cache: SessionCache,
+    batch: CaptureBatch,
+    phase: CapturePhase,
+    deadline: Option<Instant>,
+}
+
+impl PendingCapture {
+    /// Dispatch the events queued for this capture and move it on to the
+    /// next phase where possible. Returns `None` while the compositor is
+    /// still working on it.
+    fn advance(
+        &mut self,
+        inner: &WayshotConnection,
+        waker: &Waker,
+    ) -> Option<Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>>> {
+        self.try_advance(inner, waker).transpose()
+    }
+
+    fn try_advance(
+        &mut self,
+        inner: &WayshotConnection,
+        waker: &Waker,
+    ) -> Result<Option<Vec<(FrameCopy, FrameGuard, OutputInfo)>>> {
+        let mut cx = task::Context::from_waker(waker);
+
+        loop {
+            // Wakes up the source once more events are queued.
+            if let task::Poll::Ready(Err(e)) = self
+                .cache
+                .event_queue
+                .poll_dispatch_pending(&mut cx, &mut self.cache.state)
+            {
+                return Err(e.into());
+            }
+
+            let pending = match self.phase {
+                CapturePhase::Negotiation => self.batch.pending_negotiation(&self.cache.state),
+                _ => self.batch.pending_copy(&self.cache.state),
+            };
+            if let Some(output) = pending {
+                if self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    // Not necessarily an error, the caller may have expected it.
+                    tracing::debug!("Timed out during {} on output {output}", self.phase);
+                    return Err(Error::Timeout {
+                        phase: self.phase,
+                        output,
+                    });
+                }
+                return Ok(None);
+            }
+
+            match self.phase {
+                CapturePhase::Negotiation => {
+                    let qh = self.cache.event_queue.handle();
+                    self.batch.capture(&self.cache.state, &qh)?;
+                    self.phase = CapturePhase::Copy;
+                    self.deadline = inner
+                        .timeouts()
+                        .copy
+                        .map(|timeout| Instant::now() + timeout);
+                }
+                _ => return self.batch.finish(&self.cache.state, None).map(Some),
+            }
+        }
+    }
+}
+
+/// Wakes up the [`WayshotSource`] when events are queued for a capture.
+struct PingWaker(Ping);
+
+impl Wake for PingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.ping();
+    }
+}
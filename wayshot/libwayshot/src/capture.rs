@@ -0,0 +1,529 @@
+//! Capturing several outputs at once on a single event queue.
+//!
+//! All capture sessions are created before waiting on any of them, and all
+//! frames are captured right after each other once every session has sent
+//! its buffer constraints. This keeps the captured frames of different
+//! outputs as close together in time as the compositor allows.
+//!
+//! Sessions are kept in a [`SessionCache`] and reused by repeated captures of
+//! the same output, which then don't have to wait for the buffer constraints
+//! again. One-off screenshots use new sessions, as compositors may hold back
+//! the next frame of a session until the output changed.
+
+use std::{collections::HashMap, fs::File, os::fd::AsFd, time::Duration};
+
+use memmap2::MmapMut;
+use wayland_client::{
+    Connection, EventQueue, QueueHandle,
+    globals::GlobalList,
+    protocol::{
+        wl_output::WlOutput,
+        wl_shm::{self, WlShm},
+    },
+};
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1,
+        ext_image_copy_capture_manager_v1::Options,
+        ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+    },
+};
+
+use crate::{
+    CapturePhase, Error, Result,
+    convert::create_converter,
+    dispatch::{BufferConstraints, CaptureFrameState, FrameState, OutputCapture},
+    dump::DumpedFrame,
+    globals::BoundGlobals,
+    output::OutputInfo,
+    region::{EmbeddedRegion, LogicalRegion, Size, transform_swaps_axes},
+    screencopy::{FrameCopy, FrameData, FrameFormat, FrameGuard, create_shm_fd, presentation_skew},
+};
+
+#[derive(Debug)]
+struct CachedSession {
+    output: WlOutput,
+    cursor_overlay: bool,
+    source: ExtImageCaptureSourceV1,
+    session: ExtImageCopyCaptureSessionV1,
+}
+
+/// Capture sessions kept alive across captures, together with the event
+/// queue their events are dispatched on.
+#[derive(Debug)]
+pub(crate) struct SessionCache {
+    pub(crate) event_queue: EventQueue<CaptureFrameState>,
+    pub(crate) state: CaptureFrameState,
+    sessions: HashMap<usize, CachedSession>,
+    next_id: usize,
+    /// Generation of the outputs the sessions capture.
+    pub(crate) generation: u64,
+}
+
+impl SessionCache {
+    pub(crate) fn new(conn: &Connection, generation: u64) -> Self {
+        Self {
+            event_queue: conn.new_event_queue(),
+            state: CaptureFrameState::default(),
+            sessions: HashMap::new(),
+            next_id: 0,
+            generation,
+        }
+    }
+
+    /// Id of a session capturing `output`, creating one if there is none yet.
+    fn session(
+        &mut self,
+        globals: &GlobalList,
+        bound_globals: &BoundGlobals,
+        output: &WlOutput,
+        cursor_overlay: bool,
+    ) -> Result<(usize, ExtImageCopyCaptureSessionV1)> {
+        if let Some((&id, cached)) = self
+            .sessions
+            .iter()
+            .find(|(_, cached)| &cached.output == output && cached.cursor_overlay == cursor_overlay)
+        {
+            return Ok((id, cached.session.clone()));
+        }
+
+        let qh = self.event_queue.handle();
+        let options = if cursor_overlay {
+            Options::PaintCursors
+        } else {
+            Options::empty()
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        let source = bound_globals
+            .output_source_manager(globals)?
+            .create_source(output, &qh, ());
+        let session = bound_globals
+            .copy_manager(globals)?
+            .create_session(&source, options, &qh, id);
+        tracing::debug!("Created capture session {id}");
+
+        self.state.captures.insert(id, OutputCapture::default());
+        self.sessions.insert(
+            id,
+            CachedSession {
+                output: output.clone(),
+                cursor_overlay,
+                source,
+                session: session.clone(),
+            },
+        );
+        Ok((id, session))
+    }
+
+    /// Destroy the session with the given id.
+    pub(crate) fn remove(&mut self, id: usize) {
+        if let Some(cached) = self.sessions.remove(&id) {
+            tracing::debug!("Destroying capture session {id}");
+            cached.session.destroy();
+            cached.source.destroy();
+        }
+        self.state.captures.remove(&id);
+    }
+
+    /// Destroy the sessions the compositor has stopped, e.g. because their
+    /// output was removed.
+    fn remove_stopped(&mut self) {
+        let stopped: Vec<_> = self
+            .state
+            .captures
+            .iter()
+            .filter(|(_, capture)| capture.stopped)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in stopped {
+            self.remove(id);
+        }
+    }
+}
+
+impl Drop for SessionCache {
+    fn drop(&mut self) {
+        for cached in self.sessions.values() {
+            cached.session.destroy();
+            cached.source.destroy();
+        }
+    }
+}
+
+/// Buffer a frame of one output is copied into.
+struct ShmBuffer {
+    frame_format: FrameFormat,
+    mem_file: File,
+    frame_guard: FrameGuard,
+}
+
+/// Capture of a set of outputs, driven by dispatching the event queue of a
+/// [`SessionCache`] until [`CaptureBatch::pending_negotiation`] and then
+/// [`CaptureBatch::pending_copy`] return `None`.
+///
+/// If the capture fails, the sessions it used have to be removed from the
+/// cache unless [`CaptureBatch::keeps_sessions`] says otherwise.
+pub(crate) struct CaptureBatch {
+    targets: Vec<(OutputInfo, Option<EmbeddedRegion>)>,
+    sessions: Vec<(usize, ExtImageCopyCaptureSessionV1)>,
+    shm: WlShm,
+    buffers: Vec<ShmBuffer>,
+    frames: Vec<ExtImageCopyCaptureFrameV1>,
+}
+
+impl CaptureBatch {
+    /// Get a capture session for every target. Sessions the compositor
+    /// hasn't sent buffer constraints for yet will do so shortly.
+    pub(crate) fn start(
+        cache: &mut SessionCache,
+        globals: &GlobalList,
+        bound_globals: &BoundGlobals,
+        targets: Vec<(OutputInfo, Option<EmbeddedRegion>)>,
+        cursor_overlay: bool,
+    ) -> Result<Self> {
+        let shm = bound_globals.shm(globals)?;
+
+        // Pick up sessions stopped since the last capture.
+        cache.event_queue.dispatch_pending(&mut cache.state)?;
+        cache.remove_stopped();
+
+        tracing::debug!("Getting capture sessions for {} outputs", targets.len());
+        let sessions = targets
+            .iter()
+            .map(|(output_info, _)| {
+                cache.session(
+                    globals,
+                    bound_globals,
+                    &output_info.wl_output,
+                    cursor_overlay,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (id, _) in &sessions {
+            if let Some(capture) = cache.state.captures.get_mut(id) {
+                capture.frame_state = None;
+                capture.presentation_time = None;
+                capture.damage.clear();
+            }
+        }
+
+        Ok(Self {
+            targets,
+            sessions,
+            shm,
+            buffers: Vec::new(),
+            frames: Vec::new(),
+        })
+    }
+
+    /// Whether the sessions of a capture that failed with `error` can be
+    /// reused. Frames not copied in time are cancelled, events the compositor
+    /// still sends for them are dropped, and compositors may hold back the
+    /// frames of unchanged outputs until they change.
+    pub(crate) fn keeps_sessions(error: &Error) -> bool {
+        matches!(
+            error,
+            Error::Timeout {
+                phase: CapturePhase::Copy,
+                ..
+            }
+        )
+    }
+
+    /// Ids of the sessions used by this capture.
+    pub(crate) fn session_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.sessions.iter().map(|(id, _)| *id)
+    }
+
+    /// Name of an output whose buffer constraints have not arrived yet.
+    pub(crate) fn pending_negotiation(&self, state: &CaptureFrameState) -> Option<String> {
+        self.pending(state, |capture| {
+            capture.constraints.is_none() && !capture.stopped
+        })
+    }
+
+    /// Name of an output whose frame has not been copied yet.
+    pub(crate) fn pending_copy(&self, state: &CaptureFrameState) -> Option<String> {
+        self.pending(state, |capture| {
+            capture.frame_state.is_none() && !capture.stopped
+        })
+    }
+
+    fn pending(
+        &self,
+        state: &CaptureFrameState,
+        is_pending: impl Fn(&OutputCapture) -> bool,
+    ) -> Option<String> {
+        self.targets
+            .iter()
+            .zip(&self.sessions)
+            .find(|(_, (id, _))| state.captures.get(id).is_some_and(&is_pending))
+            .map(|((output_info, _), _)| output_info.name.clone())
+    }
+
+    /// Create a buffer for every output according to its constraints, then
+    /// capture all frames.
+    pub(crate) fn capture(
+        &mut self,
+        state: &CaptureFrameState,
+        qh: &QueueHandle<CaptureFrameState>,
+    ) -> Result<()> {
+        let mut buffers = Vec::with_capacity(self.targets.len());
+        for ((output_info, _), (id, _)) in self.targets.iter().zip(&self.sessions) {
+            let constraints = match state.captures.get(id) {
+                Some(OutputCapture {
+                    constraints: Some(constraints),
+                    stopped: false,
+                    ..
+                }) => constraints,
+                _ => {
+                    tracing::error!("Capture session of output {output_info} was stopped");
+                    return Err(Error::FramecopyFailed {
+                        output: output_info.name.clone(),
+                    });
+                }
+            };
+            buffers.push(self.create_buffer(output_info, constraints, qh)?);
+        }
+
+        let frames: Vec<_> = self
+            .sessions
+            .iter()
+            .zip(&buffers)
+            .map(|((id, session), buffer)| {
+                let frame = session.create_frame(qh, *id);
+                frame.attach_buffer(&buffer.frame_guard.buffer);
+                // Nothing has been captured into the buffer yet.
+                frame.damage_buffer(
+                    0,
+                    0,
+                    buffer.frame_format.size.width as i32,
+                    buffer.frame_format.size.height as i32,
+                );
+                frame
+            })
+            .collect();
+
+        tracing::debug!("Capturing {} frames", frames.len());
+        for frame in &frames {
+            frame.capture();
+        }
+        self.buffers = buffers;
+        self.frames = frames;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(output = %output_info))]
+    fn create_buffer(
+        &self,
+        output_info: &OutputInfo,
+        constraints: &BufferConstraints,
+        qh: &QueueHandle<CaptureFrameState>,
+    ) -> Result<ShmBuffer> {
+        tracing::trace!("Received buffer constraints: {constraints:#?}");
+        // Select the first advertised wl_shm format that we can convert.
+        let frame_format = constraints
+            .shm_formats
+            .iter()
+            .find_map(|&format| {
+                let bytes_per_pixel = bytes_per_pixel(format)?;
+                create_converter(format)?;
+                Some(FrameFormat {
+                    format,
+                    size: constraints.size,
+                    stride: constraints.size.width * bytes_per_pixel,
+                })
+            })
+            .ok_or_else(|| {
+                tracing::error!("No suitable frame format found");
+                Error::NoSupportedBufferFormat {
+                    output: output_info.name.clone(),
+                }
+            })?;
+        tracing::trace!("Selected frame buffer format: {frame_format:#?}");
+
+        // Create an in memory file and return it's file descriptor.
+        let mem_file = File::from(create_shm_fd()?);
+        mem_file.set_len(frame_format.byte_size())?;
+
+        let shm_pool = self.shm.create_pool(
+            mem_file.as_fd(),
+            frame_format
+                .byte_size()
+                .try_into()
+                .map_err(|_| Error::BufferTooSmall)?,
+            qh,
+            (),
+        );
+        let buffer = shm_pool.create_buffer(
+            0,
+            frame_format.size.width as i32,
+            frame_format.size.height as i32,
+            frame_format.stride as i32,
+            frame_format.format,
+            qh,
+            (),
+        );
+
+        Ok(ShmBuffer {
+            frame_format,
+            mem_file,
+            frame_guard: FrameGuard { buffer, shm_pool },
+        })
+    }
+
+    /// Map and convert the copied frames. They are also copied into `dump`
+    /// as received, before they are converted.
+    pub(crate) fn finish(
+        &mut self,
+        state: &CaptureFrameState,
+        mut dump: Option<&mut Vec<DumpedFrame>>,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        let targets = std::mem::take(&mut self.targets);
+        let buffers = std::mem::take(&mut self.buffers);
+
+        let frames = targets
+            .into_iter()
+            .zip(buffers)
+            .zip(&self.sessions)
+            .map(|(((output_info, capture_region), buffer), (id, _))| {
+                let capture = state.captures.get(id).filter(|capture| {
+                    !capture.stopped && capture.frame_state == Some(FrameState::Finished)
+                });
+                let Some(capture) = capture else {
+                    tracing::error!("Failed to capture a frame of output {output_info}");
+                    return Err(Error::FramecopyFailed {
+                        output: output_info.name.clone(),
+                    });
+                };
+
+                let ShmBuffer {
+                    frame_format,
+                    mem_file,
+                    frame_guard,
+                } = buffer;
+                let frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+                let logical_region = capture_region
+                    .map(|capture_region| capture_region.logical())
+                    .unwrap_or(output_info.logical_region);
+                if let Some(dump) = dump.as_deref_mut() {
+                    dump.push(DumpedFrame::new(
+                        &output_info,
+                        frame_format,
+                        logical_region,
+                        capture.presentation_time,
+                        frame_mmap.to_vec(),
+                    ));
+                }
+
+                let mut frame_copy = convert_frame(
+                    &output_info,
+                    frame_format,
+                    FrameData::Mmap(frame_mmap),
+                    logical_region,
+                    capture.presentation_time,
+                )?;
+                frame_copy.damage = Some(
+                    capture
+                        .damage
+                        .iter()
+                        .filter_map(|&damage| LogicalRegion::from_physical(damage, &output_info))
+                        .collect(),
+                );
+                Ok((frame_copy, frame_guard, output_info))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(skew) = presentation_skew(frames.iter().map(|(frame_copy, _, _)| frame_copy)) {
+            tracing::debug!(
+                "Captured {} outputs within {skew:?} of each other",
+                frames.len()
+            );
+        }
+
+        Ok(frames)
+    }
+}
+
+impl Drop for CaptureBatch {
+    fn drop(&mut self) {
+        // Frames are destroyed as soon as they are ready or failed, this only
+        // cancels the ones still in flight.
+        for frame in &self.frames {
+            frame.destroy();
+        }
+    }
+}
+
+/// Convert the frame `frame_data` copied from `output_info` in place, into a
+/// `FrameCopy` of the part of the output in `logical_region`.
+pub(crate) fn convert_frame(
+    output_info: &OutputInfo,
+    frame_format: FrameFormat,
+    mut frame_data: FrameData,
+    logical_region: LogicalRegion,
+    presentation_time: Option<Duration>,
+) -> Result<FrameCopy> {
+    let data: &mut [u8] = match &mut frame_data {
+        FrameData::Mmap(frame_mmap) => frame_mmap,
+        FrameData::Owned(data) => data,
+        FrameData::GBMBo(_) => {
+            return Err(Error::UnsupportedFrameData {
+                format: frame_format.format,
+            });
+        }
+    };
+    let frame_color_type = match create_converter(frame_format.format) {
+        Some(converter) => converter.convert_inplace(data),
+        None => {
+            tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
+            tracing::error!(
+                "You can send a feature request for the above format to the mailing list for wayshot over at https://sr.ht/~shinyzenith/wayshot."
+            );
+            return Err(Error::UnsupportedBufferFormat {
+                output: output_info.name.clone(),
+                format: frame_format.format,
+            });
+        }
+    };
+    let rotated_physical_size = if transform_swaps_axes(output_info.transform) {
+        Size {
+            width: frame_format.size.height,
+            height: frame_format.size.width,
+        }
+    } else {
+        frame_format.size
+    };
+
+    let frame_copy = FrameCopy {
+        frame_format,
+        frame_color_type,
+        frame_data,
+        transform: output_info.transform,
+        logical_region,
+        physical_size: rotated_physical_size,
+        presentation_time,
+        damage: None,
+    };
+    tracing::debug!("Created frame copy: {:#?}", frame_copy);
+    Ok(frame_copy)
+}
+
+/// Bytes per pixel of the `wl_shm` formats we can convert.
+fn bytes_per_pixel(format: wl_shm::Format) -> Option<u32> {
+    match format {
+        wl_shm::Format::Argb8888
+        | wl_shm::Format::Xrgb8888
+        | wl_shm::Format::Abgr8888
+        | wl_shm::Format::Xbgr8888
+        | wl_shm::Format::Abgr2101010
+        | wl_shm::Format::Xbgr2101010 => Some(4),
+        wl_shm::Format::Bgr888 => Some(3),
+        wl_shm::Format::Rgb565 => Some(2),
+        _ => None,
+    }
+}
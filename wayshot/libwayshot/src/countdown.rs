@@ -0,0 +1,96 @@
+//! The badge counting down the seconds until a delayed capture.
+
+/// Width and height of the badge in logical pixels.
+pub(crate) const SIZE: u32 = 96;
+
+/// Premultiplied `Argb8888` pixels of a badge of `size` x `size` pixels
+/// showing `seconds` with seven segment digits, white on a translucent
+/// black circle.
+pub(crate) fn draw(seconds: u64, size: u32) -> Vec<u8> {
+    let size_f = size as f32;
+    let digits: Vec<u8> = seconds
+        .to_string()
+        .bytes()
+        .map(|digit| digit - b'0')
+        .collect();
+
+    // Fit the digits into the middle of the circle.
+    let count = digits.len() as f32;
+    let height = (size_f * 0.45).min(size_f * 0.6 / (count * 0.55 + (count - 1.0) * 0.2));
+    let width = height * 0.55;
+    let gap = height * 0.2;
+    let thickness = height / 8.0;
+    let left = (size_f - count * width - (count - 1.0) * gap) / 2.0;
+    let top = (size_f - height) / 2.0;
+
+    let segments: Vec<[f32; 4]> = digits
+        .iter()
+        .enumerate()
+        .flat_map(|(index, &digit)| {
+            let x = left + index as f32 * (width + gap);
+            segments(digit, width, height, thickness)
+                .into_iter()
+                .map(move |[x0, y0, x1, y1]| [x + x0, top + y0, x + x1, top + y1])
+        })
+        .collect();
+
+    let radius = size_f / 2.0;
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let (px, py) = (x as f32, y as f32);
+            let distance = ((px + 0.5 - radius).powi(2) + (py + 0.5 - radius).powi(2)).sqrt();
+            let background = (radius - distance + 0.5).clamp(0.0, 1.0) * 0.6;
+            let digit = segments
+                .iter()
+                .map(|&[x0, y0, x1, y1]| {
+                    let overlap = |start: f32, end: f32, pixel: f32| {
+                        ((pixel + 1.0).min(end) - pixel.max(start)).clamp(0.0, 1.0)
+                    };
+                    overlap(x0, x1, px) * overlap(y0, y1, py)
+                })
+                .fold(0.0, f32::max);
+
+            // White digits over the black background.
+            let alpha = digit + background * (1.0 - digit);
+            let white = (digit * 255.0).round() as u8;
+            pixels.extend([white, white, white, (alpha * 255.0).round() as u8]);
+        }
+    }
+    pixels
+}
+
+/// Rectangles `[x0, y0, x1, y1]` lit to show `digit` in a box of `width` x
+/// `height`.
+fn segments(digit: u8, width: f32, height: f32, thickness: f32) -> Vec<[f32; 4]> {
+    let middle_top = (height - thickness) / 2.0;
+    let middle_bottom = (height + thickness) / 2.0;
+    let top = [0.0, 0.0, width, thickness];
+    let top_right = [width - thickness, 0.0, width, middle_bottom];
+    let bottom_right = [width - thickness, middle_top, width, height];
+    let bottom = [0.0, height - thickness, width, height];
+    let bottom_left = [0.0, middle_top, thickness, height];
+    let top_left = [0.0, 0.0, thickness, middle_bottom];
+    let middle = [0.0, middle_top, width, middle_bottom];
+
+    match digit {
+        0 => vec![top, top_right, bottom_right, bottom, bottom_left, top_left],
+        1 => vec![top_right, bottom_right],
+        2 => vec![top, top_right, middle, bottom_left, bottom],
+        3 => vec![top, top_right, middle, bottom_right, bottom],
+        4 => vec![top_left, middle, top_right, bottom_right],
+        5 => vec![top, top_left, middle, bottom_right, bottom],
+        6 => vec![top, top_left, middle, bottom_left, bottom_right, bottom],
+        7 => vec![top, top_right, bottom_right],
+        8 => vec![
+            top,
+            top_right,
+            bottom_right,
+            bottom,
+            bottom_left,
+            top_left,
+            middle,
+        ],
+        _ => vec![top, top_right, bottom_right, bottom, top_left, middle],
+    }
+}
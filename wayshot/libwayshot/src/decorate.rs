@@ -0,0 +1,128 @@
+//! Framing screenshots, e.g. of windows for documentation, with padding,
+//! rounded corners and a drop shadow.
+//!
+//! Lengths are in logical pixels, see [`crate::composited_scale`].
+//! Transparent padding, corners and shadows need to be encoded in a format
+//! with alpha, like PNG.
+
+use std::mem;
+
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage, imageops};
+
+use crate::{
+    image_util,
+    region::{Position, Size},
+};
+
+/// How [`decorate`] frames a screenshot. The default leaves it unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decorations {
+    /// Space around the screenshot in logical pixels. The shadow is drawn
+    /// into it, so it needs to be large enough for the shadow to fade out.
+    pub padding: u32,
+    /// Color of the padding.
+    pub background: Rgba<u8>,
+    /// Radius of the corners of the screenshot in logical pixels, 0 for
+    /// square ones.
+    pub corner_radius: u32,
+    pub shadow: Option<Shadow>,
+}
+
+/// Shadow cast by a screenshot onto its padding.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Shadow {
+    /// Distance over which the edges of the shadow fade out, in logical
+    /// pixels.
+    pub blur: u32,
+    /// Offset of the shadow from the screenshot in logical pixels, positive
+    /// coordinates move it right and down.
+    pub offset: Position,
+    pub color: Rgba<u8>,
+}
+
+impl Default for Decorations {
+    fn default() -> Self {
+        Self {
+            padding: 0,
+            background: Rgba([0, 0, 0, 0]),
+            corner_radius: 0,
+            shadow: None,
+        }
+    }
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            blur: 16,
+            offset: Position { x: 0, y: 4 },
+            color: Rgba([0, 0, 0, 128]),
+        }
+    }
+}
+
+/// Frame `image`, a screenshot that is `logical_size` large in logical
+/// pixels, with `decorations`. The image grows by the padding on every side.
+pub fn decorate(image: &mut DynamicImage, logical_size: Size, decorations: &Decorations) {
+    if *decorations == Decorations::default() {
+        return;
+    }
+    let scale = image_util::composited_scale(image, logical_size);
+    let screenshot = mem::take(image).into_rgba8();
+    let padding = (decorations.padding as f64 * scale).round() as u32;
+    let (width, height) = screenshot.dimensions();
+    let mut canvas = RgbaImage::from_pixel(
+        width + 2 * padding,
+        height + 2 * padding,
+        decorations.background,
+    );
+
+    // Coverage of the pixel at `x`, `y` of the canvas by the screenshot with
+    // rounded corners, moved by `offset` pixels.
+    let radius = decorations.corner_radius as f64 * scale;
+    let half = (width as f64 / 2.0, height as f64 / 2.0);
+    let coverage = |x: u32, y: u32, offset: (f64, f64)| {
+        let center = (
+            padding as f64 + half.0 + offset.0,
+            padding as f64 + half.1 + offset.1,
+        );
+        let distance = image_util::rounded_box_distance(
+            (x as f64 + 0.5, y as f64 + 0.5),
+            center,
+            half,
+            radius,
+        );
+        (0.5 - distance).clamp(0.0, 1.0)
+    };
+
+    if let Some(shadow) = decorations.shadow {
+        let offset = (
+            shadow.offset.x as f64 * scale,
+            shadow.offset.y as f64 * scale,
+        );
+        let mask = GrayImage::from_fn(canvas.width(), canvas.height(), |x, y| {
+            Luma([(coverage(x, y, offset) * 255.0).round() as u8])
+        });
+        // Blurring with a standard deviation of a third of the distance fades
+        // the edges out over about that distance.
+        let sigma = shadow.blur as f64 * scale / 3.0;
+        let mask = if sigma > 0.0 {
+            imageops::fast_blur(&mask, sigma as f32)
+        } else {
+            mask
+        };
+        for (pixel, shade) in canvas.pixels_mut().zip(mask.pixels()) {
+            image_util::blend(pixel, shadow.color, shade.0[0] as f64 / 255.0);
+        }
+    }
+
+    for (x, y, pixel) in screenshot.enumerate_pixels() {
+        let coverage = coverage(x + padding, y + padding, (0.0, 0.0));
+        image_util::blend(
+            canvas.get_pixel_mut(x + padding, y + padding),
+            *pixel,
+            coverage,
+        );
+    }
+    *image = canvas.into();
+}
@@ -0,0 +1,332 @@
+//! Comparing screenshots, e.g. with the baselines of visual regression tests.
+//!
+//! Changed regions are in pixels of the compared images, with the origin at
+//! their top left corner. Ignored regions are logical ones, see
+//! [`DiffOptions::capture_region`].
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::{
+    Error, Result,
+    region::{LogicalRegion, Position, Region, Size},
+};
+
+/// Changed pixels closer to each other than this many pixels are reported as
+/// one region, so that e.g. a changed line of text isn't a region per glyph.
+const MERGE_DISTANCE: u32 = 8;
+/// Size of the grid cells [`merge_close_boxes`] looks up nearby boxes in.
+const MERGE_CELL: u32 = 64;
+
+/// Left, top, right and bottom edges of a box of pixels.
+type Edges = (u32, u32, u32, u32);
+
+/// How two images are compared by [`compare`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Largest difference of any channel of a pixel that still counts as the
+    /// same pixel, to allow for e.g. dithering or rounding differences.
+    pub tolerance: u8,
+    /// Regions whose pixels are not compared, like clocks or blinking
+    /// cursors.
+    pub ignore: Vec<LogicalRegion>,
+    /// The logical region both images are screenshots of. The ignored regions
+    /// are mapped from it onto the images, at the scale they were composited
+    /// at. `None` for images without one, whose pixels are taken as logical
+    /// pixels starting at `0,0`.
+    pub capture_region: Option<LogicalRegion>,
+}
+
+/// Result of comparing two images of the same size with [`compare`].
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    /// Size of both images.
+    pub size: Size,
+    /// Number of pixels that differ by more than the tolerance.
+    pub changed_pixels: u64,
+    /// Number of pixels in ignored regions.
+    pub ignored_pixels: u64,
+    /// Bounding boxes of the areas with changed pixels, from top to bottom.
+    pub regions: Vec<Region>,
+    changed: Vec<bool>,
+    ignored: Vec<bool>,
+}
+
+/// Compare `expected` with `actual` pixel by pixel. Both images must have the
+/// same size.
+pub fn compare(
+    expected: &DynamicImage,
+    actual: &DynamicImage,
+    options: &DiffOptions,
+) -> Result<ImageDiff> {
+    check_size(expected, actual)?;
+    let (width, height) = (expected.width(), expected.height());
+    let expected = expected.to_rgba8();
+    let actual = actual.to_rgba8();
+
+    let size = Size { width, height };
+    let capture_region = options.capture_region.unwrap_or(LogicalRegion {
+        inner: Region {
+            position: Position::default(),
+            size,
+        },
+    });
+    let mut ignored = vec![false; (width as usize) * (height as usize)];
+    for region in options
+        .ignore
+        .iter()
+        .filter_map(|region| region.to_image(&capture_region, size))
+    {
+        let Region { position, size } = region;
+        let (left, top) = (position.x as usize, position.y as usize);
+        for y in top..top + size.height as usize {
+            let row = y * width as usize;
+            ignored[row + left..row + left + size.width as usize].fill(true);
+        }
+    }
+
+    let changed: Vec<bool> = expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(&ignored)
+        .map(|((expected, actual), &ignored)| {
+            !ignored
+                && expected
+                    .0
+                    .iter()
+                    .zip(actual.0)
+                    .any(|(&expected, actual)| expected.abs_diff(actual) > options.tolerance)
+        })
+        .collect();
+
+    Ok(ImageDiff {
+        size,
+        changed_pixels: changed.iter().filter(|&&changed| changed).count() as u64,
+        ignored_pixels: ignored.iter().filter(|&&ignored| ignored).count() as u64,
+        regions: changed_regions(&changed, width, height),
+        changed,
+        ignored,
+    })
+}
+
+fn check_size(expected: &DynamicImage, actual: &DynamicImage) -> Result<()> {
+    if expected.width() != actual.width() || expected.height() != actual.height() {
+        return Err(Error::ImageSizeMismatch {
+            expected: (expected.width(), expected.height()),
+            actual: (actual.width(), actual.height()),
+        });
+    }
+    Ok(())
+}
+
+/// Bounding boxes of the groups of changed pixels, see [`MERGE_DISTANCE`].
+fn changed_regions(changed: &[bool], width: u32, height: u32) -> Vec<Region> {
+    // Bounding boxes of the 8-connected components first.
+    let mut visited = vec![false; changed.len()];
+    let mut boxes: Vec<Edges> = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..changed.len() {
+        if !changed[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (x, y) = (
+            (start % width as usize) as u32,
+            (start / width as usize) as u32,
+        );
+        let (mut left, mut top, mut right, mut bottom) = (x, y, x + 1, y + 1);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (
+                (index % width as usize) as u32,
+                (index / width as usize) as u32,
+            );
+            (left, top) = (left.min(x), top.min(y));
+            (right, bottom) = (right.max(x + 1), bottom.max(y + 1));
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let neighbour = (ny * width + nx) as usize;
+                    if changed[neighbour] && !visited[neighbour] {
+                        visited[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+        boxes.push((left, top, right, bottom));
+    }
+
+    // Then merge the boxes that are close to each other.
+    let mut boxes = merge_close_boxes(boxes, width, height);
+    boxes.sort_by_key(|&(left, top, ..)| (top, left));
+    boxes
+        .into_iter()
+        .map(|(left, top, right, bottom)| Region {
+            position: Position {
+                x: left as i32,
+                y: top as i32,
+            },
+            size: Size {
+                width: right - left,
+                height: bottom - top,
+            },
+        })
+        .collect()
+}
+
+/// Merge the boxes that are within [`MERGE_DISTANCE`] of each other until
+/// none are. Merged boxes grow and may get close to others, so this repeats
+/// until nothing changes. Each box is only compared with the boxes in the grid
+/// cells around it, as noisy images can have a lot of them.
+fn merge_close_boxes(mut boxes: Vec<Edges>, width: u32, height: u32) -> Vec<Edges> {
+    let close = |a: &Edges, b: &Edges| {
+        a.0 <= b.2 + MERGE_DISTANCE
+            && b.0 <= a.2 + MERGE_DISTANCE
+            && a.1 <= b.3 + MERGE_DISTANCE
+            && b.1 <= a.3 + MERGE_DISTANCE
+    };
+    // Root of the merged boxes `index` belongs to, halving the paths to it.
+    let root = |parents: &mut [usize], mut index: usize| {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    };
+    let columns = width.div_ceil(MERGE_CELL) as usize;
+    let rows = height.div_ceil(MERGE_CELL) as usize;
+
+    loop {
+        let mut cells = vec![Vec::new(); columns * rows];
+        for (index, &(left, top, right, bottom)) in boxes.iter().enumerate() {
+            for row in top / MERGE_CELL..=(bottom - 1) / MERGE_CELL {
+                for column in left / MERGE_CELL..=(right - 1) / MERGE_CELL {
+                    cells[row as usize * columns + column as usize].push(index);
+                }
+            }
+        }
+
+        let mut parents: Vec<usize> = (0..boxes.len()).collect();
+        let mut merged = false;
+        for (index, &(left, top, right, bottom)) in boxes.iter().enumerate() {
+            // The cells of the pixels a close box can have.
+            let near_rows = top.saturating_sub(MERGE_DISTANCE + 1) / MERGE_CELL
+                ..=(bottom + MERGE_DISTANCE).min(height - 1) / MERGE_CELL;
+            let near_columns = left.saturating_sub(MERGE_DISTANCE + 1) / MERGE_CELL
+                ..=(right + MERGE_DISTANCE).min(width - 1) / MERGE_CELL;
+            for row in near_rows {
+                for column in near_columns.clone() {
+                    for &other in &cells[row as usize * columns + column as usize] {
+                        if other <= index || !close(&boxes[index], &boxes[other]) {
+                            continue;
+                        }
+                        let (a, b) = (root(&mut parents, index), root(&mut parents, other));
+                        if a != b {
+                            parents[b] = a;
+                            merged = true;
+                        }
+                    }
+                }
+            }
+        }
+        if !merged {
+            return boxes;
+        }
+
+        let mut grown: Vec<Option<Edges>> = vec![None; boxes.len()];
+        for (index, &b) in boxes.iter().enumerate() {
+            let merged = &mut grown[root(&mut parents, index)];
+            *merged = Some(match *merged {
+                Some(a) => (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)),
+                None => b,
+            });
+        }
+        boxes = grown.into_iter().flatten().collect();
+    }
+}
+
+impl ImageDiff {
+    /// Whether any pixel outside of the ignored regions changed.
+    pub fn has_changes(&self) -> bool {
+        self.changed_pixels > 0
+    }
+
+    /// Number of pixels that were compared, i.e. not ignored.
+    pub fn compared_pixels(&self) -> u64 {
+        self.size.width as u64 * self.size.height as u64 - self.ignored_pixels
+    }
+
+    /// The smallest region containing all changed pixels, `None` if there
+    /// are none.
+    pub fn bounding_box(&self) -> Option<Region> {
+        self.regions
+            .iter()
+            .map(|region| {
+                let Region { position, size } = *region;
+                (
+                    position.x,
+                    position.y,
+                    position.x + size.width as i32,
+                    position.y + size.height as i32,
+                )
+            })
+            .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+            .map(|(left, top, right, bottom)| Region {
+                position: Position { x: left, y: top },
+                size: Size {
+                    width: (right - left) as u32,
+                    height: (bottom - top) as u32,
+                },
+            })
+    }
+
+    /// `image`, one of the compared images, faded with the changed pixels in
+    /// red, their regions outlined in magenta and the ignored regions darkened.
+    pub fn highlight(&self, image: &DynamicImage) -> Result<RgbaImage> {
+        let Size { width, height } = self.size;
+        if image.width() != width || image.height() != height {
+            return Err(Error::ImageSizeMismatch {
+                expected: (width, height),
+                actual: (image.width(), image.height()),
+            });
+        }
+
+        let luma = image.to_luma8();
+        let mut highlighted = RgbaImage::from_fn(width, height, |x, y| {
+            let index = (y * width + x) as usize;
+            if self.changed[index] {
+                return Rgba([255, 0, 0, 255]);
+            }
+            let grey = luma.get_pixel(x, y).0[0];
+            if self.ignored[index] {
+                let dark = grey / 4 + 48;
+                Rgba([dark, dark, dark.saturating_add(32), 255])
+            } else {
+                let faded = 255 - (255 - grey) / 4;
+                Rgba([faded, faded, faded, 255])
+            }
+        });
+
+        // Outline just outside of the regions, so no changed pixel is covered.
+        for region in &self.regions {
+            let Region { position, size } = *region;
+            let left = position.x as i64 - 1;
+            let top = position.y as i64 - 1;
+            let right = position.x as i64 + size.width as i64;
+            let bottom = position.y as i64 + size.height as i64;
+            let mut outline = |x: i64, y: i64| {
+                if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                    highlighted.put_pixel(x as u32, y as u32, Rgba([255, 0, 255, 255]));
+                }
+            };
+            for x in left..=right {
+                outline(x, top);
+                outline(x, bottom);
+            }
+            for y in top..=bottom {
+                outline(left, y);
+                outline(right, y);
+            }
+        }
+        Ok(highlighted)
+    }
+}
@@ -1,10 +1,8 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     os::fd::{AsFd, BorrowedFd},
-    sync::atomic::{AtomicBool, Ordering},
-    fs::File,
+    time::Duration,
 };
-use wayland_client::Proxy;
 
 use wayland_client::{
     Connection, Dispatch, QueueHandle,
@@ -14,15 +12,31 @@ use wayland_client::{
     protocol::{
         wl_buffer::WlBuffer,
         wl_compositor::WlCompositor,
+        wl_keyboard::{self, WlKeyboard},
         wl_output::{self, WlOutput},
-        wl_registry::{self, WlRegistry},
-        wl_shm::Format,
-        wl_shm::WlShm,
+        wl_pointer::{self, WlPointer},
+        wl_region::WlRegion,
+        wl_registry,
+        wl_seat::{self, WlSeat},
+        wl_shm::{Format, WlShm},
         wl_shm_pool::WlShmPool,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
         wl_surface::WlSurface,
     },
 };
 use wayland_protocols::{
+    ext::{
+        image_capture_source::v1::client::{
+            ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+            ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+        },
+        image_copy_capture::v1::client::{
+            ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1},
+            ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+            ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+        },
+    },
     wp::{
         linux_dmabuf::zv1::client::{
             zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
@@ -35,77 +49,23 @@ use wayland_protocols::{
         zxdg_output_v1::{self, ZxdgOutputV1},
     },
 };
-use wayland_protocols_wlr::{
-    layer_shell::v1::client::{
-        zwlr_layer_shell_v1::ZwlrLayerShellV1,
-        zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
-    },
-    screencopy::v1::client::{
-        zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
-        zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
-    },
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::ZwlrLayerShellV1,
+    zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
 };
 
 use crate::{
+    Error, Result,
     output::OutputInfo,
-    region::{LogicalRegion, Position, Size},
-    screencopy::{DMAFrameFormat, FrameFormat, FrameGuard},
-};
-use wayland_protocols::ext::{
-    image_capture_source::v1::client::{
-        ext_image_capture_source_v1::ExtImageCaptureSourceV1,
-        ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
-    },
-    image_copy_capture::v1::client::{
-        ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1,
-        ext_image_copy_capture_manager_v1::{ExtImageCopyCaptureManagerV1, Options},
-        ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
-    },
+    region::{Position, Region, Size},
+    screencopy::DMAFrameFormat,
 };
+
 #[derive(Debug)]
 pub struct OutputCaptureState {
     pub outputs: Vec<OutputInfo>,
 }
 
-impl Dispatch<WlRegistry, ()> for OutputCaptureState {
-    #[tracing::instrument(skip(wl_registry, qh), ret, level = "trace")]
-    fn event(
-        state: &mut Self,
-        wl_registry: &WlRegistry,
-        event: wl_registry::Event,
-        _: &(),
-        _: &Connection,
-        qh: &QueueHandle<Self>,
-    ) {
-        /* > The name event is sent after binding the output object. This event
-         * is only sent once per output object, and the name does not change
-         * over the lifetime of the wl_output global. */
-
-        if let wl_registry::Event::Global {
-            name,
-            interface,
-            version,
-        } = event
-        {
-            if interface == "wl_output" {
-                if version >= 4 {
-                    let output = wl_registry.bind::<wl_output::WlOutput, _, _>(name, 4, qh, ());
-                    state.outputs.push(OutputInfo {
-                        wl_output: output,
-                        name: "".to_string(),
-                        description: String::new(),
-                        transform: wl_output::Transform::Normal,
-                        physical_size: Size::default(),
-                        logical_region: LogicalRegion::default(),
-                    });
-                } else {
-                    tracing::error!("Ignoring a wl_output with version < 4.");
-                }
-            }
-        }
-    }
-}
-
 impl Dispatch<WlOutput, ()> for OutputCaptureState {
     #[tracing::instrument(skip(wl_output), ret, level = "trace")]
     fn event(
@@ -153,7 +113,6 @@ impl Dispatch<WlOutput, ()> for OutputCaptureState {
     }
 }
 
-delegate_noop!(OutputCaptureState: ignore ZxdgOutputManagerV1);
 
 impl Dispatch<ZxdgOutputV1, usize> for OutputCaptureState {
     #[tracing::instrument(ret, level = "trace")]
@@ -196,18 +155,57 @@ impl Dispatch<ZxdgOutputV1, usize> for OutputCaptureState {
 /// State of the frame after attempting to copy it's data to a wl_buffer.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum FrameState {
-    /// Compositor returned a failed event on calling `frame.copy`.
+    /// Compositor returned a failed event on calling `frame.capture`.
     Failed,
-    /// Compositor sent a Ready event on calling `frame.copy`.
+    /// Compositor sent a Ready event on calling `frame.capture`.
     Finished,
 }
 
-pub struct CaptureFrameState {
-    pub formats: Vec<FrameFormat>,
+/// Buffer constraints a capture session sends before frames can be captured.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BufferConstraints {
+    /// Size the buffer has to have.
+    pub size: Size,
+    /// `wl_shm` formats usable for the buffer, in order of preference.
+    pub shm_formats: Vec<Format>,
     pub dmabuf_formats: Vec<DMAFrameFormat>,
-    pub state: Option<FrameState>,
-    pub buffer_done: AtomicBool,
 }
+
+/// Progress of capturing one output, see [`CaptureFrameState`].
+#[derive(Debug, Default)]
+pub struct OutputCapture {
+    /// Constraints received since the last `done` event of the session.
+    pub pending_constraints: BufferConstraints,
+    /// Constraints as of the last `done` event of the session.
+    pub constraints: Option<BufferConstraints>,
+    /// The session was stopped by the compositor, e.g. because the output
+    /// was removed.
+    pub stopped: bool,
+    pub frame_state: Option<FrameState>,
+    /// Time the captured content was presented, relative to the clock of the
+    /// compositor's presentation timestamps.
+    pub presentation_time: Option<Duration>,
+    /// Damaged regions of the captured frame in buffer coordinates.
+    pub damage: Vec<Region>,
+}
+
+/// State of the capture sessions on one event queue. Capture sessions and
+/// their frames carry the id of the session in `captures` as user data.
+#[derive(Debug, Default)]
+pub struct CaptureFrameState {
+    pub captures: HashMap<usize, OutputCapture>,
+}
+
+impl CaptureFrameState {
+    fn capture(&mut self, id: usize) -> Option<&mut OutputCapture> {
+        let capture = self.captures.get_mut(&id);
+        if capture.is_none() {
+            tracing::debug!("Received event for capture session {id} that is no longer used");
+        }
+        capture
+    }
+}
+
 impl Dispatch<ZwpLinuxDmabufV1, ()> for CaptureFrameState {
     fn event(
         _frame: &mut Self,
@@ -232,146 +230,121 @@ impl Dispatch<ZwpLinuxBufferParamsV1, ()> for CaptureFrameState {
     }
 }
 
+delegate_noop!(CaptureFrameState: ignore ExtImageCaptureSourceV1);
 
-impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for CaptureFrameState{
-    fn event(
-        _state: &mut Self,
-        _proxy: &ExtImageCopyCaptureManagerV1,
-        _event: <ExtImageCopyCaptureManagerV1 as Proxy>::Event,
-        _data: &(),
-        _conn: &wayland_client::Connection,
-        _qhandle: &QueueHandle<Self>,
-    ) {
-    }
-}
-
-impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for CaptureFrameState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &ExtOutputImageCaptureSourceManagerV1,
-        _event: <ExtOutputImageCaptureSourceManagerV1 as Proxy>::Event,
-        _data: &(),
-        _conn: &wayland_client::Connection,
-        _qhandle: &QueueHandle<Self>,
-    ) {
-    }
-}
-impl Dispatch<ExtImageCaptureSourceV1, ()> for CaptureFrameState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &ExtImageCaptureSourceV1,
-        _event: <ExtImageCaptureSourceV1 as Proxy>::Event,
-        _data: &(),
-        _conn: &wayland_client::Connection,
-        _qhandle: &QueueHandle<Self>,
-    ) {
-    }
-}
-impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureFrameState {
+impl Dispatch<ExtImageCopyCaptureSessionV1, usize> for CaptureFrameState {
+    #[tracing::instrument(skip(state, _proxy), ret, level = "trace")]
     fn event(
         state: &mut Self,
-        proxy: &ExtImageCopyCaptureSessionV1,
-        event: <ExtImageCopyCaptureSessionV1 as Proxy>::Event,
-        _: &(),
-        _: &wayland_client::Connection,
-        qh: &QueueHandle<Self>,
+        _proxy: &ExtImageCopyCaptureSessionV1,
+        event: ext_image_copy_capture_session_v1::Event,
+        id: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
     ) {
-        use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_session_v1::Event as ExtImageCopyCaptureSessionV1Event;
-        let mut pending_width = None;
-        let mut pending_height = None;
-        let mut pending_format = None;
+        let Some(capture) = state.capture(*id) else {
+            return;
+        };
 
         match event {
-            ExtImageCopyCaptureSessionV1Event::BufferSize { width, height } => {
-                pending_width = Some(width);
-                pending_height = Some(height);
-            //     state.formats.push(FrameFormat {
-            //         format: wl_shm::Format::Argb8888, // Placeholder format, will be updated in shm_format event
-            //         size: Size { width, height },
-            //         stride: 0, // Placeholder stride, will be calculated in Done event
-            //     });
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                capture.pending_constraints.size = Size { width, height };
             }
-            ExtImageCopyCaptureSessionV1Event::ShmFormat { format } => {
-                if let Value(f) = format {
-                    pending_format = Some(f);
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format } => {
+                if let Value(format) = format {
+                    capture.pending_constraints.shm_formats.push(format);
                 } else {
                     tracing::debug!("Received ShmFormat event with unidentified format");
                 }
-                // if let Value(f) = format {
-                // state.formats.push(FrameFormat {
-                //     format: f, // Placeholder format, will be updated in shm_format event
-                    
-                //     stride: 0,
-                // }) } else {
-                //     tracing::debug!("Received Buffer event with unidentified format");
-                // }
             }
-            ExtImageCopyCaptureSessionV1Event::Done => {
-                if let (Some(width), Some(height), Some(format)) = (pending_width, pending_height, pending_format) {
-                    state.formats.push(FrameFormat {
-                        format,
-                        size: Size { width, height },
-                        stride:0 ,
-                    });
-                } else {
-                    tracing::debug!("Missing parameters for frame format creation");
-                }
-                
-                // Reset temporary variables
-                pending_width = None;
-                pending_height = None;
-                pending_format = None;
-               
-                  state.state.replace(FrameState::Finished);
-                          state.buffer_done.store(true, Ordering::SeqCst);
-             
-                }
-                
-            ExtImageCopyCaptureSessionV1Event::Stopped => {
-                println!("Capture session stopped");
-                state.state.replace(FrameState::Failed);
-                state.buffer_done.store(true, Ordering::SeqCst);
+            ext_image_copy_capture_session_v1::Event::DmabufFormat { format, .. } => {
+                let size = capture.pending_constraints.size;
+                capture
+                    .pending_constraints
+                    .dmabuf_formats
+                    .push(DMAFrameFormat { format, size });
+            }
+            ext_image_copy_capture_session_v1::Event::Done => {
+                // The compositor resends all constraints before the next done
+                // event when they change.
+                capture.constraints = Some(std::mem::take(&mut capture.pending_constraints));
+            }
+            ext_image_copy_capture_session_v1::Event::Stopped => {
+                tracing::debug!("Capture session stopped");
+                capture.stopped = true;
             }
-            
             _ => {}
         }
     }
 }
 
-impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for CaptureFrameState {
+impl Dispatch<ExtImageCopyCaptureFrameV1, usize> for CaptureFrameState {
+    #[tracing::instrument(skip(state, proxy), ret, level = "trace")]
     fn event(
         state: &mut Self,
         proxy: &ExtImageCopyCaptureFrameV1,
-        event: <ExtImageCopyCaptureFrameV1 as Proxy>::Event,
-        _data: &(),
-        _conn: &wayland_client::Connection,
-        _qh: &QueueHandle<Self>,
+        event: ext_image_copy_capture_frame_v1::Event,
+        id: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
     ) {
-        use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_frame_v1::Event as ExtImageCopyCaptureFrameV1Event;
+        let Some(capture) = state.capture(*id) else {
+            return;
+        };
+
         match event {
-            ExtImageCopyCaptureFrameV1Event::Ready => {
-                println!("Frame captured successfully");
+            ext_image_copy_capture_frame_v1::Event::Damage {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                capture.damage.push(Region {
+                    position: Position { x, y },
+                    size: Size {
+                        width: width.max(0) as u32,
+                        height: height.max(0) as u32,
+                    },
+                });
+            }
+            ext_image_copy_capture_frame_v1::Event::PresentationTime {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+            } => {
+                let secs = ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64;
+                capture.presentation_time = Some(Duration::new(secs, tv_nsec));
+            }
+            ext_image_copy_capture_frame_v1::Event::Ready => {
+                tracing::debug!("Frame captured successfully");
+                capture.frame_state = Some(FrameState::Finished);
                 proxy.destroy();
-                // planning to map fd here for buffer data and initialize it to CaptureFrameState or other option
             }
-            
-            ExtImageCopyCaptureFrameV1Event::Failed { reason }=> {
-                println!("Frame capture failed");
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                tracing::error!("Frame capture failed: {reason:?}");
+                capture.frame_state = Some(FrameState::Failed);
                 proxy.destroy();
             }
             _ => {}
         }
     }
 }
-delegate_noop!(CaptureFrameState: ignore WlShm);
+
 delegate_noop!(CaptureFrameState: ignore WlShmPool);
 delegate_noop!(CaptureFrameState: ignore WlBuffer);
-// delegate_noop!(CaptureFrameState: ignore ExtImageCopyCaptureManagerV1);
 
 // TODO: Create a xdg-shell surface, check for the enter event, grab the output from it.
 
 pub struct WayshotState {}
 delegate_noop!(WayshotState: ignore ZwpLinuxDmabufV1);
+delegate_noop!(WayshotState: ignore ZxdgOutputManagerV1);
+delegate_noop!(WayshotState: ignore ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(WayshotState: ignore ExtImageCopyCaptureManagerV1);
+delegate_noop!(WayshotState: ignore WlShm);
+delegate_noop!(WayshotState: ignore WlCompositor);
+delegate_noop!(WayshotState: ignore ZwlrLayerShellV1);
+delegate_noop!(WayshotState: ignore WpViewporter);
+delegate_noop!(WayshotState: ignore WlSubcompositor);
 impl wayland_client::Dispatch<wl_registry::WlRegistry, GlobalListContents> for WayshotState {
     fn event(
         _: &mut WayshotState,
@@ -384,18 +357,137 @@ impl wayland_client::Dispatch<wl_registry::WlRegistry, GlobalListContents> for W
     }
 }
 
+/// `BTN_LEFT` and `BTN_RIGHT` of `linux/input-event-codes.h`.
+const BUTTON_LEFT: u32 = 0x110;
+const BUTTON_RIGHT: u32 = 0x111;
+/// `KEY_ESC` of `linux/input-event-codes.h`.
+const KEY_ESCAPE: u32 = 1;
+
+#[derive(Default)]
 pub struct LayerShellState {
     pub configured_outputs: HashSet<WlOutput>,
+    /// Input on the overlay surfaces, only received once a seat was bound.
+    pub(crate) input: Vec<OverlayInput>,
+    pub(crate) pointer: Option<WlPointer>,
+    pub(crate) keyboard: Option<WlKeyboard>,
+}
+
+/// Pointer and keyboard input on the overlay surfaces.
+#[derive(Debug)]
+pub(crate) enum OverlayInput {
+    /// The pointer entered `surface` at the surface local `position`.
+    Enter {
+        serial: u32,
+        surface: WlSurface,
+        position: (f64, f64),
+    },
+    /// The pointer moved to the surface local `position`.
+    Motion { position: (f64, f64) },
+    Leave,
+    /// The left button was pressed.
+    Press,
+    /// The left button was released.
+    Release,
+    /// The right button or Escape was pressed.
+    Cancel,
 }
 
-delegate_noop!(LayerShellState: ignore WlCompositor);
 delegate_noop!(LayerShellState: ignore WlShm);
 delegate_noop!(LayerShellState: ignore WlShmPool);
 delegate_noop!(LayerShellState: ignore WlBuffer);
-delegate_noop!(LayerShellState: ignore ZwlrLayerShellV1);
+delegate_noop!(LayerShellState: WlRegion);
 delegate_noop!(LayerShellState: ignore WlSurface);
+delegate_noop!(LayerShellState: WlSubsurface);
 delegate_noop!(LayerShellState: ignore WpViewport);
-delegate_noop!(LayerShellState: ignore WpViewporter);
+
+impl Dispatch<WlSeat, ()> for LayerShellState {
+    fn event(
+        state: &mut Self,
+        seat: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wl_seat::Event::Capabilities {
+            capabilities: Value(capabilities),
+        } = event
+        else {
+            return;
+        };
+        if capabilities.contains(wl_seat::Capability::Pointer) && state.pointer.is_none() {
+            state.pointer = Some(seat.get_pointer(qh, ()));
+        }
+        if capabilities.contains(wl_seat::Capability::Keyboard) && state.keyboard.is_none() {
+            state.keyboard = Some(seat.get_keyboard(qh, ()));
+        }
+    }
+}
+
+impl Dispatch<WlPointer, ()> for LayerShellState {
+    fn event(
+        state: &mut Self,
+        _: &WlPointer,
+        event: wl_pointer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let input = match event {
+            wl_pointer::Event::Enter {
+                serial,
+                surface,
+                surface_x,
+                surface_y,
+            } => OverlayInput::Enter {
+                serial,
+                surface,
+                position: (surface_x, surface_y),
+            },
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => OverlayInput::Motion {
+                position: (surface_x, surface_y),
+            },
+            wl_pointer::Event::Leave { .. } => OverlayInput::Leave,
+            wl_pointer::Event::Button {
+                button,
+                state: Value(button_state),
+                ..
+            } => match (button, button_state) {
+                (BUTTON_LEFT, wl_pointer::ButtonState::Pressed) => OverlayInput::Press,
+                (BUTTON_LEFT, wl_pointer::ButtonState::Released) => OverlayInput::Release,
+                (BUTTON_RIGHT, wl_pointer::ButtonState::Pressed) => OverlayInput::Cancel,
+                _ => return,
+            },
+            _ => return,
+        };
+        state.input.push(input);
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for LayerShellState {
+    fn event(
+        state: &mut Self,
+        _: &WlKeyboard,
+        event: wl_keyboard::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Raw key codes are enough for Escape, no need for a keymap.
+        if let wl_keyboard::Event::Key {
+            key: KEY_ESCAPE,
+            state: Value(wl_keyboard::KeyState::Pressed),
+            ..
+        } = event
+        {
+            state.input.push(OverlayInput::Cancel);
+        }
+    }
+}
 
 impl wayland_client::Dispatch<ZwlrLayerSurfaceV1, WlOutput> for LayerShellState {
     // No need to instrument here, span from lib.rs is automatically used.
@@ -438,11 +530,17 @@ impl AsFd for Card {
 impl drm::Device for Card {}
 /// Simple helper methods for opening a `Card`.
 impl Card {
-    pub fn open(path: &str) -> Self {
+    pub fn open(path: &str) -> Result<Self> {
         let mut options = std::fs::OpenOptions::new();
         options.read(true);
         options.write(true);
-        Card(options.open(path).unwrap())
+        options
+            .open(path)
+            .map(Card)
+            .map_err(|source| Error::DrmDeviceOpen {
+                path: path.to_string(),
+                source,
+            })
     }
 }
 #[derive(Debug)]
@@ -450,3 +548,22 @@ pub(crate) struct DMABUFState {
     pub linux_dmabuf: ZwpLinuxDmabufV1,
     pub gbmdev: gbm::Device<Card>,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn missing_drm_device() {
+        match Card::open("/nonexistent/dri/card0") {
+            Err(Error::DrmDeviceOpen { path, source }) => {
+                assert_eq!(path, "/nonexistent/dri/card0");
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            Err(e) => panic!("unexpected error {e:?}"),
+            Ok(_) => panic!("opened a missing DRM device"),
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! Dumps of everything received for a screenshot, to replay it offline.
+//!
+//! A dump holds the outputs, the negotiated buffer formats and the frames as
+//! the compositor copied them, before any conversion. Replaying it runs the
+//! same conversion and compositing as the original screenshot, so a broken
+//! screenshot can be reproduced without the compositor it was taken on.
+//!
+//! The file format is private to libwayshot: a magic number and a version,
+//! followed by little endian integers and length prefixed strings and frame
+//! data.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+use image::DynamicImage;
+use wayland_client::{
+    Connection, Proxy,
+    protocol::{
+        wl_output::{Transform, WlOutput},
+        wl_shm::Format,
+    },
+};
+
+use crate::{
+    Error, Result,
+    capture::convert_frame,
+    image_util,
+    output::OutputInfo,
+    region::{LogicalRegion, Position, Region, Size},
+    screencopy::{FrameCopy, FrameData, FrameFormat},
+};
+
+const MAGIC: &[u8; 16] = b"wayshot-capture\0";
+const VERSION: u32 = 1;
+/// Output names and descriptions are short, anything longer is corrupt.
+const MAX_STRING_LEN: u32 = 64 * 1024;
+
+/// Everything received for a screenshot, see [`crate::WayshotConnection::set_capture_dump`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureDump {
+    /// Region the frames were composited into.
+    pub capture_region: LogicalRegion,
+    pub frames: Vec<DumpedFrame>,
+}
+
+/// A frame as copied by the compositor, together with the output it shows.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DumpedFrame {
+    pub output_name: String,
+    pub output_description: String,
+    pub transform: Transform,
+    pub physical_size: Size,
+    pub output_region: LogicalRegion,
+    pub frame_format: FrameFormat,
+    /// Part of the output the frame shows.
+    pub logical_region: LogicalRegion,
+    pub presentation_time: Option<Duration>,
+    /// Pixels in `frame_format`, before conversion.
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for DumpedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DumpedFrame")
+            .field("output_name", &self.output_name)
+            .field("output_description", &self.output_description)
+            .field("transform", &self.transform)
+            .field("physical_size", &self.physical_size)
+            .field("output_region", &self.output_region)
+            .field("frame_format", &self.frame_format)
+            .field("logical_region", &self.logical_region)
+            .field("presentation_time", &self.presentation_time)
+            .field("data", &format_args!("{} bytes", self.data.len()))
+            .finish()
+    }
+}
+
+impl DumpedFrame {
+    pub(crate) fn new(
+        output_info: &OutputInfo,
+        frame_format: FrameFormat,
+        logical_region: LogicalRegion,
+        presentation_time: Option<Duration>,
+        data: Vec<u8>,
+    ) -> Self {
+        Self {
+            output_name: output_info.name.clone(),
+            output_description: output_info.description.clone(),
+            transform: output_info.transform,
+            physical_size: output_info.physical_size,
+            output_region: output_info.logical_region,
+            frame_format,
+            logical_region,
+            presentation_time,
+            data,
+        }
+    }
+}
+
+impl CaptureDump {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidCaptureDump("not a capture dump".to_owned()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(Error::InvalidCaptureDump(format!(
+                "unsupported version {version}"
+            )));
+        }
+
+        let capture_region = read_region(&mut reader)?;
+        let frame_count = read_u32(&mut reader)?;
+        let frames = (0..frame_count)
+            .map(|_| read_frame(&mut reader))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            capture_region,
+            frames,
+        })
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(&mut writer, VERSION)?;
+        write_region(&mut writer, self.capture_region)?;
+        let frame_count = u32::try_from(self.frames.len()).map_err(|_| {
+            Error::InvalidCaptureDump(format!("{} frames are too many", self.frames.len()))
+        })?;
+        write_u32(&mut writer, frame_count)?;
+        for frame in &self.frames {
+            write_frame(&mut writer, frame)?;
+        }
+        Ok(())
+    }
+
+    /// Convert the dumped frames like captured frames are converted.
+    ///
+    /// The outputs of the frames are inert, they never belonged to a
+    /// compositor.
+    pub fn frame_copies(&self) -> Result<Vec<(FrameCopy, OutputInfo)>> {
+        let (socket, _) = UnixStream::pair()?;
+        let conn = Connection::from_socket(socket)?;
+
+        self.frames
+            .iter()
+            .map(|frame| {
+                let output_info = OutputInfo {
+                    wl_output: WlOutput::inert(conn.backend().downgrade()),
+                    name: frame.output_name.clone(),
+                    description: frame.output_description.clone(),
+                    transform: frame.transform,
+                    physical_size: frame.physical_size,
+                    logical_region: frame.output_region,
+                };
+                let frame_copy = convert_frame(
+                    &output_info,
+                    frame.frame_format,
+                    FrameData::Owned(frame.data.clone()),
+                    frame.logical_region,
+                    frame.presentation_time,
+                )?;
+                Ok((frame_copy, output_info))
+            })
+            .collect()
+    }
+
+    /// Take the screenshot again from the dumped frames.
+    pub fn replay(&self) -> Result<DynamicImage> {
+        image_util::composite_frames(self.frame_copies()?, self.capture_region)
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<DumpedFrame> {
+    let output_name = read_string(reader)?;
+    let output_description = read_string(reader)?;
+    let transform = read_u32(reader)?;
+    let transform = Transform::try_from(transform)
+        .map_err(|()| Error::InvalidCaptureDump(format!("unknown transform {transform}")))?;
+    let physical_size = read_size(reader)?;
+    let output_region = read_region(reader)?;
+
+    let format = read_u32(reader)?;
+    let format = Format::try_from(format)
+        .map_err(|()| Error::InvalidCaptureDump(format!("unknown buffer format {format:#x}")))?;
+    let frame_format = FrameFormat {
+        format,
+        size: read_size(reader)?,
+        stride: read_u32(reader)?,
+    };
+    let logical_region = read_region(reader)?;
+
+    let mut has_time = [0];
+    reader.read_exact(&mut has_time)?;
+    let secs = read_u64(reader)?;
+    let nanos = read_u32(reader)?;
+    if nanos >= 1_000_000_000 {
+        return Err(Error::InvalidCaptureDump(format!(
+            "presentation time of output {output_name} has {nanos} nanoseconds"
+        )));
+    }
+    let presentation_time = (has_time[0] != 0).then(|| Duration::new(secs, nanos));
+
+    let len = read_u64(reader)?;
+    if len != frame_format.byte_size() {
+        return Err(Error::InvalidCaptureDump(format!(
+            "frame of output {output_name} has {len} bytes instead of {}",
+            frame_format.byte_size()
+        )));
+    }
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(Error::InvalidCaptureDump("truncated frame data".to_owned()));
+    }
+
+    Ok(DumpedFrame {
+        output_name,
+        output_description,
+        transform,
+        physical_size,
+        output_region,
+        frame_format,
+        logical_region,
+        presentation_time,
+        data,
+    })
+}
+
+fn write_frame(writer: &mut impl Write, frame: &DumpedFrame) -> Result<()> {
+    write_string(writer, &frame.output_name)?;
+    write_string(writer, &frame.output_description)?;
+    write_u32(writer, frame.transform.into())?;
+    write_size(writer, frame.physical_size)?;
+    write_region(writer, frame.output_region)?;
+
+    write_u32(writer, frame.frame_format.format.into())?;
+    write_size(writer, frame.frame_format.size)?;
+    write_u32(writer, frame.frame_format.stride)?;
+    write_region(writer, frame.logical_region)?;
+
+    let time = frame.presentation_time.unwrap_or_default();
+    writer.write_all(&[frame.presentation_time.is_some() as u8])?;
+    writer.write_all(&time.as_secs().to_le_bytes())?;
+    write_u32(writer, time.subsec_nanos())?;
+
+    writer.write_all(&(frame.data.len() as u64).to_le_bytes())?;
+    writer.write_all(&frame.data)?;
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_u32(reader)?;
+    if len > MAX_STRING_LEN {
+        return Err(Error::InvalidCaptureDump(format!(
+            "string of {len} bytes is too long"
+        )));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| Error::InvalidCaptureDump(e.to_string()))
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> Result<()> {
+    let len = u32::try_from(value.len())
+        .ok()
+        .filter(|&len| len <= MAX_STRING_LEN)
+        .ok_or_else(|| {
+            Error::InvalidCaptureDump(format!("string of {} bytes is too long", value.len()))
+        })?;
+    write_u32(writer, len)?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+fn read_size(reader: &mut impl Read) -> Result<Size> {
+    Ok(Size {
+        width: read_u32(reader)?,
+        height: read_u32(reader)?,
+    })
+}
+
+fn write_size(writer: &mut impl Write, size: Size) -> Result<()> {
+    write_u32(writer, size.width)?;
+    write_u32(writer, size.height)
+}
+
+fn read_region(reader: &mut impl Read) -> Result<LogicalRegion> {
+    let x = read_u32(reader)? as i32;
+    let y = read_u32(reader)? as i32;
+    Ok(LogicalRegion {
+        inner: Region {
+            position: Position { x, y },
+            size: read_size(reader)?,
+        },
+    })
+}
+
+fn write_region(writer: &mut impl Write, region: LogicalRegion) -> Result<()> {
+    let Region { position, size } = region.inner;
+    write_u32(writer, position.x as u32)?;
+    write_u32(writer, position.y as u32)?;
+    write_size(writer, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump() -> CaptureDump {
+        let region = LogicalRegion {
+            inner: Region {
+                position: Position { x: -2, y: 0 },
+                size: Size {
+                    width: 2,
+                    height: 1,
+                },
+            },
+        };
+        CaptureDump {
+            capture_region: region,
+            frames: vec![DumpedFrame {
+                output_name: "DP-1".to_owned(),
+                output_description: "Monitor".to_owned(),
+                transform: Transform::_90,
+                physical_size: region.inner.size,
+                output_region: region,
+                frame_format: FrameFormat {
+                    format: Format::Xrgb8888,
+                    size: region.inner.size,
+                    stride: 8,
+                },
+                logical_region: region,
+                presentation_time: Some(Duration::new(3, 999_999_999)),
+                data: (0..8).collect(),
+            }],
+        }
+    }
+
+    fn written(dump: &CaptureDump) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        dump.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let dump = dump();
+        assert_eq!(CaptureDump::read_from(written(&dump)?.as_slice())?, dump);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_presentation_times() -> Result<()> {
+        let mut bytes = written(&dump())?;
+        // The nanoseconds are followed by the length of the data and the
+        // data.
+        let nanos = bytes.len() - 8 - 8 - 4;
+        bytes[nanos..nanos + 4].copy_from_slice(&1_000_000_000u32.to_le_bytes());
+
+        let result = CaptureDump::read_from(bytes.as_slice());
+        assert!(
+            matches!(result, Err(Error::InvalidCaptureDump(_))),
+            "{result:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_truncated_dumps() -> Result<()> {
+        let bytes = written(&dump())?;
+        for len in [10, bytes.len() / 2, bytes.len() - 1] {
+            assert!(CaptureDump::read_from(&bytes[..len]).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_to_write_unreadable_strings() {
+        let mut dump = dump();
+        dump.frames[0].output_description = "x".repeat(MAX_STRING_LEN as usize + 1);
+        assert!(matches!(
+            dump.write_to(Vec::new()),
+            Err(Error::InvalidCaptureDump(_))
+        ));
+    }
+}
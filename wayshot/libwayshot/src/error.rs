@@ -1,14 +1,37 @@
-use std::{io, result};
+use std::{fmt, io, result};
 
 use drm::buffer::UnrecognizedFourcc;
 use thiserror::Error;
 use wayland_client::{
     ConnectError, DispatchError,
+    backend::WaylandError,
     globals::{BindError, GlobalError},
+    protocol::wl_shm::Format,
 };
 
 pub type Result<T, E = Error> = result::Result<T, E>;
 
+/// Phase of a capture that waits on the compositor, see [`crate::Timeouts`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CapturePhase {
+    /// Waiting for the buffer constraints of a capture session.
+    Negotiation,
+    /// Waiting for a frame to be copied.
+    Copy,
+    /// Waiting for a layer surface to be configured.
+    Configure,
+}
+
+impl fmt::Display for CapturePhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CapturePhase::Negotiation => "buffer constraint negotiation",
+            CapturePhase::Copy => "frame copy",
+            CapturePhase::Configure => "layer surface configuration",
+        })
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("no outputs supplied")]
@@ -17,22 +40,57 @@ pub enum Error {
     BufferTooSmall,
     #[error("image color type not supported")]
     InvalidColor,
+    #[error("invalid geometry: {0}")]
+    InvalidGeometry(String),
+    #[error("invalid capture dump: {0}")]
+    InvalidCaptureDump(String),
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
     #[error("dispatch error: {0}")]
     Dispatch(#[from] DispatchError),
+    #[error("wayland connection error: {0}")]
+    Wayland(#[from] WaylandError),
+    #[error("timed out during {phase} on output {output}")]
+    Timeout { phase: CapturePhase, output: String },
     #[error("bind error: {0}")]
     Bind(#[from] BindError),
     #[error("global error: {0}")]
     Global(#[from] GlobalError),
     #[error("connect error: {0}")]
     Connect(#[from] ConnectError),
-    #[error("framecopy failed")]
-    FramecopyFailed,
-    #[error("No supported buffer format")]
-    NoSupportedBufferFormat,
-    #[error("Cannot find required wayland protocol")]
-    ProtocolNotFound(String),
+    #[error("framecopy failed on output {output}")]
+    FramecopyFailed { output: String },
+    #[error("no supported buffer format offered for output {output}")]
+    NoSupportedBufferFormat { output: String },
+    #[error("buffer format {format:?} of output {output} is not supported")]
+    UnsupportedBufferFormat { output: String, format: Format },
+    #[error("converting {format:?} dmabuf frames into images is not supported")]
+    UnsupportedFrameData { format: Format },
+    #[error("cannot bind {protocol}, does the compositor implement it?")]
+    ProtocolNotFound {
+        protocol: &'static str,
+        #[source]
+        source: BindError,
+    },
+    #[error(
+        "cannot compare a {}x{} image with a {}x{} image",
+        expected.0,
+        expected.1,
+        actual.0,
+        actual.1
+    )]
+    ImageSizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    #[error("processing the frame of output {output} panicked")]
+    FrameProcessingPanicked { output: String },
+    #[error("cannot open DRM device {path}: {source}")]
+    DrmDeviceOpen {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
     #[error("error occurred in freeze callback")]
     FreezeCallbackError,
     #[error(
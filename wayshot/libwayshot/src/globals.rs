@@ -0,0 +1,155 @@
+//! Globals that are bound once, on first use, and kept for the lifetime of a
+//! [`crate::WayshotConnection`].
+
+use std::{
+    ops::RangeInclusive,
+    sync::{Mutex, PoisonError},
+};
+
+use wayland_client::{
+    Dispatch, EventQueue, Proxy,
+    globals::GlobalList,
+    protocol::{wl_compositor::WlCompositor, wl_shm::WlShm, wl_subcompositor::WlSubcompositor},
+};
+use wayland_protocols::{
+    ext::{
+        image_capture_source::v1::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+        image_copy_capture::v1::client::ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+    },
+    wp::viewporter::client::wp_viewporter::WpViewporter,
+    xdg::xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1,
+};
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+
+use crate::{Error, Result, dispatch::WayshotState};
+
+/// Lazily bound globals. None of them send events we care about, so they are
+/// all bound on the registry queue, which is never dispatched. Objects created
+/// from them are assigned to the queue passed when creating them.
+#[derive(Debug)]
+pub(crate) struct BoundGlobals {
+    event_queue: EventQueue<WayshotState>,
+    xdg_output_manager: Mutex<Option<ZxdgOutputManagerV1>>,
+    output_source_manager: Mutex<Option<ExtOutputImageCaptureSourceManagerV1>>,
+    copy_manager: Mutex<Option<ExtImageCopyCaptureManagerV1>>,
+    shm: Mutex<Option<WlShm>>,
+    compositor: Mutex<Option<WlCompositor>>,
+    layer_shell: Mutex<Option<ZwlrLayerShellV1>>,
+    viewporter: Mutex<Option<WpViewporter>>,
+    subcompositor: Mutex<Option<WlSubcompositor>>,
+}
+
+impl BoundGlobals {
+    pub(crate) fn new(event_queue: EventQueue<WayshotState>) -> Self {
+        Self {
+            event_queue,
+            xdg_output_manager: Mutex::default(),
+            output_source_manager: Mutex::default(),
+            copy_manager: Mutex::default(),
+            shm: Mutex::default(),
+            compositor: Mutex::default(),
+            layer_shell: Mutex::default(),
+            viewporter: Mutex::default(),
+            subcompositor: Mutex::default(),
+        }
+    }
+
+    fn get_or_bind<I>(
+        &self,
+        globals: &GlobalList,
+        slot: &Mutex<Option<I>>,
+        version: RangeInclusive<u32>,
+    ) -> Result<I>
+    where
+        I: Proxy + Clone + 'static,
+        WayshotState: Dispatch<I, ()>,
+    {
+        let mut slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(proxy) = slot.as_ref() {
+            return Ok(proxy.clone());
+        }
+
+        let proxy = globals
+            .bind::<I, _, _>(&self.event_queue.handle(), version, ())
+            .map_err(|source| Error::ProtocolNotFound {
+                protocol: I::interface().name,
+                source,
+            })?;
+        tracing::debug!("Bound global {}", I::interface().name);
+        *slot = Some(proxy.clone());
+        Ok(proxy)
+    }
+
+    pub(crate) fn xdg_output_manager(&self, globals: &GlobalList) -> Result<ZxdgOutputManagerV1> {
+        self.get_or_bind(globals, &self.xdg_output_manager, 3..=3)
+            .inspect_err(|_| {
+                tracing::error!(
+                    "Failed to create ZxdgOutputManagerV1 version 3. Does your compositor implement ZxdgOutputManagerV1?"
+                );
+            })
+    }
+
+    pub(crate) fn output_source_manager(
+        &self,
+        globals: &GlobalList,
+    ) -> Result<ExtOutputImageCaptureSourceManagerV1> {
+        self.get_or_bind(
+            globals,
+            &self.output_source_manager,
+            1..=ExtOutputImageCaptureSourceManagerV1::interface().version,
+        )
+        .inspect_err(|e| {
+            tracing::error!("Failed to bind ExtOutputImageCaptureSourceManagerV1: {e:?}");
+        })
+    }
+
+    pub(crate) fn copy_manager(
+        &self,
+        globals: &GlobalList,
+    ) -> Result<ExtImageCopyCaptureManagerV1> {
+        self.get_or_bind(
+            globals,
+            &self.copy_manager,
+            1..=ExtImageCopyCaptureManagerV1::interface().version,
+        )
+        .inspect_err(|e| {
+            tracing::error!("Failed to bind ExtImageCopyCaptureManagerV1: {e:?}");
+        })
+    }
+
+    pub(crate) fn shm(&self, globals: &GlobalList) -> Result<WlShm> {
+        self.get_or_bind(globals, &self.shm, 1..=1)
+    }
+
+    pub(crate) fn compositor(&self, globals: &GlobalList) -> Result<WlCompositor> {
+        self.get_or_bind(globals, &self.compositor, 3..=3)
+            .inspect_err(|e| {
+                tracing::error!(
+                    "Failed to create compositor. Does your compositor implement WlCompositor?"
+                );
+                tracing::error!("err: {e}");
+            })
+    }
+
+    pub(crate) fn layer_shell(&self, globals: &GlobalList) -> Result<ZwlrLayerShellV1> {
+        self.get_or_bind(globals, &self.layer_shell, 1..=1)
+            .inspect_err(|e| {
+                tracing::error!(
+                    "Failed to create layer shell. Does your compositor implement WlrLayerShellV1?"
+                );
+                tracing::error!("err: {e}");
+            })
+    }
+
+    /// `wp_viewporter` is optional, `None` if the compositor doesn't support
+    /// it.
+    pub(crate) fn viewporter(&self, globals: &GlobalList) -> Option<WpViewporter> {
+        self.get_or_bind(globals, &self.viewporter, 1..=1).ok()
+    }
+
+    /// `wl_subcompositor` is optional as well, `None` if the compositor
+    /// doesn't support it.
+    pub(crate) fn subcompositor(&self, globals: &GlobalList) -> Option<WlSubcompositor> {
+        self.get_or_bind(globals, &self.subcompositor, 1..=1).ok()
+    }
+}
@@ -1,7 +1,14 @@
-use image::DynamicImage;
+use std::thread::{self, ScopedJoinHandle};
+
+use image::{DynamicImage, Rgba, imageops::replace};
 use wayland_client::protocol::wl_output::Transform;
 
-use crate::region::Size;
+use crate::{
+    Error, Result,
+    output::OutputInfo,
+    region::{LogicalRegion, Size},
+    screencopy::FrameCopy,
+};
 
 #[tracing::instrument(skip(image))]
 pub(crate) fn rotate_image_buffer(
@@ -10,14 +17,8 @@ pub(crate) fn rotate_image_buffer(
     logical_size: Size,
     max_scale: f64,
 ) -> DynamicImage {
-    // TODO Better document whether width and height are before or after the transform.
-    // Perhaps this should be part of a cleanup of the FrameCopy struct.
-    let (logical_width, _logical_height) = match transform {
-        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-            (logical_size.height, logical_size.width)
-        }
-        _ => (logical_size.width, logical_size.height),
-    };
+    // The logical size is in the orientation of the rotated image already.
+    let logical_width = logical_size.width;
     let rotated_image = match transform {
         Transform::_90 => image::imageops::rotate90(&image).into(),
         Transform::_180 => image::imageops::rotate180(&image).into(),
@@ -58,3 +59,185 @@ pub(crate) fn rotate_image_buffer(
     )
     .into()
 }
+
+/// Wait for the thread processing the frame of `output`, turning a panic
+/// into an error instead of passing it on.
+fn join_frame_processing<T>(output: String, join_handle: ScopedJoinHandle<Result<T>>) -> Result<T> {
+    join_handle.join().unwrap_or_else(|_| {
+        tracing::error!("Processing the frame of output {output} panicked");
+        Err(Error::FrameProcessingPanicked { output })
+    })
+}
+
+/// Compose the captured `frames` into one image of `capture_region`, scaled
+/// to the largest scale among the outputs the frames were captured from.
+///
+/// This is what the screenshot functions of [`crate::WayshotConnection`] do
+/// with the frames they capture, use it on the frames of
+/// [`crate::WayshotConnection::capture_frame_copies`] to get the same image.
+#[tracing::instrument(skip_all, fields(max_scale = tracing::field::Empty))]
+pub fn composite_frames(
+    frames: Vec<(FrameCopy, OutputInfo)>,
+    capture_region: LogicalRegion,
+) -> Result<DynamicImage> {
+    thread::scope(|scope| {
+        let max_scale = frames
+            .iter()
+            .map(|(_, output_info)| output_info.scale())
+            .fold(1.0, f64::max);
+
+        tracing::Span::current().record("max_scale", max_scale);
+
+        let rotate_join_handles = frames
+            .into_iter()
+            .map(|(frame_copy, output_info)| {
+                let output_name = output_info.name.clone();
+                let join_handle = scope.spawn(move || {
+                    let image: DynamicImage = (&frame_copy).try_into()?;
+                    // Frames always contain the whole output, only keep
+                    // the part that was asked for.
+                    let image = match frame_copy.logical_region.to_physical(&output_info) {
+                        Some(region) if region.size != frame_copy.frame_format.size => image
+                            .crop_imm(
+                                region.position.x as u32,
+                                region.position.y as u32,
+                                region.size.width,
+                                region.size.height,
+                            ),
+                        _ => image,
+                    };
+                    Ok((
+                        rotate_image_buffer(
+                            image,
+                            frame_copy.transform,
+                            frame_copy.logical_region.inner.size,
+                            max_scale,
+                        ),
+                        frame_copy,
+                    ))
+                });
+                (output_name, join_handle)
+            })
+            .collect::<Vec<_>>();
+
+        rotate_join_handles
+            .into_iter()
+            .map(|(output, join_handle)| join_frame_processing(output, join_handle))
+            .fold(
+                None,
+                |composite_image: Option<Result<_>>, image: Result<_>| {
+                    // Default to a transparent image.
+                    let composite_image = composite_image.unwrap_or_else(|| {
+                        Ok(DynamicImage::new_rgba8(
+                            (capture_region.inner.size.width as f64 * max_scale) as u32,
+                            (capture_region.inner.size.height as f64 * max_scale) as u32,
+                        ))
+                    });
+
+                    Some(|| -> Result<_> {
+                        let mut composite_image = composite_image?;
+                        let (image, frame_copy) = image?;
+                        let offset = |position: i32, origin: i32| {
+                            ((position as i64 - origin as i64) as f64 * max_scale).floor() as i64
+                        };
+                        let (position, origin) = (
+                            frame_copy.logical_region.inner.position,
+                            capture_region.inner.position,
+                        );
+                        let (x, y) = (offset(position.x, origin.x), offset(position.y, origin.y));
+                        tracing::span!(
+                            tracing::Level::DEBUG,
+                            "replace",
+                            frame_copy_region = format!("{}", frame_copy.logical_region),
+                            capture_region = format!("{}", capture_region),
+                            x = x,
+                            y = y,
+                        )
+                        .in_scope(|| {
+                            tracing::debug!("Replacing parts of the final image");
+                            replace(&mut composite_image, &image, x, y);
+                        });
+
+                        Ok(composite_image)
+                    }())
+                },
+            )
+            .ok_or_else(|| {
+                tracing::error!("Provided capture region doesn't intersect with any outputs!");
+                Error::NoOutputs
+            })?
+    })
+}
+
+/// Pixels per logical pixel of `image`, a screenshot that is `logical_size`
+/// large in logical pixels. Screenshots are composited at the largest scale
+/// of the captured outputs, so this is that scale.
+///
+/// Annotations, decorations and redactions are given in logical pixels and
+/// scaled by this, so that they end up the same on screenshots taken at any
+/// scale.
+pub fn composited_scale(image: &DynamicImage, logical_size: Size) -> f64 {
+    image.width() as f64 / logical_size.width.max(1) as f64
+}
+
+/// Draw `color` over `pixel`, with its alpha multiplied by `coverage`, the
+/// part of the pixel covered by what is drawn.
+pub(crate) fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, coverage: f64) {
+    let alpha = color.0[3] as f64 / 255.0 * coverage;
+    let below = pixel.0[3] as f64 / 255.0 * (1.0 - alpha);
+    let out = alpha + below;
+    if out <= 0.0 {
+        return;
+    }
+    for channel in 0..3 {
+        pixel.0[channel] = ((color.0[channel] as f64 * alpha + pixel.0[channel] as f64 * below)
+            / out)
+            .round() as u8;
+    }
+    pixel.0[3] = (out * 255.0).round() as u8;
+}
+
+/// Signed distance of `point` to the edge of the box around `center` that
+/// extends `half` to either side, with corners rounded by `radius`. Negative
+/// inside of the box.
+pub(crate) fn rounded_box_distance(
+    point: (f64, f64),
+    center: (f64, f64),
+    half: (f64, f64),
+    radius: f64,
+) -> f64 {
+    let radius = radius.min(half.0).min(half.1).max(0.0);
+    let q = (
+        (point.0 - center.0).abs() - half.0 + radius,
+        (point.1 - center.1).abs() - half.1 + radius,
+    );
+    q.0.max(0.0).hypot(q.1.max(0.0)) + q.0.max(q.1).min(0.0) - radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_of_composited_screenshots() {
+        let image = DynamicImage::new_rgba8(300, 150);
+        let scale = |width, height| composited_scale(&image, Size { width, height });
+        assert_eq!(scale(300, 150), 1.0);
+        assert_eq!(scale(200, 100), 1.5);
+        assert_eq!(scale(150, 75), 2.0);
+        // An empty region doesn't divide by zero.
+        assert_eq!(scale(0, 0), 300.0);
+    }
+
+    #[test]
+    fn panics_while_processing_frames_become_errors() {
+        let result = thread::scope(|scope| {
+            let join_handle = scope.spawn(|| -> Result<()> { panic!("corrupt frame") });
+            join_frame_processing("DP-1".to_string(), join_handle)
+        });
+        assert!(
+            matches!(&result, Err(Error::FrameProcessingPanicked { output }) if output == "DP-1"),
+            "{result:?}"
+        );
+    }
+}
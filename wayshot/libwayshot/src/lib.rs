@@ -2,57 +2,67 @@
 //! that provides a simple API to take screenshots with.
 //!
 //! To get started, look at [`WayshotConnection`].
+#![deny(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::todo,
+    clippy::unimplemented
+)]
 
+#[cfg(feature = "annotate")]
+pub mod annotate;
+#[cfg(feature = "tokio")]
+mod async_connection;
+#[cfg(feature = "calloop")]
+mod calloop_source;
+mod capture;
 mod convert;
+mod countdown;
+pub mod decorate;
+pub mod diff;
 mod dispatch;
+mod dump;
 mod error;
+mod globals;
 mod image_util;
+mod measure;
 pub mod output;
+mod pick;
+pub mod redact;
 pub mod region;
 mod screencopy;
+mod timeout;
 
 use std::{
-    collections::HashSet,
+    collections::VecDeque,
     ffi::c_void,
-    fs::File,
     os::fd::{AsFd, IntoRawFd, OwnedFd},
-    sync::atomic::{AtomicBool, Ordering},
+    path::PathBuf,
+    sync::{Mutex, PoisonError, RwLock},
     thread,
+    time::{Duration, Instant},
 };
-use wayland_client::Proxy;
 
 use dispatch::{DMABUFState, LayerShellState};
-use image::{DynamicImage, imageops::replace};
+use image::DynamicImage;
 use khronos_egl::{self as egl, Instance};
-use memmap2::MmapMut;
 use region::{EmbeddedRegion, RegionCapturer};
-use screencopy::{DMAFrameFormat, DMAFrameGuard, EGLImageGuard, FrameData, FrameGuard};
+use screencopy::{DMAFrameFormat, DMAFrameGuard, EGLImageGuard};
 use tracing::debug;
 use wayland_client::{
-    Connection, EventQueue,
+    Connection, EventQueue, Proxy,
     globals::{GlobalList, registry_queue_init},
-    protocol::{
-        wl_compositor::WlCompositor,
-        wl_output::{Transform, WlOutput},
-        wl_shm::{self, WlShm},
-    },
+    protocol::{wl_output::WlOutput, wl_seat::WlSeat, wl_surface::WlSurface},
 };
 use wayland_client::protocol::wl_shm::Format;
-use wayland_protocols::{
-    wp::{
-        linux_dmabuf::zv1::client::{
-            zwp_linux_buffer_params_v1, zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
-        },
-        viewporter::client::wp_viewporter::WpViewporter,
-    },
-    xdg::xdg_output::zv1::client::{
-        zxdg_output_manager_v1::ZxdgOutputManagerV1, zxdg_output_v1::ZxdgOutputV1,
-    },
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_buffer_params_v1, zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
 };
 use wayland_protocols_wlr::{
     layer_shell::v1::client::{
-        zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
-        zwlr_layer_surface_v1::Anchor,
+        zwlr_layer_shell_v1::Layer,
+        zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity, ZwlrLayerSurfaceV1},
     },
     screencopy::v1::client::{
         zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
@@ -61,31 +71,40 @@ use wayland_protocols_wlr::{
 };
 
 use crate::{
-    convert::create_converter,
-    dispatch::{CaptureFrameState, FrameState, OutputCaptureState, WayshotState},
-    output::OutputInfo,
+    capture::{CaptureBatch, SessionCache},
+    dispatch::{OutputCaptureState, OverlayInput, WayshotState},
+    globals::BoundGlobals,
+    measure::Ruler,
+    output::{OutputInfo, OutputRefresh},
     region::{LogicalRegion, Size},
-    screencopy::{FrameCopy, FrameFormat, create_shm_fd},
 };
 
-pub use crate::error::{Error, Result};
+#[cfg(feature = "tokio")]
+pub use crate::async_connection::AsyncWayshotConnection;
+#[cfg(feature = "calloop")]
+pub use crate::calloop_source::{
+    CaptureId, CaptureRequest, CaptureSender, Captured, WayshotSource,
+};
+pub use crate::{
+    dump::{CaptureDump, DumpedFrame},
+    error::{CapturePhase, Error, Result},
+    image_util::{composite_frames, composited_scale},
+    measure::{Extent, Measurement},
+    pick::{Color, PickedColor},
+    screencopy::{FrameCopy, FrameData, FrameFormat, FrameGuard, presentation_skew},
+    timeout::Timeouts,
+};
 
 pub mod reexport {
     use wayland_client::protocol::wl_output;
     pub use wl_output::{Transform, WlOutput};
 }
 use gbm::{BufferObject, BufferObjectFlags, Device as GBMDevice};
-use wayland_protocols::ext::{
-    image_capture_source::v1::client::{
-        ext_image_capture_source_v1::ExtImageCaptureSourceV1,
-        ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
-    },
-    image_copy_capture::v1::client::{
-        ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1,
-        ext_image_copy_capture_manager_v1::{ExtImageCopyCaptureManagerV1, Options},
-        ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
-    },
-};
+
+/// How often [`WayshotConnection::screenshot_stable`] captures the region,
+/// unless the compositor waits for changes before sending a frame anyway.
+const STABLE_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
 /// Struct to store wayland connection and globals list.
 /// # Example usage
 ///
@@ -94,12 +113,68 @@ use wayland_protocols::ext::{
 /// let wayshot_connection = WayshotConnection::new()?;
 /// let image_buffer = wayshot_connection.screenshot_all()?;
 /// ```
+///
+/// The connection is `Send + Sync`, so it can be shared between threads that
+/// capture different outputs at the same time.
 #[derive(Debug)]
 pub struct WayshotConnection {
     pub conn: Connection,
     pub globals: GlobalList,
-    output_infos: Vec<OutputInfo>,
+    /// The outputs with the names of their `wl_output` globals.
+    output_infos: RwLock<Vec<(u32, OutputInfo)>>,
+    /// Only set on construction.
     dmabuf_state: Option<DMABUFState>,
+    timeouts: Timeouts,
+    bound_globals: BoundGlobals,
+    capture_sessions: Mutex<CachedSessions>,
+    dump_path: Option<PathBuf>,
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<WayshotConnection>();
+};
+
+/// Capture sessions kept for the next capture. Taken out while a capture
+/// uses them, captures running at the same time use sessions of their own.
+#[derive(Debug, Default)]
+struct CachedSessions {
+    /// Bumped whenever the outputs are refreshed, sessions of an older
+    /// generation capture `wl_output` objects that are no longer used.
+    generation: u64,
+    cache: Option<SessionCache>,
+}
+
+/// Which capture sessions a capture uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Sessions {
+    /// New sessions, destroyed afterwards. Compositors may only send another
+    /// frame of a session once the output changed, so one-off screenshots
+    /// can't rely on cached sessions.
+    OneShot,
+    /// New sessions, kept for the following captures.
+    Renew,
+    /// The sessions kept by earlier captures, for capturing repeatedly.
+    Reuse,
+}
+
+/// The frames shown to freeze the screen while the user interacts with it,
+/// see [`WayshotConnection::freeze_for_input`].
+struct FrozenScreen {
+    frames: Vec<(FrameCopy, FrameGuard, OutputInfo)>,
+    /// The frames as the compositor copied them.
+    raw_frames: Vec<DumpedFrame>,
+    /// Overlay surface of every frame.
+    surfaces: Vec<(WlSurface, ZwlrLayerSurfaceV1)>,
+}
+
+impl FrozenScreen {
+    /// Index of the frame shown on `surface`.
+    fn surface_index(&self, surface: &WlSurface) -> Option<usize> {
+        self.surfaces
+            .iter()
+            .position(|(overlay, _)| overlay == surface)
+    }
 }
 
 impl WayshotConnection {
@@ -111,27 +186,64 @@ impl WayshotConnection {
 
     /// Recommended if you already have a [`wayland_client::Connection`].
     pub fn from_connection(conn: Connection) -> Result<Self> {
-        let (globals, _) = registry_queue_init::<WayshotState>(&conn)?;
+        let initial_state = Self::from_connection_without_outputs(conn)?;
+
+        initial_state.refresh_outputs()?;
 
-        let mut initial_state = Self {
+        Ok(initial_state)
+    }
+
+    fn from_connection_without_outputs(conn: Connection) -> Result<Self> {
+        let (globals, event_queue) = registry_queue_init::<WayshotState>(&conn)?;
+
+        Ok(Self {
             conn,
             globals,
-            output_infos: Vec::new(),
+            output_infos: RwLock::default(),
             dmabuf_state: None,
-        };
+            timeouts: Timeouts::default(),
+            bound_globals: BoundGlobals::new(event_queue),
+            capture_sessions: Mutex::default(),
+            dump_path: None,
+        })
+    }
 
-        initial_state.refresh_outputs()?;
+    /// Timeouts used when waiting on the compositor during captures.
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
 
-        Ok(initial_state)
+    /// Bound how long captures wait on the compositor, so that a stalled
+    /// compositor or an output that never produces a frame results in
+    /// `Error::Timeout` instead of blocking forever.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Write everything received for each screenshot to `path`, see
+    /// [`CaptureDump`]. Every screenshot overwrites the previous dump.
+    pub fn set_capture_dump(&mut self, path: Option<PathBuf>) {
+        self.dump_path = path;
     }
 
     /// Fetch all accessible wayland outputs.
-    pub fn get_all_outputs(&self) -> &[OutputInfo] {
-        self.output_infos.as_slice()
+    ///
+    /// Returns a snapshot, another thread may refresh the outputs at any time.
+    pub fn get_all_outputs(&self) -> Vec<OutputInfo> {
+        self.output_infos
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(_, output_info)| output_info.clone())
+            .collect()
     }
 
     /// refresh the outputs, to get new outputs
-    pub fn refresh_outputs(&mut self) -> Result<()> {
+    ///
+    /// Captures already running keep using the outputs they started with.
+    /// Outputs that didn't change keep their `wl_output` objects, so
+    /// refreshing them over and over doesn't cost new capture sessions.
+    pub fn refresh_outputs(&self) -> Result<()> {
         // Connecting to wayland environment.
         let mut state = OutputCaptureState {
             outputs: Vec::new(),
@@ -139,52 +251,186 @@ impl WayshotConnection {
         let mut event_queue = self.conn.new_event_queue::<OutputCaptureState>();
         let qh = event_queue.handle();
 
-        // Bind to xdg_output global.
-        let zxdg_output_manager = match self.globals.bind::<ZxdgOutputManagerV1, _, _>(
-            &qh,
-            3..=3,
-            (),
-        ) {
-            Ok(x) => x,
-            Err(e) => {
-                tracing::error!(
-                    "Failed to create ZxdgOutputManagerV1 version 3. Does your compositor implement ZxdgOutputManagerV1?"
-                );
-                panic!("{:#?}", e);
-            }
-        };
+        // Read the globals that were announced meanwhile.
+        timeout::roundtrip(&self.conn, &mut event_queue, &mut state)?;
+        let mut refresh =
+            OutputRefresh::start(&self.globals, &self.bound_globals, &mut state, &qh)?;
+        timeout::roundtrip(&self.conn, &mut event_queue, &mut state)?;
 
-        // Fetch all outputs; when their names arrive, add them to the list
-        let _ = self.conn.display().get_registry(&qh, ());
-        event_queue.roundtrip(&mut state)?;
+        refresh.request_positions(&state, &qh);
+        timeout::roundtrip(&self.conn, &mut event_queue, &mut state)?;
 
-        // We loop over each output and request its position data.
-        let xdg_outputs: Vec<ZxdgOutputV1> = state
-            .outputs
-            .iter()
-            .enumerate()
-            .map(|(index, output)| {
-                zxdg_output_manager.get_xdg_output(&output.wl_output, &qh, index)
+        let outputs = refresh.finish(state)?;
+        self.set_outputs(outputs);
+
+        Ok(())
+    }
+
+    fn set_outputs(&self, outputs: Vec<(u32, OutputInfo)>) {
+        let mut output_infos = self
+            .output_infos
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Keep the objects of outputs that didn't change and release the ones
+        // bound again. The objects of changed outputs are kept alive, as the
+        // caller may still hold them.
+        let outputs: Vec<_> = outputs
+            .into_iter()
+            .map(|(global, output_info)| {
+                let current = output_infos.iter().find(|(current_global, current)| {
+                    *current_global == global && output::unchanged(current, &output_info)
+                });
+                match current {
+                    Some(current) => {
+                        output_info.wl_output.release();
+                        current.clone()
+                    }
+                    None => (global, output_info),
+                }
             })
             .collect();
+        let changed = outputs.len() != output_infos.len()
+            || outputs
+                .iter()
+                .zip(output_infos.iter())
+                .any(|((_, output_info), (_, current))| output_info.wl_output != current.wl_output);
+        *output_infos = outputs;
+        drop(output_infos);
+        if !changed {
+            return;
+        }
+
+        // Sessions capture the previous wl_output objects.
+        let mut cached = self
+            .capture_sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        cached.generation += 1;
+        cached.cache = None;
+    }
+
+    /// Take a screenshot of the given region, which may span several outputs.
+    pub fn screenshot(
+        &self,
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_region_capturer(RegionCapturer::Region(capture_region), cursor_overlay)
+    }
+
+    /// Take a screenshot of the given region once it stopped changing, e.g.
+    /// to wait for animations to finish.
+    ///
+    /// The region is captured over and over until neither the damage reported
+    /// by the compositor nor the pixels changed for `stable_for`. Compositors
+    /// that only send a frame once something changed count as stable when no
+    /// frame arrives in time. After `max_wait` the latest frame is returned
+    /// even if the region is still changing.
+    pub fn screenshot_stable(
+        &self,
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+        stable_for: Duration,
+        max_wait: Duration,
+    ) -> Result<DynamicImage> {
+        let targets = self.capture_targets(&RegionCapturer::Region(capture_region));
+        let composite = |frames: Vec<(FrameCopy, FrameGuard, OutputInfo)>| {
+            let frames = frames
+                .into_iter()
+                .map(|(frame_copy, _, output_info)| (frame_copy, output_info))
+                .collect();
+            image_util::composite_frames(frames, capture_region)
+        };
+
+        let start = Instant::now();
+        let mut latest = composite(self.capture_frames(
+            &targets,
+            cursor_overlay,
+            None,
+            self.timeouts,
+            Sessions::Renew,
+        )?)?;
+        let mut last_change = start;
+
+        loop {
+            let deadline = (last_change + stable_for).min(start + max_wait);
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            thread::sleep(STABLE_POLL_INTERVAL.min(deadline - now));
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
 
-        event_queue.roundtrip(&mut state)?;
+            // Don't wait for a frame past the deadline.
+            let mut timeouts = self.timeouts;
+            let deadline_first = timeouts.copy.is_none_or(|copy| left < copy);
+            if deadline_first {
+                timeouts.copy = Some(left);
+            }
+            let frames = match self.capture_frames(
+                &targets,
+                cursor_overlay,
+                None,
+                timeouts,
+                Sessions::Reuse,
+            ) {
+                Ok(frames) => frames,
+                Err(Error::Timeout {
+                    phase: CapturePhase::Copy,
+                    ..
+                }) if deadline_first => break,
+                Err(e) => return Err(e),
+            };
 
-        for xdg_output in xdg_outputs {
-            xdg_output.destroy();
+            // Only bother compositing frames with damage in the region, if
+            // the compositor reports damage at all.
+            let damaged = frames.iter().any(|(frame_copy, _, _)| {
+                frame_copy.damage.as_ref().is_none_or(|damage| {
+                    damage
+                        .iter()
+                        .any(|damage| damage.intersection(&capture_region).is_some())
+                })
+            });
+            if damaged {
+                let image = composite(frames)?;
+                if image != latest {
+                    latest = image;
+                    last_change = Instant::now();
+                }
+            }
         }
 
-        if state.outputs.is_empty() {
-            tracing::error!("Compositor did not advertise any wl_output devices!");
-            return Err(Error::NoOutputs);
+        if last_change.elapsed() < stable_for {
+            tracing::warn!("{capture_region} is still changing after {max_wait:?}");
         }
-        tracing::trace!("Outputs detected: {:#?}", state.outputs);
-        self.output_infos = state.outputs;
+        Ok(latest)
+    }
 
-        Ok(())
+    /// Freeze the screen by showing the captured frames on top of all outputs,
+    /// then call `callback` to select the region to return. The callback
+    /// usually lets the user select a region, e.g. using slurp.
+    pub fn screenshot_freeze(
+        &self,
+        callback: Box<dyn Fn() -> Result<LogicalRegion>>,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_region_capturer(RegionCapturer::Freeze(callback), cursor_overlay)
     }
+
+    /// Take a screenshot of a single output.
+    pub fn screenshot_single_output(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_outputs(std::slice::from_ref(output_info), cursor_overlay)
+    }
+
     pub fn screenshot_all(&self, cursor_overlay: bool) -> Result<DynamicImage> {
-        self.screenshot_outputs(self.get_all_outputs(), cursor_overlay)
+        self.screenshot_outputs(&self.get_all_outputs(), cursor_overlay)
     }
     pub fn screenshot_outputs(
         &self,
@@ -197,53 +443,20 @@ impl WayshotConnection {
 
         self.screenshot_region_capturer(RegionCapturer::Outputs(outputs.to_owned()), cursor_overlay)
     }
-    #[tracing::instrument(skip_all, fields(max_scale = tracing::field::Empty))]
     fn screenshot_region_capturer(
         &self,
         region_capturer: RegionCapturer,
         cursor_overlay: bool,
     ) -> Result<DynamicImage> {
-        let outputs_capture_regions: Vec<(OutputInfo, Option<EmbeddedRegion>)> =
-            match region_capturer {
-                RegionCapturer::Outputs(ref outputs) => outputs
-                    .iter()
-                    .map(|output_info| (output_info.clone(), None))
-                    .collect(),
-                RegionCapturer::Region(capture_region) => self
-                    .get_all_outputs()
-                    .iter()
-                    .filter_map(|output_info| {
-                        tracing::span!(
-                            tracing::Level::DEBUG,
-                            "filter_map",
-                            output = format!(
-                                "{output_info} at {region}",
-                                output_info = format!("{output_info}"),
-                                region = LogicalRegion::from(output_info),
-                            ),
-                            capture_region = format!("{}", capture_region),
-                        )
-                        .in_scope(|| {
-                            if let Some(relative_region) =
-                                EmbeddedRegion::new(capture_region, output_info.into())
-                            {
-                                tracing::debug!("Intersection found: {}", relative_region);
-                                Some((output_info.clone(), Some(relative_region)))
-                            } else {
-                                tracing::debug!("No intersection found");
-                                None
-                            }
-                        })
-                    })
-                    .collect(),
-                RegionCapturer::Freeze(_) => self
-                    .get_all_outputs()
-                    .iter()
-                    .map(|output_info| (output_info.clone(), None))
-                    .collect(),
-            };
-
-        let frames = self.capture_frame_copies(&outputs_capture_regions, cursor_overlay)?;
+        let outputs_capture_regions = self.capture_targets(&region_capturer);
+        let mut dump = self.dump_path.as_ref().map(|_| Vec::new());
+        let frames = self.capture_frames(
+            &outputs_capture_regions,
+            cursor_overlay,
+            dump.as_mut(),
+            self.timeouts,
+            Sessions::OneShot,
+        )?;
 
         let capture_region: LogicalRegion = match region_capturer {
             RegionCapturer::Outputs(outputs) => outputs.as_slice().try_into()?,
@@ -256,117 +469,102 @@ impl WayshotConnection {
         // TODO When freeze was used, we can still further remove the outputs
         // that don't intersect with the capture region.
 
-        thread::scope(|scope| {
-            let max_scale = outputs_capture_regions
-                .iter()
-                .map(|(output_info, _)| output_info.scale())
-                .fold(1.0, f64::max);
+        if let (Some(path), Some(frames)) = (&self.dump_path, dump) {
+            CaptureDump {
+                capture_region,
+                frames,
+            }
+            .save(path)?;
+            tracing::debug!("Wrote capture dump to {}", path.display());
+        }
 
-            tracing::Span::current().record("max_scale", max_scale);
+        let frames = frames
+            .into_iter()
+            .map(|(frame_copy, _, output_info)| (frame_copy, output_info))
+            .collect();
+        image_util::composite_frames(frames, capture_region)
+    }
 
-            let rotate_join_handles = frames
-                .into_iter()
-                .map(|(frame_copy, _, _)| {
-                    scope.spawn(move || {
-                        let image = (&frame_copy).try_into()?;
-                        Ok((
-                            image_util::rotate_image_buffer(
-                                image,
-                                frame_copy.transform,
-                                frame_copy.logical_region.inner.size,
-                                max_scale,
-                            ),
-                            frame_copy,
-                        ))
+    /// Outputs to capture for `region_capturer`, together with the part of
+    /// each output to keep.
+    fn capture_targets(
+        &self,
+        region_capturer: &RegionCapturer,
+    ) -> Vec<(OutputInfo, Option<EmbeddedRegion>)> {
+        match region_capturer {
+            RegionCapturer::Outputs(outputs) => outputs
+                .iter()
+                .map(|output_info| (output_info.clone(), None))
+                .collect(),
+            RegionCapturer::Region(capture_region) => self
+                .get_all_outputs()
+                .iter()
+                .filter_map(|output_info| {
+                    tracing::span!(
+                        tracing::Level::DEBUG,
+                        "filter_map",
+                        output = format!(
+                            "{output_info} at {region}",
+                            output_info = format!("{output_info}"),
+                            region = LogicalRegion::from(output_info),
+                        ),
+                        capture_region = format!("{}", capture_region),
+                    )
+                    .in_scope(|| {
+                        if let Some(relative_region) =
+                            EmbeddedRegion::new(*capture_region, output_info.into())
+                        {
+                            tracing::debug!("Intersection found: {}", relative_region);
+                            Some((output_info.clone(), Some(relative_region)))
+                        } else {
+                            tracing::debug!("No intersection found");
+                            None
+                        }
                     })
                 })
-                .collect::<Vec<_>>();
-
-            rotate_join_handles
+                .collect(),
+            RegionCapturer::Freeze(_) => self
+                .get_all_outputs()
                 .into_iter()
-                .flat_map(|join_handle| join_handle.join())
-                .fold(
-                    None,
-                    |composite_image: Option<Result<_>>, image: Result<_>| {
-                        // Default to a transparent image.
-                        let composite_image = composite_image.unwrap_or_else(|| {
-                            Ok(DynamicImage::new_rgba8(
-                                (capture_region.inner.size.width as f64 * max_scale) as u32,
-                                (capture_region.inner.size.height as f64 * max_scale) as u32,
-                            ))
-                        });
-
-                        Some(|| -> Result<_> {
-                            let mut composite_image = composite_image?;
-                            let (image, frame_copy) = image?;
-                            let (x, y) = (
-                                ((frame_copy.logical_region.inner.position.x as f64
-                                    - capture_region.inner.position.x as f64)
-                                    * max_scale) as i64,
-                                ((frame_copy.logical_region.inner.position.y as f64
-                                    - capture_region.inner.position.y as f64)
-                                    * max_scale) as i64,
-                            );
-                            tracing::span!(
-                                tracing::Level::DEBUG,
-                                "replace",
-                                frame_copy_region = format!("{}", frame_copy.logical_region),
-                                capture_region = format!("{}", capture_region),
-                                x = x,
-                                y = y,
-                            )
-                            .in_scope(|| {
-                                tracing::debug!("Replacing parts of the final image");
-                                replace(&mut composite_image, &image, x, y);
-                            });
-
-                            Ok(composite_image)
-                        }())
-                    },
-                )
-                .ok_or_else(|| {
-                    tracing::error!("Provided capture region doesn't intersect with any outputs!");
-                    Error::NoOutputs
-                })?
-        })
+                .map(|output_info| (output_info, None))
+                .collect(),
+        }
     }
+
     fn overlay_frames_and_select_region(
         &self,
         frames: &[(FrameCopy, FrameGuard, OutputInfo)],
         callback: Box<dyn Fn() -> Result<LogicalRegion, Error>>,
     ) -> Result<LogicalRegion> {
-        let mut state = LayerShellState {
-            configured_outputs: HashSet::new(),
-        };
+        let mut state = LayerShellState::default();
         let mut event_queue: EventQueue<LayerShellState> =
             self.conn.new_event_queue::<LayerShellState>();
+
+        let layer_shell_surfaces = self.show_frames(
+            frames,
+            &mut event_queue,
+            &mut state,
+            KeyboardInteractivity::None,
+        )?;
+        let callback_result = callback();
+        self.hide_frames(&layer_shell_surfaces, &mut event_queue, &mut state)?;
+
+        callback_result
+    }
+
+    /// Show every frame on top of its output, to freeze the screen.
+    fn show_frames(
+        &self,
+        frames: &[(FrameCopy, FrameGuard, OutputInfo)],
+        event_queue: &mut EventQueue<LayerShellState>,
+        state: &mut LayerShellState,
+        keyboard_interactivity: KeyboardInteractivity,
+    ) -> Result<Vec<(WlSurface, ZwlrLayerSurfaceV1)>> {
         let qh = event_queue.handle();
 
-        let compositor = match self.globals.bind::<WlCompositor, _, _>(&qh, 3..=3, ()) {
-            Ok(x) => x,
-            Err(e) => {
-                tracing::error!(
-                    "Failed to create compositor. Does your compositor implement WlCompositor?"
-                );
-                tracing::error!("err: {e}");
-                return Err(Error::ProtocolNotFound(
-                    "WlCompositor not found".to_string(),
-                ));
-            }
-        };
-        let layer_shell = match self.globals.bind::<ZwlrLayerShellV1, _, _>(&qh, 1..=1, ()) {
-            Ok(x) => x,
-            Err(e) => {
-                tracing::error!(
-                    "Failed to create layer shell. Does your compositor implement WlrLayerShellV1?"
-                );
-                tracing::error!("err: {e}");
-                return Err(Error::ProtocolNotFound(
-                    "WlrLayerShellV1 not found".to_string(),
-                ));
-            }
-        };
-        let viewporter = self.globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()).ok();
+        let compositor = self.bound_globals.compositor(&self.globals)?;
+        let layer_shell = self.bound_globals.layer_shell(&self.globals)?;
+        let viewporter = self.bound_globals.viewporter(&self.globals);
         if viewporter.is_none() {
             tracing::info!(
                 "Compositor does not support wp_viewporter, display scaling may be inaccurate."
@@ -394,6 +592,7 @@ impl WayshotConnection {
                 );
 
                 layer_surface.set_exclusive_zone(-1);
+                layer_surface.set_keyboard_interactivity(keyboard_interactivity);
                 layer_surface.set_anchor(Anchor::Top | Anchor::Left);
                 layer_surface.set_size(
                     frame_copy.frame_format.size.width,
@@ -404,9 +603,16 @@ impl WayshotConnection {
                 surface.commit();
 
                 debug!("Waiting for layer surface to be configured.");
-                while !state.configured_outputs.contains(&output_info.wl_output) {
-                    event_queue.blocking_dispatch(&mut state)?;
-                }
+                timeout::dispatch_until(
+                    event_queue,
+                    state,
+                    self.timeouts.configure,
+                    CapturePhase::Configure,
+                    |state| {
+                        (!state.configured_outputs.contains(&output_info.wl_output))
+                            .then(|| output_info.name.clone())
+                    },
+                )?;
 
                 surface.set_buffer_transform(output_info.transform);
                 // surface.set_buffer_scale(output_info.scale());
@@ -423,208 +629,678 @@ impl WayshotConnection {
                 debug!("Committing surface with attached buffer.");
                 surface.commit();
                 layer_shell_surfaces.push((surface, layer_surface));
-                event_queue.blocking_dispatch(&mut state)?;
+                timeout::roundtrip(&self.conn, event_queue, state)?;
 
                 Ok(())
             })?;
         }
 
-        let callback_result = callback();
+        Ok(layer_shell_surfaces)
+    }
 
+    /// Remove the surfaces shown by [`Self::show_frames`].
+    fn hide_frames(
+        &self,
+        layer_shell_surfaces: &[(WlSurface, ZwlrLayerSurfaceV1)],
+        event_queue: &mut EventQueue<LayerShellState>,
+        state: &mut LayerShellState,
+    ) -> Result<()> {
         debug!("Unmapping and destroying layer shell surfaces.");
         for (surface, layer_shell_surface) in layer_shell_surfaces.iter() {
             surface.attach(None, 0, 0);
             surface.commit(); 
             layer_shell_surface.destroy();
         }
-        event_queue.roundtrip(&mut state)?;
+        timeout::roundtrip(&self.conn, event_queue, state)
+    }
 
-        callback_result
+    /// Freeze the screen like [`Self::screenshot_freeze`] and let the user
+    /// click the pixel to pick the color of, showing a loupe with the pixels
+    /// around the pointer. Returns `None` if the user cancelled with Escape or
+    /// the right mouse button.
+    ///
+    /// The color is read from the frames as the compositor copied them, so
+    /// it keeps the precision of frames with more than 8 bits per channel.
+    pub fn pick_color(&self) -> Result<Option<PickedColor>> {
+        self.freeze_for_input(|screen, event_queue, state| {
+            self.pick_on_overlay(screen, event_queue, state)
+        })
     }
-    pub fn capture_frame_copies(
+
+    /// Freeze the screen like [`Self::screenshot_freeze`] and let the user
+    /// drag between two points to measure the distance between them, showing
+    /// the width, height and distance while dragging. Returns `None` if the
+    /// user cancelled with Escape or the right mouse button.
+    ///
+    /// Physical pixels are those of the output under the pointer when the
+    /// user lets go.
+    pub fn measure(&self) -> Result<Option<Measurement>> {
+        self.freeze_for_input(|screen, event_queue, state| {
+            self.measure_on_overlay(screen, event_queue, state)
+        })
+    }
+
+    /// Freeze the screen with frames in the format the compositor copied
+    /// them in and call `interact`, which receives the input of a seat on the
+    /// overlay surfaces.
+    fn freeze_for_input<T>(
         &self,
-        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
-        cursor_overlay: bool,
-    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
-        output_capture_regions
-            .iter()
-            .map(|(output_info, capture_region)| {
-                self.capture_frame_copy(cursor_overlay, output_info, *capture_region)
-                    .map(|(frame_copy, frame_guard)| (frame_copy, frame_guard, output_info.clone()))
+        interact: impl FnOnce(
+            &FrozenScreen,
+            &mut EventQueue<LayerShellState>,
+            &mut LayerShellState,
+        ) -> Result<T>,
+    ) -> Result<T> {
+        let targets: Vec<_> = self
+            .get_all_outputs()
+            .into_iter()
+            .map(|output_info| (output_info, None))
+            .collect();
+        let mut raw_frames = Vec::new();
+        let mut frames = self.capture_frames(
+            &targets,
+            false,
+            Some(&mut raw_frames),
+            self.timeouts,
+            Sessions::OneShot,
+        )?;
+        // The overlay shows the buffers in the format they were copied in, so
+        // undo the conversion.
+        for ((frame_copy, _, _), raw_frame) in frames.iter_mut().zip(&raw_frames) {
+            if let FrameData::Mmap(frame_mmap) = &mut frame_copy.frame_data {
+                frame_mmap.copy_from_slice(&raw_frame.data);
+            }
+        }
+
+        let mut state = LayerShellState::default();
+        let mut event_queue: EventQueue<LayerShellState> =
+            self.conn.new_event_queue::<LayerShellState>();
+        let surfaces = self.show_frames(
+            &frames,
+            &mut event_queue,
+            &mut state,
+            KeyboardInteractivity::Exclusive,
+        )?;
+        let screen = FrozenScreen {
+            frames,
+            raw_frames,
+            surfaces,
+        };
+
+        // Seat events are only of interest while the screen is frozen, so it
+        // is bound on this queue instead of with the other globals.
+        let result = self
+            .globals
+            .bind::<WlSeat, _, _>(&event_queue.handle(), 1..=5, ())
+            .map_err(|source| Error::ProtocolNotFound {
+                protocol: "wl_seat",
+                source,
             })
-            .collect()    }
-    fn capture_frame_copy(
+            .and_then(|seat| {
+                let result = interact(&screen, &mut event_queue, &mut state);
+                if let Some(pointer) = state
+                    .pointer
+                    .take()
+                    .filter(|pointer| pointer.version() >= 3)
+                {
+                    pointer.release();
+                }
+                if let Some(keyboard) = state
+                    .keyboard
+                    .take()
+                    .filter(|keyboard| keyboard.version() >= 3)
+                {
+                    keyboard.release();
+                }
+                if seat.version() >= 5 {
+                    seat.release();
+                }
+                result
+            });
+        self.hide_frames(&screen.surfaces, &mut event_queue, &mut state)?;
+
+        result
+    }
+
+    /// Let the user click a pixel on the frozen screen, see
+    /// [`Self::pick_color`].
+    fn pick_on_overlay(
         &self,
-        cursor_overlay: bool,
-        output_info: &OutputInfo,
-        capture_region: Option<EmbeddedRegion>,
-    ) -> Result<(FrameCopy, FrameGuard)> {
-        // Create an in memory file and return it's file descriptor.
-        let fd = create_shm_fd()?;
-        // Create a writeable memory map backed by a mem_file.
-        let mem_file = File::from(fd);
-
-        let mut state = CaptureFrameState {
-            formats: Vec::new(),
-            dmabuf_formats: Vec::new(),
-            state: None,
-            buffer_done: AtomicBool::new(false),
+        screen: &FrozenScreen,
+        event_queue: &mut EventQueue<LayerShellState>,
+        state: &mut LayerShellState,
+    ) -> Result<Option<PickedColor>> {
+        let FrozenScreen {
+            frames, raw_frames, ..
+        } = screen;
+        let qh = event_queue.handle();
+        let compositor = self.bound_globals.compositor(&self.globals)?;
+        let shm = self.bound_globals.shm(&self.globals)?;
+        // Without a viewport the surfaces are as large as the frames, in
+        // physical pixels.
+        let viewported = self.bound_globals.viewporter(&self.globals).is_some();
+
+        // The loupe replaces the cursor, so it follows the pointer without
+        // lagging behind.
+        let cursor = compositor.create_surface(&qh, ());
+        let mut loupe_buffers = VecDeque::new();
+
+        // Index of the frame under the pointer and the position on its
+        // surface.
+        let mut hovered: Option<(usize, (f64, f64))> = None;
+        // Transformed physical pixel of the frame at a position on its surface.
+        let pixel = |index: usize, (x, y): (f64, f64)| {
+            let scale = if viewported {
+                frames[index].2.scale()
+            } else {
+                1.0
+            };
+            ((x * scale).floor() as i64, (y * scale).floor() as i64)
         };
-        
-        let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+
+        let picked = 'picking: loop {
+            timeout::dispatch_while_pending(event_queue, state, |state| state.input.is_empty())?;
+
+            let mut moved = false;
+            for input in std::mem::take(&mut state.input) {
+                match input {
+                    OverlayInput::Enter {
+                        serial,
+                        surface,
+                        position,
+                    } => {
+                        hovered = screen
+                            .surface_index(&surface)
+                            .map(|index| (index, position));
+                        if let Some(pointer) = &state.pointer {
+                            let hotspot = pick::LOUPE_SIZE as i32 / 2;
+                            pointer.set_cursor(serial, Some(&cursor), hotspot, hotspot);
+                        }
+                        moved = true;
+                    }
+                    OverlayInput::Motion { position } => {
+                        if let Some((_, hovered_position)) = &mut hovered {
+                            *hovered_position = position;
+                            moved = true;
+                        }
+                    }
+                    OverlayInput::Leave => hovered = None,
+                    OverlayInput::Press => {
+                        let Some((index, position)) = hovered else {
+                            continue;
+                        };
+                        let (x, y) = pixel(index, position);
+                        let Some(color) = pick::read_pixel(&raw_frames[index], (x, y)) else {
+                            continue;
+                        };
+                        let output_info = &frames[index].2;
+                        let scale = output_info.scale();
+                        let origin = output_info.logical_region.inner.position;
+                        break 'picking Some(PickedColor {
+                            color,
+                            output: output_info.name.clone(),
+                            position: region::Position {
+                                x: origin.x + (x as f64 / scale).floor() as i32,
+                                y: origin.y + (y as f64 / scale).floor() as i32,
+                            },
+                        });
+                    }
+                    OverlayInput::Release => {}
+                    OverlayInput::Cancel => break 'picking None,
+                }
+            }
+
+            if let (true, Some((index, position))) = (moved, hovered) {
+                let scale = frames[index].2.scale().ceil().max(1.0) as u32;
+                let size = pick::LOUPE_SIZE * scale;
+                let buffer = screencopy::create_argb_buffer(
+                    &shm,
+                    &pick::draw_loupe(&raw_frames[index], pixel(index, position), size),
+                    Size {
+                        width: size,
+                        height: size,
+                    },
+                    &qh,
+                )?;
+                cursor.set_buffer_scale(scale as i32);
+                cursor.attach(Some(&buffer.buffer), 0, 0);
+                cursor.damage(0, 0, i32::MAX, i32::MAX);
+                cursor.commit();
+                // The previous buffer may still be shown until the new one is.
+                loupe_buffers.push_back(buffer);
+                if loupe_buffers.len() > 2 {
+                    loupe_buffers.pop_front();
+                }
+            }
+        };
+
+        cursor.destroy();
+        Ok(picked)
+    }
+
+    /// Let the user drag between two points on the frozen screen, see
+    /// [`Self::measure`].
+    fn measure_on_overlay(
+        &self,
+        screen: &FrozenScreen,
+        event_queue: &mut EventQueue<LayerShellState>,
+        state: &mut LayerShellState,
+    ) -> Result<Option<Measurement>> {
         let qh = event_queue.handle();
+        let compositor = self.bound_globals.compositor(&self.globals)?;
+        let shm = self.bound_globals.shm(&self.globals)?;
+        // The ruler is left out without subsurfaces, the measurement still
+        // works.
+        let subcompositor = self.bound_globals.subcompositor(&self.globals);
+        if subcompositor.is_none() {
+            tracing::info!("Compositor does not support wl_subcompositor, no ruler is shown.");
+        }
 
-        let output_capture_source_manager = match self.globals.bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(
-            &qh,
-            1..=ExtOutputImageCaptureSourceManagerV1::interface().version,
-            (),
-        ) {
-            Ok(manager) => manager,
-            Err(e) => {
-                tracing::error!("Failed to bind ExtOutputImageCaptureSourceManagerV1: {:?}", e);
-                return Err(Error::ProtocolNotFound(
-                    "ExtOutputImageCaptureSourceManagerV1 not found".to_string(),
-                ));
+        let output_info = |index: usize| &screen.frames[index].2;
+        // Logical position of the pixel corner closest to a position on the
+        // surface of a frame.
+        let corner = |index: usize, (x, y): (f64, f64)| {
+            let origin = output_info(index).logical_region.inner.position;
+            region::Position {
+                x: origin.x + x.round() as i32,
+                y: origin.y + y.round() as i32,
             }
         };
-        let capture_src = output_capture_source_manager.create_source(&output_info.wl_output, &qh, ());
 
-        let screencopy_manager = match self.globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(
-            &qh,
-            1..=ExtImageCopyCaptureManagerV1::interface().version,
-            (),
-        ) {
-            Ok(x) => x,
-            Err(e) => {
-                tracing::error!("Failed to bind ExtImageCopyCaptureManagerV1: {:?}", e);
-                return Err(Error::ProtocolNotFound(
-                    "ExtImageCopyCaptureManagerV1 not found".to_string(),
-                ));
+        // Index of the frame under the pointer and the position on its
+        // surface.
+        let mut hovered: Option<(usize, (f64, f64))> = None;
+        // Index of the frame the drag started on, where it started and the
+        // ruler shown on it.
+        let mut dragging: Option<(usize, region::Position, Option<Ruler>)> = None;
+
+        let measured = 'measuring: loop {
+            timeout::dispatch_while_pending(event_queue, state, |state| state.input.is_empty())?;
+
+            let mut moved = false;
+            for input in std::mem::take(&mut state.input) {
+                match input {
+                    OverlayInput::Enter {
+                        surface, position, ..
+                    } => {
+                        hovered = screen
+                            .surface_index(&surface)
+                            .map(|index| (index, position));
+                        moved = true;
+                    }
+                    OverlayInput::Motion { position } => {
+                        if let Some((_, hovered_position)) = &mut hovered {
+                            *hovered_position = position;
+                            moved = true;
+                        }
+                    }
+                    OverlayInput::Leave => hovered = None,
+                    OverlayInput::Press => {
+                        if let Some((index, position)) = hovered {
+                            let ruler = subcompositor.as_ref().map(|subcompositor| {
+                                Ruler::new(
+                                    &compositor,
+                                    subcompositor,
+                                    &shm,
+                                    &screen.surfaces[index].0,
+                                    &qh,
+                                )
+                            });
+                            dragging = Some((index, corner(index, position), ruler));
+                            moved = true;
+                        }
+                    }
+                    OverlayInput::Release => {
+                        let Some((_, start, ruler)) = dragging.take() else {
+                            continue;
+                        };
+                        if let Some(ruler) = ruler {
+                            ruler.destroy();
+                        }
+                        // Letting go outside of the outputs starts over.
+                        if let Some((index, position)) = hovered {
+                            break 'measuring Some(Measurement::new(
+                                start,
+                                corner(index, position),
+                                output_info(index),
+                            ));
+                        }
+                    }
+                    OverlayInput::Cancel => break 'measuring None,
+                }
+            }
+
+            if let (true, Some((index, position)), Some((drag_index, start, Some(ruler)))) =
+                (moved, hovered, &mut dragging)
+            {
+                let end = corner(index, position);
+                let drag_output = output_info(*drag_index);
+                let origin = drag_output.logical_region.inner.position;
+                let relative = |position: region::Position| {
+                    Some(region::Position {
+                        x: position.x.checked_sub(origin.x)?,
+                        y: position.y.checked_sub(origin.y)?,
+                    })
+                };
+                // Corners too far away to be drawn on the output leave the
+                // ruler as it is.
+                if let (Some(relative_start), Some(relative_end)) =
+                    (relative(*start), relative(end))
+                {
+                    ruler.update(
+                        LogicalRegion::between(relative_start, relative_end),
+                        &Measurement::new(*start, end, output_info(index)),
+                        relative_end,
+                        drag_output.logical_region.inner.size,
+                        drag_output.scale().ceil().max(1.0) as u32,
+                    )?;
+                }
             }
         };
 
-        tracing::debug!("Capturing output(shm buffer)...");
-        let frame_session = screencopy_manager.create_session(&capture_src, Options::empty(), &qh, ());
+        if let Some((_, _, Some(ruler))) = dragging {
+            ruler.destroy();
+        }
+        Ok(measured)
+    }
+
+    /// Show the seconds left until `delay` has passed on every output, e.g.
+    /// to give the user time to open a menu before capturing it. The
+    /// countdown is hidden again before this returns, so it never shows up
+    /// in captures taken afterwards.
+    pub fn show_countdown(&self, delay: Duration) -> Result<()> {
+        let mut state = LayerShellState::default();
+        let mut event_queue: EventQueue<LayerShellState> =
+            self.conn.new_event_queue::<LayerShellState>();
+        let qh = event_queue.handle();
+
+        let compositor = self.bound_globals.compositor(&self.globals)?;
+        let layer_shell = self.bound_globals.layer_shell(&self.globals)?;
+        let shm = self.bound_globals.shm(&self.globals)?;
+
+        let outputs = self.get_all_outputs();
+        let mut layer_shell_surfaces = Vec::with_capacity(outputs.len());
+        for output_info in &outputs {
+            let surface = compositor.create_surface(&qh, ());
+            let layer_surface = layer_shell.get_layer_surface(
+                &surface,
+                Some(&output_info.wl_output),
+                Layer::Overlay,
+                "wayshot-countdown".to_string(),
+                &qh,
+                output_info.wl_output.clone(),
+            );
+            layer_surface.set_exclusive_zone(-1);
+            layer_surface.set_size(countdown::SIZE, countdown::SIZE);
 
+            // Let clicks through to whatever is below the countdown.
+            let input_region = compositor.create_region(&qh, ());
+            surface.set_input_region(Some(&input_region));
+            input_region.destroy();
 
-        // Empty internal event buffer until buffer_done is set to true which is when the Buffer done
-        // event is fired, aka the capture from the compositor is successful.
-        while !state.buffer_done.load(Ordering::SeqCst) {
-            event_queue.blocking_dispatch(&mut state)?;
+            surface.commit();
+            layer_shell_surfaces.push((surface, layer_surface, output_info));
         }
 
-        tracing::trace!(
-            "Received compositor frame buffer formats: {:#?}",
-            state.formats
-        );
-        // Filter advertised wl_shm formats and select the first one that matches.
-        let frame_format = state
-            .formats
-            .iter()
-            .find(|frame| {
-                matches!(
-                    frame.format,
-                    wl_shm::Format::Xbgr2101010
-                        | wl_shm::Format::Abgr2101010
-                        | wl_shm::Format::Argb8888
-                        | wl_shm::Format::Xrgb8888
-                        | wl_shm::Format::Xbgr8888
-                        | wl_shm::Format::Bgr888
-                )
-            })
-            .copied()
-            // Check if frame format exists.
-            .ok_or_else(|| {
-                tracing::error!("No suitable frame format found");
-                Error::NoSupportedBufferFormat
-            })?;
-            let bytes_per_pixel = match frame_format.format {
-                wayland_client::protocol::wl_shm::Format::Argb8888 | wayland_client::protocol::wl_shm::Format::Xrgb8888
-                | wayland_client::protocol::wl_shm::Format::Abgr8888 | wayland_client::protocol::wl_shm::Format::Xbgr8888 => 4,
-                wayland_client::protocol::wl_shm::Format::Rgb565 => 2,
-                _ => {
-                    eprintln!("supported SHM format for buffer creation ");
-                    0
+        // Buffers of the last two seconds, the previous ones may still be
+        // shown until the new ones are.
+        let mut buffers = VecDeque::new();
+        let shown = (|| -> Result<()> {
+            debug!("Waiting for countdown surfaces to be configured.");
+            timeout::dispatch_until(
+                &mut event_queue,
+                &mut state,
+                self.timeouts.configure,
+                CapturePhase::Configure,
+                |state| {
+                    outputs
+                        .iter()
+                        .find(|output_info| {
+                            !state.configured_outputs.contains(&output_info.wl_output)
+                        })
+                        .map(|output_info| output_info.name.clone())
+                },
+            )?;
+
+            let start = Instant::now();
+            loop {
+                let left = delay.saturating_sub(start.elapsed());
+                if left.is_zero() {
+                    return Ok(());
                 }
-            };
-            if let Some(old_format) = state.formats.pop() {
-                let new_format = FrameFormat {
-                    stride: frame_format.size.width * bytes_per_pixel as u32,
-                    ..old_format
-                };
-                state.formats.push(new_format);
-            }
-            
-        tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
-        mem_file.set_len(frame_format.byte_size())?;
-        // proceed to add this loop
-        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        let shm_pool = shm.create_pool(
-            mem_file.as_fd(),
-            frame_format
-                .byte_size()
-                .try_into()
-                .map_err(|_| Error::BufferTooSmall)?,
-            &qh,
-            (),
-        );
-        let buffer = shm_pool.create_buffer(
-            0,
-            frame_format.size.width as i32,
-            frame_format.size.height as i32,
-            frame_format.stride as i32,
-            frame_format.format,
-            &qh,
-            (),
-        );
-        
-        let mut frame: Option<ExtImageCopyCaptureFrameV1>; 
-        let frame_before_capture = frame_session.create_frame(&qh, ());
-        let frame = frame_before_capture.clone();
-        frame.attach_buffer(&buffer);
-        frame.capture();
-
-        let frame_guard = FrameGuard {
-                buffer,
-                shm_pool,
-            };
-        event_queue.blocking_dispatch(&mut state)?;
-
-        let mut frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
-        let data = &mut *frame_mmap;
-        let frame_color_type = match create_converter(frame_format.format) {
-            Some(converter) => converter.convert_inplace(data),
-            _ => {
-                tracing::error!("Unsupported buffer format: {:?}", frame_format.format);
-                tracing::error!(
-                    "You can send a feature request for the above format to the mailing list for wayshot over at https://sr.ht/~shinyzenith/wayshot."
+                let seconds = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+
+                let mut second_buffers = Vec::with_capacity(layer_shell_surfaces.len());
+                for (surface, _, output_info) in &layer_shell_surfaces {
+                    let scale = output_info.scale().ceil().max(1.0) as u32;
+                    let size = countdown::SIZE * scale;
+                    let buffer = screencopy::create_argb_buffer(
+                        &shm,
+                        &countdown::draw(seconds, size),
+                        Size {
+                            width: size,
+                            height: size,
+                        },
+                        &qh,
+                    )?;
+                    surface.set_buffer_scale(scale as i32);
+                    surface.attach(Some(&buffer.buffer), 0, 0);
+                    surface.damage(0, 0, i32::MAX, i32::MAX);
+                    surface.commit();
+                    second_buffers.push(buffer);
+                }
+                buffers.push_back(second_buffers);
+                if buffers.len() > 2 {
+                    buffers.pop_front();
+                }
+                timeout::roundtrip(&self.conn, &mut event_queue, &mut state)?;
+
+                // Until one second less is left.
+                thread::sleep(
+                    delay
+                        .saturating_sub(Duration::from_secs(seconds - 1))
+                        .saturating_sub(start.elapsed()),
                 );
-                return Err(Error::NoSupportedBufferFormat);
             }
-        };
-        let rotated_physical_size = match output_info.transform {
-            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                Size {
-                    width: frame_format.size.height,
-                    height: frame_format.size.width,
-                }
+        })();
+
+        debug!("Unmapping and destroying countdown surfaces.");
+        for (surface, layer_surface, _) in &layer_shell_surfaces {
+            surface.attach(None, 0, 0);
+            surface.commit();
+            layer_surface.destroy();
+            surface.destroy();
+        }
+        if shown.is_ok() {
+            timeout::roundtrip(&self.conn, &mut event_queue, &mut state)?;
+        }
+        drop(buffers);
+        // Also gets rid of the surfaces right away when showing them failed.
+        let flushed = self.conn.flush();
+        shown?;
+        flushed?;
+
+        Ok(())
+    }
+
+    /// Capture a frame of every output in `output_capture_regions`.
+    ///
+    /// All outputs are captured concurrently on one event queue, so their
+    /// frames are copied as close together in time as the compositor allows.
+    ///
+    /// Every call captures with new capture sessions, which get the current
+    /// contents of the outputs right away. Use
+    /// [`Self::capture_frame_copies_until`] for capturing repeatedly.
+    pub fn capture_frame_copies(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        self.capture_frames(
+            output_capture_regions,
+            cursor_overlay,
+            None,
+            self.timeouts,
+            Sessions::OneShot,
+        )
+    }
+
+    /// Like [`Self::capture_frame_copies`], but keeps the capture sessions
+    /// for the next call, which is meant for capturing repeatedly on a
+    /// schedule, e.g. recording.
+    ///
+    /// Compositors may hold back the frames of a reused session until the
+    /// output changed. This stops waiting for the frames at `deadline` if it
+    /// comes before the copy timeout and returns `None`, as a frame held back
+    /// because nothing changed is not an error.
+    pub fn capture_frame_copies_until(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+        deadline: Instant,
+    ) -> Result<Option<Vec<(FrameCopy, FrameGuard, OutputInfo)>>> {
+        let (timeouts, deadline_first) = self.timeouts.until(deadline);
+        match self.capture_frames(
+            output_capture_regions,
+            cursor_overlay,
+            None,
+            timeouts,
+            Sessions::Reuse,
+        ) {
+            Ok(frames) => Ok(Some(frames)),
+            Err(Error::Timeout {
+                phase: CapturePhase::Copy,
+                ..
+            }) if deadline_first => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like [`Self::capture_frame_copies`], copying the received frames into
+    /// `dump` too, giving up after `timeouts` instead of the connection's and
+    /// capturing with the given `sessions`.
+    fn capture_frames(
+        &self,
+        output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
+        cursor_overlay: bool,
+        dump: Option<&mut Vec<DumpedFrame>>,
+        timeouts: Timeouts,
+        sessions: Sessions,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        if output_capture_regions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut cache = self.checkout_capture_sessions(sessions);
+        let (batch, frames) = match CaptureBatch::start(
+            &mut cache,
+            &self.globals,
+            &self.bound_globals,
+            output_capture_regions.to_vec(),
+            cursor_overlay,
+        ) {
+            Ok(mut batch) => {
+                let frames = self.run_capture_batch(&mut cache, &mut batch, dump, timeouts);
+                (Some(batch), frames)
             }
-            _ => frame_format.size,
+            Err(e) => (None, Err(e)),
         };
-        let frame_copy = FrameCopy {
-            frame_format,
-            frame_color_type,
-            frame_data: FrameData::Mmap(frame_mmap),
-            transform: output_info.transform,
-            logical_region: capture_region
-                .map(|capture_region| capture_region.logical())
-                .unwrap_or(output_info.logical_region),
-            physical_size: rotated_physical_size,
+        self.end_capture(cache, batch, &frames, sessions);
+
+        frames
+    }
+
+    /// Clean up after `batch`, if it could be started, finished with
+    /// `result`. Sessions broken by a failure are dropped and the rest are
+    /// checked in for the next capture.
+    pub(crate) fn end_capture<T>(
+        &self,
+        mut cache: SessionCache,
+        batch: Option<CaptureBatch>,
+        result: &Result<T>,
+        sessions: Sessions,
+    ) {
+        if let (Some(batch), Err(e)) = (&batch, result)
+            && !CaptureBatch::keeps_sessions(e)
+        {
+            for id in batch.session_ids() {
+                cache.remove(id);
+            }
+        }
+        drop(batch);
+        if result.is_err() {
+            // Let the compositor know about cancelled frames right away, so
+            // it doesn't spend the next change of their outputs on them. A
+            // broken connection fails the next capture anyway.
+            let _ = self.conn.flush();
+        }
+        self.checkin_capture_sessions(cache, sessions);
+    }
+
+    /// Take the cached capture sessions, or new ones if another capture is
+    /// using them or `sessions` asks for new ones.
+    fn checkout_capture_sessions(&self, sessions: Sessions) -> SessionCache {
+        let mut cached = self
+            .capture_sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let generation = cached.generation;
+        let reused = match sessions {
+            Sessions::OneShot => None,
+            Sessions::Renew => {
+                cached.cache = None;
+                None
+            }
+            Sessions::Reuse => cached.cache.take(),
         };
-        tracing::debug!("Created frame copy: {:#?}", frame_copy);
-        Ok((frame_copy, frame_guard))
+        reused.unwrap_or_else(|| SessionCache::new(&self.conn, generation))
+    }
+
+    /// Keep the capture sessions for the next capture, unless they were only
+    /// for this one, another capture returned its sessions first or the
+    /// outputs were refreshed meanwhile.
+    fn checkin_capture_sessions(&self, cache: SessionCache, sessions: Sessions) {
+        if sessions == Sessions::OneShot {
+            return;
+        }
+        let mut cached = self
+            .capture_sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if cached.cache.is_none() && cache.generation == cached.generation {
+            cached.cache = Some(cache);
+        }
     }
 
+    fn run_capture_batch(
+        &self,
+        cache: &mut SessionCache,
+        batch: &mut CaptureBatch,
+        dump: Option<&mut Vec<DumpedFrame>>,
+        timeouts: Timeouts,
+    ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
+        let qh = cache.event_queue.handle();
 
-}
+        // Wait for every new session to send its buffer constraints.
+        timeout::dispatch_until(
+            &mut cache.event_queue,
+            &mut cache.state,
+            timeouts.negotiation,
+            CapturePhase::Negotiation,
+            |state| batch.pending_negotiation(state),
+        )?;
 
+        batch.capture(&cache.state, &qh)?;
+
+        // Wait for the compositor to either copy every frame or fail doing so.
+        timeout::dispatch_until(
+            &mut cache.event_queue,
+            &mut cache.state,
+            timeouts.copy,
+            CapturePhase::Copy,
+            |state| batch.pending_copy(state),
+        )?;
+
+        batch.finish(&cache.state, dump)
+    }
+}
@@ -0,0 +1,305 @@
+//! Measuring distances on the frozen screen, see
+//! [`crate::WayshotConnection::measure`], and the ruler shown while doing so.
+
+use std::collections::VecDeque;
+
+use wayland_client::{
+    QueueHandle,
+    protocol::{
+        wl_compositor::WlCompositor, wl_shm::WlShm, wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface, wl_surface::WlSurface,
+    },
+};
+
+use crate::{
+    Result,
+    dispatch::LayerShellState,
+    output::OutputInfo,
+    region::{LogicalRegion, Position, Region, Size},
+    screencopy::{self, FrameGuard},
+};
+
+/// Color of the outline of the measured area, as the bytes of a
+/// premultiplied `Argb8888` pixel (blue, green, red, alpha).
+const OUTLINE: [u8; 4] = [255, 200, 0, 255];
+/// Logical pixels per pixel of the font of the label.
+const FONT_SCALE: u32 = 2;
+/// Font pixels from the start of one glyph to the next.
+const GLYPH_ADVANCE: u32 = 6;
+/// Font pixels from the top of one line to the next.
+const LINE_HEIGHT: u32 = 9;
+/// Space around the text of the label, in logical pixels.
+const PADDING: u32 = 8;
+/// Distance of the label from the pointer, in logical pixels.
+const LABEL_OFFSET: i32 = 16;
+
+/// Width, height and diagonal of a measured area.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Extent {
+    pub width: u32,
+    pub height: u32,
+    /// Distance between the corners the user dragged between.
+    pub distance: f64,
+}
+
+/// The distance measured with [`crate::WayshotConnection::measure`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Measurement {
+    /// Name of the output the pointer was on when the user let go.
+    pub output: String,
+    /// Logical position the user started dragging at.
+    pub start: Position,
+    /// Logical position the user let go at.
+    pub end: Position,
+    /// Extent in logical pixels.
+    pub logical: Extent,
+    /// Extent in physical pixels of [`Measurement::output`].
+    pub physical: Extent,
+}
+
+impl From<Size> for Extent {
+    fn from(Size { width, height }: Size) -> Self {
+        Self {
+            width,
+            height,
+            distance: (width as f64).hypot(height as f64),
+        }
+    }
+}
+
+impl Measurement {
+    /// The measurement between the logical positions `start` and `end`, with
+    /// physical pixels of `output`.
+    pub(crate) fn new(start: Position, end: Position, output: &OutputInfo) -> Self {
+        let region = LogicalRegion::between(start, end);
+        Self {
+            output: output.name.clone(),
+            start,
+            end,
+            logical: region.inner.size.into(),
+            physical: region.physical_size(output).into(),
+        }
+    }
+
+    /// Lines of the label of the ruler.
+    fn lines(&self) -> [String; 2] {
+        let line = |name: &str, extent: Extent| {
+            format!(
+                "{name:<8} {} x {}  {:.1}",
+                extent.width, extent.height, extent.distance
+            )
+        };
+        [
+            line("logical", self.logical),
+            line("physical", self.physical),
+        ]
+    }
+}
+
+/// Outline of the measured area and a label with its extent, shown on
+/// subsurfaces of the overlay surface the user started dragging on.
+pub(crate) struct Ruler {
+    shm: WlShm,
+    qh: QueueHandle<LayerShellState>,
+    parent: WlSurface,
+    /// Top, bottom, left and right edge of the outline, then the label.
+    parts: Vec<(WlSurface, WlSubsurface)>,
+    /// Buffers of the last two updates, the previous ones may still be shown
+    /// until the new ones are.
+    buffers: VecDeque<Vec<FrameGuard>>,
+}
+
+impl Ruler {
+    pub(crate) fn new(
+        compositor: &WlCompositor,
+        subcompositor: &WlSubcompositor,
+        shm: &WlShm,
+        parent: &WlSurface,
+        qh: &QueueHandle<LayerShellState>,
+    ) -> Self {
+        let parts = (0..5)
+            .map(|_| {
+                let surface = compositor.create_surface(qh, ());
+                let subsurface = subcompositor.get_subsurface(&surface, parent, qh, ());
+                (surface, subsurface)
+            })
+            .collect();
+        Self {
+            shm: shm.clone(),
+            qh: qh.clone(),
+            parent: parent.clone(),
+            parts,
+            buffers: VecDeque::new(),
+        }
+    }
+
+    /// Outline `area` and show the label of `measurement` next to `pointer`,
+    /// both in logical coordinates of the parent surface, which is `bounds`
+    /// large. Buffers are drawn with the given `scale`.
+    pub(crate) fn update(
+        &mut self,
+        area: LogicalRegion,
+        measurement: &Measurement,
+        pointer: Position,
+        bounds: Size,
+        scale: u32,
+    ) -> Result<()> {
+        let Region { position, size } = area.inner;
+        let (right, bottom) = (
+            position.x + size.width as i32,
+            position.y + size.height as i32,
+        );
+        let mut rectangles = vec![
+            (position.x - 1, position.y - 1, size.width + 2, 1),
+            (position.x - 1, bottom, size.width + 2, 1),
+            (position.x - 1, position.y, 1, size.height),
+            (right, position.y, 1, size.height),
+        ]
+        .into_iter()
+        .map(|(x, y, width, height)| {
+            let pixels = OUTLINE.repeat((width * height * scale * scale) as usize);
+            (Position { x, y }, Size { width, height }, pixels)
+        })
+        .collect::<Vec<_>>();
+
+        let lines = measurement.lines();
+        let label = label_size(&lines);
+        let place = |pointer: i32, length: u32, bound: u32| {
+            if pointer + LABEL_OFFSET + length as i32 <= bound as i32 {
+                pointer + LABEL_OFFSET
+            } else {
+                (pointer - LABEL_OFFSET - length as i32).max(0)
+            }
+        };
+        rectangles.push((
+            Position {
+                x: place(pointer.x, label.width, bounds.width),
+                y: place(pointer.y, label.height, bounds.height),
+            },
+            label,
+            draw_label(&lines, label, scale),
+        ));
+
+        let mut buffers = Vec::with_capacity(rectangles.len());
+        for ((surface, subsurface), (position, size, pixels)) in self.parts.iter().zip(rectangles) {
+            subsurface.set_position(position.x, position.y);
+            if size.width == 0 || size.height == 0 {
+                surface.attach(None, 0, 0);
+            } else {
+                let buffer = screencopy::create_argb_buffer(
+                    &self.shm,
+                    &pixels,
+                    Size {
+                        width: size.width * scale,
+                        height: size.height * scale,
+                    },
+                    &self.qh,
+                )?;
+                surface.set_buffer_scale(scale as i32);
+                surface.attach(Some(&buffer.buffer), 0, 0);
+                surface.damage(0, 0, i32::MAX, i32::MAX);
+                buffers.push(buffer);
+            }
+            surface.commit();
+        }
+        // Subsurfaces are synchronized, so all parts change at once when the
+        // parent is committed.
+        self.parent.commit();
+
+        self.buffers.push_back(buffers);
+        if self.buffers.len() > 2 {
+            self.buffers.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Remove the ruler from the parent surface.
+    pub(crate) fn destroy(self) {
+        for (surface, subsurface) in &self.parts {
+            subsurface.destroy();
+            surface.destroy();
+        }
+        self.parent.commit();
+    }
+}
+
+/// Logical size of the label showing `lines`.
+fn label_size(lines: &[String]) -> Size {
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as u32;
+    Size {
+        width: (columns * GLYPH_ADVANCE - 1) * FONT_SCALE + 2 * PADDING,
+        height: (lines.len() as u32 * LINE_HEIGHT - 2) * FONT_SCALE + 2 * PADDING,
+    }
+}
+
+/// Premultiplied `Argb8888` pixels of the label showing `lines` in white on
+/// translucent black, `size` logical pixels with buffers of the given `scale`.
+fn draw_label(lines: &[String], size: Size, scale: u32) -> Vec<u8> {
+    let glyphs: Vec<Vec<[u8; 7]>> = lines
+        .iter()
+        .map(|line| line.chars().map(glyph).collect())
+        .collect();
+    let (width, height) = (size.width * scale, size.height * scale);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            // Pixel of the font, which starts after the padding.
+            let font_x = (x / scale).checked_sub(PADDING).map(|x| x / FONT_SCALE);
+            let font_y = (y / scale).checked_sub(PADDING).map(|y| y / FONT_SCALE);
+            let lit = font_x.zip(font_y).is_some_and(|(font_x, font_y)| {
+                let (column, row) = (font_x % GLYPH_ADVANCE, font_y % LINE_HEIGHT);
+                let glyph = glyphs
+                    .get((font_y / LINE_HEIGHT) as usize)
+                    .and_then(|line| line.get((font_x / GLYPH_ADVANCE) as usize));
+                match glyph {
+                    Some(glyph) if column < 5 && row < 7 => {
+                        glyph[row as usize] & (0b10000 >> column) != 0
+                    }
+                    _ => false,
+                }
+            });
+            pixels.extend(if lit {
+                [255, 255, 255, 255]
+            } else {
+                [0, 0, 0, 192]
+            });
+        }
+    }
+    pixels
+}
+
+/// Rows of the 5 x 7 pixel glyph of `character`, the leftmost pixel in the
+/// highest bit. Only has the characters used in labels, others are blank.
+fn glyph(character: char) -> [u8; 7] {
+    match character {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        'a' => [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f],
+        'c' => [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e],
+        'g' => [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e],
+        'h' => [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11],
+        'i' => [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e],
+        'l' => [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'o' => [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e],
+        'p' => [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10],
+        's' => [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e],
+        'x' => [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11],
+        'y' => [0x00, 0x00, 0x11, 0x11, 0x0f, 0x01, 0x0e],
+        _ => [0; 7],
+    }
+}
@@ -1,8 +1,20 @@
 use std::fmt::Display;
 
-use wayland_client::protocol::{wl_output, wl_output::WlOutput};
+use wayland_client::{
+    Proxy, QueueHandle,
+    globals::GlobalList,
+    protocol::{wl_output, wl_output::WlOutput},
+};
+use wayland_protocols::xdg::xdg_output::zv1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1, zxdg_output_v1::ZxdgOutputV1,
+};
 
-use crate::region::{LogicalRegion, Size};
+use crate::{
+    Error, Result,
+    dispatch::OutputCaptureState,
+    globals::BoundGlobals,
+    region::{LogicalRegion, Size, transform_swaps_axes},
+};
 
 /// Represents an accessible wayland output.
 ///
@@ -29,7 +41,157 @@ impl Display for OutputInfo {
 }
 
 impl OutputInfo {
-    pub(crate) fn scale(&self) -> f64 {
-        self.physical_size.height as f64 / self.logical_region.inner.size.height as f64
+    /// Ratio between the physical and the logical size of the output.
+    pub fn scale(&self) -> f64 {
+        self.transformed_physical_size().height as f64
+            / self.logical_region.inner.size.height as f64
     }
+
+    /// Physical size of the output in the orientation of its logical region,
+    /// i.e. with width and height swapped for outputs rotated by 90 or 270
+    /// degrees.
+    pub fn transformed_physical_size(&self) -> Size {
+        if transform_swaps_axes(self.transform) {
+            Size {
+                width: self.physical_size.height,
+                height: self.physical_size.width,
+            }
+        } else {
+            self.physical_size
+        }
+    }
+}
+
+/// Name of a transform as used by compositor configurations, e.g. `normal`,
+/// `90` or `flipped-270`.
+pub fn transform_name(transform: wl_output::Transform) -> &'static str {
+    match transform {
+        wl_output::Transform::Normal => "normal",
+        wl_output::Transform::_90 => "90",
+        wl_output::Transform::_180 => "180",
+        wl_output::Transform::_270 => "270",
+        wl_output::Transform::Flipped => "flipped",
+        wl_output::Transform::Flipped90 => "flipped-90",
+        wl_output::Transform::Flipped180 => "flipped-180",
+        wl_output::Transform::Flipped270 => "flipped-270",
+        _ => "unknown",
+    }
+}
+
+/// The `wl_output` proxy is skipped as it is only meaningful for the
+/// connection it belongs to. The scale is included as it is derived from the
+/// physical and logical sizes.
+#[cfg(feature = "serde")]
+impl serde::Serialize for OutputInfo {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("OutputInfo", 6)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("description", &self.description)?;
+        state.serialize_field("transform", transform_name(self.transform))?;
+        state.serialize_field("scale", &self.scale())?;
+        state.serialize_field("physical_size", &self.physical_size)?;
+        state.serialize_field("logical_region", &self.logical_region)?;
+        state.end()
+    }
+}
+
+/// Fetching all outputs and their logical regions. Each step has to be
+/// followed by a roundtrip on the queue of `qh` before the next one.
+pub(crate) struct OutputRefresh {
+    xdg_output_manager: ZxdgOutputManagerV1,
+    xdg_outputs: Vec<ZxdgOutputV1>,
+    /// Name of the `wl_output` global of every output.
+    output_globals: Vec<u32>,
+}
+
+impl OutputRefresh {
+    /// Bind all outputs; when their names arrive, they are filled in.
+    ///
+    /// The outputs are bound from `globals`, which is only up to date once
+    /// the events sent before were read, e.g. by a roundtrip.
+    pub(crate) fn start(
+        globals: &GlobalList,
+        bound_globals: &BoundGlobals,
+        state: &mut OutputCaptureState,
+        qh: &QueueHandle<OutputCaptureState>,
+    ) -> Result<Self> {
+        let xdg_output_manager = bound_globals.xdg_output_manager(globals)?;
+
+        let mut output_globals = Vec::new();
+        globals.contents().with_list(|list| {
+            for global in list
+                .iter()
+                .filter(|global| global.interface == WlOutput::interface().name)
+            {
+                /* > The name event is sent after binding the output object. This event
+                 * is only sent once per output object, and the name does not change
+                 * over the lifetime of the wl_output global. */
+                if global.version < 4 {
+                    tracing::error!("Ignoring a wl_output with version < 4.");
+                    continue;
+                }
+                let wl_output = globals
+                    .registry()
+                    .bind::<WlOutput, _, _>(global.name, 4, qh, ());
+                state.outputs.push(OutputInfo {
+                    wl_output,
+                    name: "".to_string(),
+                    description: String::new(),
+                    transform: wl_output::Transform::Normal,
+                    physical_size: Size::default(),
+                    logical_region: LogicalRegion::default(),
+                });
+                output_globals.push(global.name);
+            }
+        });
+
+        Ok(Self {
+            xdg_output_manager,
+            xdg_outputs: Vec::new(),
+            output_globals,
+        })
+    }
+
+    /// Request the position data of every output found.
+    pub(crate) fn request_positions(
+        &mut self,
+        state: &OutputCaptureState,
+        qh: &QueueHandle<OutputCaptureState>,
+    ) {
+        self.xdg_outputs = state
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| {
+                self.xdg_output_manager
+                    .get_xdg_output(&output.wl_output, qh, index)
+            })
+            .collect();
+    }
+
+    /// The outputs found, with the names of their `wl_output` globals.
+    pub(crate) fn finish(self, state: OutputCaptureState) -> Result<Vec<(u32, OutputInfo)>> {
+        for xdg_output in self.xdg_outputs {
+            xdg_output.destroy();
+        }
+
+        if state.outputs.is_empty() {
+            tracing::error!("Compositor did not advertise any wl_output devices!");
+            return Err(Error::NoOutputs);
+        }
+        tracing::trace!("Outputs detected: {:#?}", state.outputs);
+        Ok(self.output_globals.into_iter().zip(state.outputs).collect())
+    }
+}
+
+/// Whether `output` shows the same as `other`, which may be a `wl_output`
+/// object bound from the same global.
+pub(crate) fn unchanged(output: &OutputInfo, other: &OutputInfo) -> bool {
+    output.name == other.name
+        && output.description == other.description
+        && output.transform == other.transform
+        && output.physical_size == other.physical_size
+        && output.logical_region == other.logical_region
 }
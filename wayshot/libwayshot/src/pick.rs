@@ -0,0 +1,243 @@
+//! Reading the color of a pixel from a frame, and the loupe showing the
+//! pixels around the pointer while picking one, see
+//! [`crate::WayshotConnection::pick_color`].
+
+use wayland_client::protocol::wl_shm::Format;
+
+use crate::{
+    dump::DumpedFrame,
+    region::{self, Position, Size},
+};
+
+/// Width and height of the loupe in logical pixels.
+pub(crate) const LOUPE_SIZE: u32 = 120;
+/// Number of pixels shown in every row and column of the loupe, odd so that
+/// there is one in the middle.
+const LOUPE_PIXELS: i64 = 13;
+/// Logical size of every pixel shown in the loupe.
+const ZOOM: f64 = 9.0;
+/// Width of the ring around the loupe in logical pixels.
+const BORDER: f64 = 3.0;
+
+/// Color of a pixel as it was stored in the frame, without converting it to
+/// 8 bits per channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Color {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    /// Bits per channel of the frame, e.g. 10 for `Xbgr2101010` frames.
+    /// Conversions treat depths outside of `1..=16` as the closest one of
+    /// those, and channels beyond the depth as its maximum.
+    pub depth: u8,
+}
+
+/// The color picked with [`crate::WayshotConnection::pick_color`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PickedColor {
+    pub color: Color,
+    /// Name of the output the pixel was picked on.
+    pub output: String,
+    /// Logical position of the pixel.
+    pub position: Position,
+}
+
+impl Color {
+    fn from_rgb8(red: u8, green: u8, blue: u8) -> Self {
+        Self {
+            red: red.into(),
+            green: green.into(),
+            blue: blue.into(),
+            depth: 8,
+        }
+    }
+
+    fn max(&self) -> u32 {
+        (1 << self.depth.clamp(1, 16)) - 1
+    }
+
+    fn channels(&self) -> [u32; 3] {
+        let max = self.max();
+        [self.red, self.green, self.blue].map(|channel| u32::from(channel).min(max))
+    }
+
+    /// Channels scaled to `0.0..=1.0`.
+    pub fn to_f64(&self) -> [f64; 3] {
+        let max = self.max() as f64;
+        self.channels().map(|channel| channel as f64 / max)
+    }
+
+    /// Channels rounded to 8 bits.
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        let max = self.max();
+        self.channels()
+            .map(|channel| ((channel * 255 + max / 2) / max) as u8)
+    }
+
+    /// Channels scaled to 16 bits, which keeps every depth up to 16 bits
+    /// without loss.
+    pub fn to_rgb16(&self) -> [u16; 3] {
+        let max = self.max();
+        self.channels()
+            .map(|channel| ((channel * 65535 + max / 2) / max) as u16)
+    }
+}
+
+/// Size of the image `frame` turns into once its transform is applied.
+fn transformed_size(frame: &DumpedFrame) -> Size {
+    let Size { width, height } = frame.frame_format.size;
+    if region::transform_swaps_axes(frame.transform) {
+        Size {
+            width: height,
+            height: width,
+        }
+    } else {
+        Size { width, height }
+    }
+}
+
+/// The pixel of `frame` at `position` in the transformed image, `None` if it
+/// is outside of the frame or the format of the frame is not supported.
+pub(crate) fn read_pixel(frame: &DumpedFrame, (x, y): (i64, i64)) -> Option<Color> {
+    let size = transformed_size(frame);
+    if !(0..size.width as i64).contains(&x) || !(0..size.height as i64).contains(&y) {
+        return None;
+    }
+    let Position { x, y } = region::buffer_position(
+        Position {
+            x: x as i32,
+            y: y as i32,
+        },
+        size,
+        frame.transform,
+    );
+
+    let format = frame.frame_format.format;
+    let bytes_per_pixel = match format {
+        Format::Bgr888 => 3,
+        _ => 4,
+    };
+    let offset = y as usize * frame.frame_format.stride as usize + x as usize * bytes_per_pixel;
+    let bytes = frame.data.get(offset..offset + bytes_per_pixel)?;
+    match format {
+        Format::Argb8888 | Format::Xrgb8888 => Some(Color::from_rgb8(bytes[2], bytes[1], bytes[0])),
+        Format::Abgr8888 | Format::Xbgr8888 | Format::Bgr888 => {
+            Some(Color::from_rgb8(bytes[0], bytes[1], bytes[2]))
+        }
+        Format::Abgr2101010 | Format::Xbgr2101010 => {
+            let pixel = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            Some(Color {
+                red: (pixel & 0x3ff) as u16,
+                green: (pixel >> 10 & 0x3ff) as u16,
+                blue: (pixel >> 20 & 0x3ff) as u16,
+                depth: 10,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Premultiplied `Argb8888` pixels of the loupe of `size` x `size` pixels,
+/// showing the pixels of `frame` around `center` in its transformed image.
+pub(crate) fn draw_loupe(
+    frame: &DumpedFrame,
+    (center_x, center_y): (i64, i64),
+    size: u32,
+) -> Vec<u8> {
+    let half = LOUPE_PIXELS / 2;
+    let colors: Vec<Option<[u8; 3]>> = (-half..=half)
+        .flat_map(|dy| {
+            (-half..=half).map(move |dx| {
+                read_pixel(frame, (center_x + dx, center_y + dy)).map(|color| color.to_rgb8())
+            })
+        })
+        .collect();
+    // Outline the pixel that gets picked in black or white, whatever is
+    // easier to see on it.
+    let marker = match colors[(half * LOUPE_PIXELS + half) as usize] {
+        Some([red, green, blue])
+            if 0.299 * red as f64 + 0.587 * green as f64 + 0.114 * blue as f64 > 127.5 =>
+        {
+            [0, 0, 0]
+        }
+        _ => [255, 255, 255],
+    };
+
+    let scale = size as f64 / LOUPE_SIZE as f64;
+    let radius = LOUPE_SIZE as f64 / 2.0;
+    let offset = (LOUPE_SIZE as f64 - ZOOM * LOUPE_PIXELS as f64) / 2.0;
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            // Logical position of the middle of the pixel.
+            let (lx, ly) = ((x as f64 + 0.5) / scale, (y as f64 + 0.5) / scale);
+            let distance = ((lx - radius).powi(2) + (ly - radius).powi(2)).sqrt();
+            let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+
+            let [red, green, blue] = if distance > radius - BORDER {
+                [48, 48, 48]
+            } else {
+                let cell = |position: f64| ((position - offset) / ZOOM).floor() as i64;
+                let (column, row) = (
+                    cell(lx).clamp(0, LOUPE_PIXELS - 1),
+                    cell(ly).clamp(0, LOUPE_PIXELS - 1),
+                );
+                let edge = |position: f64| {
+                    let inside = (position - offset) - (half as f64 * ZOOM);
+                    !(1.0..=ZOOM - 1.0).contains(&inside)
+                };
+                if column == half && row == half && (edge(lx) || edge(ly)) {
+                    marker
+                } else {
+                    colors[(row * LOUPE_PIXELS + column) as usize].unwrap_or([32, 32, 32])
+                }
+            };
+            let premultiply = |channel: u8| (channel as f64 * coverage).round() as u8;
+            pixels.extend([
+                premultiply(blue),
+                premultiply(green),
+                premultiply(red),
+                (coverage * 255.0).round() as u8,
+            ]);
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(value: u16, depth: u8) -> Color {
+        Color {
+            red: value,
+            green: value / 2,
+            blue: 0,
+            depth,
+        }
+    }
+
+    #[test]
+    fn conversions_keep_the_range() {
+        assert_eq!(color(1023, 10).to_rgb8(), [255, 127, 0]);
+        assert_eq!(color(1023, 10).to_rgb16(), [65535, 32735, 0]);
+        assert_eq!(color(65535, 16).to_rgb8(), [255, 127, 0]);
+        assert_eq!(color(255, 8).to_f64(), [1.0, 127.0 / 255.0, 0.0]);
+    }
+
+    #[test]
+    fn depths_out_of_range() {
+        // Treated as 1 bit per channel.
+        assert_eq!(color(1, 0).to_rgb8(), [255, 0, 0]);
+        assert_eq!(color(1, 0).to_rgb16(), [65535, 0, 0]);
+        // Treated as 16 bits per channel.
+        for depth in [17, 32, 64, u8::MAX] {
+            assert_eq!(color(65535, depth).to_rgb16(), [65535, 32767, 0]);
+        }
+        // Channels beyond the depth are the maximum.
+        assert_eq!(color(u16::MAX, 8).to_rgb8(), [255, 255, 0]);
+        assert_eq!(color(u16::MAX, 8).to_f64(), [1.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Hiding parts of screenshots, e.g. passwords or tokens before attaching
+//! them to a bug report.
+//!
+//! Regions are in logical coordinates, like the regions screenshots are taken
+//! of, see [`crate::composited_scale`].
+
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba, imageops};
+
+use crate::{
+    image_util,
+    region::{LogicalRegion, Position, Region, Size},
+};
+
+/// Size of the blocks of [`RedactionStyle::Pixelate`] in logical pixels,
+/// large enough to make text unreadable.
+const PIXELATE_BLOCK: f64 = 16.0;
+/// Standard deviation of [`RedactionStyle::Blur`] in logical pixels.
+const BLUR_SIGMA: f64 = 8.0;
+
+/// How [`redact`] hides a region.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RedactionStyle {
+    /// Replace blocks of pixels with their average color.
+    Pixelate,
+    /// Blur the pixels. Blurred text may still be guessed from its shape, so
+    /// prefer the other styles for secrets.
+    Blur,
+    /// Paint the region black, which leaves nothing to recover.
+    #[default]
+    Blackout,
+}
+
+/// Hide `regions` of `image`, a screenshot of `capture_region`. Parts of the
+/// regions outside of `capture_region` are ignored.
+pub fn redact(
+    image: &mut DynamicImage,
+    capture_region: LogicalRegion,
+    regions: &[LogicalRegion],
+    style: RedactionStyle,
+) {
+    let image_size = Size {
+        width: image.width(),
+        height: image.height(),
+    };
+    let scale = image_util::composited_scale(image, capture_region.inner.size);
+
+    for region in regions
+        .iter()
+        .filter_map(|region| region.to_image(&capture_region, image_size))
+    {
+        match style {
+            RedactionStyle::Pixelate => pixelate(
+                image,
+                region,
+                (PIXELATE_BLOCK * scale).round().max(1.0) as u32,
+            ),
+            RedactionStyle::Blur => {
+                let Region { position, size } = region;
+                let blurred = image
+                    .crop_imm(
+                        position.x as u32,
+                        position.y as u32,
+                        size.width,
+                        size.height,
+                    )
+                    .fast_blur((BLUR_SIGMA * scale) as f32);
+                imageops::replace(image, &blurred, position.x as i64, position.y as i64);
+            }
+            RedactionStyle::Blackout => fill(image, region, Rgba([0, 0, 0, 255])),
+        }
+    }
+}
+
+/// Replace every `block` x `block` pixels of `region`, starting at its top
+/// left corner, with their average color.
+fn pixelate(image: &mut DynamicImage, region: Region, block: u32) {
+    let Region { position, size } = region;
+    let (left, top) = (position.x as u32, position.y as u32);
+    let (right, bottom) = (left + size.width, top + size.height);
+    for y in (top..bottom).step_by(block as usize) {
+        for x in (left..right).step_by(block as usize) {
+            let cell = Region {
+                position: Position {
+                    x: x as i32,
+                    y: y as i32,
+                },
+                size: Size {
+                    width: block.min(right - x),
+                    height: block.min(bottom - y),
+                },
+            };
+            let mut sums = [0u64; 4];
+            for (_, _, pixel) in image.view(x, y, cell.size.width, cell.size.height).pixels() {
+                for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                    *sum += channel as u64;
+                }
+            }
+            let count = cell.size.width as u64 * cell.size.height as u64;
+            fill(image, cell, Rgba(sums.map(|sum| (sum / count) as u8)));
+        }
+    }
+}
+
+fn fill(image: &mut DynamicImage, region: Region, color: Rgba<u8>) {
+    let Region { position, size } = region;
+    for y in position.y as u32..position.y as u32 + size.height {
+        for x in position.x as u32..position.x as u32 + size.width {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use std::cmp;
+use std::str::FromStr;
+
+use wayland_client::protocol::wl_output::Transform;
 
 use crate::{
     error::{Error, Result},
@@ -22,6 +24,8 @@ pub enum RegionCapturer {
 /// scaling have been applied. A unit is a logical pixel, meaning that this is
 /// after scaling has been applied.
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct LogicalRegion {
     pub inner: Region,
 }
@@ -61,6 +65,7 @@ pub struct EmbeddedRegion {
 /// Use `LogicalRegion` or `EmbeddedRegion` instead as they convey the
 /// coordinate system used.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Region {
     /// Position of the region.
     pub position: Position,
@@ -69,6 +74,7 @@ pub struct Region {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     /// X coordinate.
     pub x: i32,
@@ -77,6 +83,7 @@ pub struct Position {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Size {
     /// Width.
     pub width: u32,
@@ -84,6 +91,570 @@ pub struct Size {
     pub height: u32,
 }
 
+/// A length in a [`Geometry`], either in logical pixels or as a percentage of
+/// the region the geometry is relative to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Length {
+    /// Logical pixels.
+    Pixels(i32),
+    /// Percentage of the width or height of the reference region.
+    Percent(f64),
+}
+
+/// A region as written by a user or a tool such as slurp, before it has been
+/// resolved against the outputs.
+///
+/// Accepted formats, where every number may also be a percentage (`50%`):
+///
+/// - `X,Y WxH` as printed by slurp.
+/// - `X Y W H`.
+/// - `WxH+X+Y` as used by X11 tools. A `-` in place of a `+` makes the
+///   coordinate negative, it does not anchor to the opposite edge.
+///
+/// Each of them may be prefixed with an output name (`DP-1:10,10 200x200`),
+/// which makes the position relative to that output. Percentages are relative
+/// to the named output, or to the bounding box of all outputs otherwise.
+///
+/// `Display` always prints the `X,Y WxH` format, which parses back into the
+/// same geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geometry {
+    /// Output the geometry is relative to.
+    pub output: Option<String>,
+    pub x: Length,
+    pub y: Length,
+    pub width: Length,
+    pub height: Length,
+}
+
+impl Length {
+    /// Length in logical pixels, using `reference` as the 100% value.
+    fn resolve(self, reference: u32) -> i32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => (reference as f64 * percent / 100.0).round() as i32,
+        }
+    }
+}
+
+impl Geometry {
+    /// Return the `LogicalRegion` if the geometry is neither relative to an
+    /// output nor uses percentages, meaning it can be resolved without
+    /// knowing the outputs, and its size isn't negative.
+    pub fn absolute(&self) -> Option<LogicalRegion> {
+        let pixels = |length| match length {
+            Length::Pixels(pixels) => Some(pixels),
+            Length::Percent(_) => None,
+        };
+
+        if self.output.is_some() {
+            return None;
+        }
+
+        Some(LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: pixels(self.x)?,
+                    y: pixels(self.y)?,
+                },
+                size: Size {
+                    width: u32::try_from(pixels(self.width)?).ok()?,
+                    height: u32::try_from(pixels(self.height)?).ok()?,
+                },
+            },
+        })
+    }
+
+    /// Resolve the geometry into a `LogicalRegion` using the given outputs
+    /// for output relative positions and percentages.
+    pub fn resolve(&self, outputs: &[OutputInfo]) -> Result<LogicalRegion> {
+        let reference = match &self.output {
+            Some(name) => outputs
+                .iter()
+                .find(|output| &output.name == name)
+                .map(LogicalRegion::from)
+                .ok_or_else(|| Error::InvalidGeometry(format!("no output named '{name}'")))?,
+            None => outputs.try_into()?,
+        };
+        let Region { position, size } = reference.inner;
+
+        let offset = |length: Length, origin: i32, extent: u32| match length {
+            Length::Pixels(pixels) if self.output.is_none() => pixels,
+            _ => origin + length.resolve(extent),
+        };
+        let extent = |length: Length, extent: u32| {
+            u32::try_from(length.resolve(extent)).map_err(|_| {
+                Error::InvalidGeometry(format!("'{self}' resolves to a negative size"))
+            })
+        };
+
+        Ok(LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: offset(self.x, position.x, size.width),
+                    y: offset(self.y, position.y, size.height),
+                },
+                size: Size {
+                    width: extent(self.width, size.width)?,
+                    height: extent(self.height, size.height)?,
+                },
+            },
+        })
+    }
+}
+
+impl FromStr for Length {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let length = match s.strip_suffix('%') {
+            Some(percent) => percent
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| percent.is_finite())
+                .map(Length::Percent),
+            None => s.parse::<i32>().ok().map(Length::Pixels),
+        };
+
+        length.ok_or_else(|| Error::InvalidGeometry(format!("'{s}' is not a valid length")))
+    }
+}
+
+impl FromStr for Geometry {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::InvalidGeometry(format!(
+                "'{s}' does not match any of `X,Y WxH`, `X Y W H` or `WxH+X+Y`"
+            ))
+        };
+
+        let (output, tail) = match s.trim().split_once(':') {
+            Some((output, tail)) if !output.is_empty() => (Some(output.to_string()), tail),
+            Some(_) => return Err(invalid()),
+            None => (None, s.trim()),
+        };
+        let tail = tail.trim();
+
+        let [x, y, width, height] = if let Some((x, tail)) = tail.split_once(',') {
+            // `X,Y WxH`
+            let (y, size) = tail.trim_start().split_once(' ').ok_or_else(invalid)?;
+            let (width, height) = size.trim().split_once('x').ok_or_else(invalid)?;
+            [x, y, width, height]
+        } else if tail.contains(char::is_whitespace) {
+            // `X Y W H`
+            let mut parts = tail.split_whitespace();
+            match (
+                parts.next(),
+                parts.next(),
+                parts.next(),
+                parts.next(),
+                parts.next(),
+            ) {
+                (Some(x), Some(y), Some(width), Some(height), None) => [x, y, width, height],
+                _ => return Err(invalid()),
+            }
+        } else {
+            // `WxH+X+Y`
+            let offsets_start = tail.find(['+', '-']).ok_or_else(invalid)?;
+            let (size, offsets) = tail.split_at(offsets_start);
+            let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+            let y_start = offsets[1..].find(['+', '-']).ok_or_else(invalid)? + 1;
+            let (x, y) = offsets.split_at(y_start);
+            [x, y, width, height]
+        };
+
+        let geometry = Geometry {
+            output,
+            x: x.parse()?,
+            y: y.parse()?,
+            width: width.parse()?,
+            height: height.parse()?,
+        };
+        for length in [geometry.width, geometry.height] {
+            if matches!(length, Length::Pixels(pixels) if pixels < 0)
+                || matches!(length, Length::Percent(percent) if percent < 0.0)
+            {
+                return Err(Error::InvalidGeometry(format!("'{s}' has a negative size")));
+            }
+        }
+
+        Ok(geometry)
+    }
+}
+
+impl FromStr for LogicalRegion {
+    type Err = Error;
+
+    /// Parse a [`Geometry`] that does not depend on the outputs. Use
+    /// [`Geometry::resolve`] for output relative geometries and percentages.
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<Geometry>()?.absolute().ok_or_else(|| {
+            Error::InvalidGeometry(format!(
+                "'{s}' is relative to the outputs and needs to be resolved against them"
+            ))
+        })
+    }
+}
+
+impl FromStr for Region {
+    type Err = Error;
+
+    /// Parse a region in the `x,y WxH` form of a [`LogicalRegion`], for
+    /// coordinates that aren't logical ones, like the pixels of an image.
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<LogicalRegion>().map(|region| region.inner)
+    }
+}
+
+impl LogicalRegion {
+    /// Create a region from its left, top, right and bottom edges, which
+    /// must not be reversed. `None` if the region can't be represented.
+    fn from_edges(left: i64, top: i64, right: i64, bottom: i64) -> Option<Self> {
+        Some(LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: i32::try_from(left).ok()?,
+                    y: i32::try_from(top).ok()?,
+                },
+                size: Size {
+                    width: u32::try_from(right - left).ok()?,
+                    height: u32::try_from(bottom - top).ok()?,
+                },
+            },
+        })
+    }
+
+    /// The region spanned by two corners, in any order. It is empty if they
+    /// share a row or column, e.g. to measure a horizontal distance.
+    pub fn between(a: Position, b: Position) -> Self {
+        LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: a.x.min(b.x),
+                    y: a.y.min(b.y),
+                },
+                size: Size {
+                    width: a.x.abs_diff(b.x),
+                    height: a.y.abs_diff(b.y),
+                },
+            },
+        }
+    }
+
+    /// Left, top, right and bottom edges. Computed as `i64` so that regions
+    /// at the far end of the coordinate space don't overflow.
+    fn edges(&self) -> (i64, i64, i64, i64) {
+        let Region { position, size } = self.inner;
+        (
+            position.x as i64,
+            position.y as i64,
+            position.x as i64 + size.width as i64,
+            position.y as i64 + size.height as i64,
+        )
+    }
+
+    /// Left, top, right and bottom edges relative to `origin`.
+    fn edges_from(&self, origin: Position) -> (i64, i64, i64, i64) {
+        let (left, top, right, bottom) = self.edges();
+        let (x, y) = (origin.x as i64, origin.y as i64);
+        (left - x, top - y, right - x, bottom - y)
+    }
+
+    /// Whether the region has no area.
+    pub fn is_empty(&self) -> bool {
+        self.inner.size.width == 0 || self.inner.size.height == 0
+    }
+
+    /// The overlapping part of both regions, `None` if they don't overlap.
+    /// Regions that only touch at an edge don't overlap.
+    pub fn intersection(&self, other: &LogicalRegion) -> Option<LogicalRegion> {
+        let (left, top, right, bottom) = self.edges();
+        let (other_left, other_top, other_right, other_bottom) = other.edges();
+
+        let region = LogicalRegion::from_edges(
+            left.max(other_left),
+            top.max(other_top),
+            right.min(other_right).max(left.max(other_left)),
+            bottom.min(other_bottom).max(top.max(other_top)),
+        )?;
+        (!region.is_empty()).then_some(region)
+    }
+
+    /// The smallest region containing both regions. Empty regions are
+    /// ignored, so the union with an empty region is the other region.
+    ///
+    /// Returns `None` if the union is too large for the size of a region,
+    /// e.g. when the regions lie at both ends of the coordinate space.
+    pub fn union(&self, other: &LogicalRegion) -> Option<LogicalRegion> {
+        if other.is_empty() {
+            return Some(*self);
+        }
+        if self.is_empty() {
+            return Some(*other);
+        }
+
+        let (left, top, right, bottom) = self.edges();
+        let (other_left, other_top, other_right, other_bottom) = other.edges();
+        LogicalRegion::from_edges(
+            left.min(other_left),
+            top.min(other_top),
+            right.max(other_right),
+            bottom.max(other_bottom),
+        )
+    }
+
+    /// The smallest region containing all the given regions, `None` if there
+    /// are none or their union is too large, see [`LogicalRegion::union`].
+    pub fn bounding_box(regions: impl IntoIterator<Item = LogicalRegion>) -> Option<LogicalRegion> {
+        let mut regions = regions.into_iter();
+        let first = regions.next()?;
+        regions.try_fold(first, |bounds, region| bounds.union(&region))
+    }
+
+    /// Whether `other` lies entirely inside of this region. An empty region
+    /// is contained if its position is.
+    pub fn contains(&self, other: &LogicalRegion) -> bool {
+        let (left, top, right, bottom) = self.edges();
+        let (other_left, other_top, other_right, other_bottom) = other.edges();
+        left <= other_left && top <= other_top && other_right <= right && other_bottom <= bottom
+    }
+
+    /// Whether the logical pixel at `position` lies inside of this region.
+    pub fn contains_point(&self, position: Position) -> bool {
+        let (left, top, right, bottom) = self.edges();
+        let (x, y) = (position.x as i64, position.y as i64);
+        left <= x && x < right && top <= y && y < bottom
+    }
+
+    /// Move the region by the given offset, `None` if its position would
+    /// overflow.
+    pub fn translate(&self, dx: i32, dy: i32) -> Option<LogicalRegion> {
+        Some(LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: self.inner.position.x.checked_add(dx)?,
+                    y: self.inner.position.y.checked_add(dy)?,
+                },
+                size: self.inner.size,
+            },
+        })
+    }
+
+    /// Shrink the region to the parts that are visible on the given outputs.
+    ///
+    /// The result is the bounding box of the intersections with each output,
+    /// `None` if the region is not visible on any of them.
+    pub fn clamp_to_outputs(&self, outputs: &[OutputInfo]) -> Option<LogicalRegion> {
+        LogicalRegion::bounding_box(
+            outputs
+                .iter()
+                .filter_map(|output| self.intersection(&output.logical_region)),
+        )
+    }
+
+    /// Convert the part of this region that lies on `output` into physical
+    /// coordinates of the output's buffer, i.e. with the scale applied and
+    /// the output transform undone. This is the area to read from a frame
+    /// captured from that output.
+    ///
+    /// Partially covered physical pixels are included. Returns `None` if the
+    /// region does not intersect with the output.
+    pub fn to_physical(&self, output: &OutputInfo) -> Option<Region> {
+        let output_region = output.logical_region;
+        let relative = self.intersection(&output_region)?;
+
+        let transformed_size = output.transformed_physical_size();
+        let scale = output.scale();
+        let (left, top, right, bottom) = relative.edges_from(output_region.inner.position);
+        let scale_edge = |edge: i64, round: fn(f64) -> f64, max: u32| {
+            (round(edge as f64 * scale) as i64).clamp(0, max as i64)
+        };
+        let transformed = LogicalRegion::from_edges(
+            scale_edge(left, f64::floor, transformed_size.width),
+            scale_edge(top, f64::floor, transformed_size.height),
+            scale_edge(right, f64::ceil, transformed_size.width),
+            scale_edge(bottom, f64::ceil, transformed_size.height),
+        )?;
+
+        Some(transform_region(
+            transformed.inner,
+            transformed_size,
+            inverse_transform(output.transform),
+        ))
+    }
+
+    /// Size of the region in physical pixels of `output`, with the scale of
+    /// the output applied but not its transform, i.e. as it appears on the
+    /// screen. Unlike [`LogicalRegion::to_physical`] the region may be empty
+    /// or extend beyond the output, as when measuring distances.
+    ///
+    /// Edges are rounded to the physical pixel grid of the output.
+    pub fn physical_size(&self, output: &OutputInfo) -> Size {
+        let (left, top, right, bottom) = self.edges_from(output.logical_region.inner.position);
+        let scale = output.scale();
+        let scale_edge = |edge: i64| (edge as f64 * scale).round() as i64;
+        Size {
+            width: (scale_edge(right) - scale_edge(left)) as u32,
+            height: (scale_edge(bottom) - scale_edge(top)) as u32,
+        }
+    }
+
+    /// Convert the part of this region inside `capture_region` into pixels of
+    /// an image of `capture_region` that is `image_size` large, such as a
+    /// screenshot composited at the largest scale of its outputs.
+    ///
+    /// Partially covered pixels are included. Returns `None` if the region
+    /// does not intersect with `capture_region`.
+    pub fn to_image(&self, capture_region: &LogicalRegion, image_size: Size) -> Option<Region> {
+        let relative = self.intersection(capture_region)?;
+
+        let Size { width, height } = capture_region.inner.size;
+        let (left, top, right, bottom) = relative.edges_from(capture_region.inner.position);
+        let scale_edge = |edge: i64, round: fn(f64) -> f64, logical: u32, image: u32| {
+            (round(edge as f64 * image as f64 / logical as f64) as i64).clamp(0, image as i64)
+        };
+        let region = LogicalRegion::from_edges(
+            scale_edge(left, f64::floor, width, image_size.width),
+            scale_edge(top, f64::floor, height, image_size.height),
+            scale_edge(right, f64::ceil, width, image_size.width),
+            scale_edge(bottom, f64::ceil, height, image_size.height),
+        )?;
+        (!region.is_empty()).then_some(region.inner)
+    }
+
+    /// Convert a region of pixels of an image of `capture_region` that is
+    /// `image_size` large into a `LogicalRegion`. This is the inverse of
+    /// [`LogicalRegion::to_image`].
+    ///
+    /// Partially covered logical pixels are included. Returns `None` if the
+    /// region ends up outside of the logical coordinate space.
+    pub fn from_image(
+        region: Region,
+        capture_region: &LogicalRegion,
+        image_size: Size,
+    ) -> Option<LogicalRegion> {
+        let Region { position, size } = capture_region.inner;
+        let (left, top, right, bottom) = LogicalRegion { inner: region }.edges();
+        let scale_edge =
+            |edge: i64, round: fn(f64) -> f64, logical: u32, image: u32, origin: i32| {
+                round(edge as f64 * logical as f64 / image.max(1) as f64) as i64 + origin as i64
+            };
+        LogicalRegion::from_edges(
+            scale_edge(left, f64::floor, size.width, image_size.width, position.x),
+            scale_edge(top, f64::floor, size.height, image_size.height, position.y),
+            scale_edge(right, f64::ceil, size.width, image_size.width, position.x),
+            scale_edge(
+                bottom,
+                f64::ceil,
+                size.height,
+                image_size.height,
+                position.y,
+            ),
+        )
+    }
+
+    /// Convert a region in physical coordinates of the output's buffer, such
+    /// as a damaged area of a captured frame, into a `LogicalRegion`. This is
+    /// the inverse of [`LogicalRegion::to_physical`].
+    ///
+    /// Partially covered logical pixels are included. Returns `None` if the
+    /// region ends up outside of the logical coordinate space.
+    pub fn from_physical(region: Region, output: &OutputInfo) -> Option<LogicalRegion> {
+        let transformed = LogicalRegion {
+            inner: transform_region(region, output.physical_size, output.transform),
+        };
+
+        let scale = output.scale();
+        let Position { x, y } = output.logical_region.inner.position;
+        let (left, top, right, bottom) = transformed.edges();
+        let scale_edge = |edge: i64, round: fn(f64) -> f64, origin: i32| {
+            round(edge as f64 / scale) as i64 + origin as i64
+        };
+        LogicalRegion::from_edges(
+            scale_edge(left, f64::floor, x),
+            scale_edge(top, f64::floor, y),
+            scale_edge(right, f64::ceil, x),
+            scale_edge(bottom, f64::ceil, y),
+        )
+    }
+}
+
+/// Whether the transform swaps the width and height.
+pub(crate) fn transform_swaps_axes(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// Position in a buffer of the pixel at `position` of the image of
+/// `transformed_size` the buffer turns into with `transform`, i.e. where to
+/// read a pixel of a screenshot from in the frame it was composited from.
+pub(crate) fn buffer_position(
+    position: Position,
+    transformed_size: Size,
+    transform: Transform,
+) -> Position {
+    let pixel = Region {
+        position,
+        size: Size {
+            width: 1,
+            height: 1,
+        },
+    };
+    transform_region(pixel, transformed_size, inverse_transform(transform)).position
+}
+
+/// The transform that undoes `transform`. Rotations by 90 and 270 degrees
+/// undo each other, all other transforms undo themselves.
+fn inverse_transform(transform: Transform) -> Transform {
+    match transform {
+        Transform::_90 => Transform::_270,
+        Transform::_270 => Transform::_90,
+        transform => transform,
+    }
+}
+
+/// Map a region inside of an image of `size` to where it ends up after the
+/// image has been transformed the same way `image_util::rotate_image_buffer`
+/// does it: flipped horizontally first for flipped transforms, then rotated
+/// clockwise.
+fn transform_region(region: Region, size: Size, transform: Transform) -> Region {
+    let Region {
+        position: Position { x, y },
+        size: Size { width, height },
+    } = region;
+    let (image_width, image_height) = (size.width as i32, size.height as i32);
+    let (w, h) = (width as i32, height as i32);
+
+    let (x, y) = match transform {
+        Transform::_90 => (image_height - y - h, x),
+        Transform::_180 => (image_width - x - w, image_height - y - h),
+        Transform::_270 => (y, image_width - x - w),
+        Transform::Flipped => (image_width - x - w, y),
+        Transform::Flipped90 => (image_height - y - h, image_width - x - w),
+        Transform::Flipped180 => (x, image_height - y - h),
+        Transform::Flipped270 => (y, x),
+        _ => (x, y),
+    };
+    let size = if transform_swaps_axes(transform) {
+        Size {
+            width: height,
+            height: width,
+        }
+    } else {
+        region.size
+    };
+
+    Region {
+        position: Position { x, y },
+        size,
+    }
+}
+
 impl EmbeddedRegion {
     /// Given two `LogicalRegion`s, one seen as the `viewport` and the other
     /// `relative_to` (think the output we want to capture), create an
@@ -92,37 +663,13 @@ impl EmbeddedRegion {
     /// See `EmbeddedRegion` for an example ASCII visualisation.
     #[tracing::instrument(ret, level = "debug")]
     pub fn new(viewport: LogicalRegion, relative_to: LogicalRegion) -> Option<Self> {
-        let x_relative: i32 = viewport.inner.position.x - relative_to.inner.position.x;
-        let y_relative = viewport.inner.position.y - relative_to.inner.position.y;
-
-        let x1 = cmp::max(x_relative, 0);
-        let x2 = cmp::min(
-            x_relative + viewport.inner.size.width as i32,
-            relative_to.inner.size.width as i32,
-        );
-        let width = if let Ok(width) = (x2 - x1).try_into() {
-            width
-        } else {
-            return None;
-        };
-
-        let y1 = cmp::max(y_relative, 0);
-        let y2 = cmp::min(
-            y_relative + viewport.inner.size.height as i32,
-            relative_to.inner.size.height as i32,
-        );
-        let height = if let Ok(height) = (y2 - y1).try_into() {
-            height
-        } else {
-            return None;
-        };
+        let (left, top, right, bottom) = viewport
+            .intersection(&relative_to)?
+            .edges_from(relative_to.inner.position);
 
         Some(Self {
             relative_to,
-            inner: Region {
-                position: Position { x: x1, y: y1 },
-                size: Size { width, height },
-            },
+            inner: LogicalRegion::from_edges(left, top, right, bottom)?.inner,
         })
     }
 
@@ -185,7 +732,39 @@ impl std::fmt::Display for Region {
 
 impl std::fmt::Display for LogicalRegion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{inner}", inner = self.inner)
+        write!(
+            f,
+            "{x},{y} {width}x{height}",
+            x = self.inner.position.x,
+            y = self.inner.position.y,
+            width = self.inner.size.width,
+            height = self.inner.size.height,
+        )
+    }
+}
+
+impl std::fmt::Display for Length {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Length::Pixels(pixels) => write!(f, "{pixels}"),
+            Length::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+impl std::fmt::Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(output) = &self.output {
+            write!(f, "{output}:")?;
+        }
+        write!(
+            f,
+            "{x},{y} {width}x{height}",
+            x = self.x,
+            y = self.y,
+            width = self.width,
+            height = self.height,
+        )
     }
 }
 
@@ -201,40 +780,531 @@ impl TryFrom<&[OutputInfo]> for LogicalRegion {
     type Error = Error;
 
     fn try_from(output_info: &[OutputInfo]) -> std::result::Result<Self, Self::Error> {
-        let x1 = output_info
-            .iter()
-            .map(|output| output.logical_region.inner.position.x)
-            .min()
-            .ok_or(Error::NoOutputs)?;
-        let y1 = output_info
-            .iter()
-            .map(|output| output.logical_region.inner.position.y)
-            .min()
-            .ok_or(Error::NoOutputs)?;
-        let x2 = output_info
-            .iter()
-            .map(|output| {
-                output.logical_region.inner.position.x
-                    + output.logical_region.inner.size.width as i32
+        if output_info.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+        LogicalRegion::bounding_box(output_info.iter().map(LogicalRegion::from)).ok_or_else(|| {
+            Error::InvalidGeometry("the outputs span more than a region can hold".to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use proptest::prelude::*;
+    use wayland_client::{Connection, Proxy, protocol::wl_output::WlOutput};
+
+    use super::*;
+
+    const TRANSFORMS: [Transform; 8] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    fn region(x: i32, y: i32, width: u32, height: u32) -> LogicalRegion {
+        LogicalRegion {
+            inner: Region {
+                position: Position { x, y },
+                size: Size { width, height },
+            },
+        }
+    }
+
+    /// An output at `logical_region` whose buffer is `scale` times as large
+    /// and turns into the logical orientation with `transform`.
+    fn output(logical_region: LogicalRegion, scale: u32, transform: Transform) -> OutputInfo {
+        let Size { width, height } = logical_region.inner.size;
+        let (width, height) = (width * scale, height * scale);
+        let physical_size = if transform_swaps_axes(transform) {
+            Size {
+                width: height,
+                height: width,
+            }
+        } else {
+            Size { width, height }
+        };
+        // Not connected to a compositor, the proxy is never used.
+        let (socket, _) = UnixStream::pair().expect("failed to create a socket pair");
+        let connection = Connection::from_socket(socket).expect("failed to create a connection");
+        OutputInfo {
+            wl_output: WlOutput::inert(connection.backend().downgrade()),
+            name: "test".to_string(),
+            description: String::new(),
+            transform,
+            physical_size,
+            logical_region,
+        }
+    }
+
+    fn offset() -> impl Strategy<Value = Length> {
+        prop_oneof![
+            any::<i32>().prop_map(Length::Pixels),
+            (-1e6..1e6).prop_map(Length::Percent),
+        ]
+    }
+
+    fn extent() -> impl Strategy<Value = Length> {
+        prop_oneof![
+            (0..=i32::MAX).prop_map(Length::Pixels),
+            (0.0..1e6).prop_map(Length::Percent),
+        ]
+    }
+
+    fn geometry() -> impl Strategy<Value = Geometry> {
+        (
+            proptest::option::of("[A-Za-z][A-Za-z0-9_-]{0,11}"),
+            offset(),
+            offset(),
+            extent(),
+            extent(),
+        )
+            .prop_map(|(output, x, y, width, height)| Geometry {
+                output,
+                x,
+                y,
+                width,
+                height,
             })
-            .max()
-            .ok_or(Error::NoOutputs)?;
-        let y2 = output_info
-            .iter()
-            .map(|output| {
-                output.logical_region.inner.position.y
-                    + output.logical_region.inner.size.height as i32
+    }
+
+    proptest! {
+        #[test]
+        fn geometry_round_trips(geometry in geometry()) {
+            prop_assert_eq!(geometry.to_string().parse::<Geometry>()?, geometry);
+        }
+
+        #[test]
+        fn logical_region_round_trips(
+            x in any::<i32>(),
+            y in any::<i32>(),
+            width in 0..=i32::MAX as u32,
+            height in 0..=i32::MAX as u32,
+        ) {
+            let region = region(x, y, width, height);
+            prop_assert_eq!(region.to_string().parse::<LogicalRegion>()?, region);
+        }
+
+        #[test]
+        fn formats_agree(
+            x in any::<i32>(),
+            y in any::<i32>(),
+            width in 0..=i32::MAX,
+            height in 0..=i32::MAX,
+        ) {
+            let slurp = format!("{x},{y} {width}x{height}").parse::<Geometry>()?;
+            prop_assert_eq!(
+                format!("{x} {y} {width} {height}").parse::<Geometry>()?,
+                slurp.clone()
+            );
+            prop_assert_eq!(format!("{width}x{height}{x:+}{y:+}").parse::<Geometry>()?, slurp);
+        }
+
+        #[test]
+        fn parsing_never_panics(s in "\\PC{0,24}") {
+            let _ = s.parse::<Geometry>();
+        }
+    }
+
+    #[test]
+    fn parses_every_format() -> Result<()> {
+        let expected = Geometry {
+            output: Some("DP-1".to_string()),
+            x: Length::Pixels(10),
+            y: Length::Pixels(-20),
+            width: Length::Percent(50.0),
+            height: Length::Pixels(200),
+        };
+        for s in [
+            "DP-1:10,-20 50%x200",
+            " DP-1: 10 -20 50% 200 ",
+            "DP-1:50%x200+10-20",
+        ] {
+            assert_eq!(s.parse::<Geometry>()?, expected, "{s}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_geometries() {
+        for s in [
+            "",
+            ":1,2 3x4",
+            "1,2 3",
+            "1 2 3",
+            "1 2 3 4 5",
+            "3x4",
+            "1,2 -3x4",
+            "1,2 3x-4%",
+            "1,2 3xNaN%",
+        ] {
+            assert!(s.parse::<Geometry>().is_err(), "{s}");
+        }
+        assert!("DP-1:1,2 3x4".parse::<LogicalRegion>().is_err());
+        assert!("1,2 50%x4".parse::<LogicalRegion>().is_err());
+    }
+
+    #[test]
+    fn negative_sizes_are_not_absolute() {
+        let geometry = Geometry {
+            output: None,
+            x: Length::Pixels(0),
+            y: Length::Pixels(0),
+            width: Length::Pixels(-1),
+            height: Length::Pixels(4),
+        };
+        assert_eq!(geometry.absolute(), None);
+        assert_eq!(
+            Geometry {
+                height: Length::Pixels(i32::MIN),
+                width: Length::Pixels(1),
+                ..geometry
+            }
+            .absolute(),
+            None
+        );
+    }
+
+    #[test]
+    fn intersections_of_negative_regions() {
+        let a = region(-20, -20, 30, 30);
+        let b = region(-5, 0, 100, 5);
+        assert_eq!(a.intersection(&b), Some(region(-5, 0, 15, 5)));
+        assert_eq!(b.intersection(&a), a.intersection(&b));
+        assert_eq!(a.intersection(&a), Some(a));
+    }
+
+    #[test]
+    fn touching_and_empty_regions_do_not_intersect() {
+        let a = region(-10, -10, 10, 10);
+        assert_eq!(a.intersection(&region(0, -10, 10, 10)), None);
+        assert_eq!(a.intersection(&region(-10, 0, 10, 10)), None);
+        assert_eq!(a.intersection(&region(-5, -5, 0, 0)), None);
+        assert_eq!(a.intersection(&region(-5, -5, 0, 4)), None);
+        assert_eq!(region(0, 0, 0, 0).intersection(&region(0, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn unions_ignore_empty_regions() {
+        let a = region(-10, -20, 5, 5);
+        let b = region(10, 20, 5, 5);
+        assert_eq!(a.union(&b), Some(region(-10, -20, 25, 45)));
+        assert_eq!(b.union(&a), a.union(&b));
+        assert_eq!(a.union(&region(100, 100, 0, 10)), Some(a));
+        assert_eq!(region(-100, -100, 10, 0).union(&a), Some(a));
+    }
+
+    #[test]
+    fn bounding_boxes() {
+        assert_eq!(LogicalRegion::bounding_box([]), None);
+        let a = region(-1920, 0, 1920, 1080);
+        assert_eq!(LogicalRegion::bounding_box([a]), Some(a));
+        assert_eq!(
+            LogicalRegion::bounding_box([a, region(0, -200, 1280, 1024)]),
+            Some(region(-1920, -200, 3200, 1280))
+        );
+    }
+
+    #[test]
+    fn containment() {
+        let a = region(-10, -10, 20, 20);
+        assert!(a.contains(&a));
+        assert!(a.contains(&region(-10, -10, 0, 0)));
+        assert!(a.contains(&region(10, 10, 0, 0)));
+        assert!(!a.contains(&region(-11, 0, 5, 5)));
+        assert!(a.contains_point(Position { x: -10, y: 9 }));
+        assert!(!a.contains_point(Position { x: 10, y: 0 }));
+        assert!(!region(0, 0, 0, 0).contains_point(Position { x: 0, y: 0 }));
+    }
+
+    #[test]
+    fn edges_far_out_do_not_overflow() {
+        let a = region(i32::MAX - 10, i32::MAX - 10, u32::MAX / 2, 10);
+        assert_eq!(
+            a.intersection(&region(i32::MAX - 5, i32::MAX - 5, 100, 100)),
+            Some(region(i32::MAX - 5, i32::MAX - 5, 100, 5))
+        );
+        assert!(!a.contains_point(Position { x: 0, y: 0 }));
+    }
+
+    #[test]
+    fn regions_at_the_ends_of_the_coordinate_space() {
+        let top_left = region(i32::MIN, i32::MIN, 10, 10);
+        let bottom_right = region(i32::MAX - 10, i32::MAX - 10, 20, 20);
+        assert_eq!(top_left.union(&bottom_right), None);
+        assert_eq!(
+            LogicalRegion::bounding_box([top_left, region(0, 0, 10, 10), bottom_right]),
+            None
+        );
+        assert_eq!(
+            top_left.union(&region(i32::MAX - 10, 0, 10, 10)),
+            Some(region(i32::MIN, i32::MIN, u32::MAX, (1 << 31) + 10))
+        );
+
+        assert_eq!(top_left.translate(-1, 0), None);
+        assert_eq!(bottom_right.translate(0, 11), None);
+        assert_eq!(
+            top_left.translate(i32::MAX, 0),
+            Some(region(-1, i32::MIN, 10, 10))
+        );
+
+        assert_eq!(
+            LogicalRegion::between(
+                Position {
+                    x: i32::MIN,
+                    y: i32::MAX
+                },
+                Position {
+                    x: i32::MAX,
+                    y: i32::MIN
+                },
+            ),
+            region(i32::MIN, i32::MIN, u32::MAX, u32::MAX)
+        );
+    }
+
+    #[test]
+    fn outputs_at_the_ends_of_the_coordinate_space() {
+        let top_left = output(region(i32::MIN, i32::MIN, 100, 50), 2, Transform::Normal);
+        assert_eq!(
+            region(i32::MIN, i32::MIN, 10, 10).to_physical(&top_left),
+            Some(Region {
+                position: Position { x: 0, y: 0 },
+                size: Size {
+                    width: 20,
+                    height: 20
+                },
             })
-            .max()
-            .ok_or(Error::NoOutputs)?;
-        Ok(LogicalRegion {
-            inner: Region {
-                position: Position { x: x1, y: y1 },
+        );
+        assert_eq!(
+            region(i32::MAX - 10, i32::MAX - 10, 10, 10).physical_size(&top_left),
+            Size {
+                width: 20,
+                height: 20
+            }
+        );
+        let embedded = EmbeddedRegion::new(
+            region(i32::MIN + 10, i32::MIN, 10, 10),
+            top_left.logical_region,
+        );
+        assert_eq!(
+            embedded.map(|embedded| embedded.logical()),
+            Some(region(i32::MIN + 10, i32::MIN, 10, 10))
+        );
+
+        let bottom_right = output(
+            region(i32::MAX - 100, i32::MAX - 50, 200, 100),
+            1,
+            Transform::Normal,
+        );
+        assert_eq!(
+            LogicalRegion::from_physical(
+                Region {
+                    position: Position { x: 90, y: 40 },
+                    size: Size {
+                        width: 10,
+                        height: 10
+                    },
+                },
+                &bottom_right
+            ),
+            Some(region(i32::MAX - 10, i32::MAX - 10, 10, 10))
+        );
+        assert_eq!(
+            LogicalRegion::try_from([top_left, bottom_right].as_slice()).ok(),
+            None
+        );
+    }
+
+    #[test]
+    fn clamping_to_outputs() {
+        let outputs = [
+            output(region(-1920, 0, 1920, 1080), 1, Transform::Normal),
+            output(region(0, 0, 1280, 1024), 1, Transform::Normal),
+        ];
+        assert_eq!(
+            region(-100, -100, 1500, 200).clamp_to_outputs(&outputs),
+            Some(region(-100, 0, 1380, 100))
+        );
+        assert_eq!(
+            region(-100, 1050, 200, 100).clamp_to_outputs(&outputs),
+            Some(region(-100, 1050, 100, 30))
+        );
+        assert_eq!(region(2000, 0, 10, 10).clamp_to_outputs(&outputs), None);
+        assert_eq!(region(-10, 10, 0, 10).clamp_to_outputs(&outputs), None);
+        assert_eq!(region(0, 0, 10, 10).clamp_to_outputs(&[]), None);
+    }
+
+    #[test]
+    fn physical_regions_of_scaled_outputs() {
+        let output = output(region(-100, -50, 100, 50), 2, Transform::Normal);
+        let physical = |x, y, width, height| Region {
+            position: Position { x, y },
+            size: Size { width, height },
+        };
+        assert_eq!(
+            region(-150, -60, 100, 20).to_physical(&output),
+            Some(physical(0, 0, 100, 20))
+        );
+        assert_eq!(region(-10, -10, 0, 5).to_physical(&output), None);
+        assert_eq!(region(0, 0, 10, 10).to_physical(&output), None);
+        assert_eq!(
+            LogicalRegion::from_physical(physical(0, 0, 100, 20), &output),
+            Some(region(-100, -50, 50, 10))
+        );
+    }
+
+    #[test]
+    fn image_regions_of_scaled_screenshots() {
+        let capture_region = region(-100, 50, 100, 50);
+        let image_size = Size {
+            width: 200,
+            height: 100,
+        };
+        let image_region = region(-90, 40, 20, 20).to_image(&capture_region, image_size);
+        assert_eq!(image_region, Some(region(20, 0, 40, 20).inner));
+        assert_eq!(
+            image_region.and_then(|image_region| {
+                LogicalRegion::from_image(image_region, &capture_region, image_size)
+            }),
+            Some(region(-90, 50, 20, 10))
+        );
+        // Partially covered logical pixels are included.
+        assert_eq!(
+            LogicalRegion::from_image(region(3, 3, 1, 2).inner, &capture_region, image_size),
+            Some(region(-99, 51, 1, 2))
+        );
+        assert_eq!(
+            region(0, 0, 10, 10).to_image(&capture_region, image_size),
+            None
+        );
+    }
+
+    #[test]
+    fn physical_regions_of_rotated_outputs() {
+        // 200x100 buffer shown as 100x200, rotated by 90 degrees.
+        let output = output(region(0, 0, 100, 200), 1, Transform::_90);
+        assert_eq!(
+            output.physical_size,
+            Size {
+                width: 200,
+                height: 100
+            }
+        );
+        let physical = region(0, 0, 10, 20).to_physical(&output);
+        assert_eq!(
+            physical,
+            Some(Region {
+                position: Position { x: 0, y: 90 },
                 size: Size {
-                    width: (x2 - x1) as u32,
-                    height: (y2 - y1) as u32,
+                    width: 20,
+                    height: 10
                 },
+            })
+        );
+    }
+
+    #[test]
+    fn physical_regions_round_trip_for_every_transform() {
+        for transform in TRANSFORMS {
+            for scale in [1, 2, 3] {
+                let output = output(region(-300, 40, 300, 200), scale, transform);
+                let logical = region(-290, 50, 100, 20);
+                let physical = logical.to_physical(&output);
+                assert_eq!(
+                    physical.and_then(|physical| LogicalRegion::from_physical(physical, &output)),
+                    Some(logical),
+                    "{transform:?} at scale {scale}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transforms_are_undone_by_their_inverse() {
+        let size = Size {
+            width: 40,
+            height: 30,
+        };
+        let region = Region {
+            position: Position { x: 3, y: 5 },
+            size: Size {
+                width: 7,
+                height: 11,
             },
-        })
+        };
+        for transform in TRANSFORMS {
+            let transformed_size = if transform_swaps_axes(transform) {
+                Size {
+                    width: size.height,
+                    height: size.width,
+                }
+            } else {
+                size
+            };
+            let transformed = transform_region(region, size, transform);
+            assert!(
+                transformed.position.x >= 0
+                    && transformed.position.y >= 0
+                    && transformed.position.x as u32 + transformed.size.width
+                        <= transformed_size.width
+                    && transformed.position.y as u32 + transformed.size.height
+                        <= transformed_size.height,
+                "{transform:?} moved {region} out of the image: {transformed}"
+            );
+            assert_eq!(
+                transform_region(transformed, transformed_size, inverse_transform(transform)),
+                region,
+                "{transform:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn transforms_match_the_image_rotation() {
+        // A single pixel ends up where rotating the image puts it.
+        let size = Size {
+            width: 4,
+            height: 2,
+        };
+        let pixel = Region {
+            position: Position { x: 1, y: 0 },
+            size: Size {
+                width: 1,
+                height: 1,
+            },
+        };
+        let mut image = image::RgbaImage::new(size.width, size.height);
+        image.put_pixel(1, 0, image::Rgba([255, 0, 0, 255]));
+        for transform in TRANSFORMS {
+            let logical_size = if transform_swaps_axes(transform) {
+                Size {
+                    width: size.height,
+                    height: size.width,
+                }
+            } else {
+                size
+            };
+            let rotated = crate::image_util::rotate_image_buffer(
+                image.clone().into(),
+                transform,
+                logical_size,
+                1.0,
+            )
+            .into_rgba8();
+            let Position { x, y } = transform_region(pixel, size, transform).position;
+            assert_eq!(
+                rotated.get_pixel(x as u32, y as u32).0[0],
+                255,
+                "{transform:?}"
+            );
+        }
     }
 }
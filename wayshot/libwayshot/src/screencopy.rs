@@ -1,7 +1,10 @@
 use std::{
     ffi::CString,
-    os::fd::OwnedFd,
-    time::{SystemTime, UNIX_EPOCH},
+    fmt,
+    fs::File,
+    io::Write as _,
+    os::fd::{AsFd, OwnedFd},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use gbm::BufferObject;
@@ -11,12 +14,19 @@ use rustix::{
     fs::{self, SealFlags},
     io, shm,
 };
-use wayland_client::protocol::{
-    wl_buffer::WlBuffer, wl_output, wl_shm::Format, wl_shm_pool::WlShmPool,
+use wayland_client::{
+    QueueHandle,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_output,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+    },
 };
 
 use crate::{
     Error, Result,
+    dispatch::LayerShellState,
     region::{LogicalRegion, Size},
 };
 
@@ -61,7 +71,7 @@ impl<T: khronos_egl::api::EGL1_5> Drop for EGLImageGuard<'_, T> {
 /// Xbgr8888.
 ///
 /// See `zwlr_screencopy_frame_v1::Event::Buffer` as it's retrieved from there.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct FrameFormat {
     pub format: Format,
     /// Size of the frame in pixels. This will always be in "landscape" so a
@@ -98,21 +108,36 @@ where
     P: Pixel<Subpixel = u8>,
 {
     tracing::debug!("Creating image buffer");
-    match frame_data {
-        FrameData::Mmap(frame_mmap) => ImageBuffer::from_vec(
-            frame_format.size.width,
-            frame_format.size.height,
-            frame_mmap.to_vec(),
-        )
-        .ok_or(Error::BufferTooSmall),
-        FrameData::GBMBo(_) => todo!(),
-    }
+    let data = match frame_data {
+        FrameData::Mmap(frame_mmap) => frame_mmap.to_vec(),
+        FrameData::Owned(data) => data.clone(),
+        FrameData::GBMBo(_) => {
+            return Err(Error::UnsupportedFrameData {
+                format: frame_format.format,
+            });
+        }
+    };
+    ImageBuffer::from_vec(frame_format.size.width, frame_format.size.height, data)
+        .ok_or(Error::BufferTooSmall)
 }
 
-#[derive(Debug)]
 pub enum FrameData {
     Mmap(MmapMut),
     GBMBo(BufferObject<()>),
+    /// Pixels in memory, for frames that were not copied by the compositor,
+    /// e.g. to composite them with [`crate::composite_frames`].
+    Owned(Vec<u8>),
+}
+
+impl fmt::Debug for FrameData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mmap(frame_mmap) => f.debug_tuple("Mmap").field(frame_mmap).finish(),
+            Self::GBMBo(bo) => f.debug_tuple("GBMBo").field(bo).finish(),
+            // Don't print every pixel.
+            Self::Owned(data) => write!(f, "Owned({} bytes)", data.len()),
+        }
+    }
 }
 /// The copied frame comprising of the FrameFormat, ColorType (Rgba8), and a memory backed shm
 /// file that holds the image data in it.
@@ -125,6 +150,27 @@ pub struct FrameCopy {
     /// Logical region with the transform already applied.
     pub logical_region: LogicalRegion,
     pub physical_size: Size,
+    /// Time the frame was presented on screen, as reported by the
+    /// compositor. The clock is the one of the compositor's presentation
+    /// timestamps.
+    pub presentation_time: Option<Duration>,
+    /// Parts of the output that changed since the previous frame captured of
+    /// it, as reported by the compositor. `None` if unknown, the first frame
+    /// of an output is entirely damaged.
+    pub damage: Option<Vec<LogicalRegion>>,
+}
+
+/// Largest difference between the presentation times of `frames`, or `None`
+/// if fewer than two of them reported one.
+pub fn presentation_skew<'a>(frames: impl IntoIterator<Item = &'a FrameCopy>) -> Option<Duration> {
+    let (earliest, latest, count) = frames
+        .into_iter()
+        .filter_map(|frame| frame.presentation_time)
+        .fold(
+            (Duration::MAX, Duration::ZERO, 0),
+            |(earliest, latest, count), time| (earliest.min(time), latest.max(time), count + 1),
+        );
+    (count > 1).then(|| latest - earliest)
 }
 
 impl TryFrom<&FrameCopy> for DynamicImage {
@@ -207,3 +253,32 @@ pub fn create_shm_fd() -> std::io::Result<OwnedFd> {
         }
     }
 }
+
+/// A `wl_shm` buffer of `size` premultiplied `Argb8888` `pixels`, e.g. to
+/// show on a layer surface.
+pub(crate) fn create_argb_buffer(
+    shm: &WlShm,
+    pixels: &[u8],
+    size: Size,
+    qh: &QueueHandle<LayerShellState>,
+) -> Result<FrameGuard> {
+    let mut mem_file = File::from(create_shm_fd()?);
+    mem_file.write_all(pixels)?;
+
+    let shm_pool = shm.create_pool(
+        mem_file.as_fd(),
+        pixels.len().try_into().map_err(|_| Error::BufferTooSmall)?,
+        qh,
+        (),
+    );
+    let buffer = shm_pool.create_buffer(
+        0,
+        size.width as i32,
+        size.height as i32,
+        size.width as i32 * 4,
+        Format::Argb8888,
+        qh,
+        (),
+    );
+    Ok(FrameGuard { buffer, shm_pool })
+}
@@ -0,0 +1,240 @@
+use std::{
+    io,
+    os::fd::OwnedFd,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+use rustix::event::{EventfdFlags, PollFd, PollFlags, Timespec, eventfd, poll};
+use wayland_client::{
+    Connection, EventQueue,
+    backend::{Backend, ObjectData, ObjectId, WaylandError, protocol::Message},
+    protocol::wl_display,
+};
+
+use crate::{CapturePhase, Error, Result};
+
+/// Upper bounds for how long each phase of a capture may take before it is
+/// given up with [`Error::Timeout`]. `None` waits forever, which is the
+/// default for every phase.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Waiting for the compositor to send the buffer constraints of a capture
+    /// session.
+    pub negotiation: Option<Duration>,
+    /// Waiting for the compositor to copy a frame into the buffer.
+    pub copy: Option<Duration>,
+    /// Waiting for the layer surfaces used to freeze the screen to be
+    /// configured.
+    pub configure: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Use the same timeout for every phase.
+    pub fn uniform(timeout: Duration) -> Self {
+        Self {
+            negotiation: Some(timeout),
+            copy: Some(timeout),
+            configure: Some(timeout),
+        }
+    }
+
+    /// Timeout of the given phase.
+    pub fn get(&self, phase: CapturePhase) -> Option<Duration> {
+        match phase {
+            CapturePhase::Negotiation => self.negotiation,
+            CapturePhase::Copy => self.copy,
+            CapturePhase::Configure => self.configure,
+        }
+    }
+
+    /// These timeouts with the copy cut short at `deadline`, and whether the
+    /// deadline comes before the copy timeout.
+    pub(crate) fn until(mut self, deadline: Instant) -> (Self, bool) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let deadline_first = self.copy.is_none_or(|copy| left < copy);
+        if deadline_first {
+            self.copy = Some(left);
+        }
+        (self, deadline_first)
+    }
+}
+
+/// Dispatch events on `event_queue` until `pending` returns `None`.
+///
+/// `pending` returns the name of an output that is still waited on. Instead
+/// of blocking on the socket like `EventQueue::blocking_dispatch`, the
+/// connection fd is polled for at most the time left until `timeout` runs
+/// out, at which point `Error::Timeout` is returned for the given `phase` and
+/// that output.
+pub(crate) fn dispatch_until<State>(
+    event_queue: &mut EventQueue<State>,
+    state: &mut State,
+    timeout: Option<Duration>,
+    phase: CapturePhase,
+    mut pending: impl FnMut(&State) -> Option<String>,
+) -> Result<()> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let waker = QueuedWaker::new()?;
+    if dispatch_while(event_queue, state, &waker, deadline, |state| {
+        pending(state).is_some()
+    })? {
+        return Ok(());
+    }
+
+    let output = pending(state).unwrap_or_default();
+    // Not necessarily an error, the caller may have expected it.
+    tracing::debug!("Timed out during {phase} on output {output}");
+    Err(Error::Timeout { phase, output })
+}
+
+/// Dispatch events on `event_queue` until `pending` returns `false`, for as
+/// long as that takes, e.g. to wait for input from the user.
+pub(crate) fn dispatch_while_pending<State>(
+    event_queue: &mut EventQueue<State>,
+    state: &mut State,
+    pending: impl FnMut(&State) -> bool,
+) -> Result<()> {
+    let waker = QueuedWaker::new()?;
+    dispatch_while(event_queue, state, &waker, None, pending)?;
+    Ok(())
+}
+
+/// `EventQueue::roundtrip` that does not hang when another thread sharing
+/// the connection reads our events, see `dispatch_while`.
+pub(crate) fn roundtrip<State>(
+    conn: &Connection,
+    event_queue: &mut EventQueue<State>,
+    state: &mut State,
+) -> Result<()> {
+    let waker = QueuedWaker::new()?;
+    let done = Arc::new(SyncDone {
+        done: AtomicBool::new(false),
+        waker: Waker::from(waker.clone()),
+    });
+    conn.send_request(
+        &conn.display(),
+        wl_display::Request::Sync {},
+        Some(done.clone()),
+    )
+    .map_err(|_| WaylandError::Io(io::ErrorKind::BrokenPipe.into()))?;
+
+    dispatch_while(event_queue, state, &waker, None, |_| {
+        !done.done.load(Ordering::Acquire)
+    })?;
+    Ok(())
+}
+
+/// Dispatch events on `event_queue` while `pending` returns `true`, returns
+/// `false` if `deadline` passed first.
+///
+/// Other threads sharing the connection may read our events off the socket
+/// at any time, `EventQueue::prepare_read` only checks that they have not
+/// been read yet. So the socket is polled together with `waker`, which is
+/// registered with the event queue and woken up when they queue one for us.
+fn dispatch_while<State>(
+    event_queue: &mut EventQueue<State>,
+    state: &mut State,
+    waker: &Arc<QueuedWaker>,
+    deadline: Option<Instant>,
+    mut pending: impl FnMut(&State) -> bool,
+) -> Result<bool> {
+    let queue_waker = Waker::from(waker.clone());
+    let mut cx = Context::from_waker(&queue_waker);
+
+    loop {
+        // Dispatches everything queued, then registers the waker for the next
+        // event.
+        if let Poll::Ready(Err(e)) = event_queue.poll_dispatch_pending(&mut cx, state) {
+            return Err(e.into());
+        }
+        if !pending(state) {
+            return Ok(true);
+        }
+
+        event_queue.flush()?;
+        // No guard means events were read but not queued yet, dispatch those
+        // first.
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+
+        let remaining = match deadline {
+            Some(deadline) => {
+                let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                    return Ok(false);
+                };
+                Some(Timespec::try_from(remaining).unwrap_or(Timespec {
+                    tv_sec: i64::MAX,
+                    tv_nsec: 0,
+                }))
+            }
+            None => None,
+        };
+
+        let connection_fd = guard.connection_fd();
+        let mut fds = [
+            PollFd::new(&connection_fd, PollFlags::IN | PollFlags::ERR),
+            PollFd::new(&waker.0, PollFlags::IN),
+        ];
+        match poll(&mut fds, remaining.as_ref()) {
+            Ok(0) => return Ok(false),
+            Ok(_) => {}
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(errno) => return Err(io::Error::from(errno).into()),
+        }
+        if !fds[1].revents().is_empty() {
+            // Dropping the guard cancels the read.
+            let _ = rustix::io::read(&waker.0, &mut [0; 8]);
+            continue;
+        }
+
+        match guard.read() {
+            Ok(_) => {}
+            Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Signals its eventfd once woken up, by an event queue it is registered
+/// with or a finished roundtrip.
+struct QueuedWaker(OwnedFd);
+
+impl QueuedWaker {
+    fn new() -> Result<Arc<Self>> {
+        let fd =
+            eventfd(0, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK).map_err(io::Error::from)?;
+        Ok(Arc::new(Self(fd)))
+    }
+}
+
+impl Wake for QueuedWaker {
+    fn wake(self: Arc<Self>) {
+        let _ = rustix::io::write(&self.0, &1u64.to_ne_bytes());
+    }
+}
+
+/// Set once the compositor answered a `wl_display.sync` request.
+struct SyncDone {
+    done: AtomicBool,
+    waker: Waker,
+}
+
+impl ObjectData for SyncDone {
+    fn event(
+        self: Arc<Self>,
+        _backend: &Backend,
+        _msg: Message<ObjectId, OwnedFd>,
+    ) -> Option<Arc<dyn ObjectData>> {
+        self.done.store(true, Ordering::Release);
+        self.waker.wake_by_ref();
+        None
+    }
+
+    fn destroyed(&self, _object_id: ObjectId) {}
+}
@@ -0,0 +1,217 @@
+//! Drawing annotations with `libwayshot::annotate`.
+
+#![cfg(feature = "annotate")]
+
+mod common;
+
+use image::{DynamicImage, GenericImageView, Rgba};
+use libwayshot::{
+    annotate::{self, Annotation, Stroke},
+    region::Position,
+};
+
+use common::size;
+
+const GREY: Rgba<u8> = Rgba([128, 128, 128, 255]);
+const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+fn grey(width: u32, height: u32) -> DynamicImage {
+    common::solid(width, height, GREY)
+}
+
+fn position(x: i32, y: i32) -> Position {
+    Position { x, y }
+}
+
+/// Bounding box of the pixels that aren't grey as `(left, top, right, bottom)`.
+fn drawn_bounds(image: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    common::bounds(
+        image
+            .pixels()
+            .filter(|(_, _, pixel)| *pixel != GREY)
+            .map(|(x, y, _)| (x, y)),
+    )
+}
+
+#[test]
+fn rectangle_strokes_are_centered_on_the_edges() {
+    let mut image = grey(50, 30);
+    annotate::annotate(
+        &mut image,
+        size(50, 30),
+        &[Annotation::Rectangle {
+            region: "10,5 20x10".parse().unwrap(),
+            stroke: Some(Stroke {
+                color: RED,
+                width: 2.0,
+            }),
+            fill: None,
+        }],
+    );
+
+    assert_eq!(drawn_bounds(&image), Some((9, 4, 31, 16)));
+    for (x, y) in [(9, 10), (10, 10), (20, 4), (30, 10)] {
+        assert_eq!(image.get_pixel(x, y), RED, "{x}, {y}");
+    }
+    assert_eq!(image.get_pixel(11, 10), GREY);
+    assert_eq!(image.get_pixel(20, 10), GREY);
+}
+
+#[test]
+fn fills_are_blended_with_the_screenshot() {
+    let mut image = grey(40, 40);
+    annotate::annotate(
+        &mut image,
+        size(40, 40),
+        &[Annotation::Circle {
+            center: position(20, 20),
+            radius: 10.0,
+            stroke: None,
+            fill: Some(Rgba([255, 255, 255, 128])),
+        }],
+    );
+
+    assert_eq!(image.get_pixel(20, 20), Rgba([192, 192, 192, 255]));
+    assert_eq!(image.get_pixel(20, 5), GREY);
+    assert_eq!(drawn_bounds(&image), Some((10, 10, 30, 30)));
+}
+
+#[test]
+fn shapes_larger_than_the_image_are_clipped_to_it() {
+    let mut image = grey(20, 20);
+    annotate::annotate(
+        &mut image,
+        size(20, 20),
+        &[Annotation::Circle {
+            center: position(10, 10),
+            radius: 1e7,
+            stroke: None,
+            fill: Some(RED),
+        }],
+    );
+
+    assert!(image.pixels().all(|(_, _, pixel)| pixel == RED));
+}
+
+#[test]
+fn arrows_point_at_their_end() {
+    let mut image = grey(100, 40);
+    annotate::annotate(
+        &mut image,
+        size(100, 40),
+        &[Annotation::Arrow {
+            from: position(10, 20),
+            to: position(90, 20),
+            stroke: Stroke {
+                color: RED,
+                width: 4.0,
+            },
+        }],
+    );
+
+    let (left, top, right, bottom) = drawn_bounds(&image).unwrap();
+    assert_eq!((left, right), (8, 90));
+    // The head is wider than the line.
+    assert!(top < 18 && bottom > 22, "{top} {bottom}");
+    assert_eq!(image.get_pixel(12, 20), RED);
+    assert_eq!(image.get_pixel(78, 24), RED);
+    assert_eq!(image.get_pixel(12, 25), GREY);
+}
+
+#[test]
+fn text_is_drawn_on_its_background() {
+    let mut image = grey(200, 60);
+    annotate::annotate(
+        &mut image,
+        size(200, 60),
+        &[Annotation::Text {
+            position: position(10, 10),
+            text: "Click".into(),
+            size: 20.0,
+            color: Rgba([255, 255, 255, 255]),
+            background: Some(Rgba([0, 0, 0, 255])),
+        }],
+    );
+
+    let (left, top, right, bottom) = drawn_bounds(&image).unwrap();
+    assert_eq!((left, top), (10, 10));
+    assert!((60..120).contains(&right) && (30..50).contains(&bottom));
+    assert_eq!(image.get_pixel(11, 11), Rgba([0, 0, 0, 255]));
+    let white = image
+        .pixels()
+        .filter(|(_, _, pixel)| *pixel == Rgba([255, 255, 255, 255]))
+        .count();
+    assert!(white > 20, "{white}");
+}
+
+#[test]
+fn text_starts_at_its_position() {
+    let text = |position: Position, size: f64| {
+        let mut image = grey(200, 100);
+        annotate::annotate(
+            &mut image,
+            common::size(200, 100),
+            &[Annotation::Text {
+                position,
+                text: "Hello".into(),
+                size,
+                color: RED,
+                background: None,
+            }],
+        );
+        drawn_bounds(&image).unwrap()
+    };
+
+    // The glyphs are placed relative to the position, and the top left
+    // corner of the position lies above and before them.
+    let (left, top, right, bottom) = text(position(10, 10), 20.0);
+    assert!(
+        (10..14).contains(&left) && (10..18).contains(&top),
+        "{left} {top}"
+    );
+    assert_eq!(
+        text(position(50, 40), 20.0),
+        (left + 40, top + 30, right + 40, bottom + 30)
+    );
+    // Larger text grows to the right and down.
+    let (large_left, large_top, large_right, large_bottom) = text(position(10, 10), 40.0);
+    assert!((10..16).contains(&large_left), "{large_left}");
+    assert!((10..26).contains(&large_top), "{large_top}");
+    assert!(large_right - large_left > (right - left) * 3 / 2);
+    assert!(large_bottom - large_top > (bottom - top) * 3 / 2);
+}
+
+#[test]
+fn markers_have_a_readable_number() {
+    let mut image = grey(80, 40);
+    annotate::annotate(
+        &mut image,
+        size(40, 20),
+        &[
+            Annotation::Marker {
+                center: position(10, 10),
+                number: 7,
+                radius: 8.0,
+                color: RED,
+            },
+            Annotation::Marker {
+                center: position(30, 10),
+                number: 12,
+                radius: 8.0,
+                color: Rgba([255, 255, 0, 255]),
+            },
+        ],
+    );
+
+    assert_eq!(drawn_bounds(&image), Some((4, 4, 76, 36)));
+    let count = |left: u32, matches: fn(&[u8]) -> bool| {
+        image
+            .view(left, 0, 40, 40)
+            .pixels()
+            .filter(|(_, _, pixel)| matches(&pixel.0[..3]))
+            .count()
+    };
+    // White on red, black on yellow.
+    assert!(count(0, |rgb| rgb.iter().all(|&channel| channel > 200)) > 10);
+    assert!(count(40, |rgb| rgb.iter().all(|&channel| channel < 50)) > 10);
+}
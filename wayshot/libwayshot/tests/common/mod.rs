@@ -0,0 +1,30 @@
+//! Helpers shared by the tests of the image processing modules.
+
+// Every test crate only uses some of them.
+#![allow(dead_code)]
+
+use std::{fmt::Debug, str::FromStr};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use libwayshot::region::Size;
+
+pub fn solid(width: u32, height: u32, color: Rgba<u8>) -> DynamicImage {
+    RgbaImage::from_pixel(width, height, color).into()
+}
+
+pub fn size(width: u32, height: u32) -> Size {
+    Size { width, height }
+}
+
+pub fn region<T: FromStr<Err: Debug>>(s: &str) -> T {
+    s.parse().unwrap()
+}
+
+/// Bounding box of the given pixel coordinates as `(left, top, right,
+/// bottom)`, `None` if there are none.
+pub fn bounds(pixels: impl IntoIterator<Item = (u32, u32)>) -> Option<(u32, u32, u32, u32)> {
+    pixels.into_iter().fold(None, |bounds, (x, y)| {
+        let (left, top, right, bottom) = bounds.unwrap_or((x, y, x + 1, y + 1));
+        Some((left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1)))
+    })
+}
@@ -0,0 +1,175 @@
+//! Framing screenshots with `libwayshot::decorate`.
+
+mod common;
+
+use image::{DynamicImage, GenericImageView, Rgba};
+use libwayshot::{
+    decorate::{self, Decorations, Shadow},
+    region::Position,
+};
+
+use common::size;
+
+const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+fn blue(width: u32, height: u32) -> DynamicImage {
+    common::solid(width, height, BLUE)
+}
+
+fn alpha(image: &DynamicImage, x: u32, y: u32) -> u8 {
+    image.get_pixel(x, y).0[3]
+}
+
+#[test]
+fn default_decorations_leave_the_screenshot_unchanged() {
+    let mut image = blue(20, 10);
+    decorate::decorate(&mut image, size(20, 10), &Decorations::default());
+
+    assert_eq!(image, blue(20, 10));
+}
+
+#[test]
+fn padding_surrounds_the_screenshot() {
+    let mut image = blue(20, 10);
+    decorate::decorate(
+        &mut image,
+        size(20, 10),
+        &Decorations {
+            padding: 5,
+            background: Rgba([255, 255, 255, 255]),
+            ..Decorations::default()
+        },
+    );
+
+    assert_eq!(image.dimensions(), (30, 20));
+    assert_eq!(image.get_pixel(4, 10), Rgba([255, 255, 255, 255]));
+    assert_eq!(image.get_pixel(25, 15), Rgba([255, 255, 255, 255]));
+    assert_eq!(image.get_pixel(5, 5), BLUE);
+    assert_eq!(image.get_pixel(24, 14), BLUE);
+}
+
+#[test]
+fn corners_are_rounded() {
+    let mut image = blue(40, 40);
+    decorate::decorate(
+        &mut image,
+        size(40, 40),
+        &Decorations {
+            corner_radius: 10,
+            ..Decorations::default()
+        },
+    );
+
+    assert_eq!(image.dimensions(), (40, 40));
+    for (x, y) in [(0, 0), (39, 0), (0, 39), (39, 39), (2, 2)] {
+        assert_eq!(image.get_pixel(x, y), TRANSPARENT, "{x}, {y}");
+    }
+    for (x, y) in [(20, 0), (0, 20), (5, 5), (20, 20)] {
+        assert_eq!(image.get_pixel(x, y), BLUE, "{x}, {y}");
+    }
+    // Smoothed edge.
+    assert!((1..255).contains(&alpha(&image, 2, 3)));
+}
+
+#[test]
+fn corner_alpha_grows_towards_the_inside() {
+    let mut image = blue(40, 30);
+    decorate::decorate(
+        &mut image,
+        size(40, 30),
+        &Decorations {
+            corner_radius: 10,
+            ..Decorations::default()
+        },
+    );
+
+    // All corners are alike, and only the alpha changes.
+    for y in 0..10 {
+        for x in 0..10 {
+            let corner = alpha(&image, x, y);
+            assert_eq!(alpha(&image, 39 - x, y), corner, "{x}, {y}");
+            assert_eq!(alpha(&image, x, 29 - y), corner, "{x}, {y}");
+            assert_eq!(alpha(&image, 39 - x, 29 - y), corner, "{x}, {y}");
+            if corner > 0 {
+                assert_eq!(image.get_pixel(x, y).0[..3], BLUE.0[..3], "{x}, {y}");
+            }
+        }
+    }
+    // Round, so the same along rows and columns, and from transparent to
+    // opaque towards the inside, with partly covered pixels along the edge.
+    for i in 0..10 {
+        let row: Vec<_> = (0..=10).map(|x| alpha(&image, x, i)).collect();
+        let column: Vec<_> = (0..=10).map(|y| alpha(&image, i, y)).collect();
+        assert_eq!(row, column);
+        assert!(row.is_sorted(), "{row:?}");
+        assert_eq!(row[10], 255);
+    }
+    assert_eq!(alpha(&image, 0, 0), 0);
+    let smoothed = (0..10)
+        .flat_map(|y| (0..10).map(move |x| (x, y)))
+        .filter(|&(x, y)| (1..255).contains(&alpha(&image, x, y)))
+        .count();
+    assert!(smoothed >= 10, "{smoothed}");
+}
+
+#[test]
+fn shadows_fall_into_the_padding() {
+    let mut image = blue(20, 20);
+    decorate::decorate(
+        &mut image,
+        size(20, 20),
+        &Decorations {
+            padding: 10,
+            shadow: Some(Shadow {
+                blur: 6,
+                offset: Position { x: 0, y: 4 },
+                color: Rgba([0, 0, 0, 255]),
+            }),
+            ..Decorations::default()
+        },
+    );
+
+    assert_eq!(image.dimensions(), (40, 40));
+    assert_eq!(image.get_pixel(20, 20), BLUE);
+    // Darker below the screenshot than above it, and fading out.
+    let (above, below) = (alpha(&image, 20, 8), alpha(&image, 20, 31));
+    assert!(below > above, "{below} {above}");
+    assert!(alpha(&image, 20, 36) < below);
+    assert_eq!(alpha(&image, 0, 0), 0);
+    // Only the alpha of the shadow changes, not its color.
+    assert_eq!(image.get_pixel(20, 31).0[..3], [0, 0, 0]);
+}
+
+#[test]
+fn shadows_fade_out_over_the_blur_distance() {
+    let mut image = blue(20, 20);
+    decorate::decorate(
+        &mut image,
+        size(20, 20),
+        &Decorations {
+            padding: 20,
+            shadow: Some(Shadow {
+                blur: 9,
+                offset: Position { x: 0, y: 0 },
+                color: Rgba([0, 0, 0, 255]),
+            }),
+            ..Decorations::default()
+        },
+    );
+
+    // Below the middle of the screenshot, from its edge outwards.
+    let below: Vec<_> = (40..60).map(|y| alpha(&image, 30, y)).collect();
+    assert!(below.is_sorted_by(|a, b| a >= b), "{below:?}");
+    assert!(below[0] > 64, "{below:?}");
+    assert!(below[4] < below[0], "{below:?}");
+    // Faded out after the blur distance.
+    assert!(below[12..].iter().all(|&alpha| alpha < 8), "{below:?}");
+    // Without an offset the shadow is the same on every side.
+    for distance in 0..20 {
+        let alpha_below = below[distance as usize];
+        assert_eq!(alpha(&image, 30, 19 - distance), alpha_below);
+        assert_eq!(alpha(&image, 19 - distance, 30), alpha_below);
+        assert_eq!(alpha(&image, 40 + distance, 30), alpha_below);
+    }
+}
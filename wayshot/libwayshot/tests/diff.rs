@@ -0,0 +1,148 @@
+//! Comparing images with `libwayshot::diff`.
+
+mod common;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use libwayshot::{
+    Error,
+    diff::{self, DiffOptions},
+    region::{LogicalRegion, Region},
+};
+
+fn image(width: u32, height: u32, changes: &[(u32, u32, [u8; 4])]) -> DynamicImage {
+    let mut image = RgbaImage::from_pixel(width, height, Rgba([40, 80, 120, 255]));
+    for &(x, y, pixel) in changes {
+        image.put_pixel(x, y, Rgba(pixel));
+    }
+    image.into()
+}
+
+fn region(s: &str) -> Region {
+    common::region(s)
+}
+
+fn logical(s: &str) -> LogicalRegion {
+    common::region(s)
+}
+
+#[test]
+fn changes_within_the_tolerance_are_ignored() {
+    let expected = image(32, 16, &[]);
+    let actual = image(32, 16, &[(3, 4, [42, 78, 120, 255])]);
+
+    let exact = diff::compare(&expected, &actual, &DiffOptions::default()).unwrap();
+    assert_eq!(exact.changed_pixels, 1);
+    assert_eq!(exact.regions, [region("3,4 1x1")]);
+
+    let options = DiffOptions {
+        tolerance: 2,
+        ..Default::default()
+    };
+    let tolerant = diff::compare(&expected, &actual, &options).unwrap();
+    assert!(!tolerant.has_changes());
+    assert!(tolerant.regions.is_empty());
+}
+
+#[test]
+fn close_changes_are_merged_into_one_region() {
+    let red = [255, 0, 0, 255];
+    let expected = image(100, 50, &[]);
+    let actual = image(
+        100,
+        50,
+        &[(10, 10, red), (14, 12, red), (11, 11, red), (80, 40, red)],
+    );
+
+    let image_diff = diff::compare(&expected, &actual, &DiffOptions::default()).unwrap();
+    assert_eq!(image_diff.changed_pixels, 4);
+    assert_eq!(
+        image_diff.regions,
+        [region("10,10 5x3"), region("80,40 1x1")]
+    );
+    assert_eq!(image_diff.bounding_box(), Some(region("10,10 71x31")));
+
+    let highlighted = image_diff.highlight(&actual).unwrap();
+    assert_eq!(highlighted.get_pixel(14, 12), &Rgba(red));
+    assert_eq!(highlighted.get_pixel(9, 9), &Rgba([255, 0, 255, 255]));
+}
+
+#[test]
+fn changes_close_to_merged_regions_are_merged_too() {
+    let red = [255, 0, 0, 255];
+    let expected = image(40, 40, &[]);
+    // The corner is only close to the region of the line and the dot together.
+    let line: Vec<_> = (0..10).map(|x| (x, 0, red)).collect();
+    let actual = image(
+        40,
+        40,
+        &[line.as_slice(), &[(18, 8, red), (0, 17, red)]].concat(),
+    );
+
+    let image_diff = diff::compare(&expected, &actual, &DiffOptions::default()).unwrap();
+    assert_eq!(image_diff.regions, [region("0,0 19x18")]);
+}
+
+#[test]
+fn distant_changes_are_separate_regions() {
+    let red = [255, 0, 0, 255];
+    let expected = image(600, 600, &[]);
+    let dots: Vec<_> = (0..600)
+        .step_by(10)
+        .flat_map(|y| (0..600).step_by(10).map(move |x| (x, y, red)))
+        .collect();
+    let actual = image(600, 600, &dots);
+
+    let image_diff = diff::compare(&expected, &actual, &DiffOptions::default()).unwrap();
+    assert_eq!(image_diff.regions.len(), 3600);
+    assert_eq!(image_diff.regions[61], region("10,10 1x1"));
+    assert_eq!(image_diff.bounding_box(), Some(region("0,0 591x591")));
+}
+
+#[test]
+fn ignored_regions_are_not_compared() {
+    let red = [255, 0, 0, 255];
+    let expected = image(20, 20, &[]);
+    let actual = image(20, 20, &[(1, 1, red), (18, 18, red)]);
+
+    let options = DiffOptions {
+        ignore: vec![logical("-5,-5 10x10"), logical("30,30 10x10")],
+        ..Default::default()
+    };
+    let image_diff = diff::compare(&expected, &actual, &options).unwrap();
+    assert_eq!(image_diff.ignored_pixels, 25);
+    assert_eq!(image_diff.compared_pixels(), 375);
+    assert_eq!(image_diff.regions, [region("18,18 1x1")]);
+}
+
+#[test]
+fn ignored_regions_are_mapped_from_the_capture_region() {
+    let red = [255, 0, 0, 255];
+    let expected = image(40, 20, &[]);
+    let actual = image(40, 20, &[(4, 4, red), (5, 5, red), (6, 6, red)]);
+
+    // A screenshot of 20x10 logical pixels, composited at a scale of 2.
+    let options = DiffOptions {
+        ignore: vec![logical("-98,52 1x1")],
+        capture_region: Some(logical("-100,50 20x10")),
+        ..Default::default()
+    };
+    let image_diff = diff::compare(&expected, &actual, &options).unwrap();
+    assert_eq!(image_diff.ignored_pixels, 4);
+    assert_eq!(image_diff.regions, [region("6,6 1x1")]);
+}
+
+#[test]
+fn images_of_different_sizes_are_not_compared() {
+    let result = diff::compare(
+        &image(10, 10, &[]),
+        &image(10, 11, &[]),
+        &DiffOptions::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::ImageSizeMismatch {
+            expected: (10, 10),
+            actual: (10, 11),
+        })
+    ));
+}
@@ -0,0 +1,282 @@
+//! Golden image tests of compositing frames into a screenshot.
+//!
+//! Every case builds synthetic frames, composites them and compares the
+//! result pixel by pixel with `tests/golden/<case>.png`. Run with
+//! `WAYSHOT_BLESS=1` to write the references after an intended change, and
+//! look at them before checking them in.
+
+use std::{env, os::unix::net::UnixStream, path::PathBuf};
+
+use image::{ColorType, RgbaImage};
+use libwayshot::{
+    FrameCopy, FrameData, FrameFormat,
+    output::OutputInfo,
+    reexport::{Transform, WlOutput},
+    region::{LogicalRegion, Position, Region, Size},
+};
+use wayland_client::{Connection, Proxy, protocol::wl_shm::Format};
+
+struct Output {
+    transform: Transform,
+    /// Size of the buffer, before the transform is applied.
+    buffer: (u32, u32),
+    /// Logical position and size, the scale is the ratio to the transformed
+    /// buffer size.
+    logical: (i32, i32, u32, u32),
+}
+
+struct Case {
+    name: String,
+    outputs: Vec<Output>,
+    /// Defaults to all outputs.
+    region: Option<(i32, i32, u32, u32)>,
+}
+
+const fn output(transform: Transform, buffer: (u32, u32), logical: (i32, i32, u32, u32)) -> Output {
+    Output {
+        transform,
+        buffer,
+        logical,
+    }
+}
+
+fn cases() -> Vec<Case> {
+    let transforms = [
+        ("normal", Transform::Normal),
+        ("90", Transform::_90),
+        ("180", Transform::_180),
+        ("270", Transform::_270),
+        ("flipped", Transform::Flipped),
+        ("flipped_90", Transform::Flipped90),
+        ("flipped_180", Transform::Flipped180),
+        ("flipped_270", Transform::Flipped270),
+    ];
+    let mut cases = Vec::new();
+
+    for (name, transform) in transforms {
+        let swapped = matches!(
+            transform,
+            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+        );
+        let (width, height) = if swapped { (8, 12) } else { (12, 8) };
+        cases.push(Case {
+            name: format!("transform_{name}"),
+            outputs: vec![output(transform, (12, 8), (0, 0, width, height))],
+            region: None,
+        });
+        // Scaled by 2 next to an unscaled output, at a negative position.
+        cases.push(Case {
+            name: format!("transform_{name}_scaled"),
+            outputs: vec![
+                output(transform, (24, 16), (-(width as i32), -2, width, height)),
+                output(Transform::Normal, (6, 6), (0, 0, 6, 6)),
+            ],
+            region: None,
+        });
+    }
+
+    cases.extend([
+        Case {
+            name: "mixed_scales".to_owned(),
+            outputs: vec![
+                output(Transform::Normal, (16, 12), (0, 0, 8, 6)),
+                output(Transform::Normal, (6, 6), (8, 0, 6, 6)),
+                output(Transform::_90, (9, 6), (0, 6, 4, 6)),
+            ],
+            region: None,
+        },
+        Case {
+            name: "fractional_scale".to_owned(),
+            outputs: vec![
+                output(Transform::Normal, (15, 9), (0, 0, 10, 6)),
+                output(Transform::_270, (6, 6), (10, 0, 6, 6)),
+            ],
+            region: None,
+        },
+        Case {
+            name: "negative_positions".to_owned(),
+            outputs: vec![
+                output(Transform::Normal, (10, 8), (-10, -4, 10, 8)),
+                output(Transform::Flipped, (12, 8), (0, 0, 12, 8)),
+                output(Transform::_180, (6, 4), (-16, 4, 6, 4)),
+            ],
+            region: None,
+        },
+        Case {
+            name: "region_across_outputs".to_owned(),
+            outputs: vec![
+                output(Transform::Normal, (10, 8), (-10, -4, 10, 8)),
+                output(Transform::_90, (16, 24), (0, 0, 12, 8)),
+            ],
+            region: Some((-4, -2, 9, 7)),
+        },
+        Case {
+            name: "region_fractional_scale".to_owned(),
+            outputs: vec![
+                output(Transform::Flipped270, (9, 15), (-10, 0, 10, 6)),
+                output(Transform::Normal, (8, 6), (0, 0, 8, 6)),
+            ],
+            region: Some((-5, 1, 8, 4)),
+        },
+        Case {
+            name: "region_with_gap".to_owned(),
+            outputs: vec![
+                output(Transform::Normal, (6, 6), (-8, 0, 6, 6)),
+                output(Transform::_180, (12, 12), (2, 2, 6, 6)),
+            ],
+            region: Some((-8, 0, 16, 8)),
+        },
+    ]);
+    cases
+}
+
+fn logical_region((x, y, width, height): (i32, i32, u32, u32)) -> LogicalRegion {
+    LogicalRegion {
+        inner: Region {
+            position: Position { x, y },
+            size: Size { width, height },
+        },
+    }
+}
+
+/// Buffer content that shows how it was oriented and scaled: red grows to
+/// the right, green to the bottom of the buffer and blue differs between
+/// outputs.
+fn buffer_content(index: usize, (width, height): (u32, u32)) -> Vec<u8> {
+    let ramp = |value: u32, max: u32| (value * 255 / (max - 1).max(1)) as u8;
+    RgbaImage::from_fn(width, height, |x, y| {
+        image::Rgba([ramp(x, width), ramp(y, height), 64 + index as u8 * 64, 255])
+    })
+    .into_raw()
+}
+
+fn composite(case: &Case) -> RgbaImage {
+    // The frames are only composited, so their outputs never have to talk to
+    // a compositor.
+    let (socket, _compositor) = UnixStream::pair().unwrap();
+    let conn = Connection::from_socket(socket).unwrap();
+
+    let outputs: Vec<_> = case
+        .outputs
+        .iter()
+        .enumerate()
+        .map(|(index, output)| {
+            let output_info = OutputInfo {
+                wl_output: WlOutput::inert(conn.backend().downgrade()),
+                name: format!("output-{index}"),
+                description: String::new(),
+                transform: output.transform,
+                physical_size: Size {
+                    width: output.buffer.0,
+                    height: output.buffer.1,
+                },
+                logical_region: logical_region(output.logical),
+            };
+            (index, output, output_info)
+        })
+        .collect();
+    let capture_region = match case.region {
+        Some(region) => logical_region(region),
+        None => LogicalRegion::bounding_box(
+            outputs
+                .iter()
+                .map(|(_, _, output_info)| output_info.logical_region),
+        )
+        .unwrap(),
+    };
+
+    let frames = outputs
+        .into_iter()
+        .filter_map(|(index, output, output_info)| {
+            let logical_region = capture_region.intersection(&output_info.logical_region)?;
+            let (width, height) = output.buffer;
+            let frame_copy = FrameCopy {
+                frame_format: FrameFormat {
+                    format: Format::Abgr8888,
+                    size: Size { width, height },
+                    stride: width * 4,
+                },
+                frame_color_type: ColorType::Rgba8,
+                frame_data: FrameData::Owned(buffer_content(index, output.buffer)),
+                transform: output.transform,
+                logical_region,
+                physical_size: output_info.transformed_physical_size(),
+                presentation_time: None,
+                damage: None,
+            };
+            Some((frame_copy, output_info))
+        })
+        .collect();
+
+    libwayshot::composite_frames(frames, capture_region)
+        .unwrap()
+        .to_rgba8()
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+#[test]
+fn composited_frames_match_golden_images() {
+    let bless = env::var_os("WAYSHOT_BLESS").is_some();
+    let mut mismatches = Vec::new();
+
+    for case in cases() {
+        let actual = composite(&case);
+        let path = golden_path(&case.name);
+        if bless {
+            actual.save(&path).unwrap();
+            continue;
+        }
+
+        let expected = match image::open(&path) {
+            Ok(expected) => expected.to_rgba8(),
+            Err(e) => {
+                mismatches.push(format!(
+                    "{}: cannot read {}: {e}",
+                    case.name,
+                    path.display()
+                ));
+                continue;
+            }
+        };
+        if expected == actual {
+            continue;
+        }
+
+        let actual_path =
+            PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", case.name));
+        actual.save(&actual_path).unwrap();
+        let difference = if expected.dimensions() != actual.dimensions() {
+            format!(
+                "size {:?} instead of {:?}",
+                actual.dimensions(),
+                expected.dimensions()
+            )
+        } else {
+            let (x, y, pixel) = actual
+                .enumerate_pixels()
+                .find(|&(x, y, pixel)| expected.get_pixel(x, y) != pixel)
+                .unwrap();
+            format!(
+                "pixel at {x},{y} is {:?} instead of {:?}",
+                pixel.0,
+                expected.get_pixel(x, y).0
+            )
+        };
+        mismatches.push(format!(
+            "{}: {difference}, see {}",
+            case.name,
+            actual_path.display()
+        ));
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "composited images differ from the golden images (set WAYSHOT_BLESS=1 to update them):\n{}",
+        mismatches.join("\n")
+    );
+}
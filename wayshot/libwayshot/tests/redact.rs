@@ -0,0 +1,119 @@
+//! Hiding regions of screenshots with `libwayshot::redact`.
+
+mod common;
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use libwayshot::{
+    redact::{self, RedactionStyle},
+    region::LogicalRegion,
+};
+
+/// Vertical dark and light stripes, one pixel wide.
+fn stripes(width: u32, height: u32) -> DynamicImage {
+    RgbaImage::from_fn(width, height, |x, _| {
+        let value = if x % 2 == 0 { 60 } else { 240 };
+        Rgba([value, value, value, 255])
+    })
+    .into()
+}
+
+fn region(s: &str) -> LogicalRegion {
+    common::region(s)
+}
+
+/// Coordinates of the pixels that differ between the images.
+fn changed(before: &DynamicImage, after: &DynamicImage) -> Vec<(u32, u32)> {
+    before
+        .pixels()
+        .zip(after.pixels())
+        .filter(|((_, _, before), (_, _, after))| before != after)
+        .map(|((x, y, _), _)| (x, y))
+        .collect()
+}
+
+/// Bounding box of the changed pixels as `(left, top, right, bottom)`.
+fn changed_bounds(before: &DynamicImage, after: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    common::bounds(changed(before, after))
+}
+
+#[test]
+fn blackout_paints_the_region_black() {
+    let original = stripes(20, 10);
+    let mut image = original.clone();
+    redact::redact(
+        &mut image,
+        region("100,50 20x10"),
+        &[region("105,52 4x3")],
+        RedactionStyle::Blackout,
+    );
+
+    assert_eq!(changed_bounds(&original, &image), Some((5, 2, 9, 5)));
+    for (x, y) in [(5, 2), (6, 2), (8, 4)] {
+        assert_eq!(image.get_pixel(x, y), Rgba([0, 0, 0, 255]), "{x}, {y}");
+    }
+}
+
+#[test]
+fn partially_covered_pixels_are_redacted() {
+    // Composited at scale 1.5.
+    let original = stripes(30, 15);
+    let mut image = original.clone();
+    redact::redact(
+        &mut image,
+        region("0,0 20x10"),
+        &[region("1,0 1x1")],
+        RedactionStyle::Blackout,
+    );
+
+    assert_eq!(changed_bounds(&original, &image), Some((1, 0, 3, 2)));
+}
+
+#[test]
+fn only_the_captured_part_of_a_region_is_redacted() {
+    let original = stripes(20, 10);
+    let mut image = original.clone();
+    redact::redact(
+        &mut image,
+        region("100,50 20x10"),
+        &[region("90,45 15x10"), region("0,0 50x50")],
+        RedactionStyle::Blackout,
+    );
+
+    assert_eq!(changed_bounds(&original, &image), Some((0, 0, 5, 5)));
+}
+
+#[test]
+fn pixelate_replaces_blocks_with_their_average() {
+    let original = stripes(40, 16);
+    let mut image = original.clone();
+    redact::redact(
+        &mut image,
+        region("0,0 40x16"),
+        &[region("0,0 20x16")],
+        RedactionStyle::Pixelate,
+    );
+
+    // A full block of 16 x 16 pixels and the 4 pixels wide rest of the region.
+    for (x, y) in [(0, 0), (15, 15), (16, 0), (19, 15)] {
+        assert_eq!(image.get_pixel(x, y), Rgba([150, 150, 150, 255]));
+    }
+    assert_eq!(changed_bounds(&original, &image), Some((0, 0, 20, 16)));
+}
+
+#[test]
+fn blur_stays_inside_the_region() {
+    let original = stripes(40, 20);
+    let mut image = original.clone();
+    redact::redact(
+        &mut image,
+        region("0,0 40x20"),
+        &[region("10,5 10x10")],
+        RedactionStyle::Blur,
+    );
+
+    let bounds = changed_bounds(&original, &image).expect("nothing was blurred");
+    assert!(bounds.0 >= 10 && bounds.1 >= 5 && bounds.2 <= 20 && bounds.3 <= 15);
+    // The stripes are smeared into grey.
+    let [value, ..] = image.get_pixel(15, 10).0;
+    assert!((100..200).contains(&value), "{value}");
+}
@@ -0,0 +1,301 @@
+//! The ext-image-capture-source and ext-image-copy-capture protocols.
+
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use wayland_protocols::ext::{
+    image_capture_source::v1::server::{
+        ext_image_capture_source_v1::{self, ExtImageCaptureSourceV1},
+        ext_output_image_capture_source_manager_v1::{self, ExtOutputImageCaptureSourceManagerV1},
+    },
+    image_copy_capture::v1::server::{
+        ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1, FailureReason},
+        ext_image_copy_capture_manager_v1::{self, ExtImageCopyCaptureManagerV1},
+        ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+    },
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, backend::ObjectId,
+    protocol::wl_buffer::WlBuffer,
+};
+
+use crate::{State, output::Failure, shm::Buffer};
+
+/// Output captured by a source or session, `None` if it was already gone.
+type OutputId = Option<usize>;
+
+#[derive(Debug)]
+pub(crate) struct FrameData {
+    output: OutputId,
+    session: ObjectId,
+    buffer: Mutex<Option<WlBuffer>>,
+}
+
+impl GlobalDispatch<ExtOutputImageCaptureSourceManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtOutputImageCaptureSourceManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ExtOutputImageCaptureSourceManagerV1,
+        request: ext_output_image_capture_source_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_output_image_capture_source_manager_v1::Request::CreateSource {
+            source,
+            output,
+        } = request
+        {
+            let output_id = output
+                .data::<usize>()
+                .copied()
+                .filter(|id| state.output(*id).is_some());
+            data_init.init(source, output_id);
+        }
+    }
+}
+
+impl Dispatch<ExtImageCaptureSourceV1, OutputId> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtImageCaptureSourceV1,
+        _request: ext_image_capture_source_v1::Request,
+        _data: &OutputId,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ExtImageCopyCaptureManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtImageCopyCaptureManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ExtImageCopyCaptureManagerV1,
+        request: ext_image_copy_capture_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_copy_capture_manager_v1::Request::CreateSession {
+                session, source, ..
+            } => {
+                let output_id = source.data::<OutputId>().copied().flatten();
+                let session = data_init.init(session, output_id);
+                state.start_session(session);
+            }
+            // There is no seat, so clients have no pointer to pass.
+            ext_image_copy_capture_manager_v1::Request::CreatePointerCursorSession {
+                session,
+                ..
+            } => {
+                data_init.post_error(
+                    session,
+                    ext_image_copy_capture_manager_v1::Error::InvalidOption,
+                    "cursor sessions are not supported",
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+impl State {
+    /// Send the buffer constraints of a new session, unless the output is
+    /// scripted otherwise.
+    fn start_session(&mut self, session: ExtImageCopyCaptureSessionV1) {
+        let output = session
+            .data::<OutputId>()
+            .copied()
+            .flatten()
+            .and_then(|id| self.output(id));
+        let Some(output) = output else {
+            session.stopped();
+            return;
+        };
+
+        match output.config.failure {
+            Some(Failure::StopSessions) => {
+                session.stopped();
+                return;
+            }
+            Some(Failure::WithholdConstraints) => {}
+            _ => {
+                let (width, height) = output.config.size;
+                session.buffer_size(width as u32, height as u32);
+                session.shm_format(output.config.format);
+                session.done();
+            }
+        }
+        self.sessions.push(session);
+    }
+
+    /// Capture the held frames again, after an output changed.
+    pub(crate) fn capture_held_frames(&mut self) {
+        for frame in std::mem::take(&mut self.held_frames) {
+            if !frame.is_alive() {
+                continue;
+            }
+            if let Some(data) = frame.data::<FrameData>() {
+                self.capture_frame(&frame, data);
+            }
+        }
+    }
+
+    /// Stop every session capturing the output `id`.
+    pub(crate) fn stop_sessions(&mut self, id: usize) {
+        self.sessions.retain(|session| {
+            if !session.is_alive() {
+                return false;
+            }
+            if session.data::<OutputId>().copied().flatten() == Some(id) {
+                session.stopped();
+                return false;
+            }
+            true
+        });
+    }
+
+    fn capture_frame(&mut self, frame: &ExtImageCopyCaptureFrameV1, data: &FrameData) {
+        let Some(output) = data.output.and_then(|id| self.output_mut(id)) else {
+            frame.failed(FailureReason::Stopped);
+            return;
+        };
+
+        match output.config.failure {
+            Some(Failure::FailFrames(reason)) => {
+                frame.failed(reason);
+                return;
+            }
+            Some(Failure::WithholdFrames) => return,
+            Some(Failure::WithholdUnchangedFrames)
+                if output.session_versions.get(&data.session) == Some(&output.version) =>
+            {
+                self.held_frames.push(frame.clone());
+                return;
+            }
+            _ => {}
+        }
+
+        let buffer = data
+            .buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let (width, height) = output.config.size;
+        let buffer = buffer
+            .as_ref()
+            .and_then(|buffer| buffer.data::<Buffer>())
+            .filter(|buffer| {
+                (buffer.width, buffer.height) == (width as u32, height as u32)
+                    && buffer.format == Some(output.config.format)
+            });
+        let Some(buffer) = buffer else {
+            frame.failed(FailureReason::BufferConstraints);
+            return;
+        };
+        if buffer.write(&output.config.content).is_err() {
+            frame.failed(FailureReason::BufferConstraints);
+            return;
+        }
+        output.frames_captured += 1;
+        if output.mapped_surfaces > 0 {
+            output.frames_with_surfaces += 1;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        frame.transform(output.config.transform);
+        // Only report damage if the output changed since the session's last
+        // frame, every first frame is damaged.
+        let version = output.version;
+        let seen = output
+            .session_versions
+            .insert(data.session.clone(), version);
+        if seen != Some(version) {
+            frame.damage(0, 0, width, height);
+        }
+        frame.presentation_time(
+            (now.as_secs() >> 32) as u32,
+            now.as_secs() as u32,
+            now.subsec_nanos(),
+        );
+        frame.ready();
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, OutputId> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &ExtImageCopyCaptureSessionV1,
+        request: ext_image_copy_capture_session_v1::Request,
+        output: &OutputId,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_image_copy_capture_session_v1::Request::CreateFrame { frame } = request {
+            data_init.init(
+                frame,
+                FrameData {
+                    output: *output,
+                    session: resource.id(),
+                    buffer: Mutex::default(),
+                },
+            );
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, FrameData> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtImageCopyCaptureFrameV1,
+        request: ext_image_copy_capture_frame_v1::Request,
+        data: &FrameData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_image_copy_capture_frame_v1::Request::AttachBuffer { buffer } => {
+                *data.buffer.lock().unwrap_or_else(PoisonError::into_inner) = Some(buffer);
+            }
+            ext_image_copy_capture_frame_v1::Request::Capture => {
+                state.capture_frame(resource, data);
+            }
+            _ => {}
+        }
+    }
+}
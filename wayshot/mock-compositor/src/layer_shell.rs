@@ -0,0 +1,320 @@
+//! `wl_compositor`, `wl_subcompositor` and `zwlr_layer_shell_v1`, enough to
+//! show the overlays used to freeze the screen.
+
+use std::sync::{Mutex, PoisonError};
+
+use wayland_protocols_wlr::layer_shell::v1::server::{
+    zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
+    zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    backend::ClientId,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_callback::WlCallback,
+        wl_compositor::{self, WlCompositor},
+        wl_output::WlOutput,
+        wl_region::{self, WlRegion},
+        wl_subcompositor::{self, WlSubcompositor},
+        wl_subsurface::{self, WlSubsurface},
+        wl_surface::{self, WlSurface},
+    },
+};
+
+use crate::{State, output::Failure};
+
+#[derive(Debug, Default)]
+pub(crate) struct SurfaceData(Mutex<Surface>);
+
+#[derive(Debug, Default)]
+struct Surface {
+    layer_surface: Option<ZwlrLayerSurfaceV1>,
+    configured: bool,
+    /// Attached since the last commit, `Some(None)` detaches the buffer.
+    pending_buffer: Option<Option<WlBuffer>>,
+    buffer: Option<WlBuffer>,
+    /// Output the buffer is shown on.
+    shown_on: Option<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct LayerSurfaceData {
+    output: Option<WlOutput>,
+    size: Mutex<(u32, u32)>,
+}
+
+impl GlobalDispatch<WlCompositor, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlCompositor>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WlCompositor, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &WlCompositor,
+        request: wl_compositor::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_compositor::Request::CreateSurface { id } => {
+                data_init.init(id, SurfaceData::default());
+                state.surfaces += 1;
+            }
+            wl_compositor::Request::CreateRegion { id } => {
+                data_init.init(id, ());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlRegion, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlRegion,
+        _request: wl_region::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<WlSubcompositor, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlSubcompositor>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WlSubcompositor, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &WlSubcompositor,
+        request: wl_subcompositor::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_subcompositor::Request::GetSubsurface { id, .. } = request {
+            data_init.init(id, ());
+            state.subsurfaces += 1;
+        }
+    }
+}
+
+/// Subsurfaces are only counted, where they are drawn doesn't matter.
+impl Dispatch<WlSubsurface, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlSubsurface,
+        _request: wl_subsurface::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, _resource: &WlSubsurface, _data: &()) {
+        state.subsurfaces -= 1;
+    }
+}
+
+impl Dispatch<WlCallback, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlCallback,
+        _request: <WlCallback as Resource>::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlSurface, SurfaceData> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WlSurface,
+        request: wl_surface::Request,
+        data: &SurfaceData,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let mut surface = data.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match request {
+            wl_surface::Request::Attach { buffer, .. } => {
+                surface.pending_buffer = Some(buffer);
+            }
+            wl_surface::Request::Frame { callback } => {
+                data_init.init(callback, ()).done(0);
+            }
+            wl_surface::Request::Commit => state.commit(resource, &mut surface),
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &WlSurface, data: &SurfaceData) {
+        let mut surface = data.0.lock().unwrap_or_else(PoisonError::into_inner);
+        state.show_on(resource, &mut surface, None);
+        state.unfocus(resource);
+        state.surfaces -= 1;
+    }
+}
+
+impl State {
+    /// Configure layer surfaces on their first commit, afterwards show their
+    /// buffer on the output.
+    fn commit(&mut self, wl_surface: &WlSurface, surface: &mut Surface) {
+        if let Some(buffer) = surface.pending_buffer.take() {
+            surface.buffer = buffer;
+        }
+        let Some(layer_surface) = surface.layer_surface.clone() else {
+            return;
+        };
+        let Some(data) = layer_surface.data::<LayerSurfaceData>() else {
+            return;
+        };
+        let output_id = data
+            .output
+            .as_ref()
+            .and_then(|output| output.data::<usize>())
+            .copied();
+        let output = output_id.and_then(|id| self.output(id));
+
+        if !surface.configured {
+            if output
+                .is_some_and(|output| output.config.failure == Some(Failure::WithholdConfigure))
+            {
+                return;
+            }
+
+            let (mut width, mut height) = *data.size.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(output) = output {
+                let (output_width, output_height) = output.config.logical_size();
+                if width == 0 {
+                    width = output_width as u32;
+                }
+                if height == 0 {
+                    height = output_height as u32;
+                }
+            }
+            self.serial = self.serial.wrapping_add(1);
+            layer_surface.configure(self.serial, width, height);
+            surface.configured = true;
+        } else {
+            self.show_on(wl_surface, surface, surface.buffer.as_ref().and(output_id));
+            if surface.buffer.is_some()
+                && let Some(output) = &data.output
+            {
+                wl_surface.enter(output);
+            }
+        }
+    }
+
+    /// Count `surface` as shown on the output with id `output` from now on.
+    fn show_on(&mut self, wl_surface: &WlSurface, surface: &mut Surface, output: Option<usize>) {
+        if surface.shown_on == output {
+            return;
+        }
+        self.shown_surfaces.retain(|(shown, _)| shown != wl_surface);
+        if let Some(output) = output {
+            self.shown_surfaces.push((wl_surface.clone(), output));
+        } else {
+            self.unfocus(wl_surface);
+        }
+        if let Some(previous) = surface.shown_on.and_then(|id| self.output_mut(id)) {
+            previous.mapped_surfaces -= 1;
+        }
+        if let Some(output) = output.and_then(|id| self.output_mut(id)) {
+            output.mapped_surfaces += 1;
+        }
+        surface.shown_on = output;
+    }
+}
+
+impl GlobalDispatch<ZwlrLayerShellV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrLayerShellV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrLayerShellV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrLayerShellV1,
+        request: zwlr_layer_shell_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_layer_shell_v1::Request::GetLayerSurface {
+            id,
+            surface,
+            output,
+            ..
+        } = request
+        {
+            let layer_surface = data_init.init(
+                id,
+                LayerSurfaceData {
+                    output,
+                    size: Mutex::default(),
+                },
+            );
+            if let Some(data) = surface.data::<SurfaceData>() {
+                data.0
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .layer_surface = Some(layer_surface);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, LayerSurfaceData> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrLayerSurfaceV1,
+        request: zwlr_layer_surface_v1::Request,
+        data: &LayerSurfaceData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_layer_surface_v1::Request::SetSize { width, height } = request {
+            *data.size.lock().unwrap_or_else(PoisonError::into_inner) = (width, height);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Minimal Wayland compositor running on a thread of the test process, to
+//! test `libwayshot` end to end without a real compositor.
+//!
+//! It offers `wl_output`, `zxdg_output_manager_v1`, `wl_shm`, the
+//! ext-image-copy-capture protocols, `wl_compositor`, `wl_subcompositor`,
+//! `zwlr_layer_shell_v1` and a `wl_seat`. What its outputs look like and how
+//! they misbehave is scripted with [`MockOutput`], the seat's pointer and
+//! keyboard are driven with methods of [`MockCompositor`].
+//!
+//! # Example usage
+//!
+//! ```ignore
+//! use wayshot_mock_compositor::{Content, MockCompositor, MockOutput};
+//! let compositor = MockCompositor::new([
+//!     MockOutput::new("DP-1", 1920, 1080).content(Content::Solid([255, 0, 0, 255])),
+//! ])?;
+//! let wayshot_connection = WayshotConnection::from_connection(compositor.connect()?)?;
+//! ```
+
+mod capture;
+mod layer_shell;
+mod output;
+mod seat;
+mod shm;
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use rustix::event::{PollFd, PollFlags, poll};
+use wayland_client::Connection;
+use wayland_protocols::{
+    ext::{
+        image_capture_source::v1::server::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+        image_copy_capture::v1::server::{
+            ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1,
+            ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+            ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1,
+        },
+    },
+    xdg::xdg_output::zv1::server::zxdg_output_manager_v1::ZxdgOutputManagerV1,
+};
+use wayland_protocols_wlr::layer_shell::v1::server::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+use wayland_server::{
+    Display, DisplayHandle, Resource,
+    backend::{ClientData, ClientId, DisconnectReason, protocol::Interface},
+    protocol::{
+        wl_compositor::WlCompositor, wl_output::WlOutput, wl_pointer::ButtonState, wl_seat::WlSeat,
+        wl_shm::WlShm, wl_subcompositor::WlSubcompositor, wl_surface::WlSurface,
+    },
+};
+
+pub use crate::{
+    output::{Content, Failure, MockOutput},
+    seat::{BUTTON_LEFT, BUTTON_RIGHT, KEY_ESCAPE},
+};
+pub use wayland_protocols::ext::image_copy_capture::v1::server::ext_image_copy_capture_frame_v1::FailureReason;
+pub use wayland_server::protocol::{wl_output::Transform, wl_shm::Format};
+
+use crate::{output::OutputState, seat::SeatState};
+
+type Command = Box<dyn FnOnce(&mut State, &DisplayHandle) + Send>;
+
+/// A compositor serving clients on a thread of its own, stopped when
+/// dropped.
+#[derive(Debug)]
+pub struct MockCompositor {
+    commands: Sender<Command>,
+    /// Written to after sending a command, to wake up the compositor thread.
+    wake: UnixStream,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockCompositor {
+    /// Start a compositor with the given outputs.
+    pub fn new(outputs: impl IntoIterator<Item = MockOutput>) -> io::Result<Self> {
+        Self::without_globals(outputs, &[])
+    }
+
+    /// Start a compositor with the given outputs that doesn't advertise the
+    /// globals with the given interface names, e.g. `"wl_shm"`.
+    pub fn without_globals(
+        outputs: impl IntoIterator<Item = MockOutput>,
+        omitted: &[&str],
+    ) -> io::Result<Self> {
+        let display = Display::<State>::new().map_err(io::Error::other)?;
+        let handle = display.handle();
+        let advertised = |interface: &Interface| !omitted.contains(&interface.name);
+        if advertised(ZxdgOutputManagerV1::interface()) {
+            handle.create_global::<State, ZxdgOutputManagerV1, _>(3, ());
+        }
+        if advertised(WlShm::interface()) {
+            handle.create_global::<State, WlShm, _>(1, ());
+        }
+        if advertised(ExtOutputImageCaptureSourceManagerV1::interface()) {
+            handle.create_global::<State, ExtOutputImageCaptureSourceManagerV1, _>(1, ());
+        }
+        if advertised(ExtImageCopyCaptureManagerV1::interface()) {
+            handle.create_global::<State, ExtImageCopyCaptureManagerV1, _>(1, ());
+        }
+        if advertised(WlCompositor::interface()) {
+            handle.create_global::<State, WlCompositor, _>(6, ());
+        }
+        if advertised(WlSubcompositor::interface()) {
+            handle.create_global::<State, WlSubcompositor, _>(1, ());
+        }
+        if advertised(ZwlrLayerShellV1::interface()) {
+            handle.create_global::<State, ZwlrLayerShellV1, _>(4, ());
+        }
+        if advertised(WlSeat::interface()) {
+            handle.create_global::<State, WlSeat, _>(5, ());
+        }
+
+        let mut state = State::default();
+        for output in outputs {
+            state.add_output(&handle, output);
+        }
+
+        let (commands, command_receiver) = mpsc::channel();
+        let (wake, wake_receiver) = UnixStream::pair()?;
+        let thread = thread::Builder::new()
+            .name("mock-compositor".to_owned())
+            .spawn(move || run(display, state, command_receiver, wake_receiver))?;
+
+        Ok(Self {
+            commands,
+            wake,
+            thread: Some(thread),
+        })
+    }
+
+    /// Connect a new client.
+    pub fn connect(&self) -> io::Result<Connection> {
+        let (client, server) = UnixStream::pair()?;
+        self.add_client(server)?;
+
+        Connection::from_socket(client).map_err(io::Error::other)
+    }
+
+    /// Serve the client at the other end of `stream`, e.g. a process that
+    /// connected to a listening socket.
+    pub fn add_client(&self, stream: UnixStream) -> io::Result<()> {
+        self.run(move |_, handle| {
+            handle
+                .clone()
+                .insert_client(stream, Arc::new(MockClient))
+                .map(|_| ())
+        })
+    }
+
+    /// Advertise another output.
+    pub fn add_output(&self, output: MockOutput) {
+        self.run(move |state, handle| state.add_output(handle, output));
+    }
+
+    /// Remove the output with the given name, stopping the sessions capturing
+    /// it.
+    pub fn remove_output(&self, name: &str) {
+        let name = name.to_owned();
+        self.run(move |state, handle| state.remove_output(handle, &name));
+    }
+
+    /// Change an output. Changes to its geometry are only seen by clients
+    /// binding the output afterwards, content, format and failures apply to
+    /// the next session or frame.
+    pub fn update_output(&self, name: &str, update: impl FnOnce(&mut MockOutput) + Send + 'static) {
+        let name = name.to_owned();
+        self.run(move |state, _| {
+            if let Some(output) = state
+                .outputs
+                .iter_mut()
+                .find(|output| output.config.name == name)
+            {
+                update(&mut output.config);
+                output.version += 1;
+            }
+            state.capture_held_frames();
+        });
+    }
+
+    /// Number of frames copied from the output with the given name.
+    pub fn frames_captured(&self, name: &str) -> usize {
+        let name = name.to_owned();
+        self.run(move |state, _| {
+            state
+                .outputs
+                .iter()
+                .find(|output| output.config.name == name)
+                .map_or(0, |output| output.frames_captured)
+        })
+    }
+
+    /// Number of frames copied from the output with the given name while a
+    /// layer surface was shown on it, which a real compositor would have
+    /// copied into the frame.
+    pub fn frames_with_layer_surfaces(&self, name: &str) -> usize {
+        let name = name.to_owned();
+        self.run(move |state, _| {
+            state
+                .outputs
+                .iter()
+                .find(|output| output.config.name == name)
+                .map_or(0, |output| output.frames_with_surfaces)
+        })
+    }
+
+    /// Number of layer surfaces shown on the output with the given name.
+    pub fn layer_surfaces_shown(&self, name: &str) -> usize {
+        let name = name.to_owned();
+        self.run(move |state, _| {
+            state
+                .outputs
+                .iter()
+                .find(|output| output.config.name == name)
+                .map_or(0, |output| output.mapped_surfaces)
+        })
+    }
+
+    /// Move the pointer to `x`, `y` on the layer surface shown on the output
+    /// with the given name, in coordinates local to the surface. Returns
+    /// `false` if no layer surface is shown on it.
+    pub fn move_pointer(&self, output: &str, x: f64, y: f64) -> bool {
+        let name = output.to_owned();
+        self.run(move |state, _| {
+            let Some(surface) = state.shown_surface(&name) else {
+                return false;
+            };
+            state.move_pointer(&surface, x, y);
+            true
+        })
+    }
+
+    /// Press and release a button, e.g. [`BUTTON_LEFT`], where the pointer
+    /// is.
+    pub fn click(&self, button: u32) {
+        self.press_button(button);
+        self.release_button(button);
+    }
+
+    /// Press a button where the pointer is and keep holding it, e.g. to drag.
+    pub fn press_button(&self, button: u32) {
+        self.run(move |state, _| state.button(button, ButtonState::Pressed));
+    }
+
+    /// Release a button pressed with [`MockCompositor::press_button`].
+    pub fn release_button(&self, button: u32) {
+        self.run(move |state, _| state.button(button, ButtonState::Released));
+    }
+
+    /// Press and release a key, e.g. [`KEY_ESCAPE`].
+    pub fn press_key(&self, key: u32) {
+        self.run(move |state, _| state.press_key(key));
+    }
+
+    /// Whether the client the pointer is on set a surface as the cursor.
+    pub fn has_cursor_surface(&self) -> bool {
+        self.run(|state, _| state.seat.cursor.is_some())
+    }
+
+    /// Number of `wl_output` objects bound by all clients.
+    pub fn output_objects(&self) -> usize {
+        self.run(|state, _| state.output_objects)
+    }
+
+    /// Number of surfaces of all clients.
+    pub fn surfaces(&self) -> usize {
+        self.run(|state, _| state.surfaces)
+    }
+
+    /// Number of subsurfaces of all clients.
+    pub fn subsurfaces(&self) -> usize {
+        self.run(|state, _| state.subsurfaces)
+    }
+
+    /// Number of `wl_shm` buffers of all clients.
+    pub fn buffers(&self) -> usize {
+        self.run(|state, _| state.buffers)
+    }
+
+    /// Run `command` on the compositor thread and wait for its result.
+    fn run<R: Send + 'static>(
+        &self,
+        command: impl FnOnce(&mut State, &DisplayHandle) -> R + Send + 'static,
+    ) -> R {
+        let (result_sender, result) = mpsc::sync_channel(1);
+        let command: Command = Box::new(move |state, handle| {
+            let _ = result_sender.send(command(state, handle));
+        });
+
+        self.commands
+            .send(command)
+            .expect("mock compositor thread stopped");
+        (&self.wake)
+            .write_all(&[0])
+            .expect("failed to wake up the mock compositor");
+        result.recv().expect("mock compositor thread stopped")
+    }
+}
+
+impl Drop for MockCompositor {
+    fn drop(&mut self) {
+        // Hanging up stops the compositor thread.
+        let _ = self.wake.shutdown(std::net::Shutdown::Both);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(
+    mut display: Display<State>,
+    mut state: State,
+    commands: Receiver<Command>,
+    mut wake: UnixStream,
+) {
+    let handle = display.handle();
+    loop {
+        let mut fds = [
+            PollFd::new(&display, PollFlags::IN),
+            PollFd::new(&wake, PollFlags::IN),
+        ];
+        match poll(&mut fds, None) {
+            Ok(_) | Err(rustix::io::Errno::INTR) => {}
+            Err(_) => return,
+        }
+        let woken = !fds[1].revents().is_empty();
+
+        if woken {
+            let mut buf = [0; 64];
+            match wake.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            while let Ok(command) = commands.try_recv() {
+                command(&mut state, &handle);
+            }
+        }
+
+        if display.dispatch_clients(&mut state).is_err() {
+            return;
+        }
+        let _ = display.flush_clients();
+    }
+}
+
+#[derive(Debug)]
+struct MockClient;
+
+impl ClientData for MockClient {
+    fn initialized(&self, _client_id: ClientId) {}
+
+    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    outputs: Vec<OutputState>,
+    next_output_id: usize,
+    output_objects: usize,
+    /// Live capture sessions, to stop them when their output is removed.
+    sessions: Vec<ExtImageCopyCaptureSessionV1>,
+    /// Frames waiting for their output to change.
+    held_frames: Vec<ExtImageCopyCaptureFrameV1>,
+    serial: u32,
+    /// Layer surfaces with a buffer, and the id of the output they are shown
+    /// on.
+    shown_surfaces: Vec<(WlSurface, usize)>,
+    surfaces: usize,
+    subsurfaces: usize,
+    buffers: usize,
+    seat: SeatState,
+}
+
+impl State {
+    fn output(&self, id: usize) -> Option<&OutputState> {
+        self.outputs.iter().find(|output| output.id == id)
+    }
+
+    fn output_mut(&mut self, id: usize) -> Option<&mut OutputState> {
+        self.outputs.iter_mut().find(|output| output.id == id)
+    }
+
+    /// The latest layer surface shown on the output with the given name.
+    fn shown_surface(&self, name: &str) -> Option<WlSurface> {
+        let id = self
+            .outputs
+            .iter()
+            .find(|output| output.config.name == name)?
+            .id;
+        self.shown_surfaces
+            .iter()
+            .rev()
+            .find(|(_, output)| *output == id)
+            .map(|(surface, _)| surface.clone())
+    }
+
+    fn add_output(&mut self, handle: &DisplayHandle, config: MockOutput) {
+        let id = self.next_output_id;
+        self.next_output_id += 1;
+        let global = handle.create_global::<State, WlOutput, _>(4, id);
+        self.outputs.push(OutputState {
+            id,
+            config,
+            global,
+            frames_captured: 0,
+            mapped_surfaces: 0,
+            frames_with_surfaces: 0,
+            version: 0,
+            session_versions: HashMap::new(),
+        });
+    }
+
+    fn remove_output(&mut self, handle: &DisplayHandle, name: &str) {
+        let Some(index) = self
+            .outputs
+            .iter()
+            .position(|output| output.config.name == name)
+        else {
+            return;
+        };
+
+        let output = self.outputs.remove(index);
+        // Disabled instead of removed, so clients binding it at the same time
+        // don't run into a protocol error.
+        handle.disable_global::<State>(output.global);
+        self.stop_sessions(output.id);
+        self.capture_held_frames();
+    }
+}
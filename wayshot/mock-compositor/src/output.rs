@@ -0,0 +1,276 @@
+//! Scriptable outputs, advertised as `wl_output` globals with their
+//! `zxdg_output_v1` counterparts.
+
+use std::collections::HashMap;
+
+use image::RgbaImage;
+use wayland_protocols::{
+    ext::image_copy_capture::v1::server::ext_image_copy_capture_frame_v1::FailureReason,
+    xdg::xdg_output::zv1::server::{
+        zxdg_output_manager_v1::{self, ZxdgOutputManagerV1},
+        zxdg_output_v1::{self, ZxdgOutputV1},
+    },
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    backend::{ClientId, ObjectId},
+    protocol::{
+        wl_output::{self, Mode, Subpixel, Transform, WlOutput},
+        wl_shm::Format,
+    },
+};
+
+use crate::State;
+
+/// Pixels an output shows, in buffer coordinates, i.e. before the output
+/// transform is applied.
+#[derive(Debug, Clone)]
+pub enum Content {
+    /// Every pixel has the same RGBA color.
+    Solid([u8; 4]),
+    /// The pixels of an image the size of the output mode. Pixels outside
+    /// the image are transparent black.
+    Image(RgbaImage),
+}
+
+impl Content {
+    /// RGBA color of the pixel at `x`, `y`.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        match self {
+            Content::Solid(color) => *color,
+            Content::Image(image) => image
+                .get_pixel_checked(x, y)
+                .map_or([0; 4], |pixel| pixel.0),
+        }
+    }
+}
+
+/// Ways an output can misbehave during captures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// Fail every frame with the given reason.
+    FailFrames(FailureReason),
+    /// Stop capture sessions as soon as they are created.
+    StopSessions,
+    /// Never send buffer constraints for capture sessions.
+    WithholdConstraints,
+    /// Never finish capturing frames.
+    WithholdFrames,
+    /// Only finish capturing a frame once the output changed since the
+    /// previous frame of its session, like compositors waiting for damage.
+    WithholdUnchangedFrames,
+    /// Never configure layer surfaces shown on the output.
+    WithholdConfigure,
+}
+
+/// Description of an output of the [`crate::MockCompositor`].
+#[derive(Debug, Clone)]
+pub struct MockOutput {
+    pub name: String,
+    pub description: String,
+    /// Position in the global compositor space.
+    pub position: (i32, i32),
+    /// Size of the mode in physical pixels, which is also the buffer size.
+    pub size: (i32, i32),
+    pub transform: Transform,
+    pub scale: i32,
+    pub content: Content,
+    /// The only `wl_shm` format offered for captures.
+    pub format: Format,
+    pub failure: Option<Failure>,
+}
+
+impl MockOutput {
+    /// An opaque black output of the given physical size at the origin.
+    pub fn new(name: impl Into<String>, width: i32, height: i32) -> Self {
+        let name = name.into();
+        Self {
+            description: format!("Mock output {name}"),
+            name,
+            position: (0, 0),
+            size: (width, height),
+            transform: Transform::Normal,
+            scale: 1,
+            content: Content::Solid([0, 0, 0, 255]),
+            format: Format::Xrgb8888,
+            failure: None,
+        }
+    }
+
+    pub fn position(mut self, x: i32, y: i32) -> Self {
+        self.position = (x, y);
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn scale(mut self, scale: i32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn content(mut self, content: Content) -> Self {
+        self.content = content;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn failure(mut self, failure: Failure) -> Self {
+        self.failure = Some(failure);
+        self
+    }
+
+    /// Size in the global compositor space, after applying the transform and
+    /// scale.
+    pub fn logical_size(&self) -> (i32, i32) {
+        let (width, height) = match self.transform {
+            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+                (self.size.1, self.size.0)
+            }
+            _ => self.size,
+        };
+        (width / self.scale, height / self.scale)
+    }
+}
+
+/// An output and the global advertising it.
+#[derive(Debug)]
+pub(crate) struct OutputState {
+    pub(crate) id: usize,
+    pub(crate) config: MockOutput,
+    pub(crate) global: wayland_server::backend::GlobalId,
+    /// Frames copied from this output so far.
+    pub(crate) frames_captured: usize,
+    /// Layer surfaces showing a buffer on this output.
+    pub(crate) mapped_surfaces: usize,
+    /// Frames copied while a layer surface was shown on this output.
+    pub(crate) frames_with_surfaces: usize,
+    /// Bumped whenever the output is updated.
+    pub(crate) version: u64,
+    /// Version of the output each capture session copied last, frames of
+    /// sessions that copied the current version are not damaged.
+    pub(crate) session_versions: HashMap<ObjectId, u64>,
+}
+
+impl GlobalDispatch<WlOutput, usize> for State {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlOutput>,
+        id: &usize,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let output = data_init.init(resource, *id);
+        state.output_objects += 1;
+        let Some(config) = state.output(*id).map(|output| &output.config) else {
+            return;
+        };
+
+        output.geometry(
+            config.position.0,
+            config.position.1,
+            0,
+            0,
+            Subpixel::Unknown,
+            "wayshot".to_owned(),
+            "mock".to_owned(),
+            config.transform,
+        );
+        output.mode(Mode::Current, config.size.0, config.size.1, 60_000);
+        if output.version() >= 2 {
+            output.scale(config.scale);
+        }
+        if output.version() >= 4 {
+            output.name(config.name.clone());
+            output.description(config.description.clone());
+        }
+        if output.version() >= 2 {
+            output.done();
+        }
+    }
+}
+
+impl Dispatch<WlOutput, usize> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlOutput,
+        _request: wl_output::Request,
+        _id: &usize,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, _resource: &WlOutput, _id: &usize) {
+        state.output_objects -= 1;
+    }
+}
+
+impl GlobalDispatch<ZxdgOutputManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZxdgOutputManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZxdgOutputManagerV1,
+        request: zxdg_output_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let zxdg_output_manager_v1::Request::GetXdgOutput { id, output } = request else {
+            return;
+        };
+
+        let output_id = *output.data::<usize>().unwrap_or(&usize::MAX);
+        let xdg_output = data_init.init(id, ());
+        let Some(config) = state.output(output_id).map(|output| &output.config) else {
+            return;
+        };
+
+        let (width, height) = config.logical_size();
+        xdg_output.logical_position(config.position.0, config.position.1);
+        xdg_output.logical_size(width, height);
+        if xdg_output.version() >= 2 {
+            xdg_output.name(config.name.clone());
+            xdg_output.description(config.description.clone());
+        }
+        // Since version 3 wl_output.done is used instead.
+        if xdg_output.version() < 3 {
+            xdg_output.done();
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZxdgOutputV1,
+        _request: zxdg_output_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
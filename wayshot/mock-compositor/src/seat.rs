@@ -0,0 +1,194 @@
+//! `wl_seat` with a pointer and a keyboard, driven by the test through
+//! [`crate::MockCompositor`] to click on layer surfaces.
+
+use wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    backend::ClientId,
+    protocol::{
+        wl_keyboard::{self, WlKeyboard},
+        wl_pointer::{self, WlPointer},
+        wl_seat::{self, WlSeat},
+        wl_surface::WlSurface,
+    },
+};
+
+use crate::State;
+
+/// `BTN_LEFT` of `linux/input-event-codes.h`.
+pub const BUTTON_LEFT: u32 = 0x110;
+/// `BTN_RIGHT` of `linux/input-event-codes.h`.
+pub const BUTTON_RIGHT: u32 = 0x111;
+/// `KEY_ESC` of `linux/input-event-codes.h`.
+pub const KEY_ESCAPE: u32 = 1;
+
+#[derive(Debug, Default)]
+pub(crate) struct SeatState {
+    pointers: Vec<WlPointer>,
+    keyboards: Vec<WlKeyboard>,
+    /// Surface the pointer is on.
+    focus: Option<WlSurface>,
+    /// Surface set as the cursor by the client the pointer is on.
+    pub(crate) cursor: Option<WlSurface>,
+}
+
+impl State {
+    fn next_serial(&mut self) -> u32 {
+        self.serial = self.serial.wrapping_add(1);
+        self.serial
+    }
+
+    /// Move the pointer onto `surface` at the surface local `x`, `y`.
+    pub(crate) fn move_pointer(&mut self, surface: &WlSurface, x: f64, y: f64) {
+        let serial = self.next_serial();
+        let focus = self.seat.focus.clone();
+        if focus.as_ref() != Some(surface) {
+            if let Some(focus) = &focus {
+                for pointer in self.pointers_of(focus) {
+                    pointer.leave(serial, focus);
+                    frame(&pointer);
+                }
+            }
+            self.seat.cursor = None;
+            for pointer in self.pointers_of(surface) {
+                pointer.enter(serial, surface, x, y);
+                frame(&pointer);
+            }
+            self.seat.focus = Some(surface.clone());
+        } else {
+            for pointer in self.pointers_of(surface) {
+                pointer.motion(0, x, y);
+                frame(&pointer);
+            }
+        }
+    }
+
+    /// Press or release `button` on the surface the pointer is on.
+    pub(crate) fn button(&mut self, button: u32, state: wl_pointer::ButtonState) {
+        let Some(focus) = self.seat.focus.clone() else {
+            return;
+        };
+        let serial = self.next_serial();
+        for pointer in self.pointers_of(&focus) {
+            pointer.button(serial, 0, button, state);
+            frame(&pointer);
+        }
+    }
+
+    /// Press and release `key` on the keyboards of every client.
+    pub(crate) fn press_key(&mut self, key: u32) {
+        for state in [
+            wl_keyboard::KeyState::Pressed,
+            wl_keyboard::KeyState::Released,
+        ] {
+            let serial = self.next_serial();
+            for keyboard in &self.seat.keyboards {
+                keyboard.key(serial, 0, key, state);
+            }
+        }
+    }
+
+    /// The pointers of the client owning `surface`.
+    fn pointers_of(&self, surface: &WlSurface) -> Vec<WlPointer> {
+        self.seat
+            .pointers
+            .iter()
+            .filter(|pointer| pointer.id().same_client_as(&surface.id()))
+            .cloned()
+            .collect()
+    }
+
+    /// Forget `surface`, e.g. because it was destroyed or unmapped.
+    pub(crate) fn unfocus(&mut self, surface: &WlSurface) {
+        if self.seat.focus.as_ref() == Some(surface) {
+            self.seat.focus = None;
+        }
+        if self.seat.cursor.as_ref() == Some(surface) {
+            self.seat.cursor = None;
+        }
+    }
+}
+
+fn frame(pointer: &WlPointer) {
+    if pointer.version() >= 5 {
+        pointer.frame();
+    }
+}
+
+impl GlobalDispatch<WlSeat, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlSeat>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let seat = data_init.init(resource, ());
+        seat.capabilities(wl_seat::Capability::Pointer | wl_seat::Capability::Keyboard);
+    }
+}
+
+impl Dispatch<WlSeat, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &WlSeat,
+        request: wl_seat::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_seat::Request::GetPointer { id } => {
+                state.seat.pointers.push(data_init.init(id, ()));
+            }
+            wl_seat::Request::GetKeyboard { id } => {
+                state.seat.keyboards.push(data_init.init(id, ()));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlPointer, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WlPointer,
+        request: wl_pointer::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_pointer::Request::SetCursor { surface, .. } = request
+            && state
+                .seat
+                .focus
+                .as_ref()
+                .is_some_and(|focus| focus.id().same_client_as(&resource.id()))
+        {
+            state.seat.cursor = surface;
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &WlPointer, _data: &()) {
+        state.seat.pointers.retain(|pointer| pointer != resource);
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlKeyboard,
+        _request: wl_keyboard::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &WlKeyboard, _data: &()) {
+        state.seat.keyboards.retain(|keyboard| keyboard != resource);
+    }
+}
@@ -0,0 +1,223 @@
+//! `wl_shm` pools and buffers, and writing output content into them.
+
+use std::{
+    fs::File,
+    io,
+    os::fd::OwnedFd,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use memmap2::MmapOptions;
+use wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, WEnum,
+    backend::ClientId,
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_shm::{self, Format, WlShm},
+        wl_shm_pool::{self, WlShmPool},
+    },
+};
+
+use crate::{State, output::Content};
+
+/// Formats advertised on `wl_shm`, all of which can be written.
+const FORMATS: &[Format] = &[
+    Format::Argb8888,
+    Format::Xrgb8888,
+    Format::Abgr8888,
+    Format::Xbgr8888,
+    Format::Abgr2101010,
+    Format::Xbgr2101010,
+    Format::Bgr888,
+    Format::Rgb888,
+    Format::Rgb565,
+];
+
+#[derive(Debug)]
+pub(crate) struct Pool {
+    fd: OwnedFd,
+    size: Mutex<usize>,
+}
+
+/// Layout of a `wl_buffer` within its pool.
+#[derive(Debug)]
+pub(crate) struct Buffer {
+    pool: Arc<Pool>,
+    offset: usize,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    stride: usize,
+    pub(crate) format: Option<Format>,
+}
+
+impl Buffer {
+    /// Fill the buffer with `content`, encoded in the format of the buffer.
+    pub(crate) fn write(&self, content: &Content) -> io::Result<()> {
+        let bytes_per_pixel = self
+            .format
+            .and_then(bytes_per_pixel)
+            .ok_or_else(|| io::Error::other("unsupported buffer format"))?;
+        let size = *self
+            .pool
+            .size
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.offset + self.stride * self.height as usize > size
+            || self.stride < self.width as usize * bytes_per_pixel
+        {
+            return Err(io::Error::other("buffer exceeds its pool"));
+        }
+
+        let file = File::from(self.pool.fd.try_clone()?);
+        // SAFETY: the client may change the pool under us, which at worst
+        // garbles the pixels it receives.
+        let mut map = unsafe { MmapOptions::new().len(size).map_mut(&file)? };
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let start = self.offset + y as usize * self.stride + x as usize * bytes_per_pixel;
+                encode(
+                    self.format.unwrap_or(Format::Xrgb8888),
+                    content.pixel(x, y),
+                    &mut map[start..start + bytes_per_pixel],
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn bytes_per_pixel(format: Format) -> Option<usize> {
+    match format {
+        Format::Argb8888
+        | Format::Xrgb8888
+        | Format::Abgr8888
+        | Format::Xbgr8888
+        | Format::Abgr2101010
+        | Format::Xbgr2101010 => Some(4),
+        Format::Bgr888 | Format::Rgb888 => Some(3),
+        Format::Rgb565 => Some(2),
+        _ => None,
+    }
+}
+
+/// Encode an RGBA pixel in the little endian layout of `format`.
+fn encode(format: Format, [r, g, b, a]: [u8; 4], pixel: &mut [u8]) {
+    let to_10_bits = |channel: u8| (u32::from(channel) << 2) | (u32::from(channel) >> 6);
+    match format {
+        Format::Argb8888 => pixel.copy_from_slice(&[b, g, r, a]),
+        Format::Xrgb8888 => pixel.copy_from_slice(&[b, g, r, 255]),
+        Format::Abgr8888 => pixel.copy_from_slice(&[r, g, b, a]),
+        Format::Xbgr8888 => pixel.copy_from_slice(&[r, g, b, 255]),
+        Format::Abgr2101010 | Format::Xbgr2101010 => {
+            let alpha = if format == Format::Abgr2101010 {
+                u32::from(a >> 6)
+            } else {
+                3
+            };
+            let value = to_10_bits(r) | (to_10_bits(g) << 10) | (to_10_bits(b) << 20) | alpha << 30;
+            pixel.copy_from_slice(&value.to_le_bytes());
+        }
+        Format::Bgr888 => pixel.copy_from_slice(&[r, g, b]),
+        Format::Rgb888 => pixel.copy_from_slice(&[b, g, r]),
+        Format::Rgb565 => {
+            let value = (u16::from(r >> 3) << 11) | (u16::from(g >> 2) << 5) | u16::from(b >> 3);
+            pixel.copy_from_slice(&value.to_le_bytes());
+        }
+        _ => pixel.fill(0),
+    }
+}
+
+impl GlobalDispatch<WlShm, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WlShm>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let shm = data_init.init(resource, ());
+        for format in FORMATS {
+            shm.format(*format);
+        }
+    }
+}
+
+impl Dispatch<WlShm, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlShm,
+        request: wl_shm::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm::Request::CreatePool { id, fd, size } = request {
+            let pool = Pool {
+                fd,
+                size: Mutex::new(size.max(0) as usize),
+            };
+            data_init.init(id, Arc::new(pool));
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, Arc<Pool>> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &WlShmPool,
+        request: wl_shm_pool::Request,
+        pool: &Arc<Pool>,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_shm_pool::Request::CreateBuffer {
+                id,
+                offset,
+                width,
+                height,
+                stride,
+                format,
+            } => {
+                let buffer = Buffer {
+                    pool: pool.clone(),
+                    offset: offset.max(0) as usize,
+                    width: width.max(0) as u32,
+                    height: height.max(0) as u32,
+                    stride: stride.max(0) as usize,
+                    format: match format {
+                        WEnum::Value(format) => Some(format),
+                        WEnum::Unknown(_) => None,
+                    },
+                };
+                data_init.init(id, buffer);
+                state.buffers += 1;
+            }
+            wl_shm_pool::Request::Resize { size } => {
+                *pool.size.lock().unwrap_or_else(PoisonError::into_inner) = size.max(0) as usize;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, Buffer> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WlBuffer,
+        _request: wl_buffer::Request,
+        _data: &Buffer,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, _resource: &WlBuffer, _data: &Buffer) {
+        state.buffers -= 1;
+    }
+}
@@ -0,0 +1,124 @@
+//! End to end tests of `AsyncWayshotConnection` against the mock compositor.
+
+use std::time::{Duration, Instant};
+
+use image::GenericImageView;
+use libwayshot::{AsyncWayshotConnection, CapturePhase, Error, Timeouts};
+use wayshot_mock_compositor::{Content, Failure, MockCompositor, MockOutput};
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+
+async fn connect(compositor: &MockCompositor) -> AsyncWayshotConnection {
+    let conn = compositor.connect().expect("failed to connect");
+    let mut wayshot = AsyncWayshotConnection::from_connection(conn)
+        .await
+        .expect("failed to set up wayshot");
+    // Failures must not hang the tests.
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_secs(5)));
+    wayshot
+}
+
+fn side_by_side() -> MockCompositor {
+    MockCompositor::new([
+        MockOutput::new("left", 40, 30).content(Content::Solid(RED)),
+        MockOutput::new("right", 20, 30)
+            .position(40, 0)
+            .content(Content::Solid(GREEN)),
+    ])
+    .expect("failed to start the mock compositor")
+}
+
+#[tokio::test]
+async fn screenshot_all_composites_every_output() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor).await;
+
+    let image = wayshot.screenshot_all(false).await.unwrap();
+    assert_eq!(image.dimensions(), (60, 30));
+    assert_eq!(image.get_pixel(0, 0).0, RED);
+    assert_eq!(image.get_pixel(59, 29).0, GREEN);
+}
+
+#[tokio::test]
+async fn concurrent_captures() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor).await;
+    let targets: Vec<_> = wayshot
+        .get_all_outputs()
+        .into_iter()
+        .map(|output| (output, None))
+        .collect();
+
+    let region = "30,10 20x10".parse().unwrap();
+    let (image, frames) = tokio::join!(
+        wayshot.screenshot(region, false),
+        wayshot.capture_frame_copies(&targets, false),
+    );
+    let image = image.unwrap();
+    assert_eq!(image.dimensions(), (20, 10));
+    assert_eq!(image.get_pixel(9, 0).0, RED);
+    assert_eq!(image.get_pixel(10, 0).0, GREEN);
+    assert_eq!(frames.map(|frames| frames.len()).ok(), Some(2));
+}
+
+#[tokio::test]
+async fn copy_timeout() {
+    let compositor = side_by_side();
+    compositor.update_output("right", |output| {
+        output.failure = Some(Failure::WithholdFrames);
+    });
+    let mut wayshot = connect(&compositor).await;
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_millis(200)));
+
+    let error = wayshot.screenshot_all(false).await.unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            Error::Timeout { phase: CapturePhase::Copy, output } if output == "right"
+        ),
+        "{error:?}"
+    );
+
+    // The output recovers, and so do captures.
+    compositor.update_output("right", |output| output.failure = None);
+    assert!(wayshot.screenshot_all(false).await.is_ok());
+}
+
+#[tokio::test]
+async fn frame_copies_when_frames_are_only_sent_on_change() {
+    let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+        .content(Content::Solid(RED))
+        .failure(Failure::WithholdUnchangedFrames)])
+    .unwrap();
+    let mut wayshot = connect(&compositor).await;
+    // Without timeouts a held back frame would block forever.
+    wayshot.set_timeouts(Timeouts::default());
+    let targets: Vec<_> = wayshot
+        .get_all_outputs()
+        .into_iter()
+        .map(|output| (output, None))
+        .collect();
+
+    for _ in 0..2 {
+        let frames = tokio::time::timeout(
+            Duration::from_secs(5),
+            wayshot.capture_frame_copies(&targets, false),
+        )
+        .await;
+        assert_eq!(frames.unwrap().unwrap().len(), 1);
+    }
+
+    // Reused sessions wait for a change, but only until the deadline.
+    let soon = || Instant::now() + Duration::from_millis(100);
+    let frames = wayshot
+        .capture_frame_copies_until(&targets, false, soon())
+        .await
+        .unwrap();
+    assert!(frames.is_some());
+    let frames = wayshot
+        .capture_frame_copies_until(&targets, false, soon())
+        .await
+        .unwrap();
+    assert!(frames.is_none());
+}
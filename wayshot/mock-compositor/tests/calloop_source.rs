@@ -0,0 +1,137 @@
+//! End to end tests of `WayshotSource` against the mock compositor.
+
+use std::time::{Duration, Instant};
+
+use calloop::EventLoop;
+use image::GenericImageView;
+use libwayshot::{
+    CaptureId, CapturePhase, CaptureRequest, CaptureSender, Captured, Error, Result, Timeouts,
+    WayshotConnection, WayshotSource, output::OutputInfo,
+};
+use wayshot_mock_compositor::{Content, Failure, MockCompositor, MockOutput};
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+
+fn side_by_side() -> MockCompositor {
+    MockCompositor::new([
+        MockOutput::new("left", 40, 30).content(Content::Solid(RED)),
+        MockOutput::new("right", 20, 30)
+            .position(40, 0)
+            .content(Content::Solid(GREEN)),
+    ])
+    .expect("failed to start the mock compositor")
+}
+
+/// Results delivered by the source.
+#[derive(Default)]
+struct Results {
+    captured: Vec<(CaptureId, Result<Captured>)>,
+}
+
+/// Insert a source capturing on `compositor` into `event_loop`, returns the
+/// outputs of the compositor and the sender to capture them with.
+fn insert_source(
+    compositor: &MockCompositor,
+    event_loop: &EventLoop<Results>,
+    timeouts: Timeouts,
+) -> (Vec<OutputInfo>, CaptureSender) {
+    let conn = compositor.connect().expect("failed to connect");
+    let mut wayshot = WayshotConnection::from_connection(conn).expect("failed to set up wayshot");
+    wayshot.set_timeouts(timeouts);
+    let (source, sender) = WayshotSource::from_wayshot_connection(wayshot).unwrap();
+    let outputs = source.connection().get_all_outputs();
+    event_loop
+        .handle()
+        .insert_source(source, |result, _, results: &mut Results| {
+            results.captured.push(result)
+        })
+        .map_err(|e| e.error)
+        .unwrap();
+    (outputs, sender)
+}
+
+/// Run `event_loop` until `count` results arrived.
+fn run_until(event_loop: &mut EventLoop<Results>, results: &mut Results, count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while results.captured.len() < count {
+        assert!(Instant::now() < deadline, "timed out waiting for captures");
+        event_loop
+            .dispatch(Some(Duration::from_millis(50)), results)
+            .unwrap();
+    }
+}
+
+#[test]
+fn captures_images_and_frames() {
+    let compositor = side_by_side();
+    let mut event_loop = EventLoop::try_new().unwrap();
+    let (outputs, sender) = insert_source(
+        &compositor,
+        &event_loop,
+        Timeouts::uniform(Duration::from_secs(5)),
+    );
+
+    let image_id = sender
+        .capture(CaptureRequest::Outputs {
+            outputs: outputs.clone(),
+            cursor_overlay: false,
+        })
+        .unwrap();
+    let frames_id = sender
+        .capture(CaptureRequest::FrameCopies {
+            output_capture_regions: outputs.into_iter().map(|output| (output, None)).collect(),
+            cursor_overlay: false,
+        })
+        .unwrap();
+
+    let mut results = Results::default();
+    run_until(&mut event_loop, &mut results, 2);
+    for (id, result) in results.captured {
+        match result {
+            Ok(Captured::Image(image)) => {
+                assert_eq!(id, image_id);
+                assert_eq!(image.dimensions(), (60, 30));
+                assert_eq!(image.get_pixel(0, 0).0, RED);
+                assert_eq!(image.get_pixel(59, 29).0, GREEN);
+            }
+            Ok(Captured::Frames(frames)) => {
+                assert_eq!(id, frames_id);
+                assert_eq!(frames.len(), 2);
+            }
+            Err(e) => panic!("capture {id:?} failed: {e}"),
+        }
+    }
+}
+
+#[test]
+fn copy_timeout() {
+    let compositor = side_by_side();
+    compositor.update_output("right", |output| {
+        output.failure = Some(Failure::WithholdFrames);
+    });
+    let mut event_loop = EventLoop::try_new().unwrap();
+    let (outputs, sender) = insert_source(
+        &compositor,
+        &event_loop,
+        Timeouts::uniform(Duration::from_millis(200)),
+    );
+
+    let start = Instant::now();
+    sender
+        .capture(CaptureRequest::Outputs {
+            outputs,
+            cursor_overlay: false,
+        })
+        .unwrap();
+    let mut results = Results::default();
+    run_until(&mut event_loop, &mut results, 1);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(
+        matches!(
+            &results.captured[0].1,
+            Err(Error::Timeout { phase: CapturePhase::Copy, output }) if output == "right"
+        ),
+        "capture didn't time out"
+    );
+}
@@ -0,0 +1,885 @@
+//! End to end tests of the capture path against the mock compositor.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::{GenericImageView, Rgba, RgbaImage};
+use libwayshot::{
+    CaptureDump, CapturePhase, Color, Error, Timeouts, WayshotConnection,
+    region::{LogicalRegion, Position},
+};
+use wayland_client::protocol::wl_shm::Format as ClientFormat;
+use wayshot_mock_compositor::{
+    BUTTON_LEFT, Content, Failure, FailureReason, Format, KEY_ESCAPE, MockCompositor, MockOutput,
+    Transform,
+};
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+fn connect(compositor: &MockCompositor) -> WayshotConnection {
+    let conn = compositor.connect().expect("failed to connect");
+    let mut wayshot = WayshotConnection::from_connection(conn).expect("failed to set up wayshot");
+    // Failures must not hang the tests.
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_secs(5)));
+    wayshot
+}
+
+fn side_by_side() -> MockCompositor {
+    MockCompositor::new([
+        MockOutput::new("left", 40, 30).content(Content::Solid(RED)),
+        MockOutput::new("right", 20, 30)
+            .position(40, 0)
+            .content(Content::Solid(GREEN)),
+    ])
+    .expect("failed to start the mock compositor")
+}
+
+fn pixel(image: &impl GenericImageView<Pixel = Rgba<u8>>, x: u32, y: u32) -> [u8; 4] {
+    image.get_pixel(x, y).0
+}
+
+/// Wait for something another thread does to the compositor.
+fn wait_for(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for the client"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn lists_outputs() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let outputs = wayshot.get_all_outputs();
+    let names: Vec<_> = outputs.iter().map(|output| output.name.as_str()).collect();
+    assert_eq!(names, ["left", "right"]);
+    assert_eq!(outputs[1].logical_region.inner.position.x, 40);
+    assert_eq!(outputs[1].logical_region.inner.size.width, 20);
+    assert_eq!(outputs[1].physical_size.height, 30);
+}
+
+#[test]
+fn screenshot_all_composites_every_output() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (60, 30));
+    assert_eq!(pixel(&image, 0, 0), RED);
+    assert_eq!(pixel(&image, 39, 29), RED);
+    assert_eq!(pixel(&image, 40, 0), GREEN);
+    assert_eq!(pixel(&image, 59, 29), GREEN);
+    assert_eq!(compositor.frames_captured("left"), 1);
+    assert_eq!(compositor.frames_captured("right"), 1);
+}
+
+#[test]
+fn screenshot_single_output() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let right = wayshot.get_all_outputs().remove(1);
+    let image = wayshot
+        .screenshot_single_output(&right, false)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(image.dimensions(), (20, 30));
+    assert!(image.pixels().all(|pixel| pixel.0 == GREEN));
+    assert_eq!(compositor.frames_captured("left"), 0);
+}
+
+#[test]
+fn region_spanning_outputs() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let region: LogicalRegion = "30,10 20x5".parse().unwrap();
+    let image = wayshot.screenshot(region, false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (20, 5));
+    assert_eq!(pixel(&image, 9, 0), RED);
+    assert_eq!(pixel(&image, 10, 4), GREEN);
+}
+
+#[test]
+fn region_within_one_output_only_captures_it() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let region: LogicalRegion = "45,5 10x10".parse().unwrap();
+    let image = wayshot.screenshot(region, false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (10, 10));
+    assert!(image.pixels().all(|pixel| pixel.0 == GREEN));
+    assert_eq!(compositor.frames_captured("left"), 0);
+}
+
+#[test]
+fn rotated_output() {
+    // Mark the top left corner of the buffer.
+    let mut content = RgbaImage::from_pixel(40, 20, Rgba(BLUE));
+    content.put_pixel(0, 0, Rgba(RED));
+    let compositor = MockCompositor::new([MockOutput::new("portrait", 40, 20)
+        .transform(Transform::_90)
+        .content(Content::Image(content))])
+    .unwrap();
+    let wayshot = connect(&compositor);
+
+    let output = &wayshot.get_all_outputs()[0];
+    assert_eq!(output.logical_region.inner.size.width, 20);
+    assert_eq!(output.logical_region.inner.size.height, 40);
+
+    let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (20, 40));
+    // Rotating clockwise moves the corner to the top right.
+    assert_eq!(pixel(&image, 19, 0), RED);
+    assert_eq!(pixel(&image, 0, 0), BLUE);
+}
+
+#[test]
+fn flipped_output() {
+    let mut content = RgbaImage::from_pixel(30, 20, Rgba(BLUE));
+    content.put_pixel(0, 0, Rgba(RED));
+    let compositor = MockCompositor::new([MockOutput::new("flipped", 30, 20)
+        .transform(Transform::Flipped)
+        .content(Content::Image(content))])
+    .unwrap();
+    let wayshot = connect(&compositor);
+
+    let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (30, 20));
+    assert_eq!(pixel(&image, 29, 0), RED);
+}
+
+#[test]
+fn mixed_scales_use_the_largest() {
+    let compositor = MockCompositor::new([
+        MockOutput::new("hidpi", 40, 40)
+            .scale(2)
+            .content(Content::Solid(RED)),
+        MockOutput::new("lodpi", 20, 20)
+            .position(20, 0)
+            .content(Content::Solid(GREEN)),
+    ])
+    .unwrap();
+    let wayshot = connect(&compositor);
+
+    let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (80, 40));
+    assert_eq!(pixel(&image, 10, 10), RED);
+    assert_eq!(pixel(&image, 60, 20), GREEN);
+}
+
+#[test]
+fn converts_buffer_formats() {
+    for format in [
+        Format::Xrgb8888,
+        Format::Argb8888,
+        Format::Xbgr8888,
+        Format::Abgr8888,
+        Format::Xbgr2101010,
+        Format::Bgr888,
+    ] {
+        let color = [200, 100, 50, 255];
+        let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+            .format(format)
+            .content(Content::Solid(color))])
+        .unwrap();
+        let wayshot = connect(&compositor);
+
+        let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+        assert_eq!(pixel(&image, 3, 2), color, "format {format:?}");
+    }
+}
+
+#[test]
+fn unsupported_buffer_format() {
+    let compositor =
+        MockCompositor::new([MockOutput::new("out", 8, 4).format(Format::Rgb565)]).unwrap();
+    let wayshot = connect(&compositor);
+
+    let error = wayshot.screenshot_all(false).unwrap_err();
+    assert!(
+        matches!(&error, Error::NoSupportedBufferFormat { output } if output == "out"),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn failed_frame() {
+    let compositor = side_by_side();
+    compositor.update_output("right", |output| {
+        output.failure = Some(Failure::FailFrames(FailureReason::Unknown));
+    });
+    let wayshot = connect(&compositor);
+
+    let error = wayshot.screenshot_all(false).unwrap_err();
+    assert!(
+        matches!(&error, Error::FramecopyFailed { output } if output == "right"),
+        "{error:?}"
+    );
+
+    // The output recovers, and so do captures.
+    compositor.update_output("right", |output| output.failure = None);
+    assert!(wayshot.screenshot_all(false).is_ok());
+}
+
+#[test]
+fn stopped_session() {
+    let compositor =
+        MockCompositor::new([MockOutput::new("out", 8, 4).failure(Failure::StopSessions)]).unwrap();
+    let wayshot = connect(&compositor);
+
+    let error = wayshot.screenshot_all(false).unwrap_err();
+    assert!(matches!(error, Error::FramecopyFailed { .. }), "{error:?}");
+}
+
+#[test]
+fn missing_protocol() {
+    let compositor = MockCompositor::without_globals(
+        [MockOutput::new("out", 8, 4)],
+        &["ext_image_copy_capture_manager_v1"],
+    )
+    .unwrap();
+    let conn = compositor.connect().unwrap();
+
+    let error = WayshotConnection::from_connection(conn)
+        .and_then(|wayshot| wayshot.screenshot_all(false))
+        .unwrap_err();
+    assert!(
+        matches!(
+            error,
+            Error::ProtocolNotFound {
+                protocol: "ext_image_copy_capture_manager_v1",
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn negotiation_timeout() {
+    let compositor =
+        MockCompositor::new([MockOutput::new("out", 8, 4).failure(Failure::WithholdConstraints)])
+            .unwrap();
+    let mut wayshot = connect(&compositor);
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_millis(200)));
+
+    let error = wayshot.screenshot_all(false).unwrap_err();
+    assert!(
+        matches!(&error, Error::Timeout { phase: CapturePhase::Negotiation, output } if output == "out"),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn copy_timeout() {
+    let compositor =
+        MockCompositor::new([MockOutput::new("out", 8, 4).failure(Failure::WithholdFrames)])
+            .unwrap();
+    let mut wayshot = connect(&compositor);
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_millis(200)));
+
+    let error = wayshot.screenshot_all(false).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            Error::Timeout {
+                phase: CapturePhase::Copy,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn stable_screenshot_waits_for_changes_to_stop() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+    let region = "0,0 40x30".parse().unwrap();
+
+    let start = Instant::now();
+    let image = thread::scope(|scope| {
+        scope.spawn(|| {
+            for color in [GREEN, BLUE, GREEN, BLUE] {
+                thread::sleep(Duration::from_millis(50));
+                compositor.update_output("left", move |output| {
+                    output.content = Content::Solid(color);
+                });
+            }
+        });
+        wayshot
+            .screenshot_stable(
+                region,
+                false,
+                Duration::from_millis(150),
+                Duration::from_secs(5),
+            )
+            .unwrap()
+    });
+    assert!(start.elapsed() >= Duration::from_millis(350));
+    assert_eq!(pixel(&image.to_rgba8(), 0, 0), BLUE);
+    // Only the output under the region is captured.
+    assert_eq!(compositor.frames_captured("right"), 0);
+}
+
+#[test]
+fn stable_screenshot_gives_up_after_max_wait() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+    let region = "0,0 40x30".parse().unwrap();
+
+    let start = Instant::now();
+    let stop = AtomicBool::new(false);
+    let image = thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut colors = [GREEN, BLUE].into_iter().cycle();
+            while !stop.load(Ordering::Relaxed) {
+                let color = colors.next().unwrap();
+                compositor.update_output("left", move |output| {
+                    output.content = Content::Solid(color);
+                });
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let image = wayshot.screenshot_stable(
+            region,
+            false,
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+        );
+        stop.store(true, Ordering::Relaxed);
+        image.unwrap()
+    });
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    assert_eq!(image.width(), 40);
+}
+
+#[test]
+fn screenshots_when_frames_are_only_sent_on_change() {
+    let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+        .content(Content::Solid(RED))
+        .failure(Failure::WithholdUnchangedFrames)])
+    .unwrap();
+    let mut wayshot = connect(&compositor);
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_millis(500)));
+
+    // Every screenshot gets a frame of its own, although nothing changed.
+    for _ in 0..3 {
+        let image = wayshot.screenshot_all(false).unwrap();
+        assert_eq!(pixel(&image.to_rgba8(), 0, 0), RED);
+    }
+}
+
+#[test]
+fn frame_copies_when_frames_are_only_sent_on_change() {
+    let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+        .content(Content::Solid(RED))
+        .failure(Failure::WithholdUnchangedFrames)])
+    .unwrap();
+    let mut wayshot = connect(&compositor);
+    // Without timeouts a held back frame would block forever.
+    wayshot.set_timeouts(Timeouts::default());
+    let targets: Vec<_> = wayshot
+        .get_all_outputs()
+        .into_iter()
+        .map(|output| (output, None))
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for _ in 0..2 {
+            let frames = wayshot.capture_frame_copies(&targets, false);
+            let _ = sender.send(frames.map(|frames| frames.len()));
+        }
+    });
+    for _ in 0..2 {
+        let frames = receiver.recv_timeout(Duration::from_secs(5));
+        assert_eq!(frames.unwrap().unwrap(), 1);
+    }
+    assert_eq!(compositor.frames_captured("out"), 2);
+}
+
+#[test]
+fn capture_until_deadline_when_frames_are_only_sent_on_change() {
+    let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+        .content(Content::Solid(RED))
+        .failure(Failure::WithholdUnchangedFrames)])
+    .unwrap();
+    let wayshot = connect(&compositor);
+    let targets: Vec<_> = wayshot
+        .get_all_outputs()
+        .into_iter()
+        .map(|output| (output, None))
+        .collect();
+    let soon = || Instant::now() + Duration::from_millis(100);
+
+    assert!(
+        wayshot
+            .capture_frame_copies_until(&targets, false, soon())
+            .unwrap()
+            .is_some()
+    );
+    let start = Instant::now();
+    assert!(
+        wayshot
+            .capture_frame_copies_until(&targets, false, soon())
+            .unwrap()
+            .is_none()
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // The sessions are kept and copy the next change, once the compositor
+    // saw the cancelled frame go.
+    thread::sleep(Duration::from_millis(50));
+    compositor.update_output("out", |output| output.content = Content::Solid(BLUE));
+    let frames = wayshot
+        .capture_frame_copies_until(&targets, false, soon())
+        .unwrap();
+    assert_eq!(frames.map(|frames| frames.len()), Some(1));
+    assert_eq!(compositor.frames_captured("out"), 2);
+}
+
+#[test]
+fn stable_screenshot_when_frames_are_only_sent_on_change() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+    let region = "0,0 40x30".parse().unwrap();
+
+    let start = Instant::now();
+    let image = thread::scope(|scope| {
+        scope.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            compositor.update_output("left", |output| {
+                output.failure = Some(Failure::WithholdFrames);
+            });
+        });
+        wayshot
+            .screenshot_stable(
+                region,
+                false,
+                Duration::from_millis(200),
+                Duration::from_secs(5),
+            )
+            .unwrap()
+    });
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(pixel(&image.to_rgba8(), 0, 0), RED);
+}
+
+#[test]
+fn freeze_selects_region() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let image = wayshot
+        .screenshot_freeze(Box::new(|| "35,0 10x10".parse()), false)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(image.dimensions(), (10, 10));
+    assert_eq!(pixel(&image, 0, 0), RED);
+    assert_eq!(pixel(&image, 9, 9), GREEN);
+}
+
+#[test]
+fn freeze_configure_timeout() {
+    let compositor =
+        MockCompositor::new([MockOutput::new("out", 8, 4).failure(Failure::WithholdConfigure)])
+            .unwrap();
+    let mut wayshot = connect(&compositor);
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_millis(200)));
+
+    let error = wayshot
+        .screenshot_freeze(Box::new(|| "0,0 8x4".parse()), false)
+        .unwrap_err();
+    assert!(
+        matches!(
+            error,
+            Error::Timeout {
+                phase: CapturePhase::Configure,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn countdown_is_hidden_before_capture() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let start = Instant::now();
+    thread::scope(|scope| {
+        let countdown = scope.spawn(|| wayshot.show_countdown(Duration::from_millis(600)));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(compositor.layer_surfaces_shown("left"), 1);
+        assert_eq!(compositor.layer_surfaces_shown("right"), 1);
+        countdown.join().unwrap().unwrap();
+    });
+    assert!(start.elapsed() >= Duration::from_millis(600));
+    assert_eq!(compositor.layer_surfaces_shown("left"), 0);
+
+    wayshot.screenshot_all(false).unwrap();
+    assert_eq!(compositor.frames_captured("left"), 1);
+    assert_eq!(compositor.frames_with_layer_surfaces("left"), 0);
+    assert_eq!(compositor.frames_with_layer_surfaces("right"), 0);
+}
+
+#[test]
+fn countdown_keeps_the_buffers_of_two_seconds() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    thread::scope(|scope| {
+        let countdown = scope.spawn(|| wayshot.show_countdown(Duration::from_millis(2500)));
+        // Showing the last second.
+        thread::sleep(Duration::from_millis(2000));
+        assert_eq!(compositor.buffers(), 4);
+        countdown.join().unwrap().unwrap();
+    });
+    assert_eq!(compositor.buffers(), 0);
+}
+
+#[test]
+fn countdown_configure_timeout() {
+    let compositor =
+        MockCompositor::new([MockOutput::new("out", 8, 4).failure(Failure::WithholdConfigure)])
+            .unwrap();
+    let mut wayshot = connect(&compositor);
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_millis(200)));
+
+    let error = wayshot.show_countdown(Duration::from_secs(3)).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            Error::Timeout {
+                phase: CapturePhase::Configure,
+                ..
+            }
+        ),
+        "{error:?}"
+    );
+    wait_for(|| compositor.surfaces() == 0);
+}
+
+#[test]
+fn pick_color_reads_the_clicked_pixel() {
+    let mut content = RgbaImage::from_pixel(20, 30, Rgba(GREEN));
+    content.put_pixel(3, 5, Rgba(BLUE));
+    let compositor = MockCompositor::new([
+        MockOutput::new("left", 40, 30).content(Content::Solid(RED)),
+        MockOutput::new("right", 20, 30)
+            .position(40, 0)
+            .transform(Transform::_90)
+            .content(Content::Image(content)),
+    ])
+    .unwrap();
+    let wayshot = connect(&compositor);
+
+    let picked = thread::scope(|scope| {
+        let picking = scope.spawn(|| wayshot.pick_color());
+        wait_for(|| compositor.layer_surfaces_shown("right") == 1);
+        // Rotated clockwise, the pixel ends up in the top right corner.
+        assert!(compositor.move_pointer("right", 24.5, 3.5));
+        wait_for(|| compositor.has_cursor_surface());
+        compositor.click(BUTTON_LEFT);
+        picking.join().unwrap().unwrap()
+    })
+    .expect("picking was cancelled");
+
+    assert_eq!(picked.output, "right");
+    assert_eq!(picked.position, Position { x: 64, y: 3 });
+    assert_eq!(picked.color.to_rgb8(), [0, 0, 255]);
+    assert_eq!(compositor.layer_surfaces_shown("left"), 0);
+    assert_eq!(compositor.layer_surfaces_shown("right"), 0);
+}
+
+#[test]
+fn pick_color_keeps_ten_bit_precision() {
+    let compositor = MockCompositor::new([MockOutput::new("deep", 10, 10)
+        .format(Format::Xbgr2101010)
+        .content(Content::Solid([255, 128, 0, 255]))])
+    .unwrap();
+    let wayshot = connect(&compositor);
+
+    let picked = thread::scope(|scope| {
+        let picking = scope.spawn(|| wayshot.pick_color());
+        wait_for(|| compositor.move_pointer("deep", 1.5, 1.5));
+        compositor.click(BUTTON_LEFT);
+        picking.join().unwrap().unwrap()
+    })
+    .expect("picking was cancelled");
+
+    assert_eq!(
+        picked.color,
+        Color {
+            red: 1023,
+            green: 514,
+            blue: 0,
+            depth: 10,
+        }
+    );
+    assert_eq!(picked.color.to_rgb16(), [65535, 32928, 0]);
+}
+
+#[test]
+fn pick_color_is_cancelled_with_escape() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let picked = thread::scope(|scope| {
+        let picking = scope.spawn(|| wayshot.pick_color());
+        wait_for(|| compositor.layer_surfaces_shown("left") == 1);
+        compositor.press_key(KEY_ESCAPE);
+        picking.join().unwrap().unwrap()
+    });
+    assert_eq!(picked, None);
+    assert_eq!(compositor.layer_surfaces_shown("left"), 0);
+}
+
+#[test]
+fn measure_reports_logical_and_physical_pixels() {
+    let compositor = MockCompositor::new([
+        MockOutput::new("left", 40, 30).content(Content::Solid(RED)),
+        MockOutput::new("hidpi", 40, 40)
+            .position(40, 0)
+            .scale(2)
+            .content(Content::Solid(GREEN)),
+    ])
+    .unwrap();
+    let wayshot = connect(&compositor);
+
+    let measured = thread::scope(|scope| {
+        let measuring = scope.spawn(|| wayshot.measure());
+        wait_for(|| compositor.move_pointer("hidpi", 2.4, 3.6));
+        compositor.press_button(BUTTON_LEFT);
+        // The outline and the label.
+        wait_for(|| compositor.subsurfaces() == 5);
+        assert!(compositor.move_pointer("hidpi", 14.6, 12.2));
+        compositor.release_button(BUTTON_LEFT);
+        measuring.join().unwrap().unwrap()
+    })
+    .expect("measuring was cancelled");
+
+    assert_eq!(measured.output, "hidpi");
+    // Snapped to the closest corners of logical pixels.
+    assert_eq!(measured.start, Position { x: 42, y: 4 });
+    assert_eq!(measured.end, Position { x: 55, y: 12 });
+    assert_eq!((measured.logical.width, measured.logical.height), (13, 8));
+    assert_eq!(
+        (measured.physical.width, measured.physical.height),
+        (26, 16)
+    );
+    assert!((measured.logical.distance - 13f64.hypot(8.0)).abs() < 1e-9);
+    assert!((measured.physical.distance - 26f64.hypot(16.0)).abs() < 1e-9);
+    assert_eq!(compositor.subsurfaces(), 0);
+    assert_eq!(compositor.layer_surfaces_shown("hidpi"), 0);
+}
+
+#[test]
+fn measure_is_cancelled_while_dragging() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    let measured = thread::scope(|scope| {
+        let measuring = scope.spawn(|| wayshot.measure());
+        wait_for(|| compositor.move_pointer("left", 5.0, 5.0));
+        compositor.press_button(BUTTON_LEFT);
+        assert!(compositor.move_pointer("left", 20.0, 10.0));
+        wait_for(|| compositor.subsurfaces() == 5);
+        compositor.press_key(KEY_ESCAPE);
+        measuring.join().unwrap().unwrap()
+    });
+    assert_eq!(measured, None);
+    assert_eq!(compositor.subsurfaces(), 0);
+    assert_eq!(compositor.layer_surfaces_shown("left"), 0);
+}
+
+#[test]
+fn refresh_outputs_picks_up_changes() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+
+    compositor.remove_output("left");
+    compositor.add_output(
+        MockOutput::new("new", 10, 10)
+            .position(60, 0)
+            .content(Content::Solid(BLUE)),
+    );
+    wayshot.refresh_outputs().unwrap();
+
+    let names: Vec<_> = wayshot
+        .get_all_outputs()
+        .into_iter()
+        .map(|output| output.name)
+        .collect();
+    assert_eq!(names, ["right", "new"]);
+
+    let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (30, 30));
+    assert_eq!(pixel(&image, 25, 5), BLUE);
+}
+
+#[test]
+fn refreshing_unchanged_outputs_keeps_their_objects() {
+    let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+        .content(Content::Solid(RED))
+        .failure(Failure::WithholdUnchangedFrames)])
+    .unwrap();
+    let wayshot = connect(&compositor);
+    let outputs = wayshot.get_all_outputs();
+    let targets: Vec<_> = outputs
+        .iter()
+        .map(|output| (output.clone(), None))
+        .collect();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let frames = wayshot
+        .capture_frame_copies_until(&targets, false, deadline)
+        .unwrap();
+    assert!(frames.is_some());
+
+    for _ in 0..3 {
+        wayshot.refresh_outputs().unwrap();
+    }
+    assert_eq!(wayshot.get_all_outputs(), outputs);
+    // The session is kept, and there is no change for it to copy.
+    let deadline = Instant::now() + Duration::from_millis(100);
+    let frames = wayshot
+        .capture_frame_copies_until(&targets, false, deadline)
+        .unwrap();
+    assert!(frames.is_none());
+    assert_eq!(compositor.output_objects(), 1);
+
+    compositor.update_output("out", |output| output.size = (16, 8));
+    wayshot.refresh_outputs().unwrap();
+    let refreshed = wayshot.get_all_outputs();
+    assert_ne!(refreshed[0].wl_output, outputs[0].wl_output);
+    assert_eq!(refreshed[0].physical_size.width, 16);
+}
+
+#[test]
+fn removed_output_fails_capture() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+    // Sessions are cached, stop them while they are idle.
+    wayshot.screenshot_all(false).unwrap();
+
+    compositor.remove_output("right");
+    let error = wayshot.screenshot_all(false).unwrap_err();
+    assert!(
+        matches!(&error, Error::FramecopyFailed { output } if output == "right"),
+        "{error:?}"
+    );
+
+    wayshot.refresh_outputs().unwrap();
+    let image = wayshot.screenshot_all(false).unwrap();
+    assert_eq!(image.width(), 40);
+}
+
+#[test]
+fn replayed_dump_matches_screenshot() {
+    let mut content = RgbaImage::from_pixel(40, 20, Rgba(BLUE));
+    content.put_pixel(0, 0, Rgba(RED));
+    let compositor = MockCompositor::new([
+        MockOutput::new("rotated", 40, 20)
+            .transform(Transform::_90)
+            .format(Format::Xrgb8888)
+            .content(Content::Image(content)),
+        MockOutput::new("scaled", 40, 40)
+            .scale(2)
+            .position(20, 0)
+            .content(Content::Solid(GREEN)),
+    ])
+    .unwrap();
+    let mut wayshot = connect(&compositor);
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("replayed_dump.wayshot");
+    wayshot.set_capture_dump(Some(path.clone()));
+
+    let region: LogicalRegion = "10,5 20x30".parse().unwrap();
+    let image = wayshot.screenshot(region, false).unwrap();
+
+    let dump = CaptureDump::load(&path).unwrap();
+    assert_eq!(dump.capture_region, region);
+    let names: Vec<_> = dump
+        .frames
+        .iter()
+        .map(|frame| frame.output_name.as_str())
+        .collect();
+    assert_eq!(names, ["rotated", "scaled"]);
+    // The client and server enums are distinct types.
+    assert_eq!(
+        u32::from(dump.frames[0].frame_format.format),
+        u32::from(Format::Xrgb8888)
+    );
+    assert_eq!(dump.replay().unwrap(), image);
+}
+
+#[test]
+fn replaying_an_unsupported_buffer_format() {
+    let compositor = side_by_side();
+    let mut wayshot = connect(&compositor);
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("unsupported_dump.wayshot");
+    wayshot.set_capture_dump(Some(path.clone()));
+    wayshot.screenshot_all(false).unwrap();
+
+    // A dump taken by a newer wayshot, with a format this one can't convert.
+    let mut dump = CaptureDump::load(&path).unwrap();
+    dump.frames[1].frame_format.format = ClientFormat::Rgb565;
+    let error = dump.replay().unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            Error::UnsupportedBufferFormat { output, format: ClientFormat::Rgb565 } if output == "right"
+        ),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn damage_since_previous_frame() {
+    let compositor = side_by_side();
+    let wayshot = connect(&compositor);
+    let targets: Vec<_> = wayshot
+        .get_all_outputs()
+        .into_iter()
+        .map(|output| (output, None))
+        .collect();
+    let deadline = || Instant::now() + Duration::from_secs(5);
+    let damage = || -> Vec<usize> {
+        wayshot
+            .capture_frame_copies_until(&targets, false, deadline())
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|(frame_copy, _, _)| frame_copy.damage.as_ref().unwrap().len())
+            .collect()
+    };
+
+    // New sessions start out damaged.
+    assert_eq!(damage(), [1, 1]);
+    assert_eq!(damage(), [0, 0]);
+
+    compositor.update_output("right", |output| output.content = Content::Solid(BLUE));
+    assert_eq!(damage(), [0, 1]);
+    let frames = wayshot
+        .capture_frame_copies_until(&targets[1..], false, deadline())
+        .unwrap()
+        .unwrap();
+    assert_eq!(frames[0].0.damage, Some(Vec::new()));
+}
@@ -0,0 +1,160 @@
+//! Stress tests sharing one `WayshotConnection` between threads.
+
+use std::{
+    panic,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use libwayshot::{Error, Timeouts, WayshotConnection};
+use wayshot_mock_compositor::{Content, MockCompositor, MockOutput};
+
+const OUTPUTS: usize = 4;
+const CAPTURES_PER_THREAD: usize = 25;
+
+fn color(index: usize) -> [u8; 4] {
+    [index as u8 * 50, 255 - index as u8 * 50, 100, 255]
+}
+
+fn compositor() -> MockCompositor {
+    MockCompositor::new((0..OUTPUTS).map(|index| {
+        MockOutput::new(format!("out-{index}"), 32, 16)
+            .position(index as i32 * 32, 0)
+            .content(Content::Solid(color(index)))
+    }))
+    .expect("failed to start the mock compositor")
+}
+
+fn connect(compositor: &MockCompositor) -> Arc<WayshotConnection> {
+    let conn = compositor.connect().expect("failed to connect");
+    let mut wayshot = WayshotConnection::from_connection(conn).expect("failed to set up wayshot");
+    wayshot.set_timeouts(Timeouts::uniform(Duration::from_secs(10)));
+    Arc::new(wayshot)
+}
+
+#[test]
+fn threads_capture_different_outputs() {
+    let compositor = compositor();
+    let wayshot = connect(&compositor);
+
+    thread::scope(|scope| {
+        for index in 0..OUTPUTS {
+            let wayshot = &wayshot;
+            scope.spawn(move || {
+                for _ in 0..CAPTURES_PER_THREAD {
+                    let output = wayshot
+                        .get_all_outputs()
+                        .into_iter()
+                        .find(|output| output.name == format!("out-{index}"))
+                        .unwrap();
+                    let image = wayshot
+                        .screenshot_single_output(&output, false)
+                        .unwrap()
+                        .to_rgba8();
+                    assert_eq!(image.dimensions(), (32, 16));
+                    assert!(image.pixels().all(|pixel| pixel.0 == color(index)));
+                }
+            });
+        }
+    });
+
+    for index in 0..OUTPUTS {
+        assert_eq!(
+            compositor.frames_captured(&format!("out-{index}")),
+            CAPTURES_PER_THREAD
+        );
+    }
+}
+
+#[test]
+fn threads_capture_all_outputs() {
+    let compositor = compositor();
+    let wayshot = connect(&compositor);
+
+    thread::scope(|scope| {
+        for _ in 0..OUTPUTS {
+            scope.spawn(|| {
+                for _ in 0..CAPTURES_PER_THREAD {
+                    let image = wayshot.screenshot_all(false).unwrap().to_rgba8();
+                    assert_eq!(image.dimensions(), (32 * OUTPUTS as u32, 16));
+                    for index in 0..OUTPUTS {
+                        assert_eq!(image.get_pixel(index as u32 * 32 + 5, 5).0, color(index));
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn refresh_while_capturing() {
+    let compositor = compositor();
+    let wayshot = connect(&compositor);
+    let done = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                wayshot.refresh_outputs().unwrap();
+                assert_eq!(wayshot.get_all_outputs().len(), OUTPUTS);
+            }
+        });
+
+        let capturing: Vec<_> = (0..OUTPUTS)
+            .map(|_| {
+                scope.spawn(|| {
+                    for _ in 0..CAPTURES_PER_THREAD {
+                        let image = wayshot.screenshot_all(false).unwrap();
+                        assert_eq!(image.width(), 32 * OUTPUTS as u32);
+                    }
+                })
+            })
+            .collect();
+        // Stop refreshing before passing on a panic, which would otherwise
+        // wait for the refreshing thread forever.
+        let results: Vec<_> = capturing.into_iter().map(|thread| thread.join()).collect();
+        done.store(true, Ordering::Relaxed);
+        for result in results {
+            if let Err(panic) = result {
+                panic::resume_unwind(panic);
+            }
+        }
+    });
+}
+
+#[test]
+fn outputs_removed_while_capturing() {
+    let compositor = compositor();
+    let wayshot = connect(&compositor);
+
+    thread::scope(|scope| {
+        let capturing: Vec<_> = (0..OUTPUTS)
+            .map(|_| {
+                scope.spawn(|| {
+                    for _ in 0..CAPTURES_PER_THREAD {
+                        // Captures either see the output or fail cleanly,
+                        // they never hang.
+                        match wayshot.screenshot_all(false) {
+                            Ok(_) | Err(Error::FramecopyFailed { .. }) => {}
+                            Err(error) => panic!("unexpected error: {error:?}"),
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        thread::sleep(Duration::from_millis(5));
+        compositor.remove_output("out-3");
+        for thread in capturing {
+            thread.join().unwrap();
+        }
+    });
+
+    wayshot.refresh_outputs().unwrap();
+    let image = wayshot.screenshot_all(false).unwrap();
+    assert_eq!(image.width(), 32 * (OUTPUTS as u32 - 1));
+}
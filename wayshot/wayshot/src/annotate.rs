@@ -0,0 +1,302 @@
+//! `--annotate`: draw arrows, boxes, text and numbered markers described in a
+//! TOML file onto screenshots, e.g.
+//!
+//! ```toml
+//! # Defaults for the annotations below, all optional.
+//! color = "#e01b24"
+//! width = 3
+//! font_size = 16
+//! marker_radius = 12
+//!
+//! [[annotation]]
+//! type = "rectangle"
+//! region = "40,60 300x120"
+//! fill = "#e01b2433"
+//!
+//! [[annotation]]
+//! type = "arrow"
+//! from = [420, 300]
+//! to = [345, 185]
+//!
+//! [[annotation]]
+//! type = "circle"
+//! center = [500, 200]
+//! radius = 40
+//!
+//! [[annotation]]
+//! type = "text"
+//! at = [420, 310]
+//! text = "Click here"
+//! color = "#ffffff"
+//! background = "#000000b0"
+//!
+//! # Numbered 1, 2, ... unless `number` is given.
+//! [[annotation]]
+//! type = "marker"
+//! at = [40, 60]
+//! ```
+//!
+//! Coordinates, widths and sizes are in logical pixels, so the same file works
+//! for screenshots taken at any scale.
+
+use std::{fmt::Display, fs, path::Path, str::FromStr};
+
+use eyre::{Result, WrapErr};
+use image::{DynamicImage, Rgba};
+use libwayshot::{
+    annotate::{self, Annotation, Stroke},
+    region::{LogicalRegion, Position},
+};
+use serde::{Deserialize, Deserializer, de};
+
+use crate::{
+    cli::Cli,
+    utils::{self, Color},
+};
+
+/// The annotations read from the file given with `--annotate`.
+pub struct Annotations(Vec<Annotation>);
+
+impl Annotations {
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        let Some(path) = &cli.annotate else {
+            return Ok(Self(Vec::new()));
+        };
+        read_spec(&utils::get_expanded_path(path)).map(Self)
+    }
+
+    /// Draw the annotations onto `image`, a screenshot of `capture_region`.
+    pub fn apply(&self, image: &mut DynamicImage, capture_region: LogicalRegion) {
+        if !self.0.is_empty() {
+            annotate::annotate(image, capture_region.inner.size, &self.0);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    #[serde(default = "Spec::default_color")]
+    color: Color,
+    #[serde(default = "Spec::default_width")]
+    width: f64,
+    #[serde(default = "Spec::default_font_size")]
+    font_size: f64,
+    #[serde(default = "Spec::default_marker_radius")]
+    marker_radius: f64,
+    #[serde(default, rename = "annotation")]
+    annotations: Vec<Item>,
+}
+
+impl Spec {
+    fn default_color() -> Color {
+        Color(Rgba([224, 27, 36, 255]))
+    }
+
+    fn default_width() -> f64 {
+        3.0
+    }
+
+    fn default_font_size() -> f64 {
+        16.0
+    }
+
+    fn default_marker_radius() -> f64 {
+        12.0
+    }
+}
+
+/// Shapes without a `width` use the default one, `width = 0` leaves out
+/// their outline.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum Item {
+    Rectangle {
+        #[serde(deserialize_with = "from_str")]
+        region: LogicalRegion,
+        color: Option<Color>,
+        width: Option<f64>,
+        fill: Option<Color>,
+    },
+    Arrow {
+        from: [i32; 2],
+        to: [i32; 2],
+        color: Option<Color>,
+        width: Option<f64>,
+    },
+    Circle {
+        center: [i32; 2],
+        radius: f64,
+        color: Option<Color>,
+        width: Option<f64>,
+        fill: Option<Color>,
+    },
+    Text {
+        at: [i32; 2],
+        text: String,
+        color: Option<Color>,
+        size: Option<f64>,
+        background: Option<Color>,
+    },
+    Marker {
+        at: [i32; 2],
+        number: Option<u32>,
+        color: Option<Color>,
+        radius: Option<f64>,
+    },
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+fn read_spec(path: &Path) -> Result<Vec<Annotation>> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    let spec: Spec = toml::from_str(&contents)
+        .wrap_err_with(|| format!("invalid annotations in {}", path.display()))?;
+    Ok(spec.annotations())
+}
+
+impl Spec {
+    fn annotations(&self) -> Vec<Annotation> {
+        let position = |[x, y]: [i32; 2]| Position { x, y };
+        let stroke = |color: Option<Color>, width: Option<f64>| {
+            let width = width.unwrap_or(self.width);
+            (width > 0.0).then(|| Stroke {
+                color: color.unwrap_or(self.color).0,
+                width,
+            })
+        };
+        let mut next_number = 1;
+        self.annotations
+            .iter()
+            .map(|item| match *item {
+                Item::Rectangle {
+                    region,
+                    color,
+                    width,
+                    fill,
+                } => Annotation::Rectangle {
+                    region,
+                    stroke: stroke(color, width),
+                    fill: fill.map(|fill| fill.0),
+                },
+                Item::Arrow {
+                    from,
+                    to,
+                    color,
+                    width,
+                } => Annotation::Arrow {
+                    from: position(from),
+                    to: position(to),
+                    stroke: Stroke {
+                        color: color.unwrap_or(self.color).0,
+                        width: width.unwrap_or(self.width),
+                    },
+                },
+                Item::Circle {
+                    center,
+                    radius,
+                    color,
+                    width,
+                    fill,
+                } => Annotation::Circle {
+                    center: position(center),
+                    radius,
+                    stroke: stroke(color, width),
+                    fill: fill.map(|fill| fill.0),
+                },
+                Item::Text {
+                    at,
+                    ref text,
+                    color,
+                    size,
+                    background,
+                } => Annotation::Text {
+                    position: position(at),
+                    text: text.clone(),
+                    size: size.unwrap_or(self.font_size),
+                    color: color.unwrap_or(self.color).0,
+                    background: background.map(|background| background.0),
+                },
+                Item::Marker {
+                    at,
+                    number,
+                    color,
+                    radius,
+                } => {
+                    let number = number.unwrap_or(next_number);
+                    next_number = number.saturating_add(1);
+                    Annotation::Marker {
+                        center: position(at),
+                        number,
+                        radius: radius.unwrap_or(self.marker_radius),
+                        color: color.unwrap_or(self.color).0,
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker_numbers(spec: &str) -> Vec<u32> {
+        let spec: Spec = toml::from_str(spec).unwrap();
+        spec.annotations()
+            .into_iter()
+            .filter_map(|annotation| match annotation {
+                Annotation::Marker { number, .. } => Some(number),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn markers_are_numbered_on_from_the_previous_one() {
+        let spec = r#"
+            [[annotation]]
+            type = "marker"
+            at = [0, 0]
+
+            [[annotation]]
+            type = "marker"
+            at = [0, 0]
+
+            [[annotation]]
+            type = "marker"
+            at = [0, 0]
+            number = 7
+
+            [[annotation]]
+            type = "marker"
+            at = [0, 0]
+        "#;
+        assert_eq!(marker_numbers(spec), [1, 2, 7, 8]);
+    }
+
+    #[test]
+    fn marker_numbers_stop_at_the_largest_one() {
+        let spec = r#"
+            [[annotation]]
+            type = "marker"
+            at = [0, 0]
+            number = 4294967295
+
+            [[annotation]]
+            type = "marker"
+            at = [0, 0]
+        "#;
+        assert_eq!(marker_numbers(spec), [u32::MAX, u32::MAX]);
+    }
+}
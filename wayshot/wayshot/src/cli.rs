@@ -1,15 +1,23 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use clap::{
-    Parser, arg,
+    Args, Parser, Subcommand, arg,
     builder::{
         Styles, TypedValueParser,
         styling::{AnsiColor, Effects},
     },
 };
 use eyre::WrapErr;
+use libwayshot::region::{Geometry, LogicalRegion};
 
-use crate::utils::EncodingFormat;
+use crate::{
+    encode::{PngCompression, PngFilter},
+    measure::MeasureFormat,
+    pick::ColorFormat,
+    redact::RedactStyle,
+    utils::{self, Color, EncodingFormat},
+    video::{StreamFormat, VideoFormat},
+};
 
 fn get_styles() -> Styles {
     Styles::styled()
@@ -20,8 +28,11 @@ fn get_styles() -> Styles {
 }
 
 #[derive(Parser)]
-#[command(version, about, styles=get_styles())]
+#[command(version, about, styles=get_styles(), args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Custom screenshot file path can be of the following types:
     ///     1. Directory (Default naming scheme is used for the screenshot file).
     ///     2. Path (Encoding is automatically inferred from the extension).
@@ -35,13 +46,25 @@ pub struct Cli {
     pub clipboard: bool,
 
     /// Log level to be used for printing to stderr
-    #[arg(long, default_value = "info", value_parser = clap::builder::PossibleValuesParser::new(["trace", "debug", "info", "warn", "error"]).map(|s| -> tracing::Level{ s.parse().wrap_err_with(|| format!("Failed to parse log level: {}", s)).unwrap()}))]
+    #[arg(long, global = true, default_value = "info", value_parser = clap::builder::PossibleValuesParser::new(["trace", "debug", "info", "warn", "error"]).map(|s| -> tracing::Level{ s.parse().wrap_err_with(|| format!("Failed to parse log level: {}", s)).unwrap()}))]
     pub log_level: tracing::Level,
 
     /// Arguments to call slurp with for selecting a region
     #[arg(short, long, value_name = "SLURP_ARGS")]
     pub slurp: Option<Option<String>>,
 
+    /// Capture the given region instead of running slurp. Accepts `X,Y WxH`, `X Y W H` and
+    /// `WxH+X+Y`, optionally prefixed with an output name to make it relative to that output
+    /// (`DP-1:10,10 200x200`). Any number may be a percentage (`50%x50%+25%+25%`).
+    #[arg(
+        short,
+        long,
+        value_name = "GEOMETRY",
+        verbatim_doc_comment,
+        conflicts_with = "slurp"
+    )]
+    pub geometry: Option<Geometry>,
+
     /// Enable cursor in screenshots
     #[arg(short, long)]
     pub cursor: bool,
@@ -55,16 +78,358 @@ pub struct Cli {
     #[arg(short, long, alias = "list-outputs")]
     pub list_outputs: bool,
 
+    /// Print the output list as JSON, including the geometry, scale and transform of every output
+    #[arg(long, requires = "list_outputs", conflicts_with = "table")]
+    pub json: bool,
+
+    /// Print the output list as a table, including the geometry, scale and transform of every output
+    #[arg(long, requires = "list_outputs")]
+    pub table: bool,
+
     /// Choose a particular output/display to screenshot
-    #[arg(short, long, conflicts_with = "slurp")]
+    #[arg(short, long, conflicts_with_all = ["slurp", "geometry"])]
     pub output: Option<String>,
 
     /// Present a fuzzy selector for output/display selection
-    #[arg(long, alias = "choose-output", conflicts_with_all = ["slurp", "output"])]
+    #[arg(long, alias = "choose-output", conflicts_with_all = ["slurp", "geometry", "output"])]
     pub choose_output: bool,
 
+    /// Give up when the compositor does not respond within the given duration
+    /// (e.g. `500ms`, `5s`), instead of waiting forever.
+    #[arg(long, global = true, value_name = "DURATION", value_parser = utils::parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Wait for the given duration (e.g. `3`, `500ms`) before taking the screenshot,
+    /// e.g. to open a menu first.
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration, verbatim_doc_comment)]
+    pub delay: Option<Duration>,
+
+    /// Count the seconds of `--delay` down on every output. The countdown is hidden
+    /// before the screenshot is taken.
+    #[arg(long, requires = "delay", verbatim_doc_comment)]
+    pub countdown: bool,
+
+    /// Keep capturing until the screenshot did not change for the given duration
+    /// (e.g. `500ms`), to wait for animations to finish.
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration, verbatim_doc_comment, conflicts_with = "slurp")]
+    pub wait_stable: Option<Duration>,
+
+    /// Take the screenshot anyway when it is still changing after the given duration.
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration, requires = "wait_stable", default_value = "10s")]
+    pub max_wait: Duration,
+
+    /// Write everything received from the compositor to the given file, to replay the
+    /// screenshot later with `--replay-capture`, e.g. to attach to a bug report.
+    #[arg(long, value_name = "DUMP_FILE", verbatim_doc_comment)]
+    pub dump_capture: Option<PathBuf>,
+
+    /// Take the screenshot from a file written by `--dump-capture` instead of the compositor.
+    #[arg(
+        long,
+        value_name = "DUMP_FILE",
+        conflicts_with_all = ["slurp", "geometry", "output", "choose_output", "list_outputs", "cursor", "dump_capture", "timeout", "delay", "wait_stable"]
+    )]
+    pub replay_capture: Option<PathBuf>,
+
+    /// Write uncompressed frames to FILE (stdout by default, or a named pipe) until
+    /// stopped with SIGINT (Ctrl+C) or SIGTERM, e.g. for ffmpeg to encode them.
+    /// The stream ends when the mode of a captured output changes.
+    #[arg(
+        long,
+        value_name = "STREAM_FORMAT",
+        verbatim_doc_comment,
+        conflicts_with_all = ["clipboard", "encoding", "list_outputs", "choose_output", "dump_capture", "replay_capture", "file_name_format", "delay", "wait_stable"]
+    )]
+    pub stream: Option<StreamFormat>,
+
+    /// Frames per second written by `--stream`, repeating the previous frame if nothing changed.
+    #[arg(long, requires = "stream", default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub fps: u32,
+
+    /// Take a screenshot every given duration (e.g. `5s`, `1m`) until stopped with SIGINT
+    /// (Ctrl+C) or SIGTERM, saving them into the directory FILE (the current directory by
+    /// default). File names get a sequence number, screenshots looking the same as the
+    /// previous one are skipped.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = utils::parse_duration,
+        verbatim_doc_comment,
+        conflicts_with_all = ["clipboard", "list_outputs", "choose_output", "dump_capture", "replay_capture", "stream", "delay", "wait_stable"]
+    )]
+    pub interval: Option<Duration>,
+
+    /// Stop after capturing the given number of times with `--interval`, including the
+    /// skipped screenshots.
+    #[arg(long, requires = "interval", value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    pub count: Option<u64>,
+
+    /// Freeze the screen and print the color of the pixel clicked on, with a loupe around
+    /// the pointer. Escape or the right mouse button cancel. With `--clipboard` the color
+    /// is copied as well.
+    #[arg(
+        long,
+        verbatim_doc_comment,
+        conflicts_with_all = ["file", "slurp", "geometry", "output", "choose_output", "cursor", "encoding", "list_outputs", "dump_capture", "replay_capture", "wait_stable", "stream", "interval", "file_name_format"]
+    )]
+    pub pick: bool,
+
+    /// How `--pick` prints the color.
+    #[arg(
+        long,
+        requires = "pick",
+        value_name = "COLOR_FORMAT",
+        default_value = "hex"
+    )]
+    pub color_format: ColorFormat,
+
+    /// Freeze the screen and print the width, height and distance between the two points
+    /// dragged between, in logical and physical pixels of the output under the pointer.
+    /// Escape or the right mouse button cancel. With `--clipboard` the measurement is
+    /// copied as well.
+    #[arg(
+        long,
+        verbatim_doc_comment,
+        conflicts_with_all = ["file", "slurp", "geometry", "output", "choose_output", "cursor", "encoding", "list_outputs", "dump_capture", "replay_capture", "wait_stable", "stream", "interval", "file_name_format", "pick"]
+    )]
+    pub measure: bool,
+
+    /// How `--measure` prints the measurement.
+    #[arg(
+        long,
+        requires = "measure",
+        value_name = "MEASURE_FORMAT",
+        default_value = "text"
+    )]
+    pub measure_format: MeasureFormat,
+
+    /// Hide the given region of the screenshot before saving or copying it, e.g. a
+    /// password. Takes `X,Y WxH` in logical pixels like `--geometry`, without output
+    /// names or percentages. Can be given multiple times.
+    #[arg(
+        long,
+        value_name = "REGION",
+        verbatim_doc_comment,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub redact: Vec<LogicalRegion>,
+
+    /// Hide the regions listed in the given file as well, one `--redact` region per line.
+    /// Empty lines and lines starting with `#` are skipped.
+    #[arg(
+        long,
+        value_name = "REGION_FILE",
+        verbatim_doc_comment,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub redact_file: Option<PathBuf>,
+
+    /// How `--redact` hides the regions.
+    #[arg(long, value_name = "REDACT_STYLE", default_value = "black")]
+    pub redact_style: RedactStyle,
+
+    /// Draw the rectangles, arrows, circles, text and numbered markers described in the
+    /// given TOML file onto the screenshot, after hiding the `--redact` regions.
+    /// Coordinates are logical pixels from the top left corner of the screenshot.
+    #[arg(
+        long,
+        value_name = "SPEC",
+        verbatim_doc_comment,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub annotate: Option<PathBuf>,
+
+    /// Add space around the screenshot, in logical pixels. Defaults to twice the blur of
+    /// `--shadow` if that is given, so that the shadow fits.
+    #[arg(
+        long,
+        value_name = "PADDING",
+        verbatim_doc_comment,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub padding: Option<u32>,
+
+    /// Color of `--padding` as `#rrggbb` or `#rrggbbaa`. Transparent by default, which needs
+    /// an encoder with alpha like png.
+    #[arg(
+        long,
+        value_name = "COLOR",
+        verbatim_doc_comment,
+        default_value = "#00000000"
+    )]
+    pub padding_color: Color,
+
+    /// Round the corners of the screenshot with the given radius in logical pixels.
+    #[arg(
+        long,
+        value_name = "RADIUS",
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub corner_radius: Option<u32>,
+
+    /// Cast a soft shadow onto the padding, fading out over the given number of logical
+    /// pixels (16 by default).
+    #[arg(
+        long,
+        value_name = "BLUR",
+        num_args = 0..=1,
+        default_missing_value = "16",
+        verbatim_doc_comment,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub shadow: Option<u32>,
+
+    /// Color of `--shadow` as `#rrggbb` or `#rrggbbaa`.
+    #[arg(long, value_name = "COLOR", default_value = "#00000080")]
+    pub shadow_color: Color,
+
+    /// Quality of jpg, avif and webp from 1 to 100. Giving a quality makes webp lossy
+    /// unless `--lossless` is given as well.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        verbatim_doc_comment,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub quality: Option<u8>,
+
+    /// How hard png compresses, trading speed for smaller files.
+    #[arg(
+        long,
+        value_name = "COMPRESSION",
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub png_compression: Option<PngCompression>,
+
+    /// Filter applied to the rows of png before compressing them.
+    #[arg(
+        long,
+        value_name = "FILTER",
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub png_filter: Option<PngFilter>,
+
+    /// Encode webp losslessly, which is the default unless `--quality` is given.
+    #[arg(
+        long,
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub lossless: bool,
+
+    /// Speed of the avif encoder from 1 (slowest, smallest files) to 10.
+    #[arg(
+        long,
+        value_name = "SPEED",
+        value_parser = clap::value_parser!(u8).range(1..=10),
+        conflicts_with_all = ["list_outputs", "stream", "pick", "measure"]
+    )]
+    pub avif_speed: Option<u8>,
+
+    /// Config file to read encoder options from, instead of
+    /// `$XDG_CONFIG_HOME/wayshot/config.toml`.
+    #[arg(long, global = true, value_name = "CONFIG_FILE", verbatim_doc_comment)]
+    pub config: Option<PathBuf>,
+
     /// Output file name's formatting.
     /// Defaults to config value (`wayshot-%Y_%m_%d-%H_%M_%S`)
     #[arg(long, verbatim_doc_comment)]
     pub file_name_format: Option<String>,
 }
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Record the screen to an animated image or a raw video stream
+    Record(RecordArgs),
+    /// Compare two screenshots pixel by pixel
+    ///
+    /// Exits with 0 if they match, 1 if they differ and 2 on errors.
+    Diff(DiffArgs),
+}
+
+#[derive(Args)]
+pub struct RecordArgs {
+    /// File to write the recording to, or `-` to write it to stdout.
+    #[arg(value_name = "FILE")]
+    pub file: PathBuf,
+
+    /// Format of the recording, by default deduced from the extension of FILE
+    /// (`.apng`/`.png`, `.gif`, `.y4m`, `.raw`/`.rgba`).
+    #[arg(long, value_name = "FORMAT", verbatim_doc_comment)]
+    pub format: Option<VideoFormat>,
+
+    /// Arguments to call slurp with for selecting the region to record
+    #[arg(short, long, value_name = "SLURP_ARGS")]
+    pub slurp: Option<Option<String>>,
+
+    /// Record the given region, see the `--geometry` option of screenshots
+    #[arg(short, long, value_name = "GEOMETRY", conflicts_with = "slurp")]
+    pub geometry: Option<Geometry>,
+
+    /// Record a particular output/display
+    #[arg(short, long, conflicts_with_all = ["slurp", "geometry"])]
+    pub output: Option<String>,
+
+    /// Enable cursor in the recording
+    #[arg(short, long)]
+    pub cursor: bool,
+
+    /// Most frames captured per second. Frames that did not change are skipped,
+    /// raw video formats repeat the previous frame instead.
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..=1000), verbatim_doc_comment)]
+    pub fps: u32,
+
+    /// Stop recording after the given duration (e.g. `10s`), instead of on SIGINT (Ctrl+C)
+    /// or SIGTERM.
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration, verbatim_doc_comment)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// The expected image, e.g. a baseline
+    #[arg(value_name = "EXPECTED")]
+    pub expected: PathBuf,
+
+    /// The image to compare with it
+    #[arg(value_name = "ACTUAL")]
+    pub actual: PathBuf,
+
+    /// Largest difference of a color channel (0-255) that still counts as the same pixel
+    #[arg(short, long, default_value_t = 0)]
+    pub tolerance: u8,
+
+    /// Region to leave out of the comparison, in logical coordinates of `--region` (e.g.
+    /// `1800,0 120x30`). Can be given multiple times.
+    #[arg(short, long, value_name = "REGION", verbatim_doc_comment)]
+    pub ignore: Vec<LogicalRegion>,
+
+    /// Logical region both images are screenshots of (e.g. `0,0 1920x1080`). `--ignore` and
+    /// the printed regions are in its coordinates, scaled onto the images. Without it they are
+    /// in pixels of the images.
+    #[arg(short, long, value_name = "REGION", verbatim_doc_comment)]
+    pub region: Option<LogicalRegion>,
+
+    /// Write ACTUAL with the changed pixels highlighted to the given file
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Quality of a jpg, avif or webp `--output`, see the option of screenshots
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100), requires = "output")]
+    pub quality: Option<u8>,
+
+    /// How hard a png `--output` is compressed
+    #[arg(long, value_name = "COMPRESSION", requires = "output")]
+    pub png_compression: Option<PngCompression>,
+
+    /// Filter applied to the rows of a png `--output` before compressing them
+    #[arg(long, value_name = "FILTER", requires = "output")]
+    pub png_filter: Option<PngFilter>,
+
+    /// Encode a webp `--output` losslessly, which is the default unless `--quality` is given
+    #[arg(long, requires = "output")]
+    pub lossless: bool,
+
+    /// Speed of the avif encoder for `--output` from 1 (slowest, smallest files) to 10
+    #[arg(long, value_name = "SPEED", value_parser = clap::value_parser!(u8).range(1..=10), requires = "output")]
+    pub avif_speed: Option<u8>,
+}
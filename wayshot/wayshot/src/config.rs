@@ -0,0 +1,137 @@
+//! The config file, `$XDG_CONFIG_HOME/wayshot/config.toml` unless another one
+//! is given with `--config`, e.g.
+//!
+//! ```toml
+//! [encoder]
+//! quality = 90
+//! png_compression = "best"
+//! png_filter = "adaptive"
+//! lossless = false
+//! avif_speed = 6
+//! ```
+//!
+//! Options given on the command line take precedence.
+
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+use crate::{encode::EncoderOptions, utils};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub encoder: EncoderOptions,
+}
+
+impl Config {
+    /// Read the config file at `path`, or the default one if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::read(&utils::get_expanded_path(path), true),
+            None => match default_path() {
+                Some(path) => Self::read(&path, false),
+                None => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Read the config file at `path`, which may only be missing if it
+    /// wasn't given `explicit`ly.
+    fn read(path: &Path, explicit: bool) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if !explicit && e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).wrap_err_with(|| format!("failed to read {}", path.display())),
+        };
+        let config: Self = toml::from_str(&contents)
+            .wrap_err_with(|| format!("invalid config in {}", path.display()))?;
+        config
+            .encoder
+            .validate()
+            .wrap_err_with(|| format!("invalid config in {}", path.display()))?;
+        Ok(config)
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("wayshot").join("config.toml"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::encode::{EncoderOptions, PngCompression};
+
+    /// A config file with `contents`, removed again afterwards.
+    pub(crate) struct ConfigFile(pub(crate) PathBuf);
+
+    impl ConfigFile {
+        pub(crate) fn new(test: &str, contents: &str) -> Self {
+            let path = env::temp_dir().join(format!("wayshot-{test}-{}.toml", process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn encoder_options_are_read() {
+        let file = ConfigFile::new(
+            "config-read",
+            "[encoder]\nquality = 90\npng_compression = \"best\"\n",
+        );
+        let config = Config::load(Some(&file.0)).unwrap();
+        assert_eq!(
+            config.encoder,
+            EncoderOptions {
+                quality: Some(90),
+                png_compression: Some(PngCompression::Best),
+                ..EncoderOptions::default()
+            }
+        );
+    }
+
+    #[test]
+    fn missing_files() {
+        let missing = env::temp_dir().join(format!("wayshot-missing-{}.toml", process::id()));
+        // Only the default file is optional.
+        let config = Config::read(&missing, false).unwrap();
+        assert_eq!(config.encoder, EncoderOptions::default());
+        let error = Config::load(Some(&missing)).unwrap_err();
+        assert!(error.to_string().contains("failed to read"), "{error}");
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        for (test, contents) in [
+            ("config-unknown-table", "[encoding]\nquality = 90\n"),
+            ("config-unknown-key", "[encoder]\nspeed = 5\n"),
+            ("config-wrong-type", "[encoder]\nquality = \"high\"\n"),
+            ("config-out-of-range", "[encoder]\navif_speed = 0\n"),
+        ] {
+            let file = ConfigFile::new(test, contents);
+            let error = Config::load(Some(&file.0)).unwrap_err();
+            assert!(
+                error.to_string().contains("invalid config"),
+                "{test}: {error}"
+            );
+        }
+    }
+}
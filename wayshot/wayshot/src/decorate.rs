@@ -0,0 +1,40 @@
+//! `--padding`, `--corner-radius` and `--shadow`: frame screenshots, e.g. of
+//! windows for documentation.
+
+use image::DynamicImage;
+use libwayshot::{
+    decorate::{self, Shadow},
+    region::{LogicalRegion, Position},
+};
+
+use crate::cli::Cli;
+
+/// The decorations given on the command line.
+pub struct Decorations(decorate::Decorations);
+
+impl Decorations {
+    pub fn from_cli(cli: &Cli) -> Self {
+        let shadow = cli.shadow.map(|blur| Shadow {
+            blur,
+            // Lit from slightly above.
+            offset: Position {
+                x: 0,
+                y: (blur / 4) as i32,
+            },
+            color: cli.shadow_color.0,
+        });
+        Self(decorate::Decorations {
+            padding: cli
+                .padding
+                .unwrap_or_else(|| cli.shadow.map_or(0, |blur| 2 * blur)),
+            background: cli.padding_color.0,
+            corner_radius: cli.corner_radius.unwrap_or(0),
+            shadow,
+        })
+    }
+
+    /// Frame `image`, a screenshot of `capture_region`.
+    pub fn apply(&self, image: &mut DynamicImage, capture_region: LogicalRegion) {
+        decorate::decorate(image, capture_region.inner.size, &self.0);
+    }
+}
@@ -0,0 +1,94 @@
+//! `wayshot diff`: compare two screenshots, e.g. a capture with its baseline
+//! in a visual regression test, and report the changed regions.
+
+use std::{path::Path, process};
+
+use eyre::{Result, WrapErr};
+use image::DynamicImage;
+use libwayshot::{
+    diff::{self, DiffOptions},
+    region::LogicalRegion,
+};
+
+use crate::{
+    cli::DiffArgs,
+    encode::EncoderOptions,
+    utils::{self, EncodingFormat},
+};
+
+/// Exit codes like diff(1), so that scripts can tell differences from errors.
+const EXIT_SAME: i32 = 0;
+const EXIT_DIFFERENT: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+pub fn exit_with_diff(args: DiffArgs, config: Option<&Path>) -> ! {
+    let code = match diff(args, config) {
+        Ok(true) => EXIT_DIFFERENT,
+        Ok(false) => EXIT_SAME,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            EXIT_ERROR
+        }
+    };
+    process::exit(code)
+}
+
+/// Whether the images differ. The highlighted image is encoded with the
+/// options given with the arguments, or else the ones of the config file at
+/// `config`, or the default one, which is only read for it.
+fn diff(args: DiffArgs, config: Option<&Path>) -> Result<bool> {
+    // Check the output before comparing, so a typo doesn't waste the work.
+    let output = match &args.output {
+        Some(path) => {
+            let encoder = EncoderOptions::from_diff_args(&args, config)?;
+            let path = utils::get_absolute_path(&utils::get_expanded_path(path));
+            let format = EncodingFormat::try_from(&path)?;
+            encoder.check(format)?;
+            Some((encoder, path, format))
+        }
+        None => None,
+    };
+
+    let open = |path| {
+        let path = utils::get_absolute_path(&utils::get_expanded_path(path));
+        image::open(&path).wrap_err_with(|| format!("failed to read {}", path.display()))
+    };
+    let expected = open(&args.expected)?;
+    let actual = open(&args.actual)?;
+
+    let options = DiffOptions {
+        tolerance: args.tolerance,
+        ignore: args.ignore,
+        capture_region: args.region,
+    };
+    let image_diff = diff::compare(&expected, &actual, &options)?;
+
+    if let Some((encoder, path, format)) = output {
+        let highlighted = DynamicImage::ImageRgba8(image_diff.highlight(&actual)?);
+        encoder.save(&highlighted, format, &path)?;
+    }
+
+    let compared = image_diff.compared_pixels();
+    if !image_diff.has_changes() {
+        println!("The images match, compared {compared} pixels");
+        return Ok(false);
+    }
+    println!(
+        "{} of {compared} pixels differ ({:.2}%)",
+        image_diff.changed_pixels,
+        image_diff.changed_pixels as f64 * 100.0 / compared as f64
+    );
+    // In the form of `--ignore`, to leave out expected changes.
+    for &region in &image_diff.regions {
+        let region = match args.region {
+            Some(capture_region) => {
+                LogicalRegion::from_image(region, &capture_region, image_diff.size)
+            }
+            None => Some(LogicalRegion { inner: region }),
+        };
+        if let Some(region) = region {
+            println!("{region}");
+        }
+    }
+    Ok(true)
+}
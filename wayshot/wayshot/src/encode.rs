@@ -0,0 +1,328 @@
+//! Encoding screenshots with the options given with `--quality`,
+//! `--png-compression`, `--png-filter`, `--lossless` and `--avif-speed`, or
+//! in the `[encoder]` table of the config file. `wayshot diff` takes the same
+//! options for its highlighted image.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use eyre::{Result, WrapErr, bail, eyre};
+use image::{
+    DynamicImage,
+    codecs::{
+        avif::AvifEncoder,
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+        pnm::{PnmEncoder, PnmSubtype, SampleEncoding},
+        qoi::QoiEncoder,
+        webp::WebPEncoder,
+    },
+};
+use serde::Deserialize;
+
+use crate::{
+    cli::{Cli, DiffArgs},
+    config::Config,
+    utils::EncodingFormat,
+};
+
+/// Quality of lossy WebP unless another one is given, like cwebp.
+const DEFAULT_WEBP_QUALITY: u8 = 75;
+/// Speed and quality of AVIF unless others are given, like the `image` crate.
+const DEFAULT_AVIF_SPEED: u8 = 4;
+const DEFAULT_AVIF_QUALITY: u8 = 80;
+
+/// How hard PNG compresses, trading speed for smaller files.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<PngCompression> for CompressionType {
+    fn from(compression: PngCompression) -> Self {
+        match compression {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        }
+    }
+}
+
+/// Filter applied to the rows of PNG before compressing them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    /// The best filter for every row.
+    Adaptive,
+}
+
+impl From<PngFilter> for FilterType {
+    fn from(filter: PngFilter) -> Self {
+        match filter {
+            PngFilter::None => FilterType::NoFilter,
+            PngFilter::Sub => FilterType::Sub,
+            PngFilter::Up => FilterType::Up,
+            PngFilter::Avg => FilterType::Avg,
+            PngFilter::Paeth => FilterType::Paeth,
+            PngFilter::Adaptive => FilterType::Adaptive,
+        }
+    }
+}
+
+/// Settings of the encoders, the defaults of the encoders where not given.
+/// Options that don't apply to the encoding format are ignored.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncoderOptions {
+    /// Quality of jpg, avif and lossy webp, from 1 to 100.
+    pub quality: Option<u8>,
+    pub png_compression: Option<PngCompression>,
+    pub png_filter: Option<PngFilter>,
+    /// Whether webp is lossless, which it is unless a `quality` is given.
+    /// jpg and avif can't be lossless.
+    pub lossless: Option<bool>,
+    /// Speed of the avif encoder, from 1 (slowest, smallest files) to 10.
+    pub avif_speed: Option<u8>,
+}
+
+impl EncoderOptions {
+    /// The options given on the command line, falling back to the ones in the
+    /// config file.
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        let config = Config::load(cli.config.as_deref())?;
+        let given = Self {
+            quality: cli.quality,
+            png_compression: cli.png_compression,
+            png_filter: cli.png_filter,
+            lossless: cli.lossless.then_some(true),
+            avif_speed: cli.avif_speed,
+        };
+        Ok(given.or(config.encoder))
+    }
+
+    /// The options given to `wayshot diff` for its highlighted image, falling
+    /// back to the ones in the config file at `config`, or the default one.
+    pub fn from_diff_args(args: &DiffArgs, config: Option<&Path>) -> Result<Self> {
+        let config = Config::load(config)?;
+        let given = Self {
+            quality: args.quality,
+            png_compression: args.png_compression,
+            png_filter: args.png_filter,
+            lossless: args.lossless.then_some(true),
+            avif_speed: args.avif_speed,
+        };
+        Ok(given.or(config.encoder))
+    }
+
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            quality: self.quality.or(fallback.quality),
+            png_compression: self.png_compression.or(fallback.png_compression),
+            png_filter: self.png_filter.or(fallback.png_filter),
+            lossless: self.lossless.or(fallback.lossless),
+            avif_speed: self.avif_speed.or(fallback.avif_speed),
+        }
+    }
+
+    /// Check the ranges the command line enforces, for options read from the
+    /// config file.
+    pub fn validate(&self) -> Result<()> {
+        if self
+            .quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+        {
+            bail!("quality must be between 1 and 100");
+        }
+        if self
+            .avif_speed
+            .is_some_and(|speed| !(1..=10).contains(&speed))
+        {
+            bail!("avif_speed must be between 1 and 10");
+        }
+        Ok(())
+    }
+
+    /// Check that `format` can be encoded with these options.
+    pub fn check(&self, format: EncodingFormat) -> Result<()> {
+        if self.lossless == Some(true)
+            && matches!(format, EncodingFormat::Jpg | EncodingFormat::Avif)
+        {
+            bail!("{format} can't be encoded losslessly, use png, qoi or webp instead");
+        }
+        Ok(())
+    }
+
+    /// Encode `image` as `format` into the file at `path`.
+    pub fn save(&self, image: &DynamicImage, format: EncodingFormat, path: &Path) -> Result<()> {
+        self.check(format)?;
+        let file =
+            File::create(path).wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        self.write(image, format, &mut writer)?;
+        writer
+            .flush()
+            .wrap_err_with(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Encode `image` as `format` into `writer`.
+    pub fn write(
+        &self,
+        image: &DynamicImage,
+        format: EncodingFormat,
+        mut writer: impl Write,
+    ) -> Result<()> {
+        self.check(format)?;
+        match format {
+            EncodingFormat::Jpg => {
+                let encoder = match self.quality {
+                    Some(quality) => JpegEncoder::new_with_quality(writer, quality),
+                    None => JpegEncoder::new(writer),
+                };
+                // JPEG has neither alpha nor more than 8 bits per channel.
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?;
+            }
+            EncodingFormat::Png => {
+                let encoder = PngEncoder::new_with_quality(
+                    writer,
+                    self.png_compression.map(Into::into).unwrap_or_default(),
+                    self.png_filter.map(Into::into).unwrap_or_default(),
+                );
+                image.write_with_encoder(encoder)?;
+            }
+            EncodingFormat::Ppm => {
+                let encoder = PnmEncoder::new(writer)
+                    .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary));
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?;
+            }
+            EncodingFormat::Qoi => {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+                    .write_with_encoder(QoiEncoder::new(writer))?;
+            }
+            EncodingFormat::Webp if self.lossless.unwrap_or(self.quality.is_none()) => {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+                    .write_with_encoder(WebPEncoder::new_lossless(writer))?;
+            }
+            EncodingFormat::Webp => {
+                // The `image` crate only encodes lossless WebP.
+                let rgba = image.to_rgba8();
+                let quality = self.quality.unwrap_or(DEFAULT_WEBP_QUALITY);
+                let encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+                    .encode_simple(false, quality as f32)
+                    .map_err(|e| eyre!("failed to encode webp: {e:?}"))?;
+                writer.write_all(&encoded)?;
+            }
+            EncodingFormat::Avif => {
+                let encoder = AvifEncoder::new_with_speed_quality(
+                    writer,
+                    self.avif_speed.unwrap_or(DEFAULT_AVIF_SPEED),
+                    self.quality.unwrap_or(DEFAULT_AVIF_QUALITY),
+                );
+                DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::config::tests::ConfigFile;
+
+    #[test]
+    fn command_line_options_take_precedence_over_the_config() {
+        let file = ConfigFile::new(
+            "encode-precedence",
+            "[encoder]\nquality = 90\navif_speed = 6\n",
+        );
+        let config = file.0.to_str().unwrap();
+        let cli = Cli::try_parse_from(["wayshot", "--config", config, "--quality", "50"]).unwrap();
+        assert_eq!(
+            EncoderOptions::from_cli(&cli).unwrap(),
+            EncoderOptions {
+                quality: Some(50),
+                avif_speed: Some(6),
+                // Neither given nor in the config, so the encoder's default.
+                png_compression: None,
+                ..EncoderOptions::default()
+            }
+        );
+    }
+
+    #[test]
+    fn out_of_range_options_are_rejected() {
+        let options = |quality, avif_speed| EncoderOptions {
+            quality,
+            avif_speed,
+            ..EncoderOptions::default()
+        };
+        assert!(options(Some(1), Some(1)).validate().is_ok());
+        assert!(options(Some(100), Some(10)).validate().is_ok());
+        assert!(options(None, None).validate().is_ok());
+        for invalid in [
+            options(Some(0), None),
+            options(Some(101), None),
+            options(None, Some(0)),
+            options(None, Some(11)),
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn only_some_formats_are_lossless() {
+        let lossless = EncoderOptions {
+            lossless: Some(true),
+            ..EncoderOptions::default()
+        };
+        for format in [EncodingFormat::Jpg, EncodingFormat::Avif] {
+            assert!(lossless.check(format).is_err(), "{format}");
+            assert!(EncoderOptions::default().check(format).is_ok(), "{format}");
+        }
+        for format in [
+            EncodingFormat::Png,
+            EncodingFormat::Qoi,
+            EncodingFormat::Webp,
+        ] {
+            assert!(lossless.check(format).is_ok(), "{format}");
+        }
+    }
+
+    #[test]
+    fn webp_is_lossy_with_a_quality() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([255, 0, 0, 255])));
+        // Lossless WebP has a `VP8L` chunk, lossy one a `VP8 ` chunk.
+        let chunk = |quality, lossless| {
+            let options = EncoderOptions {
+                quality,
+                lossless,
+                ..EncoderOptions::default()
+            };
+            let mut encoded = Vec::new();
+            options
+                .write(&image, EncodingFormat::Webp, &mut encoded)
+                .unwrap();
+            assert_eq!(&encoded[8..12], b"WEBP");
+            String::from_utf8_lossy(&encoded[12..16]).into_owned()
+        };
+        assert_eq!(chunk(None, None), "VP8L");
+        assert_eq!(chunk(Some(80), None), "VP8 ");
+        assert_eq!(chunk(Some(80), Some(true)), "VP8L");
+        assert_eq!(chunk(None, Some(false)), "VP8 ");
+    }
+}
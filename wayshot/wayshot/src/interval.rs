@@ -0,0 +1,141 @@
+//! `wayshot --interval`: take a screenshot every interval, e.g. to watch a
+//! long running test, skipping the ones that look like the previous one.
+
+use std::{
+    env, fs,
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, WrapErr, bail};
+use image::DynamicImage;
+use libwayshot::{Timeouts, WayshotConnection, output::OutputInfo, region::LogicalRegion};
+
+use crate::{
+    annotate::Annotations,
+    cli::Cli,
+    decorate::Decorations,
+    encode::EncoderOptions,
+    record::{self, StopSignals},
+    redact::Redactions,
+    utils,
+};
+
+pub fn capture_interval(cli: Cli, interval: Duration) -> Result<()> {
+    let encoding = cli.encoding.unwrap_or_default();
+    let redactions = Redactions::from_cli(&cli)?;
+    let annotations = Annotations::from_cli(&cli)?;
+    let decorations = Decorations::from_cli(&cli);
+    let encoder = EncoderOptions::from_cli(&cli)?;
+    encoder.check(encoding)?;
+    let file_name_format = cli
+        .file_name_format
+        .as_deref()
+        .unwrap_or(utils::DEFAULT_FILE_NAME_FORMAT);
+    let dir = match &cli.file {
+        Some(path) => utils::get_absolute_path(&utils::get_expanded_path(path)),
+        None => env::current_dir()?,
+    };
+    if dir.exists() && !dir.is_dir() {
+        bail!(
+            "{} is not a directory to save screenshots in",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(&dir).wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+
+    let mut wayshot_conn = WayshotConnection::new()?;
+    if let Some(timeout) = cli.timeout {
+        wayshot_conn.set_timeouts(Timeouts::uniform(timeout));
+    }
+
+    // Select with slurp only once, the region doesn't follow the outputs
+    // when their layout changes.
+    let slurp_region = match &cli.slurp {
+        Some(slurp_args) => Some(utils::slurp_region(
+            slurp_args.as_deref().unwrap_or_default(),
+        )?),
+        None => None,
+    };
+    let resolve_region = |outputs: &[OutputInfo]| -> Result<LogicalRegion> {
+        match slurp_region {
+            Some(region) => Ok(region),
+            None => record::resolve_region(outputs, &None, &cli.geometry, &cli.output),
+        }
+    };
+    let mut region = resolve_region(&wayshot_conn.get_all_outputs())?;
+
+    let signals = StopSignals::block()?;
+    let mut latest = None;
+    let (mut saved, mut skipped) = (0, 0);
+    tracing::info!(
+        "Capturing {region} every {interval:?} into {}, stop with Ctrl+C",
+        dir.display()
+    );
+
+    let mut next_capture = Instant::now();
+    for capture in 1.. {
+        if capture > 1 {
+            // Pick up outputs that were added, removed or changed meanwhile,
+            // the ones that didn't change keep their capture sessions.
+            wayshot_conn.refresh_outputs()?;
+            let previous = region;
+            region = resolve_region(&wayshot_conn.get_all_outputs())?;
+            if region != previous {
+                tracing::info!("The output layout changed, capturing {region} from now on");
+            }
+        }
+
+        let targets = record::capture_targets(&wayshot_conn.get_all_outputs(), region)?;
+        let image = match &latest {
+            None => {
+                let frames = wayshot_conn.capture_frame_copies(&targets, cli.cursor)?;
+                Some(record::composite(frames, region)?)
+            }
+            // Compositors may hold back frames until something changes,
+            // nothing did if there is none by the time the next capture is
+            // due.
+            Some(latest) => {
+                let deadline = next_capture + interval;
+                match wayshot_conn.capture_frame_copies_until(&targets, cli.cursor, deadline)? {
+                    Some(frames) => record::composite_changes(frames, region, latest)?,
+                    None => None,
+                }
+            }
+        };
+        match image {
+            Some(image) => {
+                saved += 1;
+                let path = dir.join(utils::get_sequence_file_name(
+                    file_name_format,
+                    saved,
+                    encoding,
+                ));
+                // Compare the next capture with what is on screen, not with
+                // what was saved.
+                let mut edited = DynamicImage::ImageRgba8(image.clone());
+                redactions.apply(&mut edited, region);
+                annotations.apply(&mut edited, region);
+                decorations.apply(&mut edited, region);
+                encoder.save(&edited, encoding, &path)?;
+                tracing::info!("Saved {}", path.display());
+                latest = Some(image);
+            }
+            None => {
+                skipped += 1;
+                tracing::debug!("Nothing changed, skipped capture {capture}");
+            }
+        }
+
+        if cli.count.is_some_and(|count| capture >= count) {
+            break;
+        }
+        // Don't try to catch up when capturing took longer than the interval.
+        next_capture = (next_capture + interval).max(Instant::now());
+        if signals.wait_until(next_capture)? {
+            break;
+        }
+    }
+    tracing::info!("Saved {saved} screenshots, skipped {skipped} unchanged ones");
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! `wayshot --measure`: print the distance between two points the user drags
+//! between.
+
+use std::{io::Cursor, thread};
+
+use clap::ValueEnum;
+use eyre::{Result, bail};
+use libwayshot::{Extent, Measurement, Timeouts, WayshotConnection};
+use wl_clipboard_rs::copy::MimeType;
+
+use crate::{cli::Cli, pick::number};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum MeasureFormat {
+    /// The positions, then width, height and distance in logical and physical pixels
+    #[default]
+    Text,
+    /// The output, positions and extents as a JSON object
+    Json,
+}
+
+pub fn measure(cli: Cli) -> Result<()> {
+    let mut wayshot_conn = WayshotConnection::new()?;
+    if let Some(timeout) = cli.timeout {
+        wayshot_conn.set_timeouts(Timeouts::uniform(timeout));
+    }
+    match cli.delay {
+        Some(delay) if cli.countdown => wayshot_conn.show_countdown(delay)?,
+        Some(delay) => thread::sleep(delay),
+        None => {}
+    }
+
+    let Some(measured) = wayshot_conn.measure()? else {
+        bail!("measuring was cancelled");
+    };
+    let text = format_measurement(&measured, cli.measure_format)?;
+    println!("{text}");
+
+    if cli.clipboard {
+        crate::clipboard_daemonize(Cursor::new(text.into_bytes()), MimeType::Text)?;
+    }
+    Ok(())
+}
+
+fn format_measurement(measured: &Measurement, format: MeasureFormat) -> Result<String> {
+    Ok(match format {
+        MeasureFormat::Text => {
+            let extent = |extent: Extent| {
+                format!(
+                    "{} x {}, distance {}",
+                    extent.width,
+                    extent.height,
+                    number(extent.distance, 2)
+                )
+            };
+            format!(
+                "{output}: {start} -> {end}\nlogical:  {logical}\nphysical: {physical}",
+                output = measured.output,
+                start = measured.start,
+                end = measured.end,
+                logical = extent(measured.logical),
+                physical = extent(measured.physical),
+            )
+        }
+        MeasureFormat::Json => serde_json::to_string(measured)?,
+    })
+}
@@ -0,0 +1,119 @@
+//! `wayshot --pick`: print the color of the pixel the user clicks on.
+
+use std::{io::Cursor, thread};
+
+use clap::ValueEnum;
+use eyre::{Result, bail};
+use libwayshot::{PickedColor, Timeouts, WayshotConnection};
+use wl_clipboard_rs::copy::MimeType;
+
+use crate::cli::Cli;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ColorFormat {
+    /// `#rrggbb`, or `#rrrrggggbbbb` for outputs with more than 8 bits per channel
+    #[default]
+    Hex,
+    /// `rgb(r, g, b)` from 0 to 255, with fractions for more than 8 bits per channel
+    Rgb,
+    /// `hsl(h, s%, l%)`
+    Hsl,
+    /// The channels as stored by the output with their depth, the output and position
+    Json,
+}
+
+pub fn pick(cli: Cli) -> Result<()> {
+    let mut wayshot_conn = WayshotConnection::new()?;
+    if let Some(timeout) = cli.timeout {
+        wayshot_conn.set_timeouts(Timeouts::uniform(timeout));
+    }
+    match cli.delay {
+        Some(delay) if cli.countdown => wayshot_conn.show_countdown(delay)?,
+        Some(delay) => thread::sleep(delay),
+        None => {}
+    }
+
+    let Some(picked) = wayshot_conn.pick_color()? else {
+        bail!("picking a color was cancelled");
+    };
+    let text = format_color(&picked, cli.color_format);
+    println!("{text}");
+
+    if cli.clipboard {
+        crate::clipboard_daemonize(Cursor::new(text.into_bytes()), MimeType::Text)?;
+    }
+    Ok(())
+}
+
+fn format_color(picked: &PickedColor, format: ColorFormat) -> String {
+    let color = picked.color;
+    match format {
+        ColorFormat::Hex if color.depth <= 8 => {
+            let [red, green, blue] = color.to_rgb8();
+            format!("#{red:02x}{green:02x}{blue:02x}")
+        }
+        ColorFormat::Hex => {
+            let [red, green, blue] = color.to_rgb16();
+            format!("#{red:04x}{green:04x}{blue:04x}")
+        }
+        ColorFormat::Rgb => {
+            let [red, green, blue] = color.to_f64().map(|channel| number(channel * 255.0, 2));
+            format!("rgb({red}, {green}, {blue})")
+        }
+        ColorFormat::Hsl => {
+            let (hue, saturation, lightness) = hsl(color.to_f64());
+            format!(
+                "hsl({}, {}%, {}%)",
+                number(hue, 1),
+                number(saturation * 100.0, 1),
+                number(lightness * 100.0, 1)
+            )
+        }
+        ColorFormat::Json => serde_json::json!({
+            "output": picked.output,
+            "x": picked.position.x,
+            "y": picked.position.y,
+            "red": color.red,
+            "green": color.green,
+            "blue": color.blue,
+            "depth": color.depth,
+            "hex": format_color(picked, ColorFormat::Hex),
+        })
+        .to_string(),
+    }
+}
+
+/// `value` with at most `decimals` decimals, without trailing zeros.
+pub fn number(value: f64, decimals: usize) -> String {
+    let formatted = format!("{value:.decimals$}");
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
+}
+
+/// Hue in degrees, saturation and lightness from 0 to 1 of RGB channels from 0
+/// to 1.
+fn hsl([red, green, blue]: [f64; 3]) -> (f64, f64, f64) {
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    let sector = if max == red {
+        ((green - blue) / chroma).rem_euclid(6.0)
+    } else if max == green {
+        (blue - red) / chroma + 2.0
+    } else {
+        (red - green) / chroma + 4.0
+    };
+    (sector * 60.0, saturation, lightness)
+}
@@ -0,0 +1,233 @@
+//! `wayshot record`: capture a region over and over and write the frames as
+//! an animation or video.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, bail};
+use image::RgbaImage;
+use libwayshot::{
+    FrameCopy, FrameGuard, Timeouts, WayshotConnection,
+    output::OutputInfo,
+    region::{EmbeddedRegion, Geometry, LogicalRegion},
+};
+use rustix::{
+    io::Errno,
+    runtime::{How, KernelSigSet, Signal, Timespec, kernel_sigprocmask, kernel_sigtimedwait},
+};
+
+use crate::{
+    cli::RecordArgs,
+    utils,
+    video::{Recorder, VideoFormat},
+};
+
+pub fn record(args: RecordArgs, timeout: Option<Duration>) -> Result<()> {
+    let Some(format) = args.format.or_else(|| VideoFormat::from_path(&args.file)) else {
+        bail!(
+            "cannot deduce the recording format from '{}', use --format",
+            args.file.display()
+        );
+    };
+    let output: Box<dyn Write> = if args.file.to_string_lossy() == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        let path = utils::get_absolute_path(&utils::get_expanded_path(&args.file));
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+
+    let mut wayshot_conn = WayshotConnection::new()?;
+    if let Some(timeout) = timeout {
+        wayshot_conn.set_timeouts(Timeouts::uniform(timeout));
+    }
+
+    let outputs = wayshot_conn.get_all_outputs();
+    let region = resolve_region(&outputs, &args.slurp, &args.geometry, &args.output)?;
+    let targets = capture_targets(&outputs, region)?;
+
+    let signals = StopSignals::block()?;
+    let interval = Duration::from_secs_f64(1.0 / args.fps as f64);
+    let mut recorder = Recorder::new(format, output, args.fps);
+    let mut skipped = 0;
+    tracing::info!("Recording {region}, stop with Ctrl+C");
+
+    let start = Instant::now();
+    let stop_at = args.duration.map(|duration| start + duration);
+    let result = (|| -> Result<()> {
+        let mut next_frame = start;
+        loop {
+            let time = start.elapsed();
+            if args.duration.is_some_and(|duration| time >= duration) {
+                return Ok(());
+            }
+
+            // Compositors may hold back frames until something changes, so
+            // don't wait for one past the next frame or the end, and check for
+            // signals in between. New sessions get their first frame right
+            // away.
+            let deadline = stop_at.map_or(next_frame + interval, |stop_at| {
+                stop_at.min(next_frame + interval)
+            });
+            let image = match recorder.latest() {
+                None => {
+                    let frames = wayshot_conn.capture_frame_copies(&targets, args.cursor)?;
+                    Some(composite(frames, region)?)
+                }
+                Some(latest) => {
+                    match wayshot_conn.capture_frame_copies_until(
+                        &targets,
+                        args.cursor,
+                        deadline,
+                    )? {
+                        Some(frames) => composite_changes(frames, region, latest)?,
+                        None => None,
+                    }
+                }
+            };
+            match image {
+                Some(image) => recorder.push(image, time)?,
+                None => skipped += 1,
+            }
+
+            // Never capture faster than the frame rate, but don't try to
+            // catch up when capturing is slower.
+            next_frame = (next_frame + interval).max(Instant::now());
+            if signals.wait_until(next_frame)? {
+                return Ok(());
+            }
+        }
+    })();
+
+    let end = start.elapsed().min(args.duration.unwrap_or(Duration::MAX));
+    let frames = recorder.frames();
+    let finished = recorder.finish(end);
+    result?;
+    finished?;
+    tracing::info!("Recorded {frames} frames in {end:.1?}, skipped {skipped} unchanged frames");
+
+    Ok(())
+}
+
+/// Region selected with slurp, the given geometry or output, or all outputs
+/// if none of them are given.
+pub fn resolve_region(
+    outputs: &[OutputInfo],
+    slurp: &Option<Option<String>>,
+    geometry: &Option<Geometry>,
+    output: &Option<String>,
+) -> Result<LogicalRegion> {
+    Ok(if let Some(slurp_args) = slurp {
+        utils::slurp_region(slurp_args.as_deref().unwrap_or_default())?
+    } else if let Some(geometry) = geometry {
+        geometry.resolve(outputs)?
+    } else if let Some(output_name) = output {
+        let Some(output) = outputs.iter().find(|output| &output.name == output_name) else {
+            bail!("No output found with name '{output_name}'");
+        };
+        LogicalRegion::from(output)
+    } else {
+        outputs.try_into()?
+    })
+}
+
+/// Outputs to capture for `region`, with the part of the region on them.
+pub fn capture_targets(
+    outputs: &[OutputInfo],
+    region: LogicalRegion,
+) -> Result<Vec<(OutputInfo, Option<EmbeddedRegion>)>> {
+    let targets: Vec<_> = outputs
+        .iter()
+        .filter_map(|output| {
+            EmbeddedRegion::new(region, output.into())
+                .map(|embedded| (output.clone(), Some(embedded)))
+        })
+        .collect();
+    if targets.is_empty() {
+        bail!("region {region} is not on any output");
+    }
+    Ok(targets)
+}
+
+/// Composite `frames` into an image of `region`.
+pub fn composite(
+    frames: Vec<(FrameCopy, FrameGuard, OutputInfo)>,
+    region: LogicalRegion,
+) -> Result<RgbaImage> {
+    let frames = frames
+        .into_iter()
+        .map(|(frame_copy, _, output_info)| (frame_copy, output_info))
+        .collect();
+    Ok(libwayshot::composite_frames(frames, region)?.into_rgba8())
+}
+
+/// Composite `frames` into an image of `region`, `None` if it looks the same
+/// as `latest`.
+pub fn composite_changes(
+    frames: Vec<(FrameCopy, FrameGuard, OutputInfo)>,
+    region: LogicalRegion,
+    latest: &RgbaImage,
+) -> Result<Option<RgbaImage>> {
+    // Frames without damage in the region look the same as before, don't
+    // bother compositing them.
+    let damaged = frames.iter().any(|(frame_copy, _, _)| {
+        frame_copy.damage.as_ref().is_none_or(|damage| {
+            damage
+                .iter()
+                .any(|damage| damage.intersection(&region).is_some())
+        })
+    });
+    if !damaged {
+        return Ok(None);
+    }
+
+    let image = composite(frames, region)?;
+    Ok((*latest != image).then_some(image))
+}
+
+/// SIGINT and SIGTERM, blocked to stop recording gracefully instead of being
+/// killed by them.
+pub struct StopSignals {
+    signals: KernelSigSet,
+    previous_mask: KernelSigSet,
+}
+
+impl StopSignals {
+    pub fn block() -> Result<Self> {
+        let mut signals = KernelSigSet::empty();
+        signals.insert(Signal::INT);
+        signals.insert(Signal::TERM);
+        // SAFETY: Neither signal is reserved by libc, and nothing else in
+        // wayshot relies on them being delivered.
+        let previous_mask = unsafe { kernel_sigprocmask(How::BLOCK, Some(&signals))? };
+        Ok(Self {
+            signals,
+            previous_mask,
+        })
+    }
+
+    /// Wait until `deadline`, returns whether one of the signals arrived.
+    pub fn wait_until(&self, deadline: Instant) -> Result<bool> {
+        loop {
+            let timeout = Timespec::try_from(deadline.saturating_duration_since(Instant::now()))?;
+            // SAFETY: See `block`.
+            match unsafe { kernel_sigtimedwait(&self.signals, Some(&timeout)) } {
+                Ok(_) => return Ok(true),
+                Err(Errno::AGAIN) => return Ok(false),
+                Err(Errno::INTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for StopSignals {
+    fn drop(&mut self) {
+        // SAFETY: See `block`.
+        if let Err(e) = unsafe { kernel_sigprocmask(How::SETMASK, Some(&self.previous_mask)) } {
+            tracing::warn!("Failed to unblock signals: {e}");
+        }
+    }
+}
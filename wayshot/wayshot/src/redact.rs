@@ -0,0 +1,80 @@
+//! `--redact`: hide regions of screenshots before they are saved or copied to
+//! the clipboard.
+
+use std::{fs, path::Path};
+
+use clap::ValueEnum;
+use eyre::{Result, WrapErr};
+use image::DynamicImage;
+use libwayshot::{
+    redact::{self, RedactionStyle},
+    region::LogicalRegion,
+};
+
+use crate::{cli::Cli, utils};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum RedactStyle {
+    /// Replace blocks of pixels with their average color
+    Pixelate,
+    /// Blur the regions, text may still be guessed from its shape
+    Blur,
+    /// Paint the regions black
+    #[default]
+    Black,
+}
+
+impl From<RedactStyle> for RedactionStyle {
+    fn from(style: RedactStyle) -> Self {
+        match style {
+            RedactStyle::Pixelate => RedactionStyle::Pixelate,
+            RedactStyle::Blur => RedactionStyle::Blur,
+            RedactStyle::Black => RedactionStyle::Blackout,
+        }
+    }
+}
+
+/// The regions given with `--redact` and `--redact-file`, hidden with
+/// `--redact-style`.
+pub struct Redactions {
+    regions: Vec<LogicalRegion>,
+    style: RedactionStyle,
+}
+
+impl Redactions {
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        let mut regions = cli.redact.clone();
+        if let Some(path) = &cli.redact_file {
+            regions.extend(read_regions(&utils::get_expanded_path(path))?);
+        }
+        Ok(Self {
+            regions,
+            style: cli.redact_style.into(),
+        })
+    }
+
+    /// Hide the regions in `image`, a screenshot of `capture_region`.
+    pub fn apply(&self, image: &mut DynamicImage, capture_region: LogicalRegion) {
+        if !self.regions.is_empty() {
+            redact::redact(image, capture_region, &self.regions, self.style);
+        }
+    }
+}
+
+/// Regions listed one per line, skipping empty lines and comments starting
+/// with `#`.
+fn read_regions(path: &Path) -> Result<Vec<LogicalRegion>> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            line.parse().wrap_err_with(|| {
+                format!("invalid region on line {} of {}", index + 1, path.display())
+            })
+        })
+        .collect()
+}
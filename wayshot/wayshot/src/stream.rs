@@ -0,0 +1,186 @@
+//! `wayshot --stream`: write uncompressed frames of a region at a constant
+//! frame rate for as long as the reader keeps reading, e.g. to pipe them into
+//! an encoder.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+use eyre::Result;
+use libwayshot::{
+    FrameCopy, FrameGuard, Timeouts, WayshotConnection, output::OutputInfo, region::EmbeddedRegion,
+};
+
+use crate::{
+    cli::Cli,
+    record::{self, StopSignals},
+    utils,
+    video::{self, FrameWriter, StreamFormat},
+};
+
+pub fn stream(cli: Cli, format: StreamFormat) -> Result<()> {
+    let output: Box<dyn Write> = match &cli.file {
+        Some(path) if path.to_string_lossy() != "-" => {
+            // Also opens named pipes, blocking until a reader opens them.
+            let path = utils::get_absolute_path(&utils::get_expanded_path(path));
+            Box::new(BufWriter::new(File::create(path)?))
+        }
+        _ => Box::new(io::stdout().lock()),
+    };
+
+    let mut wayshot_conn = WayshotConnection::new()?;
+    if let Some(timeout) = cli.timeout {
+        wayshot_conn.set_timeouts(Timeouts::uniform(timeout));
+    }
+
+    let outputs = wayshot_conn.get_all_outputs();
+    let region = record::resolve_region(&outputs, &cli.slurp, &cli.geometry, &cli.output)?;
+    let targets = record::capture_targets(&outputs, region)?;
+
+    let signals = StopSignals::block()?;
+    let interval = Duration::from_secs_f64(1.0 / cli.fps as f64);
+    let mut output = Some(output);
+    let mut writer: Option<Box<dyn FrameWriter>> = None;
+    let mut latest = None;
+    // End of what was written so far, relative to the start of the stream.
+    let mut written = Duration::ZERO;
+
+    let start = Instant::now();
+    let result = (|| -> Result<()> {
+        let mut next_frame = start;
+        loop {
+            let time = start.elapsed();
+            // The size of the frames can't change within a stream, and the
+            // region may not even be on the output anymore.
+            let frame = match &mut latest {
+                None => {
+                    let frames = match wayshot_conn.capture_frame_copies(&targets, cli.cursor) {
+                        Ok(frames) => frames,
+                        Err(e) => return end_on_mode_change(&mut wayshot_conn, &targets, e),
+                    };
+                    if let Some(output) = resized_output(&frames) {
+                        tracing::info!("The mode of {output} changed, ending the stream");
+                        return Ok(());
+                    }
+                    latest.insert(record::composite(frames, region)?)
+                }
+                // Compositors may hold back frames until something changes,
+                // repeat the latest frame if there is none by the time the
+                // next one is due.
+                Some(latest) => {
+                    let deadline = next_frame + interval;
+                    let frames = match wayshot_conn
+                        .capture_frame_copies_until(&targets, cli.cursor, deadline)
+                    {
+                        Ok(frames) => frames,
+                        Err(e) => return end_on_mode_change(&mut wayshot_conn, &targets, e),
+                    };
+                    if let Some(frames) = frames {
+                        if let Some(output) = resized_output(&frames) {
+                            tracing::info!("The mode of {output} changed, ending the stream");
+                            return Ok(());
+                        }
+                        if let Some(image) = record::composite_changes(frames, region, latest)? {
+                            *latest = image;
+                        }
+                    }
+                    latest
+                }
+            };
+
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let (width, height) = frame.dimensions();
+                    let fps = cli.fps;
+                    match format {
+                        StreamFormat::Y4m => tracing::info!(
+                            "Streaming {region} as {width}x{height} frames at {fps} fps, stop with Ctrl+C"
+                        ),
+                        StreamFormat::Rgba | StreamFormat::Bgra => {
+                            let pixel_format = format!("{format:?}").to_lowercase();
+                            tracing::info!(
+                                "Streaming {region}, stop with Ctrl+C. Read it with `-f rawvideo -pixel_format {pixel_format} -video_size {width}x{height} -framerate {fps}`"
+                            )
+                        }
+                    }
+                    let output = output.take().unwrap_or_else(|| Box::new(io::sink()));
+                    writer.insert(video::stream_writer(
+                        format, output, width, height, cli.fps,
+                    )?)
+                }
+            };
+            // Show the frame until the next one is due, frames missed because
+            // capturing took longer repeat it too.
+            writer.write_frame(frame, written, time + interval)?;
+            written = time + interval;
+
+            next_frame = (next_frame + interval).max(Instant::now());
+            if signals.wait_until(next_frame)? {
+                return Ok(());
+            }
+        }
+    })();
+
+    let finished = writer.map_or(Ok(()), |writer| writer.finish());
+    match result.and(finished) {
+        Err(e) if is_broken_pipe(&e) => {
+            tracing::info!("The reader closed the stream");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Ends the stream if capturing failed with `error` because the mode of an
+/// output changed, the buffer doesn't fit the new mode then.
+fn end_on_mode_change(
+    wayshot_conn: &mut WayshotConnection,
+    targets: &[(OutputInfo, Option<EmbeddedRegion>)],
+    error: libwayshot::Error,
+) -> Result<()> {
+    wayshot_conn.refresh_outputs()?;
+    if let Some(output) = changed_output(targets, &wayshot_conn.get_all_outputs()) {
+        tracing::info!("The mode of {output} changed, ending the stream");
+        return Ok(());
+    }
+    Err(error.into())
+}
+
+/// Name of the output of a frame that doesn't have the size of the output's
+/// mode.
+fn resized_output(frames: &[(FrameCopy, FrameGuard, OutputInfo)]) -> Option<&str> {
+    frames
+        .iter()
+        .find(|(frame_copy, _, output_info)| {
+            frame_copy.physical_size != output_info.transformed_physical_size()
+        })
+        .map(|(_, _, output_info)| output_info.name.as_str())
+}
+
+/// Name of an output in `targets` that is gone or changed its mode or layout
+/// according to `outputs`.
+fn changed_output<'a>(
+    targets: &'a [(OutputInfo, Option<EmbeddedRegion>)],
+    outputs: &[OutputInfo],
+) -> Option<&'a str> {
+    targets
+        .iter()
+        .find(|(target, _)| {
+            !outputs.iter().any(|output| {
+                output.name == target.name
+                    && output.physical_size == target.physical_size
+                    && output.transform == target.transform
+                    && output.logical_region == target.logical_region
+            })
+        })
+        .map(|(target, _)| target.name.as_str())
+}
+
+fn is_broken_pipe(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe)
+}
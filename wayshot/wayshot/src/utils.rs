@@ -1,55 +1,98 @@
 use clap::ValueEnum;
-use eyre::{ContextCompat, Error, Result, bail};
+use eyre::{ContextCompat, Error, Result, WrapErr, bail};
 
 use std::{
     env,
     fmt::Display,
     path::{Path, PathBuf},
+    process::Command,
     str::FromStr,
+    time::Duration,
 };
 
 use chrono::Local;
-use libwayshot::region::{LogicalRegion, Position, Region, Size};
-
-pub fn parse_geometry(g: &str) -> Result<LogicalRegion> {
-    let tail = g.trim();
-    let x_coordinate: i32;
-    let y_coordinate: i32;
-    let width: u32;
-    let height: u32;
-
-    let validation_error =
-        "Invalid geometry provided.\nValid geometries:\n1) %d,%d %dx%d\n2) %d %d %d %d";
-
-    if tail.contains(',') {
-        // this accepts: "%d,%d %dx%d"
-        let (head, tail) = tail.split_once(',').wrap_err(validation_error)?;
-        x_coordinate = head.parse::<i32>()?;
-        let (head, tail) = tail.split_once(' ').wrap_err(validation_error)?;
-        y_coordinate = head.parse::<i32>()?;
-        let (head, tail) = tail.split_once('x').wrap_err(validation_error)?;
-        width = head.parse::<u32>()?;
-        height = tail.parse::<u32>()?;
+use image::Rgba;
+use libwayshot::region::LogicalRegion;
+
+/// `--file-name-format` used unless another one is given.
+pub const DEFAULT_FILE_NAME_FORMAT: &str = "wayshot-%Y_%m_%d-%H_%M_%S";
+
+/// Parse a duration such as `500ms`, `5s` or `2m`. A plain number is in
+/// seconds and may be fractional, e.g. `1.5`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (number, unit_seconds) = if let Some(number) = s.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = s.strip_suffix('m') {
+        (number, 60.0)
     } else {
-        // this accepts: "%d %d %d %d"
-        let (head, tail) = tail.split_once(' ').wrap_err(validation_error)?;
-        x_coordinate = head.parse::<i32>()?;
-        let (head, tail) = tail.split_once(' ').wrap_err(validation_error)?;
-        y_coordinate = head.parse::<i32>()?;
-        let (head, tail) = tail.split_once(' ').wrap_err(validation_error)?;
-        width = head.parse::<u32>()?;
-        height = tail.parse::<u32>()?;
+        (s, 1.0)
+    };
+
+    let seconds = number
+        .trim()
+        .parse::<f64>()
+        .wrap_err_with(|| format!("invalid duration '{s}', expected e.g. 500ms, 5s or 2m"))?
+        * unit_seconds;
+    Duration::try_from_secs_f64(seconds)
+        .wrap_err_with(|| format!("duration '{s}' is negative or too long"))
+}
+
+/// Let the user select a region with slurp, called with `slurp_args`.
+pub fn slurp_region(slurp_args: &str) -> Result<LogicalRegion> {
+    let slurp_output = Command::new("slurp")
+        .args(slurp_args.split_whitespace())
+        .output()
+        .wrap_err("failed to run slurp")?
+        .stdout;
+
+    Ok(String::from_utf8(slurp_output)?.parse()?)
+}
+
+/// `#rgb`, `#rrggbb` or `#rrggbbaa`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub Rgba<u8>);
+
+impl FromStr for Color {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(hex) = s.strip_prefix('#').filter(|hex| hex.is_ascii()) else {
+            bail!("invalid color '{s}', expected `#rrggbb` or `#rrggbbaa`");
+        };
+        let channel = |digits: &str| u8::from_str_radix(digits, 16);
+        let channels = match hex.len() {
+            3 => hex
+                .chars()
+                .map(|digit| channel(&digit.to_string().repeat(2)))
+                .chain([Ok(255)])
+                .collect::<Result<Vec<_>, _>>(),
+            6 | 8 => (0..hex.len())
+                .step_by(2)
+                .map(|start| channel(&hex[start..start + 2]))
+                .chain((hex.len() == 6).then_some(Ok(255)))
+                .collect(),
+            _ => bail!("invalid color '{s}', expected `#rrggbb` or `#rrggbbaa`"),
+        }
+        .wrap_err_with(|| format!("invalid color '{s}'"))?;
+        Ok(Self(Rgba([
+            channels[0],
+            channels[1],
+            channels[2],
+            channels[3],
+        ])))
     }
+}
 
-    Ok(LogicalRegion {
-        inner: Region {
-            position: Position {
-                x: x_coordinate,
-                y: y_coordinate,
-            },
-            size: Size { width, height },
-        },
-    })
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse().map_err(|e: Error| e.to_string())
+    }
 }
 
 /// Supported image encoding formats.
@@ -167,6 +210,18 @@ pub fn get_default_file_name(filename_format: &str, encoding: EncodingFormat) ->
     PathBuf::from(format!("{}.{}", format, encoding))
 }
 
+/// Like [`get_default_file_name`], with the sequence number of a screenshot
+/// taken with `--interval` appended.
+pub fn get_sequence_file_name(
+    filename_format: &str,
+    sequence: u64,
+    encoding: EncodingFormat,
+) -> PathBuf {
+    let format = Local::now().format(filename_format);
+
+    PathBuf::from(format!("{}-{:04}.{}", format, sequence, encoding))
+}
+
 pub fn get_full_file_name(path: &Path, filename_format: &str, encoding: EncodingFormat) -> PathBuf {
     let expanded_path = get_expanded_path(path);
     let absolute_path = get_absolute_path(&expanded_path);
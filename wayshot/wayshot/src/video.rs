@@ -0,0 +1,307 @@
+//! Writing recorded frames as animated images or raw video.
+
+use std::{
+    io::{self, Write},
+    mem,
+    path::Path,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use eyre::{Result, bail};
+use image::RgbaImage;
+
+mod apng;
+mod gif;
+mod rawvideo;
+
+/// Format of a recording.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum VideoFormat {
+    /// Animated PNG, lossless.
+    Apng,
+    /// GIF, every frame quantized to its own palette of 256 colors.
+    Gif,
+    /// YUV4MPEG2 stream of 4:2:0 frames at a constant frame rate.
+    Y4m,
+    /// Headerless RGBA frames at a constant frame rate.
+    Raw,
+}
+
+impl VideoFormat {
+    /// Format to write to `path`, deduced from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "apng" | "png" => Some(Self::Apng),
+            "gif" => Some(Self::Gif),
+            "y4m" => Some(Self::Y4m),
+            "raw" | "rgba" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// Format of a stream written with `--stream`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum StreamFormat {
+    /// YUV4MPEG2, with the size and frame rate in its header.
+    Y4m,
+    /// Headerless RGBA frames, ffmpeg's `rawvideo` with `-pixel_format rgba`.
+    Rgba,
+    /// Headerless BGRA frames, ffmpeg's `rawvideo` with `-pixel_format bgra`.
+    Bgra,
+}
+
+/// Writes frames of a fixed size. Times are relative to the start of the
+/// recording.
+pub trait FrameWriter {
+    /// Write `frame`, shown from `start` until `end`.
+    fn write_frame(&mut self, frame: &RgbaImage, start: Duration, end: Duration) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Create a writer of `format` for frames of `width` x `height` pixels.
+/// Constant frame rate formats repeat or drop frames to get `fps` frames per
+/// second.
+pub fn frame_writer(
+    format: VideoFormat,
+    output: Box<dyn Write>,
+    width: u32,
+    height: u32,
+    fps: u32,
+) -> Result<Box<dyn FrameWriter>> {
+    if width == 0 || height == 0 {
+        bail!("cannot record an empty region");
+    }
+    Ok(match format {
+        VideoFormat::Apng => Box::new(apng::ApngWriter::new(output, width, height)),
+        VideoFormat::Gif => Box::new(gif::GifWriter::new(output, width, height)?),
+        VideoFormat::Y4m => Box::new(rawvideo::Y4mWriter::new(output, width, height, fps)?),
+        VideoFormat::Raw => Box::new(rawvideo::RawWriter::new(output, fps, false)),
+    })
+}
+
+/// Create a writer streaming frames of `width` x `height` pixels in `format`
+/// at a constant `fps`.
+pub fn stream_writer(
+    format: StreamFormat,
+    output: Box<dyn Write>,
+    width: u32,
+    height: u32,
+    fps: u32,
+) -> Result<Box<dyn FrameWriter>> {
+    Ok(match format {
+        StreamFormat::Y4m => frame_writer(VideoFormat::Y4m, output, width, height, fps)?,
+        StreamFormat::Rgba => frame_writer(VideoFormat::Raw, output, width, height, fps)?,
+        StreamFormat::Bgra => Box::new(rawvideo::RawWriter::new(output, fps, true)),
+    })
+}
+
+/// Writes frames to a [`FrameWriter`] created for the size of the first one,
+/// holding back the latest frame until it is known how long it is shown.
+pub struct Recorder {
+    format: VideoFormat,
+    fps: u32,
+    /// Handed to the writer once it is created.
+    output: Box<dyn Write>,
+    writer: Option<Box<dyn FrameWriter>>,
+    latest: Option<(RgbaImage, Duration)>,
+    frames: usize,
+}
+
+impl Recorder {
+    pub fn new(format: VideoFormat, output: Box<dyn Write>, fps: u32) -> Self {
+        Self {
+            format,
+            fps,
+            output,
+            writer: None,
+            latest: None,
+            frames: 0,
+        }
+    }
+
+    /// The frame shown at the moment.
+    pub fn latest(&self) -> Option<&RgbaImage> {
+        self.latest.as_ref().map(|(frame, _)| frame)
+    }
+
+    /// Number of distinct frames recorded.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Show `frame` from `time` on.
+    pub fn push(&mut self, frame: RgbaImage, time: Duration) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let output = mem::replace(&mut self.output, Box::new(io::sink()));
+                let (width, height) = frame.dimensions();
+                self.writer
+                    .insert(frame_writer(self.format, output, width, height, self.fps)?)
+            }
+        };
+
+        if let Some((latest, start)) = self.latest.take() {
+            if latest.dimensions() != frame.dimensions() {
+                bail!(
+                    "frame size changed from {:?} to {:?} while recording",
+                    latest.dimensions(),
+                    frame.dimensions()
+                );
+            }
+            writer.write_frame(&latest, start, time)?;
+        }
+        self.latest = Some((frame, time));
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// End the recording at `end`.
+    pub fn finish(self, end: Duration) -> Result<()> {
+        let Some(mut writer) = self.writer else {
+            bail!("no frames were recorded");
+        };
+        if let Some((latest, start)) = &self.latest {
+            writer.write_frame(latest, *start, end.max(*start))?;
+        }
+        writer.finish()
+    }
+}
+
+/// Bounding box `(x, y, width, height)` of the pixels that differ between
+/// `previous` and `frame`, `None` if they are equal.
+fn changed_area(previous: &RgbaImage, frame: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let (width, height) = frame.dimensions();
+    let row_len = width as usize * 4;
+    let rows = previous
+        .as_raw()
+        .chunks_exact(row_len)
+        .zip(frame.as_raw().chunks_exact(row_len));
+
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (y, (previous_row, row)) in rows.enumerate() {
+        if previous_row == row {
+            continue;
+        }
+        let differs = |x: &usize| previous_row[x * 4..x * 4 + 4] != row[x * 4..x * 4 + 4];
+        let first = (0..width as usize).find(differs).unwrap_or(0) as u32;
+        let last = (0..width as usize).rev().find(differs).unwrap_or(0) as u32;
+        left = left.min(first);
+        right = right.max(last + 1);
+        top = top.min(y as u32);
+        bottom = y as u32 + 1;
+    }
+    (left < right).then(|| (left, top, right - left, bottom - top))
+}
+
+/// Number of frames at a constant `fps` shown between `start` and `end`.
+fn frame_slots(start: Duration, end: Duration, fps: u32) -> u64 {
+    let slot = |time: Duration| (time.as_secs_f64() * fps as f64).round() as u64;
+    slot(end).saturating_sub(slot(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use image::Rgba;
+
+    use super::*;
+
+    pub(super) const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    pub(super) const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// Output that can still be read after the writer consumed it.
+    #[derive(Clone, Default)]
+    pub(super) struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuffer {
+        pub(super) fn take(&self) -> Vec<u8> {
+            self.0.take()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A red 5x3 frame, one with a white 2x2 square in the middle shown from
+    /// 100ms on, and the same one again from 300ms until 400ms.
+    pub(super) fn frames() -> Vec<(RgbaImage, Duration)> {
+        let red = RgbaImage::from_pixel(5, 3, RED);
+        let mut square = red.clone();
+        for (x, y) in [(2, 1), (3, 1), (2, 2), (3, 2)] {
+            square.put_pixel(x, y, WHITE);
+        }
+        vec![
+            (red, Duration::ZERO),
+            (square.clone(), Duration::from_millis(100)),
+            (square, Duration::from_millis(300)),
+        ]
+    }
+
+    pub(super) const END: Duration = Duration::from_millis(400);
+
+    /// [`frames`] recorded as `format` at 10 frames per second.
+    pub(super) fn record(format: VideoFormat) -> Result<Vec<u8>> {
+        let output = SharedBuffer::default();
+        let mut recorder = Recorder::new(format, Box::new(output.clone()), 10);
+        for (frame, time) in frames() {
+            recorder.push(frame, time)?;
+        }
+        assert_eq!(recorder.frames(), 3);
+        recorder.finish(END)?;
+        Ok(output.take())
+    }
+
+    #[test]
+    fn changed_area_bounds_the_changed_pixels() {
+        let previous = RgbaImage::from_pixel(5, 4, RED);
+        assert_eq!(changed_area(&previous, &previous), None);
+
+        let mut frame = previous.clone();
+        frame.put_pixel(1, 2, WHITE);
+        assert_eq!(changed_area(&previous, &frame), Some((1, 2, 1, 1)));
+        frame.put_pixel(3, 0, WHITE);
+        assert_eq!(changed_area(&previous, &frame), Some((1, 0, 3, 3)));
+    }
+
+    #[test]
+    fn frame_slots_round_to_the_closest_frame() {
+        let ms = Duration::from_millis;
+        assert_eq!(frame_slots(Duration::ZERO, Duration::from_secs(1), 30), 30);
+        assert_eq!(frame_slots(ms(10), ms(40), 30), 1);
+        assert_eq!(frame_slots(ms(40), ms(49), 30), 0);
+        // The rounding errors don't add up over many short frames.
+        let total: u64 = (0..100)
+            .map(|frame| frame_slots(ms(frame * 10), ms(frame * 10 + 10), 30))
+            .sum();
+        assert_eq!(total, 30);
+        assert_eq!(frame_slots(ms(100), ms(50), 30), 0);
+    }
+
+    #[test]
+    fn recorder_rejects_resized_frames() -> Result<()> {
+        let mut recorder = Recorder::new(VideoFormat::Raw, Box::new(io::sink()), 10);
+        recorder.push(RgbaImage::new(2, 2), Duration::ZERO)?;
+        let result = recorder.push(RgbaImage::new(3, 2), Duration::from_millis(100));
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn empty_recordings_fail() {
+        let recorder = Recorder::new(VideoFormat::Gif, Box::new(io::sink()), 10);
+        assert!(recorder.finish(END).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Animated PNG writer.
+//!
+//! The number of frames has to be written before the first frame, so the
+//! compressed frames are kept in memory until the recording ends. Every
+//! frame after the first only covers the area that changed.
+
+use std::{io::Write, time::Duration};
+
+use eyre::Result;
+use flate2::{Compression, Crc, write::ZlibEncoder};
+use image::RgbaImage;
+
+use super::{FrameWriter, changed_area};
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub struct ApngWriter {
+    output: Box<dyn Write>,
+    width: u32,
+    height: u32,
+    /// `fcTL`, `IDAT` and `fdAT` chunks of the frames written so far.
+    chunks: Vec<u8>,
+    frames: u32,
+    sequence: u32,
+    previous: Option<RgbaImage>,
+}
+
+impl ApngWriter {
+    pub fn new(output: Box<dyn Write>, width: u32, height: u32) -> Self {
+        Self {
+            output,
+            width,
+            height,
+            chunks: Vec::new(),
+            frames: 0,
+            sequence: 0,
+            previous: None,
+        }
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence += 1;
+        self.sequence - 1
+    }
+}
+
+impl FrameWriter for ApngWriter {
+    fn write_frame(&mut self, frame: &RgbaImage, start: Duration, end: Duration) -> Result<()> {
+        let (x, y, width, height) = match &self.previous {
+            // An unchanged frame still needs a pixel to show for its delay.
+            Some(previous) => changed_area(previous, frame).unwrap_or((0, 0, 1, 1)),
+            None => (0, 0, self.width, self.height),
+        };
+        let (delay_num, delay_den) = delay(end.saturating_sub(start));
+
+        let mut control = Vec::with_capacity(26);
+        control.extend(self.next_sequence().to_be_bytes());
+        for value in [width, height, x, y] {
+            control.extend(value.to_be_bytes());
+        }
+        control.extend(delay_num.to_be_bytes());
+        control.extend(delay_den.to_be_bytes());
+        // Don't dispose of the frame and overwrite the area it covers.
+        control.extend([0, 0]);
+        write_chunk(&mut self.chunks, b"fcTL", &control)?;
+
+        let data = compress(frame, x, y, width, height)?;
+        if self.frames == 0 {
+            write_chunk(&mut self.chunks, b"IDAT", &data)?;
+        } else {
+            let mut frame_data = Vec::with_capacity(data.len() + 4);
+            frame_data.extend(self.next_sequence().to_be_bytes());
+            frame_data.extend(data);
+            write_chunk(&mut self.chunks, b"fdAT", &frame_data)?;
+        }
+
+        self.frames += 1;
+        self.previous = Some(frame.clone());
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let mut header = Vec::with_capacity(13);
+        header.extend(self.width.to_be_bytes());
+        header.extend(self.height.to_be_bytes());
+        // 8 bit RGBA, no interlacing.
+        header.extend([8, 6, 0, 0, 0]);
+
+        let mut animation = Vec::with_capacity(8);
+        animation.extend(self.frames.to_be_bytes());
+        // Loop forever.
+        animation.extend(0u32.to_be_bytes());
+
+        self.output.write_all(SIGNATURE)?;
+        write_chunk(&mut self.output, b"IHDR", &header)?;
+        write_chunk(&mut self.output, b"acTL", &animation)?;
+        self.output.write_all(&self.chunks)?;
+        write_chunk(&mut self.output, b"IEND", &[])?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Frame delay as a fraction of a second, as precise as fits into `u16`s.
+fn delay(duration: Duration) -> (u16, u16) {
+    [1000, 100, 10, 1]
+        .into_iter()
+        .find_map(|den: u16| {
+            let num = (duration.as_secs_f64() * den as f64).round();
+            (num <= u16::MAX as f64).then_some((num as u16, den))
+        })
+        .unwrap_or((u16::MAX, 1))
+}
+
+/// Filter and compress the given area of `frame`. Every row uses the "up"
+/// filter, which suits screen content that mostly repeats between rows.
+fn compress(frame: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let row = |row_y: u32| {
+        let start = (row_y as usize * frame.width() as usize + x as usize) * 4;
+        &frame.as_raw()[start..start + width as usize * 4]
+    };
+
+    let mut filtered = Vec::with_capacity(width as usize * 4 + 1);
+    for row_y in y..y + height {
+        filtered.clear();
+        if row_y == y {
+            filtered.push(0);
+            filtered.extend_from_slice(row(row_y));
+        } else {
+            filtered.push(2);
+            filtered.extend(
+                row(row_y)
+                    .iter()
+                    .zip(row(row_y - 1))
+                    .map(|(value, above)| value.wrapping_sub(*above)),
+            );
+        }
+        encoder.write_all(&filtered)?;
+    }
+    Ok(encoder.finish()?)
+}
+
+fn write_chunk(output: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(kind)?;
+    output.write_all(data)?;
+    output.write_all(&crc.sum().to_be_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{AnimationDecoder, codecs::png::PngDecoder};
+
+    use super::*;
+    use crate::video::{
+        VideoFormat,
+        tests::{END, frames, record},
+    };
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let recording = record(VideoFormat::Apng)?;
+        let decoded = PngDecoder::new(Cursor::new(recording))?
+            .apng()?
+            .into_frames()
+            .collect_frames()?;
+
+        let frames = frames();
+        assert_eq!(decoded.len(), frames.len());
+        let ends = frames.iter().skip(1).map(|(_, time)| *time).chain([END]);
+        for ((decoded, (frame, start)), end) in decoded.into_iter().zip(&frames).zip(ends) {
+            assert_eq!(Duration::from(decoded.delay()), end - *start);
+            assert_eq!(decoded.buffer(), frame);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn delays_keep_the_most_precise_fraction() {
+        assert_eq!(delay(Duration::from_millis(1234)), (1234, 1000));
+        assert_eq!(delay(Duration::from_millis(123_456)), (12346, 100));
+        assert_eq!(delay(Duration::from_secs(100_000)), (u16::MAX, 1));
+    }
+}
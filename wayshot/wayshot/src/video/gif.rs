@@ -0,0 +1,433 @@
+//! GIF writer.
+//!
+//! Every frame only covers the area that changed and gets its own palette,
+//! found with median cut over the colors of that area.
+
+use std::{io::Write, time::Duration};
+
+use eyre::{Result, bail};
+use image::RgbaImage;
+
+use super::{FrameWriter, changed_area};
+
+/// Most colors sampled per frame to build its palette.
+const MAX_SAMPLES: usize = 1 << 16;
+
+pub struct GifWriter {
+    output: Box<dyn Write>,
+    previous: Option<RgbaImage>,
+}
+
+impl GifWriter {
+    pub fn new(mut output: Box<dyn Write>, width: u32, height: u32) -> Result<Self> {
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+            bail!("GIF frames cannot be larger than 65535x65535");
+        };
+
+        output.write_all(b"GIF89a")?;
+        output.write_all(&width.to_le_bytes())?;
+        output.write_all(&height.to_le_bytes())?;
+        // No global color table, 8 bits per primary color.
+        output.write_all(&[0x70, 0, 0])?;
+        // Loop forever.
+        output.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(Self {
+            output,
+            previous: None,
+        })
+    }
+}
+
+impl FrameWriter for GifWriter {
+    fn write_frame(&mut self, frame: &RgbaImage, start: Duration, end: Duration) -> Result<()> {
+        let (x, y, width, height) = match &self.previous {
+            Some(previous) => changed_area(previous, frame).unwrap_or((0, 0, 1, 1)),
+            None => (0, 0, frame.width(), frame.height()),
+        };
+        // Delays are in hundredths of a second, round the start and end so
+        // the rounding errors don't add up.
+        let centis = |time: Duration| (time.as_millis() + 5) / 10;
+        let delay = (centis(end) - centis(start)).min(u16::MAX as u128) as u16;
+
+        let pixels: Vec<[u8; 3]> = (y..y + height)
+            .flat_map(|row| (x..x + width).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let [r, g, b, _] = frame.get_pixel(column, row).0;
+                [r, g, b]
+            })
+            .collect();
+        let palette = median_cut(&pixels);
+        let indices = map_to_palette(&pixels, &palette);
+
+        // Graphic control extension: keep the frame in place for the next
+        // one to draw over.
+        self.output.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.output.write_all(&delay.to_le_bytes())?;
+        self.output.write_all(&[0, 0])?;
+
+        // Image descriptor with a local color table of 256 colors.
+        self.output.write_all(&[0x2c])?;
+        for value in [x, y, width, height] {
+            self.output.write_all(&(value as u16).to_le_bytes())?;
+        }
+        self.output.write_all(&[0x87])?;
+        for index in 0..256 {
+            self.output
+                .write_all(&palette.get(index).copied().unwrap_or_default())?;
+        }
+
+        self.output.write_all(&[8])?;
+        for block in lzw_encode(&indices).chunks(255) {
+            self.output.write_all(&[block.len() as u8])?;
+            self.output.write_all(block)?;
+        }
+        self.output.write_all(&[0])?;
+
+        self.previous = Some(frame.clone());
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.output.write_all(&[0x3b])?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Up to 256 colors representing `pixels`, from repeatedly splitting the box
+/// of colors with the widest range at the median of that range.
+fn median_cut(pixels: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    let step = pixels.len().div_ceil(MAX_SAMPLES).max(1);
+    let mut boxes: Vec<Vec<[u8; 3]>> = vec![pixels.iter().step_by(step).copied().collect()];
+
+    while boxes.len() < 256 {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (index, channel, range)
+            })
+            .filter(|&(_, _, range)| range > 0)
+            .max_by_key(|&(_, _, range)| range);
+        let Some((index, channel, _)) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| {
+            let mut sum = [0u64; 3];
+            for color in colors {
+                for (sum, value) in sum.iter_mut().zip(color) {
+                    *sum += *value as u64;
+                }
+            }
+            sum.map(|sum| (sum / colors.len() as u64) as u8)
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let (min, max) = colors.iter().fold((u8::MAX, 0), |(min, max), color| {
+                (min.min(color[channel]), max.max(color[channel]))
+            });
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+/// Index of the closest palette color of every pixel. Lookups are cached by
+/// the 5 most significant bits of every channel.
+fn map_to_palette(pixels: &[[u8; 3]], palette: &[[u8; 3]]) -> Vec<u8> {
+    let mut cache = vec![None; 1 << 15];
+    pixels
+        .iter()
+        .map(|&color| {
+            let [r, g, b] = color.map(|value| value as usize >> 3);
+            *cache[r << 10 | g << 5 | b].get_or_insert_with(|| closest(color, palette))
+        })
+        .collect()
+}
+
+fn closest(color: [u8; 3], palette: &[[u8; 3]]) -> u8 {
+    let distance = |entry: &[u8; 3]| -> i32 {
+        color
+            .iter()
+            .zip(entry)
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+            .sum()
+    };
+    (0..palette.len())
+        .min_by_key(|&index| distance(&palette[index]))
+        .unwrap_or(0) as u8
+}
+
+/// Variable code length LZW as used by GIF, with a minimum code size of 8.
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const FIRST: u16 = 258;
+    const MAX_CODES: u16 = 4096;
+
+    // Code extending the prefix code by a byte, at `prefix * 256 + byte`.
+    let mut table = vec![0u16; MAX_CODES as usize * 256];
+    let mut used = Vec::new();
+    let mut next = FIRST;
+    let mut size = 9;
+    let mut writer = BitWriter::default();
+
+    writer.write(CLEAR, size);
+    let mut prefix: Option<u16> = None;
+    for &byte in indices {
+        let Some(code) = prefix else {
+            prefix = Some(byte as u16);
+            continue;
+        };
+        let key = code as usize * 256 + byte as usize;
+        if table[key] != 0 {
+            prefix = Some(table[key]);
+            continue;
+        }
+
+        writer.write(code, size);
+        if next < MAX_CODES {
+            table[key] = next;
+            used.push(key);
+            next += 1;
+            // The decoder adds codes one step behind.
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        } else {
+            writer.write(CLEAR, size);
+            for key in used.drain(..) {
+                table[key] = 0;
+            }
+            next = FIRST;
+            size = 9;
+        }
+        prefix = Some(byte as u16);
+    }
+    if let Some(code) = prefix {
+        writer.write(code, size);
+    }
+    writer.write(END, size);
+    writer.finish()
+}
+
+/// Packs codes least significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.len;
+        self.len += size;
+        while self.len >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+
+    use image::Rgba;
+
+    use super::*;
+    use crate::video::{
+        VideoFormat,
+        tests::{END, SharedBuffer, frames, record},
+    };
+
+    /// Pseudo random numbers below `below`, the same in every run.
+    fn noise(below: u32) -> impl Iterator<Item = u32> {
+        let mut state = 0x2545_f491u32;
+        iter::repeat_with(move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % below
+        })
+    }
+
+    /// Frames of a GIF as written by [`GifWriter`], each drawn over the
+    /// previous ones, with their delays.
+    fn decode(gif: &[u8]) -> Vec<(RgbaImage, Duration)> {
+        assert_eq!(&gif[..6], b"GIF89a");
+        let width = u16::from_le_bytes([gif[6], gif[7]]);
+        let height = u16::from_le_bytes([gif[8], gif[9]]);
+        let mut canvas = RgbaImage::new(width.into(), height.into());
+        let mut frames = Vec::new();
+        let mut delay = Duration::ZERO;
+
+        let mut at = 13;
+        loop {
+            match gif[at] {
+                0x3b => return frames,
+                0x21 => {
+                    if gif[at + 1] == 0xf9 {
+                        let centis = u16::from_le_bytes([gif[at + 4], gif[at + 5]]);
+                        delay = Duration::from_millis(u64::from(centis) * 10);
+                    }
+                    at = sub_blocks(gif, at + 2).1;
+                }
+                0x2c => {
+                    let field =
+                        |index: usize| u16::from_le_bytes([gif[at + index], gif[at + index + 1]]);
+                    let [x, y, width, _] = [1, 3, 5, 7].map(|index| u32::from(field(index)));
+                    assert_eq!(gif[at + 9], 0x87);
+                    let palette = &gif[at + 10..at + 10 + 768];
+                    let min_size = gif[at + 10 + 768];
+                    let (data, end) = sub_blocks(gif, at + 11 + 768);
+                    at = end;
+
+                    for (index, color) in lzw_decode(&data, min_size).into_iter().enumerate() {
+                        let [r, g, b] =
+                            [0, 1, 2].map(|channel| palette[color as usize * 3 + channel]);
+                        let (column, row) = (index as u32 % width, index as u32 / width);
+                        canvas.put_pixel(x + column, y + row, Rgba([r, g, b, 255]));
+                    }
+                    frames.push((canvas.clone(), delay));
+                }
+                block => panic!("unexpected block {block:#x}"),
+            }
+        }
+    }
+
+    /// Data of the sub-blocks starting at `at`, and the index after them.
+    fn sub_blocks(gif: &[u8], mut at: usize) -> (Vec<u8>, usize) {
+        let mut data = Vec::new();
+        while gif[at] != 0 {
+            let len = gif[at] as usize;
+            data.extend_from_slice(&gif[at + 1..at + 1 + len]);
+            at += 1 + len;
+        }
+        (data, at + 1)
+    }
+
+    /// A plain LZW decoder, growing the code size and adding codes the way
+    /// GIF decoders do.
+    fn lzw_decode(data: &[u8], min_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_size;
+        // Bytes, then the clear and end codes.
+        let initial: Vec<Vec<u8>> = (0..clear)
+            .map(|byte| vec![byte as u8])
+            .chain([Vec::new(), Vec::new()])
+            .collect();
+        let mut table = initial.clone();
+        let mut size = u32::from(min_size) + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output = Vec::new();
+
+        let (mut bits, mut len, mut bytes) = (0u32, 0, data.iter());
+        loop {
+            while len < size {
+                bits |= u32::from(*bytes.next().expect("end code")) << len;
+                len += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            len -= size;
+
+            if code == clear {
+                table = initial.clone();
+                size = u32::from(min_size) + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return output;
+            }
+
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) if code == table.len() => {
+                    [previous.as_slice(), &previous[..1]].concat()
+                }
+                _ => panic!("invalid code {code}"),
+            };
+            output.extend_from_slice(&entry);
+            if let Some(previous) = previous
+                && table.len() < 4096
+            {
+                table.push([previous.as_slice(), &entry[..1]].concat());
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let decoded = decode(&record(VideoFormat::Gif)?);
+
+        let frames = frames();
+        assert_eq!(decoded.len(), frames.len());
+        let ends = frames.iter().skip(1).map(|(_, time)| *time).chain([END]);
+        for ((decoded, (frame, start)), end) in decoded.iter().zip(&frames).zip(ends) {
+            assert_eq!(decoded.1, end - *start);
+            assert_eq!(&decoded.0, frame);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_up_to_256_colors_exact() -> Result<()> {
+        let colors: Vec<Rgba<u8>> = noise(1 << 24)
+            .take(16)
+            .map(|color| {
+                let [r, g, b, _] = color.to_le_bytes();
+                Rgba([r, g, b, 255])
+            })
+            .collect();
+        let mut pixels = noise(16);
+        let frame = RgbaImage::from_fn(128, 128, |_, _| colors[pixels.next().unwrap() as usize]);
+
+        let output = SharedBuffer::default();
+        let mut writer = GifWriter::new(Box::new(output.clone()), 128, 128)?;
+        writer.write_frame(&frame, Duration::ZERO, Duration::from_millis(50))?;
+        Box::new(writer).finish()?;
+        assert_eq!(decode(&output.take()), [(frame, Duration::from_millis(50))]);
+        Ok(())
+    }
+
+    #[test]
+    fn lzw_round_trips_through_table_resets() {
+        let noisy: Vec<u8> = noise(256).take(1 << 16).map(|byte| byte as u8).collect();
+        let repetitive: Vec<u8> = (0..1 << 16).map(|index| (index / 300 % 3) as u8).collect();
+        for indices in [Vec::new(), vec![7], noisy, repetitive] {
+            assert_eq!(lzw_decode(&lzw_encode(&indices), 8), indices);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Uncompressed video at a constant frame rate, for encoders to read.
+
+use std::{borrow::Cow, io::Write, time::Duration};
+
+use eyre::Result;
+use image::RgbaImage;
+
+use super::{FrameWriter, frame_slots};
+
+/// YUV4MPEG2 with 4:2:0 chroma subsampling and BT.601 limited range colors.
+pub struct Y4mWriter {
+    output: Box<dyn Write>,
+    fps: u32,
+}
+
+impl Y4mWriter {
+    pub fn new(mut output: Box<dyn Write>, width: u32, height: u32, fps: u32) -> Result<Self> {
+        writeln!(
+            output,
+            "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED"
+        )?;
+        Ok(Self { output, fps })
+    }
+}
+
+impl FrameWriter for Y4mWriter {
+    fn write_frame(&mut self, frame: &RgbaImage, start: Duration, end: Duration) -> Result<()> {
+        let slots = frame_slots(start, end, self.fps);
+        if slots == 0 {
+            return Ok(());
+        }
+
+        let planes = yuv420(frame);
+        for _ in 0..slots {
+            self.output.write_all(b"FRAME\n")?;
+            self.output.write_all(&planes)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Y, U and V planes of `frame`. Chroma is averaged over blocks of 2x2
+/// pixels, or fewer at odd edges.
+fn yuv420(frame: &RgbaImage) -> Vec<u8> {
+    let (width, height) = frame.dimensions();
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut planes =
+        Vec::with_capacity((width * height + 2 * chroma_width * chroma_height) as usize);
+
+    planes.extend(frame.pixels().map(|pixel| {
+        let [r, g, b, _] = pixel.0.map(|value| value as i32);
+        (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8
+    }));
+
+    let mut v_plane = Vec::with_capacity((chroma_width * chroma_height) as usize);
+    for chroma_y in 0..chroma_height {
+        for chroma_x in 0..chroma_width {
+            let (mut r, mut g, mut b, mut count) = (0, 0, 0, 0);
+            for y in chroma_y * 2..(chroma_y * 2 + 2).min(height) {
+                for x in chroma_x * 2..(chroma_x * 2 + 2).min(width) {
+                    let [pr, pg, pb, _] = frame.get_pixel(x, y).0;
+                    r += pr as i32;
+                    g += pg as i32;
+                    b += pb as i32;
+                    count += 1;
+                }
+            }
+            let (r, g, b) = (r / count, g / count, b / count);
+            planes.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+            v_plane.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+        }
+    }
+    planes.extend(v_plane);
+    planes
+}
+
+/// Frames of RGBA or BGRA pixels, one after another without any header.
+pub struct RawWriter {
+    output: Box<dyn Write>,
+    fps: u32,
+    bgra: bool,
+}
+
+impl RawWriter {
+    pub fn new(output: Box<dyn Write>, fps: u32, bgra: bool) -> Self {
+        Self { output, fps, bgra }
+    }
+}
+
+impl FrameWriter for RawWriter {
+    fn write_frame(&mut self, frame: &RgbaImage, start: Duration, end: Duration) -> Result<()> {
+        let slots = frame_slots(start, end, self.fps);
+        if slots == 0 {
+            return Ok(());
+        }
+
+        let pixels = if self.bgra {
+            let mut pixels = frame.as_raw().clone();
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            Cow::Owned(pixels)
+        } else {
+            Cow::Borrowed(frame.as_raw())
+        };
+        for _ in 0..slots {
+            self.output.write_all(&pixels)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video::{
+        StreamFormat, VideoFormat, stream_writer,
+        tests::{RED, SharedBuffer, frames, record},
+    };
+
+    /// The frames shown in each of the 4 slots of the recording at 10 frames
+    /// per second.
+    fn slots() -> Vec<RgbaImage> {
+        let frames = frames();
+        [0, 1, 1, 2]
+            .into_iter()
+            .map(|index| frames[index].0.clone())
+            .collect()
+    }
+
+    #[test]
+    fn yuv420_uses_bt601_limited_range() {
+        let black = RgbaImage::new(2, 2);
+        assert_eq!(yuv420(&black), [16, 16, 16, 16, 128, 128]);
+        let red = RgbaImage::from_pixel(2, 2, RED);
+        assert_eq!(yuv420(&red), [82, 82, 82, 82, 90, 240]);
+    }
+
+    #[test]
+    fn yuv420_averages_chroma_up_to_odd_edges() {
+        // Red with a white 2x2 square at (2, 1), the chroma blocks at x = 2
+        // are half white and the ones at x = 4 and y = 2 cover fewer pixels.
+        let square = &frames()[1].0;
+        #[rustfmt::skip]
+        let expected = [
+            82, 82, 82, 82, 82,
+            82, 82, 235, 235, 82,
+            82, 82, 235, 235, 82,
+            90, 109, 90,
+            90, 128, 90,
+            240, 184, 240,
+            240, 128, 240,
+        ];
+        assert_eq!(yuv420(square), expected);
+    }
+
+    #[test]
+    fn y4m_repeats_frames_for_their_slots() -> Result<()> {
+        let mut expected = b"YUV4MPEG2 W5 H3 F10:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n".to_vec();
+        for frame in slots() {
+            expected.extend(b"FRAME\n");
+            expected.extend(yuv420(&frame));
+        }
+        assert_eq!(record(VideoFormat::Y4m)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn raw_repeats_frames_for_their_slots() -> Result<()> {
+        let expected: Vec<u8> = slots()
+            .iter()
+            .flat_map(|frame| frame.as_raw().clone())
+            .collect();
+        assert_eq!(record(VideoFormat::Raw)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn bgra_swaps_red_and_blue() -> Result<()> {
+        let output = SharedBuffer::default();
+        let mut writer = stream_writer(StreamFormat::Bgra, Box::new(output.clone()), 1, 2, 10)?;
+        let red = RgbaImage::from_pixel(1, 2, RED);
+        writer.write_frame(&red, Duration::ZERO, Duration::from_millis(100))?;
+        writer.finish()?;
+        assert_eq!(output.take(), [0, 0, 255, 255, 0, 0, 255, 255]);
+        Ok(())
+    }
+}
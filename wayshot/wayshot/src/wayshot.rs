@@ -1,15 +1,33 @@
 use std::{
+    cell::Cell,
     env,
     io::{self, BufWriter, Cursor, Write},
-    process::Command,
+    rc::Rc,
+    thread,
 };
 
 use clap::Parser;
 use eyre::{Result, bail};
-use libwayshot::{WayshotConnection, region::LogicalRegion};
+use libwayshot::{
+    CaptureDump, Timeouts, WayshotConnection,
+    output::{OutputInfo, transform_name},
+    region::LogicalRegion,
+};
 
+mod annotate;
 mod cli;
+mod config;
+mod decorate;
+mod diff;
+mod encode;
+mod interval;
+mod measure;
+mod pick;
+mod record;
+mod redact;
+mod stream;
 mod utils;
+mod video;
 
 use dialoguer::{FuzzySelect, theme::ColorfulTheme};
 use utils::EncodingFormat;
@@ -40,6 +58,33 @@ fn main() -> Result<()> {
         .with_writer(io::stderr)
         .init();
 
+    match cli.command {
+        Some(cli::Command::Record(args)) => return record::record(args, cli.timeout),
+        Some(cli::Command::Diff(args)) => diff::exit_with_diff(args, cli.config.as_deref()),
+        None => {}
+    }
+    if let Some(format) = cli.stream {
+        return stream::stream(cli, format);
+    }
+    if let Some(interval) = cli.interval {
+        return interval::capture_interval(cli, interval);
+    }
+    if cli.pick {
+        return pick::pick(cli);
+    }
+    if cli.measure {
+        return measure::measure(cli);
+    }
+    if cli.list_outputs {
+        return list_outputs(&cli);
+    }
+
+    // Read before capturing, so a typo doesn't waste the screenshot.
+    let redactions = redact::Redactions::from_cli(&cli)?;
+    let annotations = annotate::Annotations::from_cli(&cli)?;
+    let decorations = decorate::Decorations::from_cli(&cli);
+    let encoder = encode::EncoderOptions::from_cli(&cli)?;
+
     let input_encoding = cli
         .file
         .as_ref()
@@ -56,10 +101,11 @@ fn main() -> Result<()> {
             );
         }
     }
+    encoder.check(encoding)?;
 
     let file_name_format = cli
         .file_name_format
-        .unwrap_or("wayshot-%Y_%m_%d-%H_%M_%S".to_string());
+        .unwrap_or(utils::DEFAULT_FILE_NAME_FORMAT.to_string());
     let mut stdout_print = false;
     let file = match cli.file {
         Some(pathbuf) => {
@@ -88,50 +134,203 @@ fn main() -> Result<()> {
         }
     };
 
-    let wayshot_conn = WayshotConnection::new()?;
-
     let stdout = io::stdout();
     let mut writer = BufWriter::new(stdout.lock());
 
-    if cli.list_outputs {
-        let valid_outputs = wayshot_conn.get_all_outputs();
-        for output in valid_outputs {
-            writeln!(writer, "{}", output.name)?;
+    // The screenshot and the region it shows, to redact regions in it.
+    let (mut image_buffer, capture_region) = if let Some(dump_path) = cli.replay_capture {
+        let dump = CaptureDump::load(dump_path)?;
+        (dump.replay()?, dump.capture_region)
+    } else {
+        let mut wayshot_conn = WayshotConnection::new()?;
+        if let Some(timeout) = cli.timeout {
+            wayshot_conn.set_timeouts(Timeouts::uniform(timeout));
         }
+        wayshot_conn.set_capture_dump(cli.dump_capture);
 
-        writer.flush()?;
-
-        return Ok(());
-    }
+        // Called right before capturing, after anything else asking the user.
+        let wait_for_delay = || -> Result<()> {
+            match cli.delay {
+                Some(delay) if cli.countdown => wayshot_conn.show_countdown(delay)?,
+                Some(delay) => thread::sleep(delay),
+                None => {}
+            }
+            Ok(())
+        };
+        // With `--wait-stable`, screenshots of regions are taken once they
+        // stopped changing.
+        let screenshot_stable = |region: LogicalRegion, stable_for| {
+            wayshot_conn.screenshot_stable(region, cli.cursor, stable_for, cli.max_wait)
+        };
 
-    let image_buffer = wayshot_conn.screenshot_all(cli.cursor)?;
+        if let Some(slurp_region) = cli.slurp {
+            let slurp_region = slurp_region.unwrap_or_default();
+            let selected = Rc::new(Cell::new(None));
+            let selection = Rc::clone(&selected);
+            wait_for_delay()?;
+            let image = wayshot_conn.screenshot_freeze(
+                Box::new(move || {
+                    let region = utils::slurp_region(&slurp_region)
+                        .map_err(|_| libwayshot::Error::FreezeCallbackError)?;
+                    selection.set(Some(region));
+                    Ok(region)
+                }),
+                cli.cursor,
+            )?;
+            let Some(region) = selected.get() else {
+                bail!("No region was selected");
+            };
+            (image, region)
+        } else if let Some(geometry) = cli.geometry {
+            let region = geometry.resolve(&wayshot_conn.get_all_outputs())?;
+            wait_for_delay()?;
+            let image = match cli.wait_stable {
+                Some(stable_for) => screenshot_stable(region, stable_for)?,
+                None => wayshot_conn.screenshot(region, cli.cursor)?,
+            };
+            (image, region)
+        } else if let Some(output_name) = cli.output {
+            let outputs = wayshot_conn.get_all_outputs();
+            let Some(output) = outputs.iter().find(|output| output.name == output_name) else {
+                bail!("No output found with name '{output_name}'");
+            };
+            wait_for_delay()?;
+            let image = match cli.wait_stable {
+                Some(stable_for) => screenshot_stable(output.into(), stable_for)?,
+                None => wayshot_conn.screenshot_single_output(output, cli.cursor)?,
+            };
+            (image, output.into())
+        } else if cli.choose_output {
+            let outputs = wayshot_conn.get_all_outputs();
+            let output_names: Vec<&str> =
+                outputs.iter().map(|output| output.name.as_str()).collect();
+            let Some(index) = select_output(&output_names) else {
+                bail!("No output was selected");
+            };
+            wait_for_delay()?;
+            let image = match cli.wait_stable {
+                Some(stable_for) => screenshot_stable((&outputs[index]).into(), stable_for)?,
+                None => wayshot_conn.screenshot_single_output(&outputs[index], cli.cursor)?,
+            };
+            (image, (&outputs[index]).into())
+        } else {
+            let region = wayshot_conn.get_all_outputs().as_slice().try_into()?;
+            wait_for_delay()?;
+            let image = match cli.wait_stable {
+                Some(stable_for) => screenshot_stable(region, stable_for)?,
+                None => wayshot_conn.screenshot_all(cli.cursor)?,
+            };
+            (image, region)
+        }
+    };
+    redactions.apply(&mut image_buffer, capture_region);
+    annotations.apply(&mut image_buffer, capture_region);
+    decorations.apply(&mut image_buffer, capture_region);
 
     let mut image_buf: Option<Cursor<Vec<u8>>> = None;
     if let Some(f) = file {
-        image_buffer.save(f)?;
+        encoder.save(&image_buffer, encoding, &f)?;
     } else if stdout_print {
         let mut buffer = Cursor::new(Vec::new());
-        image_buffer.write_to(&mut buffer, encoding.into())?;
+        encoder.write(&image_buffer, encoding, &mut buffer)?;
         writer.write_all(buffer.get_ref())?;
         image_buf = Some(buffer);
     }
 
     if cli.clipboard {
-        clipboard_daemonize(match image_buf {
-            Some(buf) => buf,
-            None => {
-                let mut buffer = Cursor::new(Vec::new());
-                image_buffer.write_to(&mut buffer, encoding.into())?;
-                buffer
-            }
-        })?;
+        clipboard_daemonize(
+            match image_buf {
+                Some(buf) => buf,
+                None => {
+                    let mut buffer = Cursor::new(Vec::new());
+                    encoder.write(&image_buffer, encoding, &mut buffer)?;
+                    buffer
+                }
+            },
+            MimeType::Autodetect,
+        )?;
     }
 
     Ok(())
 }
 
-/// Daemonize and copy the given buffer containing the encoded image to the clipboard
-fn clipboard_daemonize(buffer: Cursor<Vec<u8>>) -> Result<()> {
+/// Print the outputs as JSON with `--json`, as a table with `--table`, or else
+/// their names.
+fn list_outputs(cli: &cli::Cli) -> Result<()> {
+    let wayshot_conn = WayshotConnection::new()?;
+    let valid_outputs = wayshot_conn.get_all_outputs();
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if cli.json {
+        serde_json::to_writer_pretty(&mut writer, &valid_outputs)?;
+        writeln!(writer)?;
+    } else if cli.table {
+        write_output_table(&mut writer, &valid_outputs)?;
+    } else {
+        for output in valid_outputs {
+            writeln!(writer, "{}", output.name)?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write one row per output with its geometry, scale and transform, columns
+/// padded to the widest value.
+fn write_output_table(writer: &mut impl Write, outputs: &[OutputInfo]) -> Result<()> {
+    let header = [
+        "NAME",
+        "POSITION",
+        "LOGICAL SIZE",
+        "PHYSICAL SIZE",
+        "SCALE",
+        "TRANSFORM",
+        "DESCRIPTION",
+    ];
+    let rows: Vec<[String; 7]> = outputs
+        .iter()
+        .map(|output| {
+            let region = output.logical_region.inner;
+            [
+                output.name.clone(),
+                format!("{},{}", region.position.x, region.position.y),
+                format!("{}x{}", region.size.width, region.size.height),
+                format!(
+                    "{}x{}",
+                    output.physical_size.width, output.physical_size.height
+                ),
+                format!("{:.2}", output.scale()),
+                transform_name(output.transform).to_string(),
+                output.description.clone(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+/// Daemonize and copy the given buffer containing the encoded image, or other content of
+/// `mime_type`, to the clipboard
+fn clipboard_daemonize(buffer: Cursor<Vec<u8>>, mime_type: MimeType) -> Result<()> {
     let mut opts = Options::new();
     match unsafe { runtime::kernel_fork() } {
         // Having the image persistently available on the clipboard requires a wayshot process to be alive.
@@ -141,20 +340,14 @@ fn clipboard_daemonize(buffer: Cursor<Vec<u8>>) -> Result<()> {
         }
         Ok(Fork::Child(_)) => {
             opts.foreground(true); // Offer the image till something else is available on the clipboard
-            opts.copy(
-                Source::Bytes(buffer.into_inner().into()),
-                MimeType::Autodetect,
-            )?;
+            opts.copy(Source::Bytes(buffer.into_inner().into()), mime_type)?;
         }
         Err(e) => {
             tracing::warn!(
                 "Fork failed with error: {e}, couldn't offer image on the clipboard persistently.
                  Use a clipboard manager to record screenshot."
             );
-            opts.copy(
-                Source::Bytes(buffer.into_inner().into()),
-                MimeType::Autodetect,
-            )?;
+            opts.copy(Source::Bytes(buffer.into_inner().into()), mime_type)?;
         }
     }
     Ok(())
@@ -0,0 +1,183 @@
+//! `wayshot --interval` run against the mock compositor.
+
+use std::{
+    env, fs, io,
+    io::Read,
+    os::unix::net::UnixListener,
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::Rgba;
+use wayshot_mock_compositor::{Content, Failure, MockCompositor, MockOutput};
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+/// Directory of a test, removed again afterwards.
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(test: &str) -> Self {
+        let path = env::temp_dir().join(format!("wayshot-{test}-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("shots")).unwrap();
+        Self(path)
+    }
+
+    fn shots(&self) -> PathBuf {
+        self.0.join("shots")
+    }
+
+    /// Names of the saved screenshots, in order.
+    fn saved(&self) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(self.shots())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run wayshot with `args`, connected to `compositor` through a socket in
+/// `dir`, which also keeps any config file of the user out.
+fn spawn_wayshot(compositor: &MockCompositor, dir: &Path, args: &[&str]) -> Child {
+    let listener = UnixListener::bind(dir.join("wayland-0")).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wayshot"))
+        .args(args)
+        .env("XDG_RUNTIME_DIR", dir)
+        .env("WAYLAND_DISPLAY", "wayland-0")
+        .env_remove("WAYLAND_SOCKET")
+        .env("XDG_CONFIG_HOME", dir)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                compositor.add_client(stream).unwrap();
+                return child;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if child.try_wait().unwrap().is_some() || Instant::now() > deadline {
+                    panic!("wayshot didn't connect: {}", finish(child));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+/// Wait for `child` to exit, returns what it logged.
+fn finish(mut child: Child) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            break child.wait().unwrap();
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let mut log = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut log)
+        .unwrap();
+    assert!(status.success(), "wayshot failed with {status}: {log}");
+    log
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for wayshot");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn color(path: &Path) -> [u8; 4] {
+    image::open(path).unwrap().to_rgba8().get_pixel(0, 0).0
+}
+
+#[test]
+fn skips_unchanged_screenshots() {
+    let dir = TestDir::new("skips-unchanged");
+    // Frames only come when something changed, that must not stall the
+    // captures in between.
+    let compositor = MockCompositor::new([MockOutput::new("out", 8, 4)
+        .content(Content::Solid(RED))
+        .failure(Failure::WithholdUnchangedFrames)])
+    .unwrap();
+    let shots = dir.shots();
+    let args = [
+        "--interval",
+        "200ms",
+        "--count",
+        "4",
+        "--file-name-format",
+        "shot",
+        shots.to_str().unwrap(),
+    ];
+    let wayshot = spawn_wayshot(&compositor, &dir.0, &args);
+
+    wait_for(|| !dir.saved().is_empty());
+    compositor.update_output("out", |output| output.content = Content::Solid(BLUE));
+    let log = finish(wayshot);
+
+    assert_eq!(dir.saved(), ["shot-0001.png", "shot-0002.png"]);
+    assert_eq!(color(&shots.join("shot-0001.png")), RED);
+    assert_eq!(color(&shots.join("shot-0002.png")), BLUE);
+    assert!(
+        log.contains("Saved 2 screenshots, skipped 2 unchanged ones"),
+        "{log}"
+    );
+}
+
+#[test]
+fn follows_the_output_layout() {
+    let dir = TestDir::new("follows-layout");
+    let compositor =
+        MockCompositor::new([MockOutput::new("left", 8, 4).content(Content::Solid(RED))]).unwrap();
+    let shots = dir.shots();
+    let args = [
+        "--interval",
+        "200ms",
+        "--count",
+        "3",
+        "--file-name-format",
+        "shot",
+        shots.to_str().unwrap(),
+    ];
+    let wayshot = spawn_wayshot(&compositor, &dir.0, &args);
+
+    wait_for(|| !dir.saved().is_empty());
+    compositor.add_output(
+        MockOutput::new("right", 8, 4)
+            .position(8, 0)
+            .content(Content::Solid(BLUE)),
+    );
+    finish(wayshot);
+
+    assert_eq!(dir.saved(), ["shot-0001.png", "shot-0002.png"]);
+    let image = image::open(shots.join("shot-0002.png")).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (16, 4));
+    assert_eq!(*image.get_pixel(12, 0), Rgba(BLUE));
+}